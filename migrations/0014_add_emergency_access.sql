@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS emergency_access (
+    id TEXT PRIMARY KEY NOT NULL,
+    grantor_id TEXT NOT NULL,
+    grantee_id TEXT,
+    email TEXT,
+    key_encrypted TEXT,
+    atype INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    wait_time_days INTEGER NOT NULL,
+    recovery_initiated_at TEXT,
+    last_notification_at TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (grantor_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (grantee_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantor_id
+    ON emergency_access(grantor_id);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantee_id
+    ON emergency_access(grantee_id);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_status
+    ON emergency_access(status);
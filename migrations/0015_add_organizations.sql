@@ -0,0 +1,94 @@
+-- Minimal organization support for sharing ciphers among a small team.
+-- Membership types: 0=Owner, 1=Admin, 2=User, 3=Manager
+-- Membership status: 0=Invited, 1=Accepted, 2=Confirmed
+
+CREATE TABLE IF NOT EXISTS organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    billing_email TEXT NOT NULL,
+    private_key TEXT,
+    public_key TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS users_organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    org_id TEXT NOT NULL,
+    akey TEXT, -- Organization key encrypted with the member's public key (set on confirm)
+    status INTEGER NOT NULL,
+    atype INTEGER NOT NULL,
+    access_all INTEGER NOT NULL DEFAULT 0,
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    UNIQUE(user_id, org_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_organizations_org_id
+    ON users_organizations(org_id);
+
+CREATE TABLE IF NOT EXISTS collections (
+    id TEXT PRIMARY KEY NOT NULL,
+    org_id TEXT NOT NULL,
+    name TEXT NOT NULL, -- Encrypted with the organization key
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_collections_org_id ON collections(org_id);
+
+CREATE TABLE IF NOT EXISTS users_collections (
+    user_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, collection_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_collections_collection_id
+    ON users_collections(collection_id);
+
+CREATE TABLE IF NOT EXISTS ciphers_collections (
+    cipher_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    FOREIGN KEY (cipher_id) REFERENCES ciphers(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    PRIMARY KEY (cipher_id, collection_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_ciphers_collections_collection_id
+    ON ciphers_collections(collection_id);
+
+CREATE INDEX IF NOT EXISTS idx_ciphers_organization_id ON ciphers(organization_id);
+
+-- Organization ciphers a user can reach, one row per granting path.
+-- Owners, admins and members with access_all see every cipher of the organization
+-- (with and without a collection); other members only see ciphers in their collections.
+CREATE VIEW IF NOT EXISTS cipher_access AS
+    SELECT c.id AS cipher_id, uo.user_id AS user_id, NULL AS collection_id,
+           0 AS read_only, 0 AS hide_passwords
+    FROM ciphers c
+    JOIN users_organizations uo ON uo.org_id = c.organization_id
+    WHERE uo.status = 2 AND (uo.access_all = 1 OR uo.atype IN (0, 1))
+    UNION ALL
+    SELECT cc.cipher_id, uo.user_id, cc.collection_id, 0, 0
+    FROM ciphers_collections cc
+    JOIN collections col ON col.id = cc.collection_id
+    JOIN users_organizations uo ON uo.org_id = col.org_id
+    WHERE uo.status = 2 AND (uo.access_all = 1 OR uo.atype IN (0, 1))
+    UNION ALL
+    SELECT cc.cipher_id, uc.user_id, cc.collection_id, uc.read_only, uc.hide_passwords
+    FROM ciphers_collections cc
+    JOIN users_collections uc ON uc.collection_id = cc.collection_id
+    JOIN collections col ON col.id = cc.collection_id
+    JOIN users_organizations uo ON uo.org_id = col.org_id AND uo.user_id = uc.user_id
+    WHERE uo.status = 2;
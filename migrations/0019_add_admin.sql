@@ -0,0 +1,6 @@
+ALTER TABLE users ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE IF NOT EXISTS invitations (
+    email TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY NOT NULL,
+    event_type INTEGER NOT NULL,
+    user_id TEXT,
+    organization_id TEXT,
+    cipher_id TEXT,
+    acting_user_id TEXT,
+    device_type INTEGER,
+    ip_address TEXT,
+    event_date TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_user_id_date ON events(user_id, event_date);
+CREATE INDEX IF NOT EXISTS idx_events_cipher_id_date ON events(cipher_id, event_date);
+CREATE INDEX IF NOT EXISTS idx_events_event_date ON events(event_date);
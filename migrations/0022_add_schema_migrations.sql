@@ -0,0 +1,13 @@
+-- Migrations applied by the Worker's built-in runner, one row per file in migrations/.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+);
+
+-- Single-row lease held by the isolate applying migrations; expires_at is in milliseconds.
+CREATE TABLE IF NOT EXISTS schema_lock (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    owner TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
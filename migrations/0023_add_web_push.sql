@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS web_push_subscriptions (
+    device_identifier TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (device_identifier, user_id),
+    FOREIGN KEY (device_identifier, user_id) REFERENCES devices(identifier, user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_web_push_subscriptions_user_id ON web_push_subscriptions(user_id);
+
+CREATE TABLE IF NOT EXISTS vapid_keys (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    private_key TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
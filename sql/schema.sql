@@ -20,6 +20,9 @@ CREATE TABLE IF NOT EXISTS users (
     equivalent_domains TEXT NOT NULL DEFAULT '[]', -- JSON: Vec<Vec<String>>
     excluded_globals TEXT NOT NULL DEFAULT '[]', -- JSON: Vec<i32> (reserved for future global groups)
     totp_recover TEXT, -- Recovery code for 2FA
+    last_verifying_at TEXT, -- When the last email verification link was sent (resend throttle)
+    api_key TEXT, -- Personal API key (client_credentials grant), NULL until first requested
+    enabled INTEGER NOT NULL DEFAULT 1, -- 0 when disabled by the operator (no new logins)
     created_at TEXT NOT NULL,
     updated_at TEXT NOT NULL
 );
@@ -196,3 +199,242 @@ CREATE TABLE IF NOT EXISTS sends_pending (
   disabled INTEGER NOT NULL DEFAULT 0,
   hide_email INTEGER NOT NULL DEFAULT 0
 );
+
+-- Emergency access grants between a grantor and a trusted contact (grantee).
+-- Types: 0=View, 1=Takeover
+-- Status: 0=Invited, 1=Accepted, 2=Confirmed, 3=RecoveryInitiated, 4=RecoveryApproved
+CREATE TABLE IF NOT EXISTS emergency_access (
+    id TEXT PRIMARY KEY NOT NULL,
+    grantor_id TEXT NOT NULL,
+    grantee_id TEXT,
+    email TEXT,
+    key_encrypted TEXT,
+    atype INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    wait_time_days INTEGER NOT NULL,
+    recovery_initiated_at TEXT,
+    last_notification_at TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (grantor_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (grantee_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantor_id ON emergency_access(grantor_id);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_grantee_id ON emergency_access(grantee_id);
+CREATE INDEX IF NOT EXISTS idx_emergency_access_status ON emergency_access(status);
+
+-- Organizations, memberships and collections for sharing ciphers.
+-- Membership types: 0=Owner, 1=Admin, 2=User, 3=Manager
+-- Membership status: 0=Invited, 1=Accepted, 2=Confirmed
+
+CREATE TABLE IF NOT EXISTS organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    billing_email TEXT NOT NULL,
+    private_key TEXT,
+    public_key TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS users_organizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    org_id TEXT NOT NULL,
+    akey TEXT, -- Organization key encrypted with the member's public key (set on confirm)
+    status INTEGER NOT NULL,
+    atype INTEGER NOT NULL,
+    access_all INTEGER NOT NULL DEFAULT 0,
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    UNIQUE(user_id, org_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_organizations_org_id
+    ON users_organizations(org_id);
+
+CREATE TABLE IF NOT EXISTS collections (
+    id TEXT PRIMARY KEY NOT NULL,
+    org_id TEXT NOT NULL,
+    name TEXT NOT NULL, -- Encrypted with the organization key
+    external_id TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_collections_org_id ON collections(org_id);
+
+CREATE TABLE IF NOT EXISTS users_collections (
+    user_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    read_only INTEGER NOT NULL DEFAULT 0,
+    hide_passwords INTEGER NOT NULL DEFAULT 0,
+    manage INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, collection_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_collections_collection_id
+    ON users_collections(collection_id);
+
+CREATE TABLE IF NOT EXISTS ciphers_collections (
+    cipher_id TEXT NOT NULL,
+    collection_id TEXT NOT NULL,
+    FOREIGN KEY (cipher_id) REFERENCES ciphers(id) ON DELETE CASCADE,
+    FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+    PRIMARY KEY (cipher_id, collection_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_ciphers_collections_collection_id
+    ON ciphers_collections(collection_id);
+
+CREATE INDEX IF NOT EXISTS idx_ciphers_organization_id ON ciphers(organization_id);
+
+-- Organization ciphers a user can reach, one row per granting path.
+-- Owners, admins and members with access_all see every cipher of the organization
+-- (with and without a collection); other members only see ciphers in their collections.
+CREATE VIEW IF NOT EXISTS cipher_access AS
+    SELECT c.id AS cipher_id, uo.user_id AS user_id, NULL AS collection_id,
+           0 AS read_only, 0 AS hide_passwords
+    FROM ciphers c
+    JOIN users_organizations uo ON uo.org_id = c.organization_id
+    WHERE uo.status = 2 AND (uo.access_all = 1 OR uo.atype IN (0, 1))
+    UNION ALL
+    SELECT cc.cipher_id, uo.user_id, cc.collection_id, 0, 0
+    FROM ciphers_collections cc
+    JOIN collections col ON col.id = cc.collection_id
+    JOIN users_organizations uo ON uo.org_id = col.org_id
+    WHERE uo.status = 2 AND (uo.access_all = 1 OR uo.atype IN (0, 1))
+    UNION ALL
+    SELECT cc.cipher_id, uc.user_id, cc.collection_id, uc.read_only, uc.hide_passwords
+    FROM ciphers_collections cc
+    JOIN users_collections uc ON uc.collection_id = cc.collection_id
+    JOIN collections col ON col.id = cc.collection_id
+    JOIN users_organizations uo ON uo.org_id = col.org_id AND uo.user_id = uc.user_id
+    WHERE uo.status = 2;
+
+-- Passkeys used to log in (and, with PRF, unlock) instead of the master password.
+-- credential_id and public_key are base64url; public_key holds the COSE_Key.
+-- The encrypted_* columns hold the PRF key set: the user key encrypted with the
+-- PRF-derived public key, and that key pair encrypted with the user key.
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    credential_id TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    counter INTEGER NOT NULL DEFAULT 0,
+    supports_prf INTEGER NOT NULL DEFAULT 0,
+    encrypted_user_key TEXT,
+    encrypted_public_key TEXT,
+    encrypted_private_key TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(credential_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
+
+-- Addresses invited by the operator through the admin API; consumed on registration.
+CREATE TABLE IF NOT EXISTS invitations (
+    email TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- Runtime settings edited through the admin API; each key overrides the env var of the same name.
+CREATE TABLE IF NOT EXISTS config (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- Audit log of security-relevant actions. Cipher events outlive the cipher they refer to.
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY NOT NULL,
+    event_type INTEGER NOT NULL,
+    user_id TEXT,
+    organization_id TEXT,
+    cipher_id TEXT,
+    acting_user_id TEXT,
+    device_type INTEGER,
+    ip_address TEXT,
+    event_date TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_user_id_date ON events(user_id, event_date);
+CREATE INDEX IF NOT EXISTS idx_events_cipher_id_date ON events(cipher_id, event_date);
+CREATE INDEX IF NOT EXISTS idx_events_event_date ON events(event_date);
+
+-- Web Push subscriptions of browser extensions and the web vault, one per device.
+CREATE TABLE IF NOT EXISTS web_push_subscriptions (
+    device_identifier TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (device_identifier, user_id),
+    FOREIGN KEY (device_identifier, user_id) REFERENCES devices(identifier, user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_web_push_subscriptions_user_id ON web_push_subscriptions(user_id);
+
+-- The server's VAPID key pair (RFC 8292), generated on first use; keys are base64url.
+CREATE TABLE IF NOT EXISTS vapid_keys (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    private_key TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- Fan-out and push deliveries that failed, waiting for the cron job to retry them.
+-- `status` is 'pending' or 'dead'; `payload` is the JSON job.
+CREATE TABLE IF NOT EXISTS outbox (
+    id TEXT PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    target TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL,
+    last_error TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_status_next_attempt ON outbox(status, next_attempt_at);
+
+-- Pending passkey ceremony challenges, deleted when the client answers them.
+-- `user_id` is NULL for logins, where the user is not known until the assertion arrives.
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    id TEXT PRIMARY KEY NOT NULL,
+    purpose TEXT NOT NULL,
+    challenge TEXT NOT NULL,
+    user_id TEXT,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_challenges_expires_at ON webauthn_challenges(expires_at);
+
+-- Migrations applied by the Worker's built-in runner, one row per file in migrations/.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+);
+
+-- Single-row lease held by the isolate applying migrations; expires_at is in milliseconds.
+CREATE TABLE IF NOT EXISTS schema_lock (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    owner TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -8,14 +8,19 @@ use jwt_compact::AlgorithmExt;
 use jwt_compact::{alg::Hs256Key, TimeOptions, UntrustedToken};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use worker::Env;
 
+use crate::client_context::request_ip_from_headers;
+use crate::crypto::{ct_eq, hash_password_for_storage};
 use crate::db;
+use crate::env::Env;
 use crate::error::AppError;
 use crate::models::device::Device;
 
 pub(crate) const JWT_VALIDATION_LEEWAY_SECS: u64 = 60;
 
+/// Prefix of the `ADMIN_TOKEN` secret: `pbkdf2-sha256$<iterations>$<base64 salt>$<base64 hash>`.
+const ADMIN_TOKEN_SCHEME: &str = "pbkdf2-sha256";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,    // User ID
@@ -75,6 +80,73 @@ impl FromRequestParts<Arc<Env>> for AuthUser {
     }
 }
 
+/// AdminAuth extractor - the request carries `Authorization: Bearer <admin token>`
+/// matching the hash stored in the `ADMIN_TOKEN` secret.
+pub struct AdminAuth;
+
+impl FromRequestParts<Arc<Env>> for AdminAuth {
+    type Rejection = AppError;
+    #[worker::send]
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<Env>,
+    ) -> Result<Self, Self::Rejection> {
+        // Without ADMIN_TOKEN the admin API does not exist.
+        let stored = state
+            .secret("ADMIN_TOKEN")
+            .map(|v| v.to_string())
+            .map_err(|_| AppError::NotFound("Not found".to_string()))?;
+
+        // Rate limit by IP address to slow down guessing.
+        if let Ok(rate_limiter) = state.rate_limiter("LOGIN_RATE_LIMITER") {
+            let rate_limit_key = format!("admin:{}", request_ip_from_headers(&parts.headers));
+            if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+                if !outcome.success {
+                    return Err(AppError::TooManyRequests(
+                        "Too many requests. Please try again later.".to_string(),
+                    ));
+                }
+            }
+        }
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|auth_header| auth_header.to_str().ok())
+            .and_then(bearer_token_from_header_value)
+            .ok_or_else(|| AppError::Unauthorized("Missing or invalid token".to_string()))?;
+
+        if !verify_admin_token(&stored, &token).await? {
+            return Err(AppError::Unauthorized("Invalid admin token".to_string()));
+        }
+        Ok(AdminAuth)
+    }
+}
+
+/// Check `token` against the PBKDF2 hash stored in `ADMIN_TOKEN`.
+async fn verify_admin_token(stored: &str, token: &str) -> Result<bool, AppError> {
+    let mut fields = stored.trim().split('$');
+    let (Some(ADMIN_TOKEN_SCHEME), Some(iterations), Some(salt), Some(hash), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        log::error!(
+            "ADMIN_TOKEN must have the form {ADMIN_TOKEN_SCHEME}$<iterations>$<salt>$<hash>"
+        );
+        return Err(AppError::Internal);
+    };
+    let iterations = iterations.parse::<u32>().map_err(|_| {
+        log::error!("ADMIN_TOKEN has an invalid iteration count");
+        AppError::Internal
+    })?;
+
+    let derived = hash_password_for_storage(token, salt, iterations).await?;
+    Ok(ct_eq(&derived, hash))
+}
+
 pub(crate) fn bearer_token_from_header_value(auth_value: &str) -> Option<String> {
     auth_value
         .strip_prefix("Bearer ")
@@ -1,25 +1,35 @@
-use std::future::Future;
-use std::panic::AssertUnwindSafe;
-use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::future_to_promise;
+#[cfg(not(test))]
+pub use wait_until::spawn_background;
 
-#[wasm_bindgen(raw_module = "cloudflare:workers")]
-extern "C" {
-    #[wasm_bindgen(js_name = waitUntil)]
-    fn wait_until_js(promise: &js_sys::Promise);
-}
+/// Unit tests queue background tasks and run them once the response is ready.
+#[cfg(test)]
+pub use crate::testing::spawn_background;
+
+#[cfg(not(test))]
+mod wait_until {
+    use std::future::Future;
+    use std::panic::AssertUnwindSafe;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::future_to_promise;
+
+    #[wasm_bindgen(raw_module = "cloudflare:workers")]
+    extern "C" {
+        #[wasm_bindgen(js_name = waitUntil)]
+        fn wait_until_js(promise: &js_sys::Promise);
+    }
 
-/// Schedule a fire-and-forget background task via `waitUntil`.
-///
-/// The response is sent to the client immediately; the Worker keeps running
-/// until the future settles (up to the 30-second `waitUntil` budget).
-pub fn spawn_background<F>(future: F)
-where
-    F: Future<Output = ()> + 'static,
-{
-    let promise = future_to_promise(AssertUnwindSafe(async {
-        future.await;
-        Ok(JsValue::UNDEFINED)
-    }));
-    wait_until_js(&promise);
+    /// Schedule a fire-and-forget background task via `waitUntil`.
+    ///
+    /// The response is sent to the client immediately; the Worker keeps running
+    /// until the future settles (up to the 30-second `waitUntil` budget).
+    pub fn spawn_background<F>(future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let promise = future_to_promise(AssertUnwindSafe(async {
+            future.await;
+            Ok(JsValue::UNDEFINED)
+        }));
+        wait_until_js(&promise);
+    }
 }
@@ -0,0 +1,882 @@
+//! Vault backups to R2.
+//!
+//! A backup is a gzip-compressed JSON dump of every D1 table together with a manifest of the
+//! attachment and Send files in storage; the files themselves are not copied. When the
+//! `BACKUP_ENCRYPTION_KEY` secret is set, the archive is encrypted with AES-256-GCM under a key
+//! derived from it. Backups are written to the `BACKUP_BUCKET` R2 binding by the scheduled job
+//! and can be restored into an empty database through the admin API.
+//!
+//! The dump is written one page of rows at a time into a gzip stream, so only the compressed
+//! archive is held in memory. A restore is not streamed: it holds the compressed and the
+//! decompressed archive in memory at once, which bounds the vault size a Worker can restore
+//! (see `docs/db-backup-recovery.md`). It fills staging tables first and moves their rows into
+//! place in a single batch: it either lands completely or leaves the database empty, and can
+//! simply be run again.
+
+use futures_util::future::LocalBoxFuture;
+use js_sys::{Array, Uint8Array};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, value::RawValue, Map, Value};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    Blob, ReadableStream, ReadableWritablePair, WritableStream, WritableStreamDefaultWriter,
+};
+use worker::Bucket;
+
+use crate::{
+    config::Config,
+    crypto::{aes_gcm_decrypt, aes_gcm_encrypt, random_bytes, webcrypto_pbkdf2_sha256},
+    db,
+    env::Env,
+    error::AppError,
+    storage::{get_storage_backend, StorageBackend},
+};
+
+const BACKUP_BUCKET: &str = "BACKUP_BUCKET";
+const BACKUP_PREFIX: &str = "backups/";
+
+const ARCHIVE_FORMAT: &str = "warden-backup";
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Encrypted archives start with this header, followed by the salt, the IV and the ciphertext.
+const ENCRYPTED_MAGIC: &[u8; 4] = b"WBK1";
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+/// Workers WebCrypto limits PBKDF2 to 100,000 iterations.
+const KEY_DERIVATION_ITERATIONS: u32 = 100_000;
+
+/// Rows fetched per query while dumping a table.
+const DUMP_PAGE_SIZE: i64 = 500;
+/// Archive bytes collected before they are handed to the gzip stream.
+const DUMP_CHUNK_SIZE: usize = 64 * 1024;
+/// Rows inserted per D1 batch while restoring.
+const RESTORE_BATCH_SIZE: usize = 50;
+/// A restore stages each table in `_restore_<table>`; the dump skips these.
+const STAGING_PREFIX: &str = "_restore_";
+
+/// Tables only meaningful while the worker runs: failed deliveries waiting for a retry and
+/// unanswered passkey challenges. They are not backed up.
+const TRANSIENT_TABLES: &[&str] = &["outbox", "webauthn_challenges"];
+/// Holds the Web Push private key, so it is only backed up into encrypted archives. Without
+/// it a restored server generates a new key pair and browsers subscribe again.
+const VAPID_KEYS_TABLE: &str = "vapid_keys";
+
+/// Tables in foreign key order, so a restore never inserts a row before the row it references.
+/// Tables missing from this list are still dumped, after these ones.
+const TABLE_ORDER: &[&str] = &[
+    "users",
+    "folders",
+    "ciphers",
+    "attachments",
+    "attachments_pending",
+    "twofactor",
+    "devices",
+    "auth_requests",
+    "global_equivalent_domains",
+    "sends",
+    "sends_pending",
+    "emergency_access",
+    "organizations",
+    "users_organizations",
+    "collections",
+    "users_collections",
+    "ciphers_collections",
+    "webauthn_credentials",
+    "invitations",
+    "config",
+    "events",
+];
+
+/// Everything in an archive but the tables, which [`write_archive`] appends as `tables`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchiveHeader {
+    format: String,
+    version: u32,
+    created_at: String,
+    /// Storage backend of the files listed in `files`, if attachments are enabled.
+    storage: Option<String>,
+    files: Vec<FileEntry>,
+}
+
+/// The parts of an archive a restore reads. Rows stay unparsed until they are inserted.
+#[derive(Debug, Deserialize)]
+struct Archive<'a> {
+    format: String,
+    version: u32,
+    #[serde(borrow)]
+    tables: Vec<TableDump<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TableDump<'a> {
+    name: String,
+    #[serde(borrow)]
+    rows: Vec<&'a RawValue>,
+}
+
+/// An attachment or Send file referenced by the database.
+#[derive(Debug, Serialize, Deserialize)]
+struct FileEntry {
+    key: String,
+    size: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameRow {
+    name: String,
+}
+
+/// A stored backup, as listed by the admin API.
+pub struct BackupInfo {
+    pub name: String,
+    pub size: u64,
+    pub uploaded: String,
+    pub encrypted: bool,
+}
+
+impl BackupInfo {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "size": self.size,
+            "uploaded": self.uploaded,
+            "encrypted": self.encrypted,
+            "object": "backup",
+        })
+    }
+}
+
+pub fn backups_enabled(env: &Env) -> bool {
+    env.bucket(BACKUP_BUCKET).is_ok()
+}
+
+fn backup_bucket(env: &Env) -> Result<Bucket, AppError> {
+    env.bucket(BACKUP_BUCKET)
+        .map_err(|_| AppError::BadRequest("Backups are not enabled".to_string()))
+}
+
+fn encryption_passphrase(env: &Env) -> Option<String> {
+    env.secret("BACKUP_ENCRYPTION_KEY")
+        .ok()
+        .map(|secret| secret.to_string())
+        .filter(|secret| !secret.is_empty())
+}
+
+/// Backup names are object keys below `backups/`; refuse anything else.
+fn object_key(name: &str) -> Result<String, AppError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !name.starts_with('.');
+    if !valid {
+        return Err(AppError::BadRequest("Invalid backup name".to_string()));
+    }
+    Ok(format!("{BACKUP_PREFIX}{name}"))
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Dump the database, upload the archive and drop the snapshots beyond `BACKUP_RETAIN_COUNT`.
+pub async fn create_backup(env: &Env) -> Result<BackupInfo, AppError> {
+    let bucket = backup_bucket(env)?;
+    let config = Config::load(env).await?;
+    let db = db::get_db(env)?;
+
+    let header = ArchiveHeader {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created_at: db::now_string(),
+        storage: get_storage_backend(env).map(|backend| match backend {
+            StorageBackend::R2 => "r2".to_string(),
+            StorageBackend::KV => "kv".to_string(),
+        }),
+        files: list_files(&db).await?,
+    };
+    let passphrase = encryption_passphrase(env);
+    let encrypted = passphrase.is_some();
+    if !encrypted {
+        log::warn!(
+            "BACKUP_ENCRYPTION_KEY is not set: the backup is stored in plaintext and leaves out the Web Push key pair ({VAPID_KEYS_TABLE})"
+        );
+    }
+    let mut gzip = Gzip::new()?;
+    write_archive(&db, &header, encrypted, &mut gzip).await?;
+    let mut data = gzip.finish().await?;
+
+    if let Some(passphrase) = passphrase {
+        data = encrypt_archive(&passphrase, &data).await?;
+    }
+
+    let name = format!(
+        "warden-{}.json.gz{}",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        if encrypted { ".enc" } else { "" }
+    );
+    let size = data.len() as u64;
+    let metadata = HashMap::from([
+        ("version".to_string(), ARCHIVE_VERSION.to_string()),
+        ("encrypted".to_string(), encrypted.to_string()),
+    ]);
+    bucket
+        .put(object_key(&name)?, data)
+        .custom_metadata(metadata)
+        .execute()
+        .await?;
+
+    prune_backups(&bucket, config.backup_retain_count).await?;
+
+    Ok(BackupInfo {
+        name,
+        size,
+        uploaded: header.created_at,
+        encrypted,
+    })
+}
+
+/// Tables to back up, in [`TABLE_ORDER`] and then by name. `vapid_keys` is only included in
+/// `encrypted` archives.
+async fn dump_table_names(db: &db::Db, encrypted: bool) -> Result<Vec<String>, AppError> {
+    let mut existing: Vec<String> = db
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table'
+             AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '_cf_%' AND name NOT LIKE 'd1_%'
+             AND name NOT IN ('schema_migrations', 'schema_lock')
+             ORDER BY name",
+        )
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results::<NameRow>()
+        .map_err(|_| AppError::Database)?
+        .into_iter()
+        .map(|row| row.name)
+        .filter(|name| is_identifier(name) && !name.starts_with(STAGING_PREFIX))
+        .filter(|name| !TRANSIENT_TABLES.contains(&name.as_str()))
+        .filter(|name| encrypted || name != VAPID_KEYS_TABLE)
+        .collect();
+
+    let mut ordered: Vec<String> = TABLE_ORDER
+        .iter()
+        .filter(|table| existing.iter().any(|name| name == *table))
+        .map(|table| table.to_string())
+        .collect();
+    existing.retain(|name| !TABLE_ORDER.contains(&name.as_str()));
+    ordered.append(&mut existing);
+    Ok(ordered)
+}
+
+/// Where [`write_archive`] sends the archive JSON: gzip in the Worker, memory in tests.
+trait ArchiveSink {
+    fn write<'a>(&'a mut self, bytes: &'a [u8]) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+#[cfg(test)]
+impl ArchiveSink for Vec<u8> {
+    fn write<'a>(&'a mut self, bytes: &'a [u8]) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        self.extend_from_slice(bytes);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, AppError> {
+    serde_json::to_vec(value).map_err(|_| AppError::Internal)
+}
+
+/// Write `header` and every table as one JSON archive, a page of rows at a time.
+async fn write_archive(
+    db: &db::Db,
+    header: &ArchiveHeader,
+    encrypted: bool,
+    sink: &mut impl ArchiveSink,
+) -> Result<(), AppError> {
+    // The header is an object; reopen it to append the tables.
+    let mut buf = to_json(header)?;
+    buf.pop();
+    buf.extend_from_slice(br#","tables":["#);
+
+    for (index, name) in dump_table_names(db, encrypted).await?.iter().enumerate() {
+        if index > 0 {
+            buf.push(b',');
+        }
+        buf.extend_from_slice(br#"{"name":"#);
+        buf.extend(to_json(name)?);
+        buf.extend_from_slice(br#","rows":["#);
+        let mut offset = 0;
+        loop {
+            let page: Vec<Map<String, Value>> = db
+                .prepare(format!(
+                    "SELECT * FROM \"{name}\" ORDER BY rowid LIMIT ?1 OFFSET ?2"
+                ))
+                .bind(&[db::bind_value(&DUMP_PAGE_SIZE)?, db::bind_value(&offset)?])?
+                .all()
+                .await
+                .map_err(|_| AppError::Database)?
+                .results()
+                .map_err(|_| AppError::Database)?;
+            for row in &page {
+                if offset > 0 {
+                    buf.push(b',');
+                }
+                buf.extend(to_json(row)?);
+                offset += 1;
+            }
+            if buf.len() >= DUMP_CHUNK_SIZE {
+                sink.write(&buf).await?;
+                buf.clear();
+            }
+            if (page.len() as i64) < DUMP_PAGE_SIZE {
+                break;
+            }
+        }
+        buf.extend_from_slice(b"]}");
+    }
+
+    buf.extend_from_slice(b"]}");
+    sink.write(&buf).await
+}
+
+async fn list_files(db: &db::Db) -> Result<Vec<FileEntry>, AppError> {
+    db.prepare(
+        "SELECT cipher_id || '/' || id AS key, file_size AS size FROM attachments
+         UNION ALL
+         SELECT 'sends/' || id || '/' || json_extract(data, '$.id') AS key,
+                CAST(json_extract(data, '$.size') AS INTEGER) AS size
+         FROM sends WHERE type = 1
+         ORDER BY key",
+    )
+    .all()
+    .await
+    .map_err(|_| AppError::Database)?
+    .results()
+    .map_err(|_| AppError::Database)
+}
+
+/// Keep the newest `keep` backups. Names embed the creation time, so they sort by age.
+async fn prune_backups(bucket: &Bucket, keep: usize) -> Result<(), AppError> {
+    let mut keys: Vec<String> = list_objects(bucket)
+        .await?
+        .into_iter()
+        .map(|object| object.key())
+        .collect();
+    keys.sort();
+    let excess = keys.len().saturating_sub(keep);
+    for key in keys.into_iter().take(excess) {
+        bucket.delete(&key).await?;
+        log::info!("Deleted old backup {key}");
+    }
+    Ok(())
+}
+
+async fn list_objects(bucket: &Bucket) -> Result<Vec<worker::Object>, AppError> {
+    let mut objects = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut request = bucket
+            .list()
+            .prefix(BACKUP_PREFIX)
+            .include(vec![worker::Include::CustomMetadata]);
+        if let Some(cursor) = cursor.take() {
+            request = request.cursor(cursor);
+        }
+        let page = request.execute().await?;
+        objects.extend(page.objects());
+        match page.cursor() {
+            Some(next) if page.truncated() => cursor = Some(next),
+            _ => break,
+        }
+    }
+    Ok(objects)
+}
+
+/// Stored backups, newest first.
+pub async fn list_backups(env: &Env) -> Result<Vec<BackupInfo>, AppError> {
+    let bucket = backup_bucket(env)?;
+    let mut backups: Vec<BackupInfo> = list_objects(&bucket)
+        .await?
+        .into_iter()
+        .map(|object| {
+            let key = object.key();
+            let encrypted = object
+                .custom_metadata()
+                .ok()
+                .and_then(|metadata| metadata.get("encrypted").cloned())
+                .map(|value| value == "true")
+                .unwrap_or_else(|| key.ends_with(".enc"));
+            let uploaded =
+                chrono::DateTime::from_timestamp_millis(object.uploaded().as_millis() as i64)
+                    .map(|date| date.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+                    .unwrap_or_default();
+            BackupInfo {
+                name: key.trim_start_matches(BACKUP_PREFIX).to_string(),
+                size: object.size(),
+                uploaded,
+                encrypted,
+            }
+        })
+        .collect();
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+/// The raw archive, as stored in R2.
+pub async fn read_backup(env: &Env, name: &str) -> Result<Vec<u8>, AppError> {
+    let bucket = backup_bucket(env)?;
+    let object = bucket
+        .get(object_key(name)?)
+        .execute()
+        .await?
+        .ok_or_else(|| AppError::NotFound("Backup not found".to_string()))?;
+    let body = object
+        .body()
+        .ok_or_else(|| AppError::NotFound("Backup not found".to_string()))?;
+    Ok(body.bytes().await?)
+}
+
+/// Rebuild an empty database from a backup. Returns the number of rows restored per table.
+///
+/// The schema must already be in place (`sql/schema.sql` or all migrations), and no user may
+/// exist yet, so a restore can never merge two vaults.
+pub async fn restore_backup(env: &Env, name: &str) -> Result<Vec<(String, usize)>, AppError> {
+    let data = read_backup(env, name).await?;
+    let data = if data.starts_with(ENCRYPTED_MAGIC) {
+        let passphrase = encryption_passphrase(env).ok_or_else(|| {
+            AppError::BadRequest(
+                "This backup is encrypted but BACKUP_ENCRYPTION_KEY is not set".to_string(),
+            )
+        })?;
+        decrypt_archive(&passphrase, &data).await?
+    } else {
+        data
+    };
+    let json = gunzip(&data).await?;
+    restore_archive(&db::get_db(env)?, &json).await
+}
+
+async fn restore_archive(db: &db::Db, json: &[u8]) -> Result<Vec<(String, usize)>, AppError> {
+    let archive: Archive = serde_json::from_slice(json)
+        .map_err(|_| AppError::BadRequest("Not a valid backup archive".to_string()))?;
+    if archive.format != ARCHIVE_FORMAT || archive.version > ARCHIVE_VERSION {
+        return Err(AppError::BadRequest(format!(
+            "Unsupported backup format {} version {}",
+            archive.format, archive.version
+        )));
+    }
+
+    let users: Option<i64> = db
+        .prepare("SELECT COUNT(*) AS count FROM users")
+        .first(Some("count"))
+        .await
+        .map_err(|_| AppError::Database)?;
+    if users.unwrap_or(0) > 0 {
+        return Err(AppError::BadRequest(
+            "Backups can only be restored into an empty database".to_string(),
+        ));
+    }
+
+    let existing: Vec<String> = db
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table'")
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results::<NameRow>()
+        .map_err(|_| AppError::Database)?
+        .into_iter()
+        .map(|row| row.name)
+        .collect();
+    for table in &archive.tables {
+        if !is_identifier(&table.name)
+            || table.name.starts_with(STAGING_PREFIX)
+            || !existing.contains(&table.name)
+        {
+            return Err(AppError::BadRequest(format!(
+                "Table {} does not exist; apply all migrations before restoring",
+                table.name
+            )));
+        }
+    }
+
+    // Staging tables left behind by an interrupted restore.
+    let leftovers = existing
+        .iter()
+        .filter(|name| name.starts_with(STAGING_PREFIX) && is_identifier(name))
+        .map(|name| db.prepare(format!("DROP TABLE \"{name}\"")))
+        .collect();
+    db::execute_in_batches(db, leftovers, 0).await?;
+
+    let mut restored = Vec::with_capacity(archive.tables.len());
+    for table in &archive.tables {
+        let staging = format!("{STAGING_PREFIX}{}", table.name);
+        db.prepare(format!(
+            "CREATE TABLE \"{staging}\" AS SELECT * FROM \"{}\" WHERE 0",
+            table.name
+        ))
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        for rows in table.rows.chunks(RESTORE_BATCH_SIZE) {
+            let statements = rows
+                .iter()
+                .map(|row| insert_statement(db, &staging, &table.name, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            db.batch(statements).await.map_err(|e| {
+                log::error!("Staging rows for {} failed: {e}", table.name);
+                AppError::Database
+            })?;
+        }
+        restored.push((table.name.clone(), table.rows.len()));
+    }
+
+    // One batch, so the vault appears all at once or not at all.
+    let mut swap = Vec::with_capacity(archive.tables.len() * 2);
+    for table in &archive.tables {
+        swap.push(db.prepare(format!(
+            "INSERT OR REPLACE INTO \"{0}\" SELECT * FROM \"{STAGING_PREFIX}{0}\"",
+            table.name
+        )));
+    }
+    for table in &archive.tables {
+        swap.push(db.prepare(format!("DROP TABLE \"{STAGING_PREFIX}{}\"", table.name)));
+    }
+    db.batch(swap).await.map_err(|e| {
+        log::error!("Restoring the staged tables failed: {e}");
+        AppError::Database
+    })?;
+
+    Ok(restored)
+}
+
+/// An insert of one archived `row` of `table` into `staging`.
+fn insert_statement(
+    db: &db::Db,
+    staging: &str,
+    table: &str,
+    row: &RawValue,
+) -> Result<db::Statement, AppError> {
+    let row: Map<String, Value> = serde_json::from_str(row.get())
+        .map_err(|_| AppError::BadRequest(format!("Invalid row in table {table}")))?;
+    if let Some(column) = row.keys().find(|column| !is_identifier(column)) {
+        return Err(AppError::BadRequest(format!(
+            "Invalid column {column} in table {table}"
+        )));
+    }
+    let columns: Vec<String> = row.keys().map(|column| format!("\"{column}\"")).collect();
+    let placeholders: Vec<String> = (1..=row.len()).map(|i| format!("?{i}")).collect();
+    let values = row
+        .values()
+        .map(db::bind_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::BadRequest("Invalid value in backup".to_string()))?;
+    Ok(db
+        .prepare(format!(
+            "INSERT INTO \"{staging}\" ({}) VALUES ({})",
+            columns.join(", "),
+            placeholders.join(", ")
+        ))
+        .bind(&values)?)
+}
+
+async fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, AppError> {
+    webcrypto_pbkdf2_sha256(passphrase.as_bytes(), salt, KEY_DERIVATION_ITERATIONS, 256).await
+}
+
+async fn encrypt_archive(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let salt = random_bytes(SALT_LEN)?;
+    let iv = random_bytes(IV_LEN)?;
+    let key = derive_key(passphrase, &salt).await?;
+    let ciphertext = aes_gcm_encrypt(&key, &iv, data).await?;
+
+    let mut out = Vec::with_capacity(ENCRYPTED_MAGIC.len() + SALT_LEN + IV_LEN + ciphertext.len());
+    out.extend_from_slice(ENCRYPTED_MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+async fn decrypt_archive(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let header_len = ENCRYPTED_MAGIC.len() + SALT_LEN + IV_LEN;
+    if data.len() < header_len {
+        return Err(AppError::BadRequest(
+            "Backup archive is truncated".to_string(),
+        ));
+    }
+    let salt = &data[ENCRYPTED_MAGIC.len()..ENCRYPTED_MAGIC.len() + SALT_LEN];
+    let iv = &data[ENCRYPTED_MAGIC.len() + SALT_LEN..header_len];
+    let key = derive_key(passphrase, salt).await?;
+    aes_gcm_decrypt(&key, iv, &data[header_len..])
+        .await
+        .map_err(|_| {
+            AppError::BadRequest(
+                "Could not decrypt the backup; check BACKUP_ENCRYPTION_KEY".to_string(),
+            )
+        })
+}
+
+// web-sys only exposes the compression streams behind `web_sys_unstable_apis`.
+#[wasm_bindgen]
+extern "C" {
+    type CompressionStream;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(format: &str) -> Result<CompressionStream, JsValue>;
+
+    #[wasm_bindgen(method, getter)]
+    fn readable(this: &CompressionStream) -> ReadableStream;
+
+    #[wasm_bindgen(method, getter)]
+    fn writable(this: &CompressionStream) -> WritableStream;
+
+    type DecompressionStream;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(format: &str) -> Result<DecompressionStream, JsValue>;
+
+    #[wasm_bindgen(method, getter)]
+    fn readable(this: &DecompressionStream) -> ReadableStream;
+
+    #[wasm_bindgen(method, getter)]
+    fn writable(this: &DecompressionStream) -> WritableStream;
+}
+
+/// A gzip stream over the runtime's native `CompressionStream`. Its output is collected while
+/// the input is still being written.
+struct Gzip {
+    writer: WritableStreamDefaultWriter,
+    output: js_sys::Promise,
+}
+
+fn compression_error(e: JsValue) -> AppError {
+    log::error!("Backup compression failed: {e:?}");
+    AppError::Internal
+}
+
+impl Gzip {
+    fn new() -> Result<Self, AppError> {
+        let stream = CompressionStream::new("gzip").map_err(compression_error)?;
+        let output = web_sys::Response::new_with_opt_readable_stream(Some(&stream.readable()))
+            .and_then(|response| response.array_buffer())
+            .map_err(compression_error)?;
+        let writer = stream.writable().get_writer().map_err(compression_error)?;
+        Ok(Self { writer, output })
+    }
+
+    async fn finish(self) -> Result<Vec<u8>, AppError> {
+        JsFuture::from(self.writer.close())
+            .await
+            .map_err(compression_error)?;
+        let buffer = JsFuture::from(self.output)
+            .await
+            .map_err(compression_error)?;
+        Ok(Uint8Array::new(&buffer).to_vec())
+    }
+}
+
+impl ArchiveSink for Gzip {
+    fn write<'a>(&'a mut self, bytes: &'a [u8]) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            JsFuture::from(self.writer.write_with_chunk(&Uint8Array::from(bytes)))
+                .await
+                .map_err(compression_error)?;
+            Ok(())
+        })
+    }
+}
+
+/// Gunzip `data` with the runtime's native decompression stream.
+async fn gunzip(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let parts = Array::of1(&Uint8Array::from(data));
+    let blob = Blob::new_with_u8_array_sequence(&parts).map_err(compression_error)?;
+    let stream = DecompressionStream::new("gzip").map_err(compression_error)?;
+    let pair = ReadableWritablePair::new(&stream.readable(), &stream.writable());
+    let output = blob.stream().pipe_through(&pair);
+    let response = web_sys::Response::new_with_opt_readable_stream(Some(&output))
+        .map_err(compression_error)?;
+    let buffer = JsFuture::from(response.array_buffer().map_err(compression_error)?)
+        .await
+        .map_err(|e| {
+            log::warn!("Backup archive could not be decompressed: {e:?}");
+            AppError::BadRequest("Backup archive is corrupted".to_string())
+        })?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, enc_string, Env, TestClient, TestUser};
+    use axum::http::StatusCode;
+
+    /// A database with one user who owns one cipher.
+    fn vault_with_cipher() -> (Env, TestUser, Value) {
+        let env = Env::new();
+        let client = TestClient::with_env(env.clone());
+        let user = client.register();
+        let token = client.login(&user);
+        let cipher = json!({
+            "type": 2,
+            "name": enc_string(),
+            "notes": null,
+            "favorite": false,
+            "secureNote": { "type": 0 },
+        });
+        let created = client.post("/api/ciphers", Some(&token), cipher);
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        (env, user, created.body["id"].clone())
+    }
+
+    fn dump(env: &Env, encrypted: bool) -> Vec<u8> {
+        let db = db::get_db(env).unwrap();
+        let header = ArchiveHeader {
+            format: ARCHIVE_FORMAT.to_string(),
+            version: ARCHIVE_VERSION,
+            created_at: db::now_string(),
+            storage: None,
+            files: Vec::new(),
+        };
+        let mut archive = Vec::new();
+        block_on(write_archive(&db, &header, encrypted, &mut archive)).unwrap();
+        archive
+    }
+
+    fn count(env: &Env, table: &str) -> i64 {
+        let db = db::get_db(env).unwrap();
+        block_on(
+            db.prepare(format!("SELECT COUNT(*) AS count FROM \"{table}\""))
+                .first(Some("count")),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    fn staging_tables(env: &Env) -> i64 {
+        let db = db::get_db(env).unwrap();
+        block_on(
+            db.prepare(
+                "SELECT COUNT(*) AS count FROM sqlite_master WHERE name LIKE '\\_restore\\_%' ESCAPE '\\'",
+            )
+            .first(Some("count")),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn a_dump_restores_into_an_empty_database() {
+        let (env, user, cipher_id) = vault_with_cipher();
+        let archive = dump(&env, false);
+        let parsed: Value = serde_json::from_slice(&archive).unwrap();
+        assert_eq!(parsed["format"], ARCHIVE_FORMAT);
+        assert_eq!(parsed["tables"][0]["name"], "users");
+
+        let restored_env = Env::new();
+        let restored = block_on(restore_archive(
+            &db::get_db(&restored_env).unwrap(),
+            &archive,
+        ))
+        .unwrap();
+        assert!(restored.contains(&("users".to_string(), 1)));
+        assert!(restored.contains(&("ciphers".to_string(), 1)));
+        assert_eq!(count(&restored_env, "ciphers"), 1);
+        assert_eq!(staging_tables(&restored_env), 0);
+
+        let client = TestClient::with_env(restored_env.clone());
+        let token = client.login(&user);
+        let sync = client.get("/api/sync", Some(&token));
+        assert_eq!(sync.body["ciphers"][0]["id"], cipher_id);
+
+        let again = block_on(restore_archive(
+            &db::get_db(&restored_env).unwrap(),
+            &archive,
+        ));
+        assert!(matches!(again, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn a_failed_restore_leaves_the_database_empty() {
+        let (env, _, _) = vault_with_cipher();
+        let archive = dump(&env, false);
+
+        // Without the users table every other row references a missing user.
+        let mut broken: Value = serde_json::from_slice(&archive).unwrap();
+        broken["tables"]
+            .as_array_mut()
+            .unwrap()
+            .retain(|table| table["name"] != "users");
+        let broken = serde_json::to_vec(&broken).unwrap();
+
+        let target = Env::new();
+        let db = db::get_db(&target).unwrap();
+        let failed = block_on(restore_archive(&db, &broken));
+        assert!(matches!(failed, Err(AppError::Database)));
+        assert_eq!(count(&target, "users"), 0);
+        assert_eq!(count(&target, "ciphers"), 0);
+
+        let restored = block_on(restore_archive(&db, &archive));
+        assert!(restored.is_ok());
+        assert_eq!(count(&target, "users"), 1);
+        assert_eq!(count(&target, "ciphers"), 1);
+        assert_eq!(staging_tables(&target), 0);
+    }
+
+    fn dumped_tables(archive: &[u8]) -> Vec<String> {
+        let parsed: Value = serde_json::from_slice(archive).unwrap();
+        parsed["tables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|table| table["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn transient_tables_are_skipped_and_vapid_keys_need_encryption() {
+        let (env, _, _) = vault_with_cipher();
+        let db = db::get_db(&env).unwrap();
+        let now = db::now_string();
+        block_on(db.batch(vec![
+            db.prepare(
+                "INSERT INTO outbox (id, kind, target, payload, next_attempt_at, created_at, updated_at)
+                 VALUES ('job', 'push', 'relay', '{}', ?1, ?1, ?1)",
+            )
+            .bind(&[now.clone().into()])
+            .unwrap(),
+            db.prepare(
+                "INSERT INTO webauthn_challenges (id, purpose, challenge, expires_at)
+                 VALUES ('challenge', 'login', 'state', 0)",
+            ),
+            db.prepare(
+                "INSERT INTO vapid_keys (id, private_key, public_key, created_at)
+                 VALUES (1, 'private', 'public', ?1)",
+            )
+            .bind(&[now.into()])
+            .unwrap(),
+        ]))
+        .unwrap();
+
+        let plaintext = dumped_tables(&dump(&env, false));
+        assert!(plaintext.contains(&"users".to_string()));
+        for skipped in ["outbox", "webauthn_challenges", "vapid_keys"] {
+            assert!(!plaintext.contains(&skipped.to_string()), "{skipped}");
+        }
+
+        let archive = dump(&env, true);
+        let encrypted = dumped_tables(&archive);
+        assert!(encrypted.contains(&"vapid_keys".to_string()));
+        assert!(!encrypted.contains(&"outbox".to_string()));
+        assert!(!encrypted.contains(&"webauthn_challenges".to_string()));
+
+        let target = Env::new();
+        block_on(restore_archive(&db::get_db(&target).unwrap(), &archive)).unwrap();
+        assert_eq!(count(&target, "vapid_keys"), 1);
+        assert_eq!(count(&target, "outbox"), 0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Runtime server configuration.
+//!
+//! Each setting is read from the `config` table first, then from the environment
+//! variable of the same name, then from the built-in default. Values stored in D1 can be
+//! changed through the admin API without a redeploy.
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::d1_query;
+use crate::{crypto::MIN_SERVER_PBKDF2_ITERATIONS, db, env::Env, error::AppError};
+
+/// Settings that can be stored in the `config` table, in the order they are listed.
+pub const CONFIG_KEYS: &[&str] = &[
+    "DISABLE_USER_REGISTRATION",
+    "SIGNUPS_VERIFY",
+    "SIGNUPS_VERIFY_RESEND_SECS",
+    "PASSWORD_ITERATIONS",
+    "AUTHENTICATOR_DISABLE_TIME_DRIFT",
+    "TRASH_AUTO_DELETE_DAYS",
+    "EVENTS_DAYS_RETAIN",
+    "BACKUP_RETAIN_COUNT",
+    "OUTBOX_MAX_ATTEMPTS",
+    "IMPORT_BATCH_SIZE",
+    "ALLOW_PLAINTEXT_CONVERSION",
+    "CIPHERS_DEFAULT_ROW_QUERY",
+    "SYNC_RESPONSE_PREALLOC_BYTES",
+    "ATTACHMENT_MAX_BYTES",
+    "ATTACHMENT_TOTAL_LIMIT_KB",
+    "ATTACHMENT_TTL_SECS",
+    "SEND_TEXT_MAX_BYTES",
+    "SEND_MAX_BYTES",
+    "USER_SEND_LIMIT_KB",
+    "SEND_TTL_SECS",
+    "DISABLE_ICON_DOWNLOAD",
+    "ICON_CACHE_TTL_SECS",
+    "ICON_CACHE_NEGTTL_SECS",
+    "EXPERIMENTAL_CLIENT_FEATURE_FLAGS",
+];
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Database,
+    Environment,
+    Default,
+}
+
+impl ConfigSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConfigSource::Database => "database",
+            ConfigSource::Environment => "environment",
+            ConfigSource::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Hide the registration button in the clients (DISABLE_USER_REGISTRATION).
+    pub disable_user_registration: bool,
+    /// Refuse password logins until the email address is verified (SIGNUPS_VERIFY).
+    pub signups_verify: bool,
+    /// Minimum seconds between two verification emails to the same user.
+    pub signups_verify_resend_secs: i64,
+    /// Per-user server-side PBKDF2 iterations, never below `MIN_SERVER_PBKDF2_ITERATIONS`.
+    pub password_iterations: u32,
+    /// Disable the ±1 time step drift for TOTP validation.
+    pub authenticator_disable_time_drift: bool,
+    /// Days to keep soft-deleted items before purging; `<= 0` disables the purge.
+    pub trash_auto_delete_days: i64,
+    /// Days to keep audit events; `<= 0` keeps them forever.
+    pub events_days_retain: i64,
+    /// Number of backups kept in the backup bucket; older ones are deleted.
+    pub backup_retain_count: usize,
+    /// Delivery attempts before a failed outbox job becomes a dead letter.
+    pub outbox_max_attempts: u32,
+    /// Batch size for import/delete operations; `0` disables batching.
+    pub import_batch_size: usize,
+    /// Convert plaintext exports from other password managers, and to CXF, on the server.
+    pub allow_plaintext_conversion: bool,
+    /// Prefer fetching cipher JSON rows over `json_group_array` aggregation.
+    pub ciphers_default_row_query: bool,
+    /// Initial capacity of the `/api/sync` response buffer.
+    pub sync_response_prealloc_bytes: Option<usize>,
+    pub attachment_max_bytes: Option<u64>,
+    pub attachment_total_limit_kb: Option<u64>,
+    pub attachment_ttl_secs: i64,
+    pub send_text_max_bytes: usize,
+    pub send_max_bytes: i64,
+    pub user_send_limit_kb: Option<i64>,
+    pub send_ttl_secs: i64,
+    /// Serve cached icons only, never fetch new ones.
+    pub disable_icon_download: bool,
+    /// Seconds to cache a fetched icon.
+    pub icon_cache_ttl_secs: u64,
+    /// Seconds to remember that a site has no usable icon.
+    pub icon_cache_negttl_secs: u64,
+    /// Client feature flags switched on in addition to the defaults.
+    pub experimental_client_feature_flags: Vec<String>,
+    sources: Vec<(&'static str, ConfigSource, String)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            disable_user_registration: true,
+            signups_verify: false,
+            signups_verify_resend_secs: 600,
+            password_iterations: MIN_SERVER_PBKDF2_ITERATIONS,
+            authenticator_disable_time_drift: false,
+            trash_auto_delete_days: 30,
+            events_days_retain: 365,
+            backup_retain_count: 7,
+            outbox_max_attempts: 8,
+            import_batch_size: 30,
+            allow_plaintext_conversion: false,
+            ciphers_default_row_query: false,
+            sync_response_prealloc_bytes: None,
+            attachment_max_bytes: None,
+            attachment_total_limit_kb: None,
+            attachment_ttl_secs: 300,
+            send_text_max_bytes: 1_887_436, // ~1.8 MiB
+            send_max_bytes: 100 * 1024 * 1024,
+            user_send_limit_kb: None,
+            send_ttl_secs: 300,
+            disable_icon_download: false,
+            icon_cache_ttl_secs: 30 * 24 * 60 * 60,
+            icon_cache_negttl_secs: 3 * 24 * 60 * 60,
+            experimental_client_feature_flags: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigRow {
+    key: String,
+    value: String,
+}
+
+impl Config {
+    /// Load the configuration: D1 rows over environment variables over defaults.
+    ///
+    /// Invalid values are logged and skipped so a typo cannot take the server down.
+    pub async fn load(env: &Env) -> Result<Self, AppError> {
+        let db = db::get_db_unconstrained(env)?;
+        let rows: Vec<ConfigRow> = db
+            .prepare("SELECT key, value FROM config")
+            .all()
+            .await
+            .map_err(|_| AppError::Database)?
+            .results()
+            .map_err(|_| AppError::Database)?;
+
+        let mut config = Config::default();
+        for &key in CONFIG_KEYS {
+            let stored = rows.iter().find(|row| row.key == key);
+            if let Some(row) = stored {
+                match config.apply(key, &row.value) {
+                    Ok(()) => {
+                        config
+                            .sources
+                            .push((key, ConfigSource::Database, row.value.clone()));
+                        continue;
+                    }
+                    Err(e) => log::error!("Ignoring invalid {key} in the config table: {e}"),
+                }
+            }
+            if let Ok(value) = env.var(key) {
+                let raw = value.to_string();
+                match config.apply(key, &raw) {
+                    Ok(()) => {
+                        config.sources.push((key, ConfigSource::Environment, raw));
+                        continue;
+                    }
+                    Err(e) => log::error!("Ignoring invalid {key} environment variable: {e}"),
+                }
+            }
+            config
+                .sources
+                .push((key, ConfigSource::Default, String::new()));
+        }
+
+        Ok(config)
+    }
+
+    /// Check that `raw` is a valid value for `key` without changing anything.
+    pub fn validate(key: &str, raw: &str) -> Result<(), String> {
+        Config::default().apply(key, raw)
+    }
+
+    fn apply(&mut self, key: &str, raw: &str) -> Result<(), String> {
+        let raw = raw.trim();
+        match key {
+            "DISABLE_USER_REGISTRATION" => self.disable_user_registration = parse_bool(raw)?,
+            "SIGNUPS_VERIFY" => self.signups_verify = parse_bool(raw)?,
+            "SIGNUPS_VERIFY_RESEND_SECS" => self.signups_verify_resend_secs = parse_int(raw, 0)?,
+            "PASSWORD_ITERATIONS" => {
+                self.password_iterations = parse_int(raw, MIN_SERVER_PBKDF2_ITERATIONS)?
+            }
+            "AUTHENTICATOR_DISABLE_TIME_DRIFT" => {
+                self.authenticator_disable_time_drift = parse_bool(raw)?
+            }
+            "TRASH_AUTO_DELETE_DAYS" => self.trash_auto_delete_days = parse_int(raw, i64::MIN)?,
+            "EVENTS_DAYS_RETAIN" => self.events_days_retain = parse_int(raw, i64::MIN)?,
+            "BACKUP_RETAIN_COUNT" => self.backup_retain_count = parse_int(raw, 1)?,
+            "OUTBOX_MAX_ATTEMPTS" => self.outbox_max_attempts = parse_int(raw, 1)?,
+            "IMPORT_BATCH_SIZE" => self.import_batch_size = parse_int(raw, 0)?,
+            "ALLOW_PLAINTEXT_CONVERSION" => self.allow_plaintext_conversion = parse_bool(raw)?,
+            "CIPHERS_DEFAULT_ROW_QUERY" => self.ciphers_default_row_query = parse_bool(raw)?,
+            "SYNC_RESPONSE_PREALLOC_BYTES" => {
+                self.sync_response_prealloc_bytes = Some(parse_int(raw, 0)?)
+            }
+            "ATTACHMENT_MAX_BYTES" => self.attachment_max_bytes = Some(parse_int(raw, 0)?),
+            "ATTACHMENT_TOTAL_LIMIT_KB" => {
+                let kb: u64 = parse_int(raw, 0)?;
+                kb.checked_mul(1024)
+                    .ok_or_else(|| "overflows when converted to bytes".to_string())?;
+                self.attachment_total_limit_kb = Some(kb);
+            }
+            "ATTACHMENT_TTL_SECS" => self.attachment_ttl_secs = parse_int(raw, 60)?,
+            "SEND_TEXT_MAX_BYTES" => self.send_text_max_bytes = parse_int(raw, 0)?,
+            "SEND_MAX_BYTES" => self.send_max_bytes = parse_int(raw, 0)?,
+            "USER_SEND_LIMIT_KB" => {
+                let kb: i64 = parse_int(raw, 0)?;
+                kb.checked_mul(1024)
+                    .ok_or_else(|| "overflows when converted to bytes".to_string())?;
+                self.user_send_limit_kb = Some(kb);
+            }
+            "SEND_TTL_SECS" => self.send_ttl_secs = parse_int(raw, 1)?,
+            "DISABLE_ICON_DOWNLOAD" => self.disable_icon_download = parse_bool(raw)?,
+            // KV refuses expiration TTLs below 60 seconds.
+            "ICON_CACHE_TTL_SECS" => self.icon_cache_ttl_secs = parse_int(raw, 60)?,
+            "ICON_CACHE_NEGTTL_SECS" => self.icon_cache_negttl_secs = parse_int(raw, 60)?,
+            "EXPERIMENTAL_CLIENT_FEATURE_FLAGS" => {
+                self.experimental_client_feature_flags = raw
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|flag| !flag.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Whether the password grant waits for a verified address. Needs mail to be configured,
+    /// since users would have no way to verify otherwise.
+    pub fn signups_verify_enabled(&self, env: &Env) -> bool {
+        self.signups_verify && crate::mail::mail_enabled(env)
+    }
+
+    pub fn attachment_total_limit_bytes(&self) -> Option<u64> {
+        self.attachment_total_limit_kb.map(|kb| kb * 1024)
+    }
+
+    pub fn user_send_limit_bytes(&self) -> Option<i64> {
+        self.user_send_limit_kb.map(|kb| kb * 1024)
+    }
+
+    /// Every setting with its effective source, for the admin API.
+    pub fn to_json(&self) -> Value {
+        let entries: Vec<Value> = self
+            .sources
+            .iter()
+            .map(|(key, source, value)| {
+                json!({
+                    "key": key,
+                    "value": if *source == ConfigSource::Default { Value::Null } else { Value::String(value.clone()) },
+                    "source": source.as_str(),
+                })
+            })
+            .collect();
+        json!({ "data": entries, "object": "list" })
+    }
+}
+
+impl FromRequestParts<Arc<Env>> for Config {
+    type Rejection = AppError;
+    #[worker::send]
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Env>,
+    ) -> Result<Self, Self::Rejection> {
+        Config::load(state).await
+    }
+}
+
+/// Store `value` for `key`, or drop the stored value (falling back to the environment) when `None`.
+pub async fn save_config_value(
+    db: &db::Db,
+    key: &str,
+    value: Option<&str>,
+) -> Result<(), AppError> {
+    match value {
+        Some(value) => d1_query!(
+            db,
+            "INSERT INTO config (key, value, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            key,
+            value.trim(),
+            db::now_string()
+        ),
+        None => d1_query!(db, "DELETE FROM config WHERE key = ?1", key),
+    }
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+    Ok(())
+}
+
+fn parse_bool(raw: &str) -> Result<bool, String> {
+    match raw.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected true or false, got '{raw}'")),
+    }
+}
+
+fn parse_int<T>(raw: &str, min: T) -> Result<T, String>
+where
+    T: std::str::FromStr + PartialOrd + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    let value = raw
+        .parse::<T>()
+        .map_err(|e| format!("invalid number '{raw}': {e}"))?;
+    if value < min {
+        return Err(format!("must be at least {min}"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, Env};
+
+    #[test]
+    fn outbox_max_attempts_must_be_positive() {
+        assert_eq!(Config::default().outbox_max_attempts, 8);
+        assert!(Config::validate("OUTBOX_MAX_ATTEMPTS", "0").is_err());
+        assert!(Config::validate("OUTBOX_MAX_ATTEMPTS", "-1").is_err());
+        assert!(Config::validate("OUTBOX_MAX_ATTEMPTS", "many").is_err());
+
+        let env = Env::with_vars(&[("OUTBOX_MAX_ATTEMPTS", "3")]);
+        assert_eq!(block_on(Config::load(&env)).unwrap().outbox_max_attempts, 3);
+        // An invalid value falls back to the default instead of retrying forever.
+        let env = Env::with_vars(&[("OUTBOX_MAX_ATTEMPTS", "0")]);
+        assert_eq!(block_on(Config::load(&env)).unwrap().outbox_max_attempts, 8);
+    }
+}
@@ -0,0 +1,736 @@
+//! FIDO Credential Exchange Format (CXF) 1.0, in both directions.
+//!
+//! Imports read the CXF header JSON (`version`, `accounts[].items[].credentials[]`) and map
+//! passkeys to `login.fido2Credentials`. Exports turn Bitwarden's unencrypted JSON export into
+//! a CXF header for [`Account`]. Collections and folders map onto each other by name.
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use super::{card, identity, login, Conversion, Item, Kind, Warning};
+
+/// Bitwarden stores credential ids that are not 16-byte GUIDs with this prefix.
+const CREDENTIAL_ID_PREFIX: &str = "b64.";
+const DEFAULT_TOTP_PERIOD: u64 = 30;
+const DEFAULT_TOTP_DIGITS: u64 = 6;
+const DEFAULT_TOTP_ALGORITHM: &str = "sha1";
+
+pub(super) fn convert(input: &[u8], out: &mut Conversion) -> Result<(), String> {
+    let header: Value =
+        serde_json::from_slice(input).map_err(|e| format!("The CXF export cannot be read: {e}"))?;
+    if header["version"]["major"].as_u64() != Some(1) {
+        return Err("Not a CXF 1.x export: missing or unsupported version".to_string());
+    }
+    let accounts = header["accounts"]
+        .as_array()
+        .ok_or("Not a CXF export: missing accounts")?;
+
+    for account in accounts {
+        let mut item_folders = HashMap::new();
+        for collection in account["collections"].as_array().into_iter().flatten() {
+            collect_folders(collection, "", &mut item_folders);
+        }
+        for item in account["items"].as_array().into_iter().flatten() {
+            let folder = item["id"]
+                .as_str()
+                .and_then(|id| item_folders.get(id))
+                .map(String::as_str)
+                .unwrap_or("");
+            let folder_id = out.folder(folder);
+            convert_item(item, folder_id, out);
+        }
+    }
+    Ok(())
+}
+
+/// Map item ids to the path of the first collection that links them.
+fn collect_folders(collection: &Value, parent: &str, item_folders: &mut HashMap<String, String>) {
+    let title = collection["title"].as_str().unwrap_or("").trim();
+    let path = match (parent, title) {
+        ("", title) => title.to_string(),
+        (parent, "") => parent.to_string(),
+        (parent, title) => format!("{parent}/{title}"),
+    };
+    for linked in collection["items"].as_array().into_iter().flatten() {
+        if let Some(id) = linked["item"].as_str() {
+            item_folders
+                .entry(id.to_string())
+                .or_insert_with(|| path.clone());
+        }
+    }
+    for sub in collection["subCollections"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        collect_folders(sub, &path, item_folders);
+    }
+}
+
+fn convert_item(item: &Value, folder_id: Option<String>, out: &mut Conversion) {
+    let name = item["title"].as_str().unwrap_or("");
+    let credentials: Vec<&Value> = item["credentials"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .collect();
+    let of_type = |kind: &str| -> Vec<&Value> {
+        credentials
+            .iter()
+            .copied()
+            .filter(|c| c["type"] == kind)
+            .collect()
+    };
+    let basic_auth = of_type("basic-auth");
+    let passkeys = of_type("passkey");
+    let totps = of_type("totp");
+    let cards = of_type("credit-card");
+    let addresses = of_type("address");
+    let names = of_type("person-name");
+
+    // TOTP codes beyond the login's own become hidden fields.
+    let mut used = vec!["note", "custom-fields", "totp"];
+    let mut extra_totps = totps.as_slice();
+    let mut fido2 = Vec::new();
+    let kind = if !basic_auth.is_empty() || !passkeys.is_empty() {
+        used.extend(["basic-auth", "passkey"]);
+        let auth = basic_auth.first().copied().unwrap_or(&Value::Null);
+        let mut username = editable(&auth["username"]);
+        if username.is_empty() {
+            username = passkeys
+                .first()
+                .and_then(|p| p["username"].as_str())
+                .unwrap_or("");
+        }
+        let mut uris: Vec<String> = item["scope"]["urls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|url| url.as_str().map(str::to_string))
+            .collect();
+        uris.extend(
+            item["scope"]["androidApps"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|app| app["bundleId"].as_str())
+                .map(|bundle| format!("androidapp://{bundle}")),
+        );
+        let uris: Vec<&str> = uris.iter().map(String::as_str).collect();
+        let totp = totps.first().map(|t| totp_uri(t)).unwrap_or_default();
+        extra_totps = totps.get(1..).unwrap_or(&[]);
+
+        let created = item["creationAt"]
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .unwrap_or_else(Utc::now)
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        for passkey in &passkeys {
+            match import_passkey(passkey, &created) {
+                Some(passkey) => fido2.push(passkey),
+                None => out.warn(name, "A passkey with an invalid credential id was skipped"),
+            }
+        }
+        login(username, editable(&auth["password"]), &uris, &totp)
+    } else if let Some(credit_card) = cards.first() {
+        used.push("credit-card");
+        let (year, month) = editable(&credit_card["expiryDate"])
+            .split_once('-')
+            .unwrap_or_default();
+        card(
+            editable(&credit_card["fullName"]),
+            editable(&credit_card["number"]),
+            editable(&credit_card["verificationNumber"]),
+            month,
+            year,
+        )
+    } else if !addresses.is_empty() || !names.is_empty() {
+        used.extend(["address", "person-name"]);
+        let address = addresses.first().copied().unwrap_or(&Value::Null);
+        let person = names.first().copied().unwrap_or(&Value::Null);
+        identity(&[
+            ("title", editable(&person["title"])),
+            ("firstName", editable(&person["given"])),
+            ("middleName", editable(&person["given2"])),
+            ("lastName", editable(&person["surname"])),
+            ("address1", editable(&address["streetAddress"])),
+            ("city", editable(&address["city"])),
+            ("state", editable(&address["territory"])),
+            ("postalCode", editable(&address["postalCode"])),
+            ("country", editable(&address["country"])),
+            ("phone", editable(&address["tel"])),
+        ])
+    } else {
+        Kind::SecureNote
+    };
+
+    let mut converted = Item::new(name, kind);
+    if let Kind::Login(login) = &mut converted.kind {
+        if !fido2.is_empty() {
+            login["fido2Credentials"] = Value::Array(fido2);
+        }
+    }
+    converted.folder_id = folder_id;
+    converted.favorite = item["favorite"].as_bool().unwrap_or(false);
+    for note in of_type("note") {
+        converted.note(editable(&note["content"]));
+    }
+    for fields in of_type("custom-fields") {
+        for field in fields["fields"].as_array().into_iter().flatten() {
+            let label = field["label"].as_str().unwrap_or("");
+            let hidden = field["fieldType"] == "concealed-string";
+            converted.field(label, &editable_text(field), hidden);
+        }
+    }
+    for totp in extra_totps {
+        converted.field("TOTP", &totp_uri(totp), true);
+    }
+
+    let mut skipped: Vec<&str> = Vec::new();
+    for kind in credentials.iter().filter_map(|c| c["type"].as_str()) {
+        if !used.contains(&kind) && !skipped.contains(&kind) {
+            skipped.push(kind);
+            out.warn(name, format!("{kind} credentials are not imported"));
+        }
+    }
+    out.push(converted);
+}
+
+fn import_passkey(passkey: &Value, created: &str) -> Option<Value> {
+    let raw_id = decode_b64url(passkey["credentialId"].as_str()?)?;
+    let credential_id = match Uuid::from_slice(&raw_id) {
+        Ok(uuid) => uuid.to_string(),
+        Err(_) => format!("{CREDENTIAL_ID_PREFIX}{}", URL_SAFE_NO_PAD.encode(&raw_id)),
+    };
+    Some(json!({
+        "credentialId": credential_id,
+        "keyType": "public-key",
+        "keyAlgorithm": "ECDSA",
+        "keyCurve": "P-256",
+        "keyValue": passkey["key"],
+        "rpId": passkey["rpId"],
+        "rpName": passkey["rpId"],
+        "userHandle": passkey["userHandle"],
+        "userName": passkey["username"],
+        "userDisplayName": passkey["userDisplayName"],
+        "counter": "0",
+        "discoverable": "true",
+        "creationDate": created,
+    }))
+}
+
+/// A CXF TOTP credential as the `otpauth://` URI the clients store, or the bare secret when
+/// every parameter is the default.
+fn totp_uri(totp: &Value) -> String {
+    let secret = totp["secret"].as_str().unwrap_or("");
+    let period = totp["period"].as_u64().unwrap_or(DEFAULT_TOTP_PERIOD);
+    let digits = totp["digits"].as_u64().unwrap_or(DEFAULT_TOTP_DIGITS);
+    let algorithm = totp["algorithm"].as_str().unwrap_or(DEFAULT_TOTP_ALGORITHM);
+    let issuer = totp["issuer"].as_str().unwrap_or("");
+    if period == DEFAULT_TOTP_PERIOD
+        && digits == DEFAULT_TOTP_DIGITS
+        && algorithm.eq_ignore_ascii_case(DEFAULT_TOTP_ALGORITHM)
+        && issuer.is_empty()
+    {
+        return secret.to_string();
+    }
+
+    let Ok(mut uri) = worker::Url::parse("otpauth://totp/") else {
+        return secret.to_string();
+    };
+    let label = match totp["username"].as_str().unwrap_or("") {
+        "" => issuer.to_string(),
+        username if issuer.is_empty() => username.to_string(),
+        username => format!("{issuer}:{username}"),
+    };
+    if let Ok(mut segments) = uri.path_segments_mut() {
+        segments.pop().push(&label);
+    }
+    let mut query = uri.query_pairs_mut();
+    query.append_pair("secret", secret);
+    if !issuer.is_empty() {
+        query.append_pair("issuer", issuer);
+    }
+    query
+        .append_pair("algorithm", &algorithm.to_ascii_uppercase())
+        .append_pair("digits", &digits.to_string())
+        .append_pair("period", &period.to_string());
+    drop(query);
+    uri.to_string()
+}
+
+/// The value of a CXF editable field. Plain strings are accepted as well.
+fn editable(field: &Value) -> &str {
+    match field {
+        Value::String(value) => value,
+        _ => field["value"].as_str().unwrap_or(""),
+    }
+}
+
+/// Like [`editable`], but also renders boolean and numeric values.
+fn editable_text(field: &Value) -> String {
+    match &field["value"] {
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => editable(field).to_string(),
+    }
+}
+
+fn decode_b64url(value: &str) -> Option<Vec<u8>> {
+    let value = value
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_");
+    URL_SAFE_NO_PAD.decode(value).ok()
+}
+
+/// The account a CXF export is made for.
+pub struct Account<'a> {
+    pub id: &'a str,
+    pub username: &'a str,
+    pub email: &'a str,
+}
+
+/// Convert Bitwarden's unencrypted JSON export into a CXF header for `account`.
+pub fn export(
+    input: &[u8],
+    account: &Account,
+    exporter_rp_id: &str,
+) -> Result<(Value, Vec<Warning>), String> {
+    let export: Value = serde_json::from_slice(input)
+        .map_err(|e| format!("The Bitwarden export cannot be read: {e}"))?;
+    if export["encrypted"] != false {
+        return Err("Export the vault as unencrypted JSON (.json) first".to_string());
+    }
+    let mut warnings = Vec::new();
+
+    let mut items = Vec::new();
+    let mut folder_items: HashMap<&str, Vec<Value>> = HashMap::new();
+    for item in export["items"].as_array().into_iter().flatten() {
+        let id = cxf_id(item["id"].as_str().unwrap_or(""));
+        if let Some(folder_id) = item["folderId"].as_str() {
+            folder_items
+                .entry(folder_id)
+                .or_default()
+                .push(json!({ "item": id }));
+        }
+        items.push(export_item(item, id, &mut warnings));
+    }
+
+    let collections: Vec<Value> = export["folders"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|folder| {
+            let folder_id = folder["id"].as_str().unwrap_or("");
+            json!({
+                "id": cxf_id(folder_id),
+                "title": folder["name"],
+                "items": folder_items.remove(folder_id).unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    let header = json!({
+        "version": { "major": 1, "minor": 0 },
+        "exporterRpId": exporter_rp_id,
+        "exporterDisplayName": "Warden",
+        "timestamp": Utc::now().timestamp(),
+        "accounts": [{
+            "id": cxf_id(account.id),
+            "username": account.username,
+            "email": account.email,
+            "collections": collections,
+            "items": items,
+        }],
+    });
+    Ok((header, warnings))
+}
+
+fn export_item(item: &Value, id: String, warnings: &mut Vec<Warning>) -> Value {
+    let name = item["name"].as_str().unwrap_or("");
+    let mut warn = |message: String| {
+        warnings.push(Warning {
+            item: name.to_string(),
+            message,
+        })
+    };
+    let mut credentials = Vec::new();
+    let mut scope = None;
+
+    match item["type"].as_i64() {
+        Some(1) => {
+            let login = &item["login"];
+            let username = login["username"].as_str().unwrap_or("");
+            let password = login["password"].as_str().unwrap_or("");
+            if !username.is_empty() || !password.is_empty() {
+                credentials.push(json!({
+                    "type": "basic-auth",
+                    "username": field("string", username),
+                    "password": field("concealed-string", password),
+                }));
+            }
+            for passkey in login["fido2Credentials"].as_array().into_iter().flatten() {
+                match export_passkey(passkey) {
+                    Some(passkey) => credentials.push(passkey),
+                    None => warn("A passkey with an invalid credential id was skipped".into()),
+                }
+            }
+            match login["totp"].as_str().map(export_totp) {
+                Some(Ok(totp)) => credentials.push(totp),
+                Some(Err(e)) => warn(e),
+                None => {}
+            }
+            let urls: Vec<&str> = login["uris"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|uri| uri["uri"].as_str())
+                .collect();
+            if !urls.is_empty() {
+                scope = Some(json!({ "urls": urls, "androidApps": [] }));
+            }
+        }
+        Some(2) => {}
+        Some(3) => {
+            let card = &item["card"];
+            let mut credit_card = Map::new();
+            credit_card.insert("type".into(), "credit-card".into());
+            for (cxf, bitwarden, field_type) in [
+                ("number", "number", "concealed-string"),
+                ("fullName", "cardholderName", "string"),
+                ("cardType", "brand", "string"),
+                ("verificationNumber", "code", "concealed-string"),
+            ] {
+                if let Some(value) = card[bitwarden].as_str() {
+                    credit_card.insert(cxf.into(), field(field_type, value));
+                }
+            }
+            let month = card["expMonth"]
+                .as_str()
+                .and_then(|m| m.parse::<u32>().ok());
+            if let (Some(month), Some(year)) = (month, card["expYear"].as_str()) {
+                credit_card.insert(
+                    "expiryDate".into(),
+                    field("year-month", &format!("{year}-{month:02}")),
+                );
+            }
+            credentials.push(Value::Object(credit_card));
+        }
+        Some(4) => {
+            let identity = &item["identity"];
+            let text = |key: &str| identity[key].as_str().unwrap_or("");
+            credentials.push(json!({
+                "type": "person-name",
+                "title": field("string", text("title")),
+                "given": field("string", text("firstName")),
+                "given2": field("string", text("middleName")),
+                "surname": field("string", text("lastName")),
+            }));
+            let street: Vec<&str> = ["address1", "address2", "address3"]
+                .into_iter()
+                .map(text)
+                .filter(|line| !line.is_empty())
+                .collect();
+            credentials.push(json!({
+                "type": "address",
+                "streetAddress": field("string", &street.join("\n")),
+                "postalCode": field("string", text("postalCode")),
+                "city": field("string", text("city")),
+                "territory": field("subdivision-code", text("state")),
+                "country": field("country-code", text("country")),
+                "tel": field("string", text("phone")),
+            }));
+            let others: Vec<Value> = [
+                ("email", "Email"),
+                ("company", "Company"),
+                ("username", "Username"),
+                ("ssn", "Social Security number"),
+                ("passportNumber", "Passport number"),
+                ("licenseNumber", "License number"),
+            ]
+            .into_iter()
+            .filter(|(key, _)| !text(key).is_empty())
+            .map(|(key, label)| {
+                let mut value = field("string", text(key));
+                value["label"] = label.into();
+                value
+            })
+            .collect();
+            if !others.is_empty() {
+                credentials.push(json!({ "type": "custom-fields", "fields": others }));
+            }
+        }
+        Some(5) => warn("SSH keys are not exported".into()),
+        _ => warn("Unknown item type; only its notes and fields are exported".into()),
+    }
+
+    if let Some(notes) = item["notes"].as_str().filter(|n| !n.is_empty()) {
+        credentials.push(json!({ "type": "note", "content": field("string", notes) }));
+    }
+    let mut fields = Vec::new();
+    for custom in item["fields"].as_array().into_iter().flatten() {
+        let label = custom["name"].as_str().unwrap_or("");
+        let value = custom["value"].as_str().unwrap_or("");
+        let mut exported = match custom["type"].as_i64() {
+            Some(1) => field("concealed-string", value),
+            Some(2) => json!({ "fieldType": "boolean", "value": value == "true" }),
+            Some(3) => {
+                warn(format!("Linked field {label:?} is not exported"));
+                continue;
+            }
+            _ => field("string", value),
+        };
+        exported["label"] = label.into();
+        fields.push(exported);
+    }
+    if !fields.is_empty() {
+        credentials.push(json!({ "type": "custom-fields", "fields": fields }));
+    }
+
+    let mut exported = json!({
+        "id": id,
+        "title": name,
+        "favorite": item["favorite"].as_bool().unwrap_or(false),
+        "credentials": credentials,
+    });
+    for (cxf, bitwarden) in [
+        ("creationAt", "creationDate"),
+        ("modifiedAt", "revisionDate"),
+    ] {
+        if let Some(date) = item[bitwarden]
+            .as_str()
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+        {
+            exported[cxf] = date.timestamp().into();
+        }
+    }
+    if let Some(scope) = scope {
+        exported["scope"] = scope;
+    }
+    exported
+}
+
+fn export_passkey(passkey: &Value) -> Option<Value> {
+    let id = passkey["credentialId"].as_str()?;
+    let raw_id = match id.strip_prefix(CREDENTIAL_ID_PREFIX) {
+        Some(encoded) => decode_b64url(encoded)?,
+        None => Uuid::parse_str(id).ok()?.as_bytes().to_vec(),
+    };
+    Some(json!({
+        "type": "passkey",
+        "credentialId": URL_SAFE_NO_PAD.encode(raw_id),
+        "rpId": passkey["rpId"],
+        "username": passkey["userName"].as_str().unwrap_or(""),
+        "userDisplayName": passkey["userDisplayName"].as_str().unwrap_or(""),
+        "userHandle": passkey["userHandle"],
+        "key": passkey["keyValue"],
+    }))
+}
+
+/// A stored TOTP (bare base32 secret or `otpauth://` URI) as a CXF TOTP credential.
+fn export_totp(totp: &str) -> Result<Value, String> {
+    if !totp.starts_with("otpauth://") {
+        if totp.starts_with("steam://") {
+            return Err("Steam Guard codes are not exported".to_string());
+        }
+        return Ok(json!({
+            "type": "totp",
+            "secret": totp.replace(' ', "").to_ascii_uppercase(),
+            "period": DEFAULT_TOTP_PERIOD,
+            "digits": DEFAULT_TOTP_DIGITS,
+            "algorithm": DEFAULT_TOTP_ALGORITHM,
+        }));
+    }
+    let uri = worker::Url::parse(totp).map_err(|_| "Invalid TOTP URI".to_string())?;
+    let query: HashMap<String, String> = uri.query_pairs().into_owned().collect();
+    let secret = query.get("secret").ok_or("TOTP URI without a secret")?;
+    let label = uri
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|label| {
+            percent_decode(label)
+                .rsplit(':')
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .unwrap_or_default();
+    let number = |key: &str, default: u64| {
+        query
+            .get(key)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    Ok(json!({
+        "type": "totp",
+        "secret": secret,
+        "period": number("period", DEFAULT_TOTP_PERIOD),
+        "digits": number("digits", DEFAULT_TOTP_DIGITS),
+        "algorithm": query
+            .get("algorithm")
+            .map_or(DEFAULT_TOTP_ALGORITHM.to_string(), |a| a.to_ascii_lowercase()),
+        "issuer": query.get("issuer"),
+        "username": label,
+    }))
+}
+
+fn percent_decode(value: &str) -> String {
+    worker::Url::parse(&format!("x:?v={value}"))
+        .ok()
+        .and_then(|url| url.query_pairs().next().map(|(_, v)| v.into_owned()))
+        .unwrap_or_else(|| value.to_string())
+}
+
+fn field(field_type: &str, value: &str) -> Value {
+    json!({ "fieldType": field_type, "value": value })
+}
+
+/// CXF ids are base64url; Bitwarden ids are GUIDs.
+fn cxf_id(id: &str) -> String {
+    match Uuid::parse_str(id) {
+        Ok(uuid) => URL_SAFE_NO_PAD.encode(uuid.as_bytes()),
+        Err(_) => URL_SAFE_NO_PAD.encode(id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREDENTIAL_ID: &str = "3b5f4ad8-1d53-4f37-b8a4-2b9d05c1d0de";
+
+    fn bitwarden_export() -> Value {
+        json!({
+            "encrypted": false,
+            "folders": [{ "id": "a1b2c3d4-0000-4000-8000-000000000001", "name": "Work" }],
+            "items": [{
+                "id": "a1b2c3d4-0000-4000-8000-000000000002",
+                "folderId": "a1b2c3d4-0000-4000-8000-000000000001",
+                "type": 1,
+                "name": "Example",
+                "notes": "hello",
+                "favorite": true,
+                "fields": [{ "name": "PIN", "value": "1234", "type": 1, "linkedId": null }],
+                "login": {
+                    "username": "alice",
+                    "password": "secret",
+                    "totp": "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example&digits=8",
+                    "uris": [{ "uri": "https://example.com", "match": null }],
+                    "fido2Credentials": [{
+                        "credentialId": CREDENTIAL_ID,
+                        "keyType": "public-key", "keyAlgorithm": "ECDSA", "keyCurve": "P-256",
+                        "keyValue": "cGtjczgta2V5", "rpId": "example.com", "rpName": "Example",
+                        "userHandle": "dXNlcg", "userName": "alice", "userDisplayName": "Alice",
+                        "counter": "0", "discoverable": "true",
+                        "creationDate": "2026-01-02T03:04:05.000Z"
+                    }]
+                },
+                "creationDate": "2026-01-02T03:04:05.000Z",
+                "revisionDate": "2026-02-02T03:04:05.000Z"
+            }]
+        })
+    }
+
+    #[test]
+    fn exports_and_reimports_logins_with_passkeys() {
+        let account = Account {
+            id: "a1b2c3d4-0000-4000-8000-00000000000a",
+            username: "Alice",
+            email: "alice@example.com",
+        };
+        let (header, warnings) = export(
+            bitwarden_export().to_string().as_bytes(),
+            &account,
+            "vault.example.com",
+        )
+        .unwrap();
+        assert!(warnings.is_empty());
+        let item = &header["accounts"][0]["items"][0];
+        let types: Vec<&str> = item["credentials"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            ["basic-auth", "passkey", "totp", "note", "custom-fields"]
+        );
+        assert_eq!(item["credentials"][2]["digits"], 8);
+        assert_eq!(item["credentials"][2]["username"], "alice");
+        assert_eq!(
+            header["accounts"][0]["collections"][0]["items"][0]["item"],
+            item["id"]
+        );
+
+        let mut out = Conversion::default();
+        convert(header.to_string().as_bytes(), &mut out).unwrap();
+        assert!(out.warnings.is_empty(), "{:?}", out.warnings);
+        assert_eq!(out.folders[0].name, "Work");
+        let converted = serde_json::to_value(&out.items[0]).unwrap();
+        assert_eq!(converted["type"], 1);
+        assert_eq!(converted["favorite"], true);
+        assert_eq!(converted["notes"], "hello");
+        assert_eq!(converted["fields"][0]["name"], "PIN");
+        assert_eq!(converted["fields"][0]["type"], 1);
+        assert_eq!(converted["login"]["username"], "alice");
+        assert!(converted["login"]["totp"]
+            .as_str()
+            .unwrap()
+            .contains("digits=8"));
+
+        let passkey = &converted["login"]["fido2Credentials"][0];
+        assert_eq!(passkey["credentialId"], CREDENTIAL_ID);
+        assert_eq!(passkey["keyValue"], "cGtjczgta2V5");
+        assert_eq!(passkey["userHandle"], "dXNlcg");
+        assert_eq!(passkey["creationDate"], "2026-01-02T03:04:05.000Z");
+        assert!(out.items[0]
+            .data
+            .type_fields
+            .validate_fido2_credentials()
+            .is_ok());
+    }
+
+    #[test]
+    fn long_credential_ids_keep_the_b64_prefix() {
+        let passkey = json!({ "credentialId": "AAECAwQFBgcICQoLDA0ODxAR", "rpId": "example.com", "key": "a" });
+        let imported = import_passkey(&passkey, "2026-01-01T00:00:00.000Z").unwrap();
+        assert_eq!(imported["credentialId"], "b64.AAECAwQFBgcICQoLDA0ODxAR");
+        let exported =
+            export_passkey(&json!({ "credentialId": imported["credentialId"] })).unwrap();
+        assert_eq!(exported["credentialId"], "AAECAwQFBgcICQoLDA0ODxAR");
+    }
+
+    #[test]
+    fn unsupported_credentials_are_reported() {
+        let header = json!({
+            "version": { "major": 1, "minor": 0 },
+            "accounts": [{ "items": [{
+                "id": "aXRlbQ", "title": "Home", "credentials": [
+                    { "type": "wifi", "ssid": { "fieldType": "string", "value": "home" } },
+                    { "type": "note", "content": { "fieldType": "string", "value": "router" } }
+                ]
+            }]}]
+        });
+        let mut out = Conversion::default();
+        convert(header.to_string().as_bytes(), &mut out).unwrap();
+        assert_eq!(out.items[0].r#type, 2);
+        assert_eq!(out.warnings[0].message, "wifi credentials are not imported");
+
+        assert!(export(
+            br#"{"encrypted":true}"#,
+            &Account {
+                id: "",
+                username: "",
+                email: ""
+            },
+            ""
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! KeePass 2 / KeePassXC XML export.
+//!
+//! Every entry becomes a login. Groups below the root become folders named by their path, and
+//! the entry history and the recycle bin are left out.
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+use super::{login, Conversion, Item};
+
+/// Entry strings with a place of their own in a login.
+const MAPPED_KEYS: &[&str] = &[
+    "Title",
+    "UserName",
+    "Password",
+    "URL",
+    "Notes",
+    "otp",
+    "TimeOtp-Secret-Base32",
+];
+
+/// The all-zero UUID, used when the database has no recycle bin.
+const NO_UUID: &str = "AAAAAAAAAAAAAAAAAAAAAA==";
+
+pub(super) fn convert(input: &[u8], out: &mut Conversion) -> Result<(), String> {
+    let text = std::str::from_utf8(input).map_err(|_| "The XML file is not UTF-8".to_string())?;
+    let options = ParsingOptions {
+        allow_dtd: false,
+        ..ParsingOptions::default()
+    };
+    let doc = Document::parse_with_options(text, options)
+        .map_err(|e| format!("The XML file cannot be read: {e}"))?;
+
+    let file = doc.root_element();
+    if !file.has_tag_name("KeePassFile") {
+        return Err("Not a KeePass XML export: missing KeePassFile".to_string());
+    }
+    let recycle_bin = child(file, "Meta")
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"))
+        .filter(|uuid| !uuid.is_empty() && *uuid != NO_UUID);
+    let root_group = child(file, "Root")
+        .and_then(|root| child(root, "Group"))
+        .ok_or("Not a KeePass XML export: missing the root group")?;
+
+    let mut groups = Groups {
+        recycle_bin,
+        skipped: 0,
+    };
+    convert_group(root_group, None, &mut groups, out);
+    if groups.skipped > 0 {
+        out.warn(
+            "Recycle Bin",
+            format!("{} entries in the recycle bin were skipped", groups.skipped),
+        );
+    }
+    Ok(())
+}
+
+struct Groups<'a> {
+    recycle_bin: Option<&'a str>,
+    skipped: usize,
+}
+
+/// Convert the entries of `group` and its subgroups. `path` is `None` for the root group.
+fn convert_group(group: Node, path: Option<&str>, groups: &mut Groups, out: &mut Conversion) {
+    let folder_id = path.and_then(|path| out.folder(path));
+    for entry in group.children().filter(|n| n.has_tag_name("Entry")) {
+        convert_entry(entry, folder_id.clone(), out);
+    }
+
+    for subgroup in group.children().filter(|n| n.has_tag_name("Group")) {
+        if groups.recycle_bin.is_some() && child_text(subgroup, "UUID") == groups.recycle_bin {
+            groups.skipped += subgroup
+                .descendants()
+                .filter(|n| n.has_tag_name("Entry") && !in_history(*n))
+                .count();
+            continue;
+        }
+        let name = child_text(subgroup, "Name").unwrap_or("").trim();
+        let subpath = match path {
+            Some(path) if !name.is_empty() => format!("{path}/{name}"),
+            Some(path) => path.to_string(),
+            None => name.to_string(),
+        };
+        convert_group(subgroup, Some(&subpath), groups, out);
+    }
+}
+
+fn convert_entry(entry: Node, folder_id: Option<String>, out: &mut Conversion) {
+    let mut strings = Vec::new();
+    let mut unreadable = Vec::new();
+    for string in entry.children().filter(|n| n.has_tag_name("String")) {
+        let key = child_text(string, "Key").unwrap_or("");
+        let Some(value) = child(string, "Value") else {
+            continue;
+        };
+        if value.attribute("Protected") == Some("True") {
+            unreadable.push(key);
+            continue;
+        }
+        let hidden = value.attribute("ProtectInMemory") == Some("True");
+        strings.push((key, value.text().unwrap_or(""), hidden));
+    }
+    let get = |key: &str| {
+        strings
+            .iter()
+            .find(|(k, _, _)| *k == key)
+            .map(|(_, v, _)| *v)
+            .unwrap_or("")
+    };
+
+    let name = get("Title");
+    let totp = match get("otp") {
+        "" => get("TimeOtp-Secret-Base32"),
+        otp => otp,
+    };
+    let mut uris = vec![get("URL")];
+    uris.extend(
+        strings
+            .iter()
+            .filter(|(k, _, _)| k.starts_with("KP2A_URL"))
+            .map(|(_, v, _)| *v),
+    );
+
+    let mut item = Item::new(name, login(get("UserName"), get("Password"), &uris, totp));
+    item.folder_id = folder_id;
+    item.note(get("Notes"));
+    for (key, value, hidden) in &strings {
+        if !MAPPED_KEYS.contains(key) && !key.starts_with("KP2A_URL") {
+            item.field(key, value, *hidden);
+        }
+    }
+
+    if !unreadable.is_empty() {
+        out.warn(
+            name,
+            format!(
+                "Encrypted values were skipped ({}); export the database as unencrypted XML",
+                unreadable.join(", ")
+            ),
+        );
+    }
+    let attachments = entry
+        .children()
+        .filter(|n| n.has_tag_name("Binary"))
+        .count();
+    if attachments > 0 {
+        out.warn(
+            name,
+            format!("{attachments} attachment(s) were not imported"),
+        );
+    }
+    out.push(item);
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).map(|n| n.text().unwrap_or(""))
+}
+
+fn in_history(node: Node) -> bool {
+    node.ancestors().any(|n| n.has_tag_name("History"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+  <Meta><RecycleBinUUID>YmluYmluYmluYmluYmluYg==</RecycleBinUUID></Meta>
+  <Root>
+    <Group>
+      <UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID>
+      <Name>Database</Name>
+      <Entry>
+        <String><Key>Title</Key><Value>Top</Value></String>
+        <String><Key>UserName</Key><Value>root</Value></String>
+      </Entry>
+      <Group>
+        <UUID>d29ya3dvcmt3b3Jrd29yaw==</UUID>
+        <Name>Work</Name>
+        <Group>
+          <UUID>bWFpbG1haWxtYWlsbWFpbA==</UUID>
+          <Name>Mail</Name>
+          <Entry>
+            <String><Key>Title</Key><Value>Mail</Value></String>
+            <String><Key>UserName</Key><Value>alice</Value></String>
+            <String><Key>Password</Key><Value ProtectInMemory="True">secret</Value></String>
+            <String><Key>URL</Key><Value>mail.example.com</Value></String>
+            <String><Key>otp</Key><Value>otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP</Value></String>
+            <String><Key>PIN</Key><Value ProtectInMemory="True">1234</Value></String>
+            <String><Key>Notes</Key><Value>hello</Value></String>
+            <Binary><Key>key.pem</Key><Value Ref="0" /></Binary>
+            <History>
+              <Entry><String><Key>Title</Key><Value>Old mail</Value></String></Entry>
+            </History>
+          </Entry>
+        </Group>
+      </Group>
+      <Group>
+        <UUID>YmluYmluYmluYmluYmluYg==</UUID>
+        <Name>Recycle Bin</Name>
+        <Entry><String><Key>Title</Key><Value>Deleted</Value></String></Entry>
+      </Group>
+    </Group>
+  </Root>
+</KeePassFile>"#;
+
+    #[test]
+    fn converts_groups_entries_and_fields() {
+        let mut out = Conversion::default();
+        convert(EXPORT.as_bytes(), &mut out).unwrap();
+
+        let folders: Vec<&str> = out.folders.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(folders, ["Work", "Work/Mail"]);
+        let items: Vec<Value> = out
+            .items
+            .iter()
+            .map(|item| serde_json::to_value(item).unwrap())
+            .collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["name"], "Top");
+        assert_eq!(items[0]["folderId"], Value::Null);
+
+        let mail = &items[1];
+        assert_eq!(mail["name"], "Mail");
+        assert_eq!(mail["folderId"], out.folders[1].id.as_str());
+        assert_eq!(mail["login"]["username"], "alice");
+        assert_eq!(mail["login"]["password"], "secret");
+        assert_eq!(mail["login"]["uris"][0]["uri"], "http://mail.example.com");
+        assert_eq!(
+            mail["login"]["totp"],
+            "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP"
+        );
+        assert_eq!(mail["notes"], "hello");
+        assert_eq!(mail["fields"][0]["name"], "PIN");
+        assert_eq!(mail["fields"][0]["type"], 1);
+
+        let warnings: Vec<&str> = out.warnings.iter().map(|w| w.item.as_str()).collect();
+        assert_eq!(warnings, ["Mail", "Recycle Bin"]);
+    }
+
+    #[test]
+    fn rejects_other_xml() {
+        let mut out = Conversion::default();
+        assert!(convert(b"<html></html>", &mut out).is_err());
+    }
+}
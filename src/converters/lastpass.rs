@@ -0,0 +1,339 @@
+//! LastPass CSV export: `url,username,password,totp,extra,name,grouping,fav`.
+//!
+//! Secure notes have the url `http://sn`. Structured notes keep their fields in `extra` as
+//! `Key:Value` lines starting with `NoteType:`, with a trailing multi-line `Notes:` entry.
+
+use serde_json::Value;
+
+use super::{card, identity, login, month_number, Conversion, Item, Kind};
+
+const SECURE_NOTE_URL: &str = "http://sn";
+
+/// LastPass address fields and the identity fields they map to.
+const ADDRESS_FIELDS: &[(&str, &str)] = &[
+    ("Title", "title"),
+    ("First Name", "firstName"),
+    ("Middle Name", "middleName"),
+    ("Last Name", "lastName"),
+    ("Username", "username"),
+    ("Company", "company"),
+    ("Address 1", "address1"),
+    ("Address 2", "address2"),
+    ("Address 3", "address3"),
+    ("City / Town", "city"),
+    ("State", "state"),
+    ("Zip / Postal Code", "postalCode"),
+    ("Country", "country"),
+    ("Email Address", "email"),
+    ("Phone", "phone"),
+];
+
+pub(super) fn convert(input: &[u8], out: &mut Conversion) -> Result<(), String> {
+    let text = std::str::from_utf8(input).map_err(|_| "The CSV file is not UTF-8".to_string())?;
+    let mut rows = parse_csv(text.trim_start_matches('\u{feff}')).into_iter();
+    let header = rows.next().ok_or("The CSV file is empty")?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+    };
+    let (Some(url), Some(name)) = (column("url"), column("name")) else {
+        return Err("Not a LastPass CSV export: missing the url and name columns".to_string());
+    };
+    let columns = Columns {
+        url,
+        name,
+        username: column("username"),
+        password: column("password"),
+        totp: column("totp"),
+        extra: column("extra"),
+        grouping: column("grouping"),
+        fav: column("fav"),
+    };
+
+    for row in rows {
+        if row.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+        convert_row(&row, &columns, out);
+    }
+    Ok(())
+}
+
+struct Columns {
+    url: usize,
+    name: usize,
+    username: Option<usize>,
+    password: Option<usize>,
+    totp: Option<usize>,
+    extra: Option<usize>,
+    grouping: Option<usize>,
+    fav: Option<usize>,
+}
+
+fn convert_row(row: &[String], columns: &Columns, out: &mut Conversion) {
+    let cell = |index: Option<usize>| {
+        index
+            .and_then(|i| row.get(i))
+            .map(String::as_str)
+            .unwrap_or("")
+    };
+    let name = cell(Some(columns.name));
+    let url = cell(Some(columns.url));
+    let extra = cell(columns.extra);
+
+    let mut item = if url == SECURE_NOTE_URL {
+        convert_note(name, extra, out)
+    } else {
+        let mut item = Item::new(
+            name,
+            login(
+                cell(columns.username),
+                cell(columns.password),
+                &[url],
+                cell(columns.totp),
+            ),
+        );
+        item.note(extra);
+        item
+    };
+
+    let grouping = cell(columns.grouping);
+    if grouping != "(none)" {
+        item.folder_id = out.folder(&grouping.replace('\\', "/"));
+    }
+    item.favorite = cell(columns.fav) == "1";
+    out.push(item);
+}
+
+fn convert_note(name: &str, extra: &str, out: &mut Conversion) -> Item {
+    let Some(Note {
+        note_type,
+        fields,
+        notes,
+    }) = parse_note(extra)
+    else {
+        let mut item = Item::new(name, Kind::SecureNote);
+        item.note(extra);
+        return item;
+    };
+    let get = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    };
+
+    let (mut item, mapped): (Item, Vec<&str>) = match note_type.as_str() {
+        "Credit Card" => {
+            let (month, year) = expiry(get("Expiration Date"));
+            let kind = card(
+                get("Name on Card"),
+                get("Number"),
+                get("Security Code"),
+                &month,
+                &year,
+            );
+            let mapped = vec![
+                "Name on Card",
+                "Number",
+                "Security Code",
+                "Expiration Date",
+                "Type",
+            ];
+            (Item::new(name, kind), mapped)
+        }
+        "Address" => {
+            let values: Vec<(&str, String)> = ADDRESS_FIELDS
+                .iter()
+                .map(|(lastpass, client)| (*client, phone_number(get(lastpass))))
+                .collect();
+            let values: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            let mapped = ADDRESS_FIELDS
+                .iter()
+                .map(|(lastpass, _)| *lastpass)
+                .collect();
+            (Item::new(name, identity(&values)), mapped)
+        }
+        other => {
+            out.warn(
+                name,
+                format!(
+                    "LastPass \"{other}\" notes are imported as secure notes with custom fields"
+                ),
+            );
+            (Item::new(name, Kind::SecureNote), Vec::new())
+        }
+    };
+
+    for (key, value) in &fields {
+        // Empty LastPass dates are exported as a lone comma.
+        if !mapped.contains(&key.as_str()) && value != "," {
+            let hidden = key.contains("Password") || key.contains("PIN");
+            item.field(key, &phone_number(value), hidden);
+        }
+    }
+    item.note(&notes);
+    item
+}
+
+/// A structured note: its type, its `Key:Value` fields and the free-text notes.
+struct Note {
+    note_type: String,
+    fields: Vec<(String, String)>,
+    notes: String,
+}
+
+fn parse_note(extra: &str) -> Option<Note> {
+    let mut lines = extra.lines();
+    let note_type = lines.next()?.strip_prefix("NoteType:")?.trim().to_string();
+    let mut fields = Vec::new();
+    let mut notes = String::new();
+    while let Some(line) = lines.next() {
+        if let Some(first) = line.strip_prefix("Notes:") {
+            notes = std::iter::once(first)
+                .chain(lines.by_ref())
+                .collect::<Vec<_>>()
+                .join("\n");
+            break;
+        }
+        match line.split_once(':') {
+            Some((key, value)) => fields.push((key.to_string(), value.trim().to_string())),
+            // A line without a key continues the previous value.
+            None => match fields.last_mut() {
+                Some((_, value)) => {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+                None => notes.push_str(line),
+            },
+        }
+    }
+    Some(Note {
+        note_type,
+        fields,
+        notes,
+    })
+}
+
+/// `June,2025` as month and year numbers.
+fn expiry(value: &str) -> (String, String) {
+    let (month, year) = value.split_once(',').unwrap_or((value, ""));
+    let month = month_number(month)
+        .map(|m| m.to_string())
+        .unwrap_or_default();
+    (month, year.trim().to_string())
+}
+
+/// LastPass stores phone numbers as `{"num":"...","ext":"...","cc3l":"USA"}`.
+fn phone_number(value: &str) -> String {
+    if !value.starts_with('{') {
+        return value.to_string();
+    }
+    match serde_json::from_str::<Value>(value) {
+        Ok(phone) => {
+            let num = phone["num"].as_str().unwrap_or_default();
+            match phone["ext"].as_str().filter(|ext| !ext.is_empty()) {
+                Some(ext) => format!("{num} ext. {ext}"),
+                None => num.to_string(),
+            }
+        }
+        Err(_) => value.to_string(),
+    }
+}
+
+/// RFC 4180 CSV: quoted cells may contain commas, doubled quotes and line breaks.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    cell.push('"');
+                    chars.next();
+                }
+                '"' => quoted = false,
+                _ => cell.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => quoted = true,
+            ',' => row.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = "url,username,password,totp,extra,name,grouping,fav\r\n\
+        https://example.com,alice,\"p,a\"\"ss\",JBSWY3DPEHPK3PXP,\"line one\nline two\",Example,Work\\Email,1\r\n\
+        http://sn,,,,\"NoteType:Credit Card\nName on Card:Alice\nType:Visa\nNumber:4111 1111 1111 1111\nSecurity Code:123\nStart Date:January,2020\nExpiration Date:June,2027\nNotes:first\nsecond\",Visa,(none),0\r\n\
+        http://sn,,,,\"NoteType:Bank Account\nBank Name:Big Bank\nPIN:1234\nNotes:\",Bank,Work\\Email,0\r\n";
+
+    #[test]
+    fn converts_logins_cards_and_other_notes() {
+        let mut out = Conversion::default();
+        convert(EXPORT.as_bytes(), &mut out).unwrap();
+
+        assert_eq!(out.folders.len(), 1);
+        assert_eq!(out.folders[0].name, "Work/Email");
+        let items: Vec<Value> = out
+            .items
+            .iter()
+            .map(|item| serde_json::to_value(item).unwrap())
+            .collect();
+
+        let login = &items[0];
+        assert_eq!(login["type"], 1);
+        assert_eq!(login["favorite"], true);
+        assert_eq!(login["folderId"], out.folders[0].id.as_str());
+        assert_eq!(login["login"]["password"], "p,a\"ss");
+        assert_eq!(login["login"]["totp"], "JBSWY3DPEHPK3PXP");
+        assert_eq!(login["login"]["uris"][0]["uri"], "https://example.com");
+        assert_eq!(login["notes"], "line one\nline two");
+
+        let card = &items[1];
+        assert_eq!(card["type"], 3);
+        assert_eq!(card["folderId"], Value::Null);
+        assert_eq!(card["card"]["number"], "4111111111111111");
+        assert_eq!(card["card"]["brand"], "Visa");
+        assert_eq!(card["card"]["expMonth"], "6");
+        assert_eq!(card["card"]["expYear"], "2027");
+        assert_eq!(card["notes"], "first\nsecond");
+        assert_eq!(card["fields"][0]["name"], "Start Date");
+        assert_eq!(card["fields"][0]["value"], "January,2020");
+
+        let bank = &items[2];
+        assert_eq!(bank["type"], 2);
+        assert_eq!(bank["fields"][1]["name"], "PIN");
+        assert_eq!(bank["fields"][1]["type"], 1);
+        assert_eq!(out.warnings.len(), 1);
+        assert_eq!(out.warnings[0].item, "Bank");
+    }
+
+    #[test]
+    fn rejects_other_csv_files() {
+        let mut out = Conversion::default();
+        assert!(convert(b"a,b\n1,2\n", &mut out).is_err());
+    }
+}
@@ -0,0 +1,387 @@
+//! Converters between Bitwarden's plaintext export and those of other password managers.
+//!
+//! Each converter turns an export into Bitwarden's unencrypted JSON export, which the clients
+//! then import and encrypt with the user's key as usual. The server never holds that key, so it
+//! cannot store the converted items itself; see `handlers::import::convert_import`.
+//! [`export_cxf`] goes the other way, for `handlers::export::export_cxf`.
+//!
+//! Items that cannot be mapped completely are still converted as far as possible, and every
+//! loss is reported as a [`Warning`] naming the item.
+
+mod cxf;
+mod keepass;
+mod lastpass;
+mod onepassword;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::models::cipher::{CipherData, CipherTypeFields};
+
+pub use cxf::{export as export_cxf, Account as CxfAccount};
+
+// Cipher types, as in `models::cipher`.
+const TYPE_LOGIN: i32 = 1;
+const TYPE_SECURE_NOTE: i32 = 2;
+const TYPE_CARD: i32 = 3;
+const TYPE_IDENTITY: i32 = 4;
+
+// Custom field types.
+const FIELD_TEXT: i32 = 0;
+const FIELD_HIDDEN: i32 = 1;
+
+/// A supported export format, named as in the `format` path segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// FIDO Credential Exchange Format header JSON.
+    Cxf,
+    /// 1Password `.1pux` archive, or the `export.data` file inside it.
+    OnePassword,
+    /// KeePass 2 / KeePassXC XML export.
+    KeePass,
+    /// LastPass CSV export.
+    LastPass,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cxf" => Ok(Format::Cxf),
+            "1pux" => Ok(Format::OnePassword),
+            "keepass" => Ok(Format::KeePass),
+            "lastpass" => Ok(Format::LastPass),
+            _ => Err(format!(
+                "Unsupported import format {s:?}; expected cxf, 1pux, keepass or lastpass"
+            )),
+        }
+    }
+}
+
+/// Convert `input` from `format`. Fails only when the export as a whole cannot be read.
+pub fn convert(format: Format, input: &[u8]) -> Result<Conversion, String> {
+    let mut out = Conversion::default();
+    match format {
+        Format::Cxf => cxf::convert(input, &mut out)?,
+        Format::OnePassword => onepassword::convert(input, &mut out)?,
+        Format::KeePass => keepass::convert(input, &mut out)?,
+        Format::LastPass => lastpass::convert(input, &mut out)?,
+    }
+    Ok(out)
+}
+
+/// The converted vault: Bitwarden's unencrypted export plus the warnings raised on the way.
+#[derive(Debug, Default)]
+pub struct Conversion {
+    pub folders: Vec<ExportFolder>,
+    pub items: Vec<ExportItem>,
+    pub warnings: Vec<Warning>,
+    folder_ids: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportFolder {
+    pub id: String,
+    pub name: String,
+}
+
+/// One item of Bitwarden's unencrypted JSON export.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportItem {
+    #[serde(rename = "type")]
+    pub r#type: i32,
+    pub folder_id: Option<String>,
+    pub favorite: bool,
+    #[serde(flatten)]
+    pub data: CipherData,
+}
+
+/// Something about an item that could not be carried over.
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    /// Name of the item, or of the export when the problem is not tied to one item.
+    pub item: String,
+    pub message: String,
+}
+
+impl Conversion {
+    /// The export in Bitwarden's unencrypted JSON format.
+    pub fn export_json(&self) -> Value {
+        json!({
+            "encrypted": false,
+            "folders": self.folders,
+            "items": self.items,
+        })
+    }
+
+    /// The id of the folder called `name`, creating it on first use. Blank names mean no folder.
+    fn folder(&mut self, name: &str) -> Option<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        if let Some(id) = self.folder_ids.get(name) {
+            return Some(id.clone());
+        }
+        let id = Uuid::new_v4().to_string();
+        self.folders.push(ExportFolder {
+            id: id.clone(),
+            name: name.to_string(),
+        });
+        self.folder_ids.insert(name.to_string(), id.clone());
+        Some(id)
+    }
+
+    fn warn(&mut self, item: &str, message: impl Into<String>) {
+        self.warnings.push(Warning {
+            item: item.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn push(&mut self, item: Item) {
+        let mut type_fields = CipherTypeFields {
+            fields: (!item.fields.is_empty()).then_some(Value::Array(item.fields)),
+            ..Default::default()
+        };
+        let r#type = match item.kind {
+            Kind::Login(login) => {
+                type_fields.login = Some(login);
+                TYPE_LOGIN
+            }
+            Kind::SecureNote => {
+                type_fields.secure_note = Some(json!({ "type": 0 }));
+                TYPE_SECURE_NOTE
+            }
+            Kind::Card(card) => {
+                type_fields.card = Some(card);
+                TYPE_CARD
+            }
+            Kind::Identity(identity) => {
+                type_fields.identity = Some(identity);
+                TYPE_IDENTITY
+            }
+        };
+        let name = if item.name.trim().is_empty() {
+            "--".to_string()
+        } else {
+            item.name
+        };
+        self.items.push(ExportItem {
+            r#type,
+            folder_id: item.folder_id,
+            favorite: item.favorite,
+            data: CipherData::new(name, non_empty(item.notes), type_fields),
+        });
+    }
+}
+
+/// An item as the converters build it, before it becomes an [`ExportItem`].
+struct Item {
+    name: String,
+    notes: String,
+    folder_id: Option<String>,
+    favorite: bool,
+    kind: Kind,
+    fields: Vec<Value>,
+}
+
+enum Kind {
+    Login(Value),
+    SecureNote,
+    Card(Value),
+    Identity(Value),
+}
+
+impl Item {
+    fn new(name: impl Into<String>, kind: Kind) -> Self {
+        Self {
+            name: name.into(),
+            notes: String::new(),
+            folder_id: None,
+            favorite: false,
+            kind,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Add a custom field, skipping empty values.
+    fn field(&mut self, name: &str, value: &str, hidden: bool) {
+        if value.is_empty() {
+            return;
+        }
+        self.fields.push(json!({
+            "name": name,
+            "value": value,
+            "type": if hidden { FIELD_HIDDEN } else { FIELD_TEXT },
+            "linkedId": null,
+        }));
+    }
+
+    /// Append a paragraph to the notes.
+    fn note(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        if !self.notes.is_empty() {
+            self.notes.push_str("\n\n");
+        }
+        self.notes.push_str(text);
+    }
+}
+
+fn login(username: &str, password: &str, uris: &[&str], totp: &str) -> Kind {
+    let uris: Vec<Value> = uris
+        .iter()
+        .filter(|uri| !uri.trim().is_empty())
+        .map(|uri| json!({ "uri": fix_uri(uri), "match": null }))
+        .collect();
+    Kind::Login(json!({
+        "username": non_empty(username.to_string()),
+        "password": non_empty(password.to_string()),
+        "totp": non_empty(totp.to_string()),
+        "uris": (!uris.is_empty()).then_some(uris),
+    }))
+}
+
+/// Card fields; `exp_month` is `1`-`12` without padding, as the clients store it.
+fn card(holder: &str, number: &str, code: &str, exp_month: &str, exp_year: &str) -> Kind {
+    let number: String = number.chars().filter(|c| !c.is_whitespace()).collect();
+    Kind::Card(json!({
+        "cardholderName": non_empty(holder.to_string()),
+        "brand": card_brand(&number),
+        "number": non_empty(number),
+        "code": non_empty(code.to_string()),
+        "expMonth": non_empty(exp_month.trim_start_matches('0').to_string()),
+        "expYear": non_empty(exp_year.to_string()),
+    }))
+}
+
+/// Identity fields are given as `(client field, value)` pairs; unknown names are ignored.
+fn identity(values: &[(&str, &str)]) -> Kind {
+    let mut identity = serde_json::Map::new();
+    for key in IDENTITY_FIELDS {
+        let value = values
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| non_empty(v.to_string()));
+        identity.insert(key.to_string(), value.map_or(Value::Null, Value::String));
+    }
+    Kind::Identity(Value::Object(identity))
+}
+
+const IDENTITY_FIELDS: &[&str] = &[
+    "title",
+    "firstName",
+    "middleName",
+    "lastName",
+    "address1",
+    "address2",
+    "address3",
+    "city",
+    "state",
+    "postalCode",
+    "country",
+    "company",
+    "email",
+    "phone",
+    "ssn",
+    "username",
+    "passportNumber",
+    "licenseNumber",
+];
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.trim().is_empty()).then_some(value)
+}
+
+/// Give bare host names a scheme, as the clients do on import.
+fn fix_uri(uri: &str) -> String {
+    let uri = uri.trim();
+    if uri.contains("://") {
+        uri.to_string()
+    } else {
+        format!("http://{uri}")
+    }
+}
+
+/// The brand the clients would detect for a card number.
+fn card_brand(number: &str) -> Option<&'static str> {
+    let prefix = |len: usize| number.get(..len).and_then(|p| p.parse::<u32>().ok());
+    if number.starts_with('4') {
+        Some("Visa")
+    } else if matches!(prefix(2), Some(51..=55)) || matches!(prefix(4), Some(2221..=2720)) {
+        Some("Mastercard")
+    } else if matches!(prefix(2), Some(34 | 37)) {
+        Some("Amex")
+    } else if number.starts_with("6011") || number.starts_with("65") {
+        Some("Discover")
+    } else if matches!(prefix(2), Some(36 | 38 | 39)) || matches!(prefix(3), Some(300..=305)) {
+        Some("Diners Club")
+    } else if matches!(prefix(4), Some(3528..=3589)) {
+        Some("JCB")
+    } else {
+        None
+    }
+}
+
+/// `1`-`12` for an English month name or number, as used in expiry dates.
+fn month_number(month: &str) -> Option<u32> {
+    let month = month.trim().to_ascii_lowercase();
+    if let Ok(n) = month.parse::<u32>() {
+        return (1..=12).contains(&n).then_some(n);
+    }
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let prefix = month.get(..3)?;
+    MONTHS
+        .iter()
+        .position(|m| *m == prefix)
+        .map(|i| i as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_parse_from_path_names() {
+        assert_eq!("cxf".parse(), Ok(Format::Cxf));
+        assert_eq!("1pux".parse(), Ok(Format::OnePassword));
+        assert_eq!("keepass".parse(), Ok(Format::KeePass));
+        assert_eq!("lastpass".parse(), Ok(Format::LastPass));
+        assert!("csv".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn folders_are_created_once_per_name() {
+        let mut out = Conversion::default();
+        let a = out.folder("Work");
+        assert_eq!(out.folder(" Work "), a);
+        assert_eq!(out.folder(""), None);
+        assert_eq!(out.folders.len(), 1);
+    }
+
+    #[test]
+    fn card_brands_and_uris() {
+        assert_eq!(card_brand("4111111111111111"), Some("Visa"));
+        assert_eq!(card_brand("5500000000000004"), Some("Mastercard"));
+        assert_eq!(card_brand("378282246310005"), Some("Amex"));
+        assert_eq!(card_brand("1234"), None);
+        assert_eq!(fix_uri("example.com"), "http://example.com");
+        assert_eq!(
+            fix_uri("androidapp://com.example"),
+            "androidapp://com.example"
+        );
+        assert_eq!(month_number("June"), Some(6));
+        assert_eq!(month_number("07"), Some(7));
+    }
+}
@@ -0,0 +1,389 @@
+//! 1Password `.1pux` export: a zip archive whose `export.data` holds every account, vault and
+//! item as JSON. The `export.data` file on its own is accepted too.
+//!
+//! Vaults become folders. Logins, passwords, credit cards, identities and secure notes map to
+//! their Bitwarden types; every other category is kept as a secure note with custom fields.
+
+use std::io::{Cursor, Read};
+
+use serde_json::Value;
+
+use super::{card, identity, login, Conversion, Item, Kind};
+
+const EXPORT_DATA: &str = "export.data";
+/// Largest `export.data` read from an archive, so a small upload cannot inflate without bound.
+const MAX_EXPORT_DATA_BYTES: u64 = 64 * 1024 * 1024;
+
+const CATEGORY_LOGIN: &str = "001";
+const CATEGORY_CREDIT_CARD: &str = "002";
+const CATEGORY_SECURE_NOTE: &str = "003";
+const CATEGORY_IDENTITY: &str = "004";
+const CATEGORY_PASSWORD: &str = "005";
+
+/// 1Password identity field ids and the identity fields they map to.
+const IDENTITY_FIELDS: &[(&str, &str)] = &[
+    ("firstname", "firstName"),
+    ("initial", "middleName"),
+    ("lastname", "lastName"),
+    ("company", "company"),
+    ("email", "email"),
+    ("defphone", "phone"),
+    ("username", "username"),
+];
+
+pub(super) fn convert(input: &[u8], out: &mut Conversion) -> Result<(), String> {
+    let data = if input.starts_with(b"PK") {
+        read_export_data(input)?
+    } else {
+        input.to_vec()
+    };
+    let export: Value = serde_json::from_slice(&data)
+        .map_err(|e| format!("The 1Password export cannot be read: {e}"))?;
+    let accounts = export["accounts"]
+        .as_array()
+        .ok_or("Not a 1Password export: missing accounts")?;
+
+    for account in accounts {
+        for vault in account["vaults"].as_array().into_iter().flatten() {
+            let folder_id = out.folder(vault["attrs"]["name"].as_str().unwrap_or(""));
+            for item in vault["items"].as_array().into_iter().flatten() {
+                convert_item(item, folder_id.clone(), out);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_export_data(input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(input))
+        .map_err(|e| format!("The 1PUX archive cannot be read: {e}"))?;
+    let file = archive
+        .by_name(EXPORT_DATA)
+        .map_err(|_| "Not a 1PUX archive: missing export.data".to_string())?;
+    let mut data = Vec::new();
+    file.take(MAX_EXPORT_DATA_BYTES + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("The 1PUX archive cannot be read: {e}"))?;
+    if data.len() as u64 > MAX_EXPORT_DATA_BYTES {
+        return Err("The 1PUX export is too large".to_string());
+    }
+    Ok(data)
+}
+
+fn convert_item(item: &Value, folder_id: Option<String>, out: &mut Conversion) {
+    let overview = &item["overview"];
+    let details = &item["details"];
+    let name = overview["title"].as_str().unwrap_or("");
+
+    match item["state"].as_str() {
+        Some("archived") => {
+            out.warn(name, "Archived items are not imported");
+            return;
+        }
+        Some("trashed") | Some("deleted") => return,
+        _ => {}
+    }
+
+    let sections = details["sections"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    let section_fields: Vec<&Value> = sections
+        .iter()
+        .flat_map(|section| section["fields"].as_array().into_iter().flatten())
+        .collect();
+    let by_id = |id: &str| {
+        section_fields
+            .iter()
+            .find(|field| field["id"] == id)
+            .map(|field| field_value(&field["value"]))
+            .unwrap_or_default()
+    };
+
+    let category = item["categoryUuid"].as_str().unwrap_or("");
+    let mut mapped: Vec<&str> = Vec::new();
+    let mut totps: Vec<String> = section_fields
+        .iter()
+        .filter_map(|field| field["value"]["totp"].as_str())
+        .filter(|totp| !totp.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let kind = match category {
+        CATEGORY_LOGIN | CATEGORY_PASSWORD => {
+            let login_fields = details["loginFields"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or(&[]);
+            let designated = |designation: &str| {
+                login_fields
+                    .iter()
+                    .find(|field| field["designation"] == designation)
+                    .and_then(|field| field["value"].as_str())
+                    .unwrap_or("")
+            };
+            let password = match designated("password") {
+                "" => details["password"].as_str().unwrap_or(""),
+                password => password,
+            };
+            let mut uris: Vec<&str> = overview["urls"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|url| url["url"].as_str())
+                .collect();
+            if uris.is_empty() {
+                uris.extend(overview["url"].as_str());
+            }
+            let totp = if totps.is_empty() {
+                String::new()
+            } else {
+                totps.remove(0)
+            };
+            login(designated("username"), password, &uris, &totp)
+        }
+        CATEGORY_CREDIT_CARD => {
+            mapped = vec!["cardholder", "ccnum", "cvv", "expiry", "type"];
+            let (month, year) = month_year(section_fields.iter().find(|f| f["id"] == "expiry"));
+            card(
+                &by_id("cardholder"),
+                &by_id("ccnum"),
+                &by_id("cvv"),
+                &month,
+                &year,
+            )
+        }
+        CATEGORY_IDENTITY => {
+            mapped = IDENTITY_FIELDS.iter().map(|(id, _)| *id).collect();
+            mapped.push("address");
+            let mut values: Vec<(&str, String)> = IDENTITY_FIELDS
+                .iter()
+                .map(|(id, client)| (*client, by_id(id)))
+                .collect();
+            if let Some(address) = section_fields
+                .iter()
+                .find(|f| f["id"] == "address")
+                .map(|f| &f["value"]["address"])
+            {
+                for (key, client) in [
+                    ("street", "address1"),
+                    ("city", "city"),
+                    ("state", "state"),
+                    ("zip", "postalCode"),
+                    ("country", "country"),
+                ] {
+                    values.push((client, address[key].as_str().unwrap_or("").to_string()));
+                }
+            }
+            let values: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            identity(&values)
+        }
+        CATEGORY_SECURE_NOTE => Kind::SecureNote,
+        other => {
+            out.warn(
+                name,
+                format!(
+                    "1Password category {other} is imported as a secure note with custom fields"
+                ),
+            );
+            Kind::SecureNote
+        }
+    };
+
+    let mut item_out = Item::new(name, kind);
+    item_out.folder_id = folder_id;
+    item_out.favorite = item["favIndex"].as_i64().unwrap_or(0) > 0;
+    item_out.note(details["notesPlain"].as_str().unwrap_or(""));
+
+    if category == CATEGORY_LOGIN || category == CATEGORY_PASSWORD {
+        for field in details["loginFields"].as_array().into_iter().flatten() {
+            let designation = field["designation"].as_str().unwrap_or("");
+            if designation == "username" || designation == "password" {
+                continue;
+            }
+            let hidden = field["fieldType"] == "P";
+            item_out.field(
+                field["name"].as_str().unwrap_or(""),
+                field["value"].as_str().unwrap_or(""),
+                hidden,
+            );
+        }
+    }
+    for field in &section_fields {
+        let id = field["id"].as_str().unwrap_or("");
+        if mapped.contains(&id) {
+            continue;
+        }
+        let value = &field["value"];
+        if let Some(totp) = value["totp"].as_str() {
+            // Skip the code already used as the login's TOTP.
+            if !totps.iter().any(|t| t == totp) {
+                continue;
+            }
+        }
+        if value.get("file").is_some() {
+            out.warn(name, "Files are not imported");
+            continue;
+        }
+        let title = field["title"]
+            .as_str()
+            .filter(|t| !t.is_empty())
+            .unwrap_or(id);
+        let hidden = value.get("concealed").is_some() || value.get("totp").is_some();
+        item_out.field(title, &field_value(value), hidden);
+    }
+
+    let history = details["passwordHistory"].as_array().map_or(0, Vec::len);
+    if history > 0 {
+        out.warn(
+            name,
+            format!("{history} previous password(s) were not imported"),
+        );
+    }
+    out.push(item_out);
+}
+
+/// A section field value as text. Values are objects keyed by their kind.
+fn field_value(value: &Value) -> String {
+    let Some((kind, inner)) = value.as_object().and_then(|v| v.iter().next()) else {
+        return String::new();
+    };
+    match (kind.as_str(), inner) {
+        (_, Value::String(s)) => s.clone(),
+        ("email", email) => email["email_address"].as_str().unwrap_or("").to_string(),
+        ("address", address) => ["street", "city", "state", "zip", "country"]
+            .iter()
+            .filter_map(|key| address[key].as_str())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        ("date", Value::Number(n)) => n
+            .as_i64()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        (_, Value::Number(n)) => n.to_string(),
+        (_, Value::Bool(b)) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// `{"monthYear": 202712}` as month and year numbers.
+fn month_year(field: Option<&&Value>) -> (String, String) {
+    let Some(value) = field.and_then(|f| f["value"]["monthYear"].as_u64()) else {
+        return (String::new(), String::new());
+    };
+    ((value % 100).to_string(), (value / 100).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn export() -> Value {
+        json!({
+            "accounts": [{
+                "attrs": { "name": "Family" },
+                "vaults": [{
+                    "attrs": { "name": "Shared" },
+                    "items": [
+                        {
+                            "uuid": "a", "favIndex": 1, "state": "active", "categoryUuid": "001",
+                            "overview": { "title": "Example", "urls": [{ "url": "https://example.com" }] },
+                            "details": {
+                                "loginFields": [
+                                    { "value": "alice", "name": "email", "fieldType": "E", "designation": "username" },
+                                    { "value": "secret", "name": "password", "fieldType": "P", "designation": "password" }
+                                ],
+                                "notesPlain": "hello",
+                                "sections": [{ "title": "", "fields": [
+                                    { "title": "one-time password", "id": "otp", "value": { "totp": "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP" } },
+                                    { "title": "recovery", "id": "r", "value": { "concealed": "abc" } }
+                                ]}],
+                                "passwordHistory": [{ "value": "old", "time": 1 }]
+                            }
+                        },
+                        {
+                            "uuid": "b", "favIndex": 0, "state": "active", "categoryUuid": "002",
+                            "overview": { "title": "Visa" },
+                            "details": { "sections": [{ "title": "", "fields": [
+                                { "title": "cardholder name", "id": "cardholder", "value": { "string": "Alice" } },
+                                { "title": "number", "id": "ccnum", "value": { "creditCardNumber": "4111111111111111" } },
+                                { "title": "verification number", "id": "cvv", "value": { "concealed": "123" } },
+                                { "title": "expiry date", "id": "expiry", "value": { "monthYear": 202712 } },
+                                { "title": "issuing bank", "id": "bank", "value": { "string": "Big Bank" } }
+                            ]}]}
+                        },
+                        {
+                            "uuid": "c", "state": "archived", "categoryUuid": "003",
+                            "overview": { "title": "Old" }, "details": {}
+                        },
+                        {
+                            "uuid": "d", "state": "active", "categoryUuid": "110",
+                            "overview": { "title": "Server" },
+                            "details": { "sections": [{ "title": "", "fields": [
+                                { "title": "host", "id": "url", "value": { "string": "10.0.0.1" } }
+                            ]}]}
+                        }
+                    ]
+                }]
+            }]
+        })
+    }
+
+    #[test]
+    fn converts_items_by_category() {
+        let mut out = Conversion::default();
+        convert(export().to_string().as_bytes(), &mut out).unwrap();
+
+        assert_eq!(out.folders.len(), 1);
+        assert_eq!(out.folders[0].name, "Shared");
+        let items: Vec<Value> = out
+            .items
+            .iter()
+            .map(|item| serde_json::to_value(item).unwrap())
+            .collect();
+        assert_eq!(items.len(), 3);
+
+        let login = &items[0];
+        assert_eq!(login["type"], 1);
+        assert_eq!(login["favorite"], true);
+        assert_eq!(login["login"]["username"], "alice");
+        assert_eq!(login["login"]["password"], "secret");
+        assert_eq!(
+            login["login"]["totp"],
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP"
+        );
+        assert_eq!(login["fields"].as_array().unwrap().len(), 1);
+        assert_eq!(login["fields"][0]["name"], "recovery");
+        assert_eq!(login["fields"][0]["type"], 1);
+
+        let card = &items[1];
+        assert_eq!(card["card"]["cardholderName"], "Alice");
+        assert_eq!(card["card"]["expMonth"], "12");
+        assert_eq!(card["card"]["expYear"], "2027");
+        assert_eq!(card["fields"][0]["value"], "Big Bank");
+
+        assert_eq!(items[2]["type"], 2);
+        assert_eq!(items[2]["fields"][0]["value"], "10.0.0.1");
+
+        let warnings: Vec<&str> = out.warnings.iter().map(|w| w.item.as_str()).collect();
+        assert_eq!(warnings, ["Example", "Old", "Server"]);
+    }
+
+    #[test]
+    fn reads_export_data_from_the_archive() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        archive
+            .start_file(EXPORT_DATA, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(export().to_string().as_bytes()).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+
+        let mut out = Conversion::default();
+        convert(&bytes, &mut out).unwrap();
+        assert_eq!(out.items.len(), 3);
+    }
+}
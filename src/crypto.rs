@@ -23,6 +23,11 @@ pub const PASSWORD_SALT_LENGTH: usize = 64;
 /// Derived key length in bits
 const KEY_LENGTH_BITS: u32 = 256;
 
+/// Fills `buf` from the platform CSPRNG (`crypto.getRandomValues` in the Worker).
+fn fill_random(buf: &mut [u8], what: &str) -> Result<(), AppError> {
+    getrandom::fill(buf).map_err(|e| AppError::Crypto(format!("Failed to generate {what}: {e}")))
+}
+
 /// Gets the Crypto interface from the global scope.
 /// Works in Cloudflare Workers by using js_sys::Reflect instead of WorkerGlobalScope.
 fn get_crypto() -> Result<Crypto, AppError> {
@@ -64,6 +69,7 @@ pub fn pbkdf2_sha256(
 ///
 /// Workers WebCrypto limits PBKDF2 to <= 100,000 iterations.
 /// Use for Send passwords and other low-iteration use cases.
+#[cfg(not(test))]
 pub async fn webcrypto_pbkdf2_sha256(
     password: &[u8],
     salt: &[u8],
@@ -110,15 +116,23 @@ pub async fn webcrypto_pbkdf2_sha256(
     Ok(Uint8Array::new(&derived).to_vec())
 }
 
+/// Unit tests have no Web Crypto, so they derive the same bits in pure Rust.
+#[cfg(test)]
+pub async fn webcrypto_pbkdf2_sha256(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    key_length_bits: u32,
+) -> Result<Vec<u8>, AppError> {
+    pbkdf2_sha256(password, salt, iterations, key_length_bits)
+}
+
 /// Generates a cryptographically secure random salt.
 pub fn generate_salt() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let salt = Uint8Array::new_with_length(PASSWORD_SALT_LENGTH as u32);
-    crypto
-        .get_random_values_with_array_buffer_view(&salt)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate random salt: {:?}", e)))?;
+    let mut salt = [0u8; PASSWORD_SALT_LENGTH];
+    fill_random(&mut salt, "random salt")?;
 
-    Ok(BASE64.encode(salt.to_vec()))
+    Ok(BASE64.encode(salt))
 }
 
 /// Hashes the client-provided master password hash with server-side PBKDF2.
@@ -177,16 +191,14 @@ pub fn base32_encode(data: &[u8]) -> String {
 /// Generates a random TOTP secret (20 bytes = 160 bits).
 /// Returns the Base32 encoded secret.
 pub fn generate_totp_secret() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let secret = Uint8Array::new_with_length(20);
-    crypto
-        .get_random_values_with_array_buffer_view(&secret)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate TOTP secret: {:?}", e)))?;
+    let mut secret = [0u8; 20];
+    fill_random(&mut secret, "TOTP secret")?;
 
-    Ok(base32_encode(&secret.to_vec()))
+    Ok(base32_encode(&secret))
 }
 
 /// Computes HMAC-SHA1 using Web Crypto API.
+#[cfg(not(test))]
 async fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>, AppError> {
     let subtle = subtle_crypto()?;
 
@@ -230,6 +242,17 @@ async fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>, AppError> {
     Ok(Uint8Array::new(&signature).to_vec())
 }
 
+/// Native HMAC-SHA1 for unit tests, which have no Web Crypto.
+#[cfg(test)]
+async fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>, AppError> {
+    use hmac::{KeyInit, Mac};
+
+    let mut mac = hmac::Hmac::<sha1::Sha1>::new_from_slice(key)
+        .map_err(|e| AppError::Crypto(format!("HMAC key rejected: {e}")))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
 /// Generates a TOTP code for the given secret and time.
 ///
 /// # Arguments
@@ -307,13 +330,110 @@ pub async fn validate_totp(
 
 /// Generates a recovery code (20 characters, Base32 encoded).
 pub fn generate_recovery_code() -> Result<String, AppError> {
-    let crypto = get_crypto()?;
-    let bytes = Uint8Array::new_with_length(20);
-    crypto
-        .get_random_values_with_array_buffer_view(&bytes)
-        .map_err(|e| AppError::Crypto(format!("Failed to generate recovery code: {:?}", e)))?;
+    let mut bytes = [0u8; 20];
+    fill_random(&mut bytes, "recovery code")?;
+
+    Ok(base32_encode(&bytes))
+}
+
+/// Generates a numeric one-time code of `digits` digits (e.g. for email 2FA).
+pub fn generate_numeric_token(digits: u32) -> Result<String, AppError> {
+    let bound = 10u32.pow(digits);
+    // Reject values from the incomplete last range to keep the code uniform.
+    let zone = u32::MAX - (u32::MAX % bound);
+    loop {
+        let mut buf = [0u8; 4];
+        fill_random(&mut buf, "token")?;
+        let value = u32::from_le_bytes(buf);
+        if value < zone {
+            return Ok(format!(
+                "{:0width$}",
+                value % bound,
+                width = digits as usize
+            ));
+        }
+    }
+}
+
+/// Generates a personal API key (30 alphanumeric characters).
+pub fn generate_api_key() -> Result<String, AppError> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    const LENGTH: usize = 30;
+    // Reject bytes from the incomplete last range to keep every character equally likely.
+    let zone = 256 - (256 % ALPHABET.len());
+
+    let mut key = String::with_capacity(LENGTH);
+    while key.len() < LENGTH {
+        let mut bytes = [0u8; LENGTH];
+        fill_random(&mut bytes, "API key")?;
+        for byte in bytes {
+            if key.len() < LENGTH && (byte as usize) < zone {
+                key.push(ALPHABET[byte as usize % ALPHABET.len()] as char);
+            }
+        }
+    }
+
+    Ok(key)
+}
+
+/// Generates `len` cryptographically secure random bytes.
+pub fn random_bytes(len: usize) -> Result<Vec<u8>, AppError> {
+    let mut bytes = vec![0u8; len];
+    fill_random(&mut bytes, "random bytes")?;
+
+    Ok(bytes)
+}
+
+async fn import_aes_gcm_key(key: &[u8], usage: &str) -> Result<CryptoKey, AppError> {
+    let subtle = subtle_crypto()?;
+    let key_array = Uint8Array::new_from_slice(key);
+    let crypto_key = JsFuture::from(
+        subtle
+            .import_key_with_str(
+                "raw",
+                key_array.as_ref(),
+                "AES-GCM",
+                false,
+                &js_sys::Array::of1(&JsValue::from_str(usage)),
+            )
+            .map_err(|e| AppError::Crypto(format!("AES-GCM import_key failed: {e:?}")))?,
+    )
+    .await
+    .map_err(|e| AppError::Crypto(format!("AES-GCM import_key await failed: {e:?}")))?;
+
+    Ok(CryptoKey::from(crypto_key))
+}
+
+/// Encrypts `data` with AES-256-GCM via Web Crypto. The 16-byte tag is appended to the output.
+pub async fn aes_gcm_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let crypto_key = import_aes_gcm_key(key, "encrypt").await?;
+    let params =
+        web_sys::AesGcmParams::new_with_u8_array("AES-GCM", &Uint8Array::new_from_slice(iv));
+    let encrypted = JsFuture::from(
+        subtle_crypto()?
+            .encrypt_with_object_and_u8_array(params.as_ref(), &crypto_key, data)
+            .map_err(|e| AppError::Crypto(format!("AES-GCM encrypt failed: {e:?}")))?,
+    )
+    .await
+    .map_err(|e| AppError::Crypto(format!("AES-GCM encrypt await failed: {e:?}")))?;
+
+    Ok(Uint8Array::new(&encrypted).to_vec())
+}
+
+/// Decrypts the output of [`aes_gcm_encrypt`]; fails if the key is wrong or the data was modified.
+pub async fn aes_gcm_decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let crypto_key = import_aes_gcm_key(key, "decrypt").await?;
+    let params =
+        web_sys::AesGcmParams::new_with_u8_array("AES-GCM", &Uint8Array::new_from_slice(iv));
+    let decrypted = JsFuture::from(
+        subtle_crypto()?
+            .decrypt_with_object_and_u8_array(params.as_ref(), &crypto_key, data)
+            .map_err(|e| AppError::Crypto(format!("AES-GCM decrypt failed: {e:?}")))?,
+    )
+    .await
+    .map_err(|_| AppError::Crypto("AES-GCM decryption failed".to_string()))?;
 
-    Ok(base32_encode(&bytes.to_vec()))
+    Ok(Uint8Array::new(&decrypted).to_vec())
 }
 
 /// Constant-time string comparison wrapper.
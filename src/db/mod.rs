@@ -1,33 +1,63 @@
 use crate::d1_query;
+use crate::env::Env;
 use crate::error::AppError;
 use chrono::Utc;
-use worker::{D1Database, D1DatabaseSession, D1PreparedStatement, D1Result, Env, Error};
+use serde::Serialize;
+use worker::Error;
+#[cfg(not(test))]
+use worker::{D1Database, D1DatabaseSession};
+
+#[cfg(test)]
+mod sqlite;
+
+/// A prepared statement, bound with [`BindValue`]s.
+#[cfg(not(test))]
+pub use worker::D1PreparedStatement as Statement;
+/// The outcome of running a statement.
+#[cfg(not(test))]
+pub use worker::D1Result as QueryResult;
+/// A value bound to a statement parameter.
+#[cfg(not(test))]
+pub type BindValue = worker::wasm_bindgen::JsValue;
+
+#[cfg(test)]
+pub use sqlite::{BindValue, Database, QueryResult, Statement};
 
 /// Unified database handle that wraps either a raw `D1Database` or a `D1DatabaseSession`.
 ///
 /// When read replication is enabled, all queries go through a session to benefit from
-/// sequential consistency and reduced read latency via global replicas.
+/// sequential consistency and reduced read latency via global replicas. Unit tests run
+/// against an in-memory SQLite database instead (see `sqlite`).
 pub enum Db {
+    #[cfg(not(test))]
     #[allow(dead_code)]
     Raw(D1Database),
+    #[cfg(not(test))]
     Session(D1DatabaseSession),
+    #[cfg(test)]
+    Sqlite(Database),
 }
 
 impl Db {
-    pub fn prepare<T: Into<String>>(&self, query: T) -> D1PreparedStatement {
+    pub fn prepare<T: Into<String>>(&self, query: T) -> Statement {
         match self {
+            #[cfg(not(test))]
             Db::Raw(db) => db.prepare(query),
+            #[cfg(not(test))]
             Db::Session(s) => s.prepare(query),
+            #[cfg(test)]
+            Db::Sqlite(db) => db.prepare(query),
         }
     }
 
-    pub async fn batch(
-        &self,
-        statements: Vec<D1PreparedStatement>,
-    ) -> Result<Vec<D1Result>, Error> {
+    pub async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<QueryResult>, Error> {
         match self {
+            #[cfg(not(test))]
             Db::Raw(db) => db.batch(statements).await,
+            #[cfg(not(test))]
             Db::Session(s) => s.batch(statements).await,
+            #[cfg(test)]
+            Db::Sqlite(db) => db.batch(statements).await,
         }
     }
 }
@@ -37,6 +67,7 @@ impl Db {
 /// Uses `first-primary` so the first query hits the primary database, ensuring freshness.
 /// This prevents stale reads when another device fetches data immediately after a write
 /// triggered a WebSocket notification.
+#[cfg(not(test))]
 pub fn get_db(env: &Env) -> Result<Db, AppError> {
     let raw = env.d1("vault1").map_err(AppError::Worker)?;
     let session = raw
@@ -49,6 +80,7 @@ pub fn get_db(env: &Env) -> Result<Db, AppError> {
 ///
 /// Uses `first-unconstrained` so the first read may hit any replica (lowest latency).
 /// Suitable when there is no preceding write that must be immediately visible.
+#[cfg(not(test))]
 pub fn get_db_unconstrained(env: &Env) -> Result<Db, AppError> {
     let raw = env.d1("vault1").map_err(AppError::Worker)?;
     let session = raw.with_session(None).map_err(AppError::Worker)?;
@@ -56,11 +88,67 @@ pub fn get_db_unconstrained(env: &Env) -> Result<Db, AppError> {
 }
 
 /// Obtain a raw (non-session) database handle — only for cases that cannot use sessions.
+#[cfg(not(test))]
 #[allow(dead_code)]
 pub fn get_db_raw(env: &Env) -> Result<D1Database, AppError> {
     env.d1("vault1").map_err(AppError::Worker)
 }
 
+#[cfg(test)]
+pub fn get_db(env: &Env) -> Result<Db, AppError> {
+    Ok(Db::Sqlite(env.database()))
+}
+
+#[cfg(test)]
+pub fn get_db_unconstrained(env: &Env) -> Result<Db, AppError> {
+    get_db(env)
+}
+
+/// Convert a value for [`Statement::bind`], the way `d1_query!` binds its arguments.
+#[cfg(not(test))]
+pub fn bind_value<T: Serialize + ?Sized>(value: &T) -> Result<BindValue, Error> {
+    let serializer =
+        worker::d1::serde_wasm_bindgen::Serializer::new().serialize_missing_as_null(true);
+    value
+        .serialize(&serializer)
+        .map_err(|e| Error::Internal(e.into()))
+}
+
+#[cfg(test)]
+pub fn bind_value<T: Serialize + ?Sized>(value: &T) -> Result<BindValue, Error> {
+    serde_json::to_value(value).map_err(|e| Error::RustError(e.to_string()))
+}
+
+/// Call `f` with the first column of every row, which must be a string, without
+/// deserializing the rows. Returns the number of rows.
+#[cfg(not(test))]
+pub async fn for_each_first_column(
+    statement: Statement,
+    mut f: impl FnMut(&str),
+) -> Result<usize, Error> {
+    use worker::js_sys::Array;
+    use worker::wasm_bindgen::JsCast;
+
+    // Each row is a JS array [column0, column1, ...].
+    let rows = statement.raw_js_value().await?;
+    for row in &rows {
+        let value = row
+            .dyn_ref::<Array>()
+            .and_then(|columns| columns.get(0).as_string())
+            .ok_or_else(|| Error::RustError("Expected a string column".to_string()))?;
+        f(&value);
+    }
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+pub async fn for_each_first_column(
+    statement: Statement,
+    f: impl FnMut(&str),
+) -> Result<usize, Error> {
+    statement.for_each_first_column(f)
+}
+
 /// Map D1 JSON parsing errors to 400 while leaving other errors untouched.
 pub fn map_d1_json_error(err: Error) -> AppError {
     let msg = err.to_string();
@@ -94,7 +182,7 @@ pub async fn touch_user_updated_at(db: &Db, user_id: &str, now: &str) -> Result<
 /// Execute D1 statements in batches, allowing batch_size 0 to run everything at once.
 pub async fn execute_in_batches(
     db: &Db,
-    statements: Vec<D1PreparedStatement>,
+    statements: Vec<Statement>,
     batch_size: usize,
 ) -> Result<(), AppError> {
     if statements.is_empty() {
@@ -119,15 +207,10 @@ macro_rules! d1_query {
         $db.prepare($query)
     };
     ($db:expr, $query:expr, $($args:expr),* $(,)?) => {{
-        || -> worker::Result<worker::d1::D1PreparedStatement> {
+        || -> worker::Result<$crate::db::Statement> {
             let prepared = $db.prepare($query);
-            let serializer = worker::d1::serde_wasm_bindgen::Serializer::new()
-                .serialize_missing_as_null(true);
-            let bindings = &[$(
-                ::serde::ser::Serialize::serialize(&$args, &serializer)
-                    .map_err(|e| worker::Error::Internal(e.into()))?
-            ),*];
-            worker::d1::D1PreparedStatement::bind(prepared, bindings)
+            let bindings = &[$($crate::db::bind_value(&$args)?),*];
+            prepared.bind(bindings)
         }()
     }};
 }
@@ -0,0 +1,194 @@
+//! An in-memory SQLite database with the slice of the D1 API the handlers use, so unit tests
+//! can run them natively. Rows and bindings are converted the way D1 converts them to and
+//! from JavaScript: integers and reals become numbers, booleans become 1 or 0, and a batch
+//! runs as one transaction.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+use worker::Error;
+
+pub type BindValue = Value;
+
+#[derive(Clone)]
+pub struct Database(Arc<Mutex<Connection>>);
+
+impl Database {
+    pub fn open_in_memory() -> Self {
+        let conn = Connection::open_in_memory().expect("SQLite opens an in-memory database");
+        // D1 enforces foreign keys.
+        conn.pragma_update(None, "foreign_keys", true)
+            .expect("SQLite supports foreign keys");
+        Self(Arc::new(Mutex::new(conn)))
+    }
+
+    pub fn prepare<T: Into<String>>(&self, query: T) -> Statement {
+        Statement {
+            database: self.clone(),
+            sql: query.into(),
+            params: Vec::new(),
+        }
+    }
+
+    pub async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<QueryResult>, Error> {
+        let mut conn = self.lock();
+        let tx = conn.transaction().map_err(sql_error)?;
+        let results = statements
+            .iter()
+            .map(|statement| statement.execute(&tx))
+            .collect::<Result<Vec<_>, _>>()?;
+        tx.commit().map_err(sql_error)?;
+        Ok(results)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Clone)]
+pub struct Statement {
+    database: Database,
+    sql: String,
+    params: Vec<SqlValue>,
+}
+
+impl Statement {
+    pub fn bind(mut self, values: &[BindValue]) -> Result<Self, Error> {
+        self.params = values.iter().map(to_sql).collect();
+        Ok(self)
+    }
+
+    pub async fn first<T: DeserializeOwned>(
+        &self,
+        column: Option<&str>,
+    ) -> Result<Option<T>, Error> {
+        let result = self.execute(&self.database.lock())?;
+        let Some(row) = result.rows.into_iter().next() else {
+            return Ok(None);
+        };
+        let value = match column {
+            Some(column) => row
+                .get(column)
+                .cloned()
+                .ok_or_else(|| Error::RustError(format!("D1_COLUMN_NOTFOUND: {column}")))?,
+            None => row,
+        };
+        serde_json::from_value(value).map_err(json_error)
+    }
+
+    pub async fn all(&self) -> Result<QueryResult, Error> {
+        self.execute(&self.database.lock())
+    }
+
+    pub async fn run(&self) -> Result<QueryResult, Error> {
+        self.all().await
+    }
+
+    pub(super) fn for_each_first_column(&self, mut f: impl FnMut(&str)) -> Result<usize, Error> {
+        let conn = self.database.lock();
+        let mut statement = conn.prepare(&self.sql).map_err(sql_error)?;
+        let mut query = statement
+            .query(rusqlite::params_from_iter(&self.params))
+            .map_err(sql_error)?;
+        let mut count = 0;
+        while let Some(row) = query.next().map_err(sql_error)? {
+            let ValueRef::Text(value) = row.get_ref(0).map_err(sql_error)? else {
+                return Err(Error::RustError("Expected a string column".to_string()));
+            };
+            f(&String::from_utf8_lossy(value));
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn execute(&self, conn: &Connection) -> Result<QueryResult, Error> {
+        let mut statement = conn.prepare(&self.sql).map_err(sql_error)?;
+        let names: Vec<String> = statement
+            .column_names()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut rows = Vec::new();
+        let mut query = statement
+            .query(rusqlite::params_from_iter(&self.params))
+            .map_err(sql_error)?;
+        while let Some(row) = query.next().map_err(sql_error)? {
+            let mut object = Map::new();
+            for (index, name) in names.iter().enumerate() {
+                let value = row.get_ref(index).map_err(sql_error)?;
+                object.insert(name.clone(), from_sql(value));
+            }
+            rows.push(Value::Object(object));
+        }
+        drop(query);
+        let changes = if statement.readonly() {
+            0
+        } else {
+            conn.changes() as usize
+        };
+        Ok(QueryResult { rows, changes })
+    }
+}
+
+pub struct QueryResult {
+    rows: Vec<Value>,
+    changes: usize,
+}
+
+pub struct QueryMeta {
+    pub changes: Option<usize>,
+}
+
+impl QueryResult {
+    pub fn results<T: DeserializeOwned>(&self) -> Result<Vec<T>, Error> {
+        self.rows
+            .iter()
+            .map(|row| serde_json::from_value(row.clone()).map_err(json_error))
+            .collect()
+    }
+
+    pub fn meta(&self) -> Result<Option<QueryMeta>, Error> {
+        Ok(Some(QueryMeta {
+            changes: Some(self.changes),
+        }))
+    }
+}
+
+fn to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => n
+            .as_i64()
+            .map(SqlValue::Integer)
+            .unwrap_or_else(|| SqlValue::Real(n.as_f64().unwrap_or_default())),
+        Value::String(s) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn from_sql(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        // JavaScript has one number type, so D1 hands out whole reals as integers.
+        ValueRef::Real(f) if f.fract() == 0.0 && f.abs() < 2f64.powi(53) => Value::from(f as i64),
+        ValueRef::Real(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        ValueRef::Text(t) => Value::String(String::from_utf8_lossy(t).into_owned()),
+        ValueRef::Blob(b) => Value::from(b.to_vec()),
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> Error {
+    Error::RustError(format!("D1_ERROR: {e}"))
+}
+
+fn json_error(e: serde_json::Error) -> Error {
+    Error::RustError(format!("D1_TYPE_ERROR: {e}"))
+}
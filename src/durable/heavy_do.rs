@@ -1,9 +1,9 @@
-use axum::{extract::DefaultBodyLimit, Extension};
+use axum::{extract::DefaultBodyLimit, response::IntoResponse, Extension};
 use tower_http::cors::{Any, CorsLayer};
 use tower_service::Service;
-use worker::{durable_object, DurableObject, Env, HttpRequest, Request, Response, Result, State};
+use worker::{durable_object, DurableObject, HttpRequest, Request, Response, Result, State};
 
-use crate::{router, BaseUrl};
+use crate::{env::Env, migrations, router, BaseUrl};
 
 /// Durable Object used to run CPU-heavy API flows with a higher CPU budget.
 ///
@@ -16,8 +16,11 @@ pub struct HeavyDo {
 }
 
 impl DurableObject for HeavyDo {
-    fn new(state: State, env: Env) -> Self {
-        Self { state, env }
+    fn new(state: State, env: worker::Env) -> Self {
+        Self {
+            state,
+            env: crate::env::from_worker(env),
+        }
     }
 
     async fn fetch(&self, req: Request) -> Result<Response> {
@@ -28,6 +31,11 @@ impl DurableObject for HeavyDo {
         // Keep fields used to avoid "unused" warnings even if we don't currently rely on them.
         let _ = &self.state;
 
+        // Offloaded requests skip the main worker's fetch handler, so check the schema here too.
+        if let Err(e) = migrations::ensure_schema(&self.env).await {
+            return e.into_response().try_into();
+        }
+
         // Convert worker::Request -> worker::HttpRequest so we can reuse axum Router.
         let http_req: HttpRequest = req.try_into()?;
 
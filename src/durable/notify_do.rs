@@ -1,14 +1,17 @@
+use std::cell::Cell;
+
 use serde::{Deserialize, Serialize};
 use worker::{
-    durable_object, DurableObject, Env, Method, Request, Response, Result, State, WebSocket,
-    WebSocketIncomingMessage, WebSocketPair,
+    durable_object, DurableObject, Method, Request, Response, Result, SqlStorage, SqlStorageValue,
+    State, WebSocket, WebSocketIncomingMessage, WebSocketPair,
 };
 
 use crate::{
     auth, db,
+    env::Env,
     notifications::{
-        self, ConnectionAttachment, PublishSelector, ANONYMOUS_KIND_TAG, INITIAL_RESPONSE,
-        USER_KIND_TAG,
+        self, ConnectionAttachment, ConnectionKind, PublishSelector, Replay, ANONYMOUS_KIND_TAG,
+        INITIAL_RESPONSE, REPLAY_BUFFER_SIZE, SHARD_HEADER, USER_KIND_TAG,
     },
 };
 
@@ -16,6 +19,8 @@ use crate::{
 pub struct NotifyDo {
     state: State,
     env: Env,
+    /// Whether the replay tables exist; anonymous hubs never create them.
+    replay_ready: Cell<bool>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -31,17 +36,36 @@ struct FanoutStats {
     matched: usize,
     sent: usize,
     pruned: usize,
+    /// Replay sequence of the message, when it was buffered.
+    seq: Option<i64>,
 }
 
 #[derive(Deserialize)]
 struct DoFanoutRequest {
     selector: PublishSelector,
     message: String,
+    #[serde(default)]
+    replay: bool,
+}
+
+#[derive(Deserialize)]
+struct SeqRow {
+    seq: i64,
+}
+
+#[derive(Deserialize)]
+struct BufferBounds {
+    oldest: Option<i64>,
+    latest: Option<i64>,
 }
 
 impl DurableObject for NotifyDo {
-    fn new(state: State, env: Env) -> Self {
-        Self { state, env }
+    fn new(state: State, env: worker::Env) -> Self {
+        Self {
+            state,
+            env: crate::env::from_worker(env),
+            replay_ready: Cell::new(false),
+        }
     }
 
     async fn fetch(&self, mut req: Request) -> Result<Response> {
@@ -69,6 +93,16 @@ impl DurableObject for NotifyDo {
             self.close_socket(&ws, 1008, "missing connection attachment");
             return Ok(());
         };
+        // Sockets accepted on the old global instance never receive fan-out again. Their next
+        // message (clients ping every few seconds) makes them reconnect to their own shard.
+        if attachment.shard.is_none() {
+            self.close_socket(&ws, 1012, "reconnect to notification shard");
+            return Ok(());
+        }
+
+        if attachment.protocol_initialized {
+            self.confirm_delivery(&ws, &mut attachment)?;
+        }
 
         match message {
             WebSocketIncomingMessage::String(text) => {
@@ -76,6 +110,9 @@ impl DurableObject for NotifyDo {
                     attachment.protocol_initialized = true;
                     ws.serialize_attachment(&attachment)?;
                     ws.send_with_bytes(INITIAL_RESPONSE)?;
+                    if attachment.kind == ConnectionKind::User {
+                        self.replay_missed(&ws, &mut attachment)?;
+                    }
                 }
             }
             WebSocketIncomingMessage::Binary(bytes) => {
@@ -131,9 +168,13 @@ impl NotifyDo {
             }
         };
 
-        let pair = WebSocketPair::new()?;
         let attachment =
             ConnectionAttachment::user(claims.sub.clone(), Some(claims.device), db::now_string());
+        if !self.is_routed_to(&req, &attachment) {
+            return Response::error("Wrong notification shard", 400);
+        }
+
+        let pair = WebSocketPair::new()?;
         pair.server.serialize_attachment(&attachment)?;
 
         let user_tag = notifications::user_tag(&claims.sub);
@@ -153,8 +194,12 @@ impl NotifyDo {
             return Response::error("Missing token", 400);
         };
 
-        let pair = WebSocketPair::new()?;
         let attachment = ConnectionAttachment::anonymous(token.clone(), db::now_string());
+        if !self.is_routed_to(&req, &attachment) {
+            return Response::error("Wrong notification shard", 400);
+        }
+
+        let pair = WebSocketPair::new()?;
         pair.server.serialize_attachment(&attachment)?;
 
         let anonymous_tag = notifications::anonymous_tag(&token);
@@ -179,7 +224,12 @@ impl NotifyDo {
             worker::Error::RustError("Invalid base64".into())
         })?;
 
-        let stats = self.ws_fanout(&body.selector, &ws_bytes);
+        let seq = match &body.selector {
+            PublishSelector::ByUser { .. } if body.replay => self.buffer_update(&ws_bytes),
+            _ => None,
+        };
+        let mut stats = self.ws_fanout(&body.selector, &ws_bytes);
+        stats.seq = seq;
         Response::from_json(&stats)
     }
 
@@ -190,6 +240,7 @@ impl NotifyDo {
             matched: 0,
             sent: 0,
             pruned: 0,
+            seq: None,
         };
 
         for ws in self.state.get_websockets_with_tag(&selector.tag()) {
@@ -224,6 +275,137 @@ impl NotifyDo {
         stats
     }
 
+    // ── Replay buffer ───────────────────────────────────────────────
+    //
+    // The user's recent updates are kept in this instance's SQLite storage under increasing
+    // sequence numbers, and each device's last confirmed sequence next to them. A sent message
+    // only counts as delivered once the client has spoken after the message that followed it,
+    // since a send to a half-open socket succeeds too.
+
+    fn sql(&self) -> Result<SqlStorage> {
+        let sql = self.state.storage().sql();
+        if !self.replay_ready.get() {
+            sql.exec(
+                "CREATE TABLE IF NOT EXISTS updates (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    message BLOB NOT NULL
+                )",
+                None,
+            )?;
+            sql.exec(
+                "CREATE TABLE IF NOT EXISTS device_cursors (
+                    device_id TEXT PRIMARY KEY,
+                    seq INTEGER NOT NULL
+                )",
+                None,
+            )?;
+            self.replay_ready.set(true);
+        }
+        Ok(sql)
+    }
+
+    /// Store `message` for replay and drop what falls out of the buffer. Failures only cost
+    /// the replay, so they are logged and the live fan-out goes ahead.
+    fn buffer_update(&self, message: &[u8]) -> Option<i64> {
+        let stored = self.sql().and_then(|sql| {
+            let row: SeqRow = sql
+                .exec(
+                    "INSERT INTO updates (message) VALUES (?) RETURNING seq",
+                    vec![SqlStorageValue::Blob(message.to_vec())],
+                )?
+                .one()?;
+            sql.exec(
+                "DELETE FROM updates WHERE seq <= ?",
+                vec![(row.seq - REPLAY_BUFFER_SIZE).into()],
+            )?;
+            Ok(row.seq)
+        });
+        match stored {
+            Ok(seq) => Some(seq),
+            Err(error) => {
+                log::warn!("NotifyDo failed to buffer update for replay: {error}");
+                None
+            }
+        }
+    }
+
+    fn buffer_bounds(&self, sql: &SqlStorage) -> Result<BufferBounds> {
+        sql.exec(
+            "SELECT MIN(seq) AS oldest, MAX(seq) AS latest FROM updates",
+            None,
+        )?
+        .one()
+    }
+
+    /// Record the sequence the client's previous message vouched for, and remember the latest
+    /// one for its next message.
+    fn confirm_delivery(
+        &self,
+        ws: &WebSocket,
+        attachment: &mut ConnectionAttachment,
+    ) -> Result<()> {
+        let (ConnectionKind::User, Some(device_id)) = (&attachment.kind, &attachment.device_id)
+        else {
+            return Ok(());
+        };
+        let sql = self.sql()?;
+        if let Some(seq) = attachment.pending_seq {
+            sql.exec(
+                "INSERT INTO device_cursors (device_id, seq) VALUES (?, ?)
+                 ON CONFLICT (device_id) DO UPDATE SET seq = MAX(seq, excluded.seq)",
+                vec![device_id.as_str().into(), seq.into()],
+            )?;
+        }
+        let latest = self.buffer_bounds(&sql)?.latest.unwrap_or(0);
+        if attachment.pending_seq != Some(latest) {
+            attachment.pending_seq = Some(latest);
+            ws.serialize_attachment(&*attachment)?;
+        }
+        Ok(())
+    }
+
+    /// Send a reconnecting device what it missed while it was away. Devices seen for the first
+    /// time start from the current sequence.
+    fn replay_missed(&self, ws: &WebSocket, attachment: &mut ConnectionAttachment) -> Result<()> {
+        let Some(device_id) = attachment.device_id.as_deref() else {
+            return Ok(());
+        };
+        let sql = self.sql()?;
+        let bounds = self.buffer_bounds(&sql)?;
+        let cursor: Vec<SeqRow> = sql
+            .exec(
+                "SELECT seq FROM device_cursors WHERE device_id = ?",
+                vec![device_id.into()],
+            )?
+            .to_array()?;
+
+        if let Some(last_seen) = cursor.first().map(|row| row.seq) {
+            match notifications::plan_replay(last_seen, bounds.oldest, bounds.latest) {
+                Replay::UpToDate => {}
+                Replay::After(seq) => {
+                    let rows = sql
+                        .exec(
+                            "SELECT message FROM updates WHERE seq > ? ORDER BY seq",
+                            vec![seq.into()],
+                        )?
+                        .raw();
+                    for row in rows {
+                        if let Some(SqlStorageValue::Blob(message)) = row?.into_iter().next() {
+                            ws.send_with_bytes(message)?;
+                        }
+                    }
+                }
+                Replay::SyncVault => {
+                    let user_id = attachment.user_id.as_deref().unwrap_or_default();
+                    ws.send_with_bytes(notifications::create_sync_vault(user_id))?;
+                }
+            }
+        }
+
+        attachment.pending_seq = Some(bounds.latest.unwrap_or(0));
+        ws.serialize_attachment(&*attachment)
+    }
+
     // ── Utility ─────────────────────────────────────────────────────
 
     fn deserialize_attachment(&self, ws: &WebSocket) -> Option<ConnectionAttachment> {
@@ -242,6 +424,13 @@ impl NotifyDo {
         }
     }
 
+    /// Whether `entry.js` routed the request to the shard the connection belongs on, so fan-out
+    /// for its user or token reaches this instance.
+    fn is_routed_to(&self, req: &Request, attachment: &ConnectionAttachment) -> bool {
+        let routed = req.headers().get(SHARD_HEADER).ok().flatten();
+        routed.is_some() && routed == attachment.shard
+    }
+
     fn is_websocket_upgrade(&self, req: &Request) -> bool {
         req.headers()
             .get("Upgrade")
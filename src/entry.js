@@ -55,6 +55,19 @@ function normalizePathname(pathname) {
   return pathname.replace(/\/+$/, "");
 }
 
+// NotifyDo instance for a hub connection, aligned with `PublishSelector::shard_name` in
+// src/notifications.rs so fan-out for a user (or auth-request token) reaches its sockets. The
+// token is only decoded here; NotifyDo verifies it and checks the shard again.
+function getNotifyShardName(request, url) {
+  if (url.pathname === "/notifications/anonymous-hub") {
+    const token = url.searchParams.get("Token") || url.searchParams.get("token");
+    return token ? `anon:${token}` : null;
+  }
+  const token = url.searchParams.get("access_token") || getBearerToken(request);
+  const sub = token ? decodeJwtPayloadUnsafe(token)?.sub : null;
+  return typeof sub === "string" && sub ? `user:${sub}` : null;
+}
+
 async function getHeavyDoShardKey(request, url) {
   const pathname = url.pathname;
 
@@ -85,6 +98,10 @@ async function getHeavyDoShardKey(request, url) {
 const HEAVY_DO_ROUTE_METHODS = new Map([
   // Import
   ["/api/ciphers/import", new Set(["POST"])],
+  ["/api/ciphers/import/cxf", new Set(["POST"])],
+  ["/api/ciphers/import/1pux", new Set(["POST"])],
+  ["/api/ciphers/import/keepass", new Set(["POST"])],
+  ["/api/ciphers/import/lastpass", new Set(["POST"])],
 
   // Identity/Auth (password hashing / verification)
   ["/identity/accounts/register", new Set(["POST"])],
@@ -102,6 +119,10 @@ const HEAVY_DO_ROUTE_METHODS = new Map([
   // Security stamp rotation requires password verification
   ["/api/accounts/security-stamp", new Set(["POST"])],
 
+  // Personal API key reveal/rotation requires password verification
+  ["/api/accounts/api-key", new Set(["POST"])],
+  ["/api/accounts/rotate-api-key", new Set(["POST"])],
+
   // Key rotation needs verify master password and update entire vault
   ["/api/accounts/key-management/rotate-user-account-keys", new Set(["POST"])],
 
@@ -110,13 +131,27 @@ const HEAVY_DO_ROUTE_METHODS = new Map([
   ["/api/two-factor/authenticator", new Set(["POST", "PUT", "DELETE"])],
   ["/api/two-factor/disable", new Set(["POST", "PUT"])],
   ["/api/two-factor/get-recover", new Set(["POST"])],
+  ["/api/two-factor/get-email", new Set(["POST"])],
+  ["/api/two-factor/send-email", new Set(["POST"])],
+  ["/api/two-factor/email", new Set(["POST", "PUT"])],
+  ["/api/two-factor/send-email-login", new Set(["POST"])],
 ]);
 
+// Routes with path parameters, matched by pattern.
+const HEAVY_DO_ROUTE_PATTERNS = [
+  // Emergency access takeover hashes the grantor's new master password
+  [/^\/api\/emergency-access\/[^/]+\/password$/, new Set(["POST"])],
+  // Every admin request verifies ADMIN_TOKEN with PBKDF2
+  [/^\/admin(\/.*)?$/, new Set(["GET", "POST"])],
+];
+
 function shouldOffloadToHeavyDo(request, url) {
-  const methods = HEAVY_DO_ROUTE_METHODS.get(url.pathname);
-  if (!methods) return false;
   const method = (request.method || "GET").toUpperCase();
-  return methods.has(method);
+  const methods = HEAVY_DO_ROUTE_METHODS.get(url.pathname);
+  if (methods) return methods.has(method);
+  return HEAVY_DO_ROUTE_PATTERNS.some(
+    ([pattern, patternMethods]) => pattern.test(url.pathname) && patternMethods.has(method)
+  );
 }
 
 // Main fetch handler
@@ -133,9 +168,17 @@ export default {
       method === "GET" &&
       (url.pathname === "/notifications/hub" || url.pathname === "/notifications/anonymous-hub")
     ) {
-      const id = env.NOTIFY_DO.idFromName("global");
-      const stub = env.NOTIFY_DO.get(id);
-      return stub.fetch(request);
+      const shard = getNotifyShardName(request, url);
+      if (!shard) {
+        // Same answers NotifyDo gives for a missing token.
+        return url.pathname === "/notifications/hub"
+          ? new Response("Missing access token", { status: 401 })
+          : new Response("Missing token", { status: 400 });
+      }
+      const headers = new Headers(request.headers);
+      headers.set("X-Notify-Shard", shard);
+      const stub = env.NOTIFY_DO.get(env.NOTIFY_DO.idFromName(shard));
+      return stub.fetch(new Request(request, { headers }));
     }
 
     // Optional: route selected CPU-heavy endpoints to Durable Objects.
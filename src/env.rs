@@ -0,0 +1,23 @@
+//! The bindings handlers run against.
+//!
+//! In the Worker this is `worker::Env`. `cargo test` builds the crate natively, where the
+//! JavaScript behind `worker::Env` does not exist, so unit tests swap in
+//! [`crate::testing::Env`] instead.
+
+#[cfg(not(test))]
+pub use worker::Env;
+
+#[cfg(test)]
+pub use crate::testing::Env;
+
+/// The environment for code called from a Worker entry point (`fetch`, `scheduled` or a
+/// Durable Object).
+#[cfg(not(test))]
+pub fn from_worker(env: worker::Env) -> Env {
+    env
+}
+
+#[cfg(test)]
+pub fn from_worker(_env: worker::Env) -> Env {
+    unreachable!("Worker entry points do not run under cargo test")
+}
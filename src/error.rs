@@ -27,6 +27,9 @@ pub enum AppError {
     #[error("Cryptography error: {0}")]
     Crypto(String),
 
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
     #[error("Internal server error")]
     Internal,
 
@@ -55,6 +58,7 @@ impl IntoResponse for AppError {
                     AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
                     AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
                     AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+                    AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
                     AppError::Crypto(msg) => (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         format!("Crypto error: {}", msg),
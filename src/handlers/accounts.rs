@@ -1,37 +1,70 @@
-use axum::{extract::State, http::HeaderMap, Json};
+use crate::db::Statement;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
 use glob_match::glob_match;
+use jwt_compact::{alg::Hs256Key, AlgorithmExt, Claims as JwtClaims, Header, UntrustedToken};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{D1PreparedStatement, Env};
 
 use crate::d1_query;
 
-use super::{get_batch_size, server_password_iterations, two_factor_enabled};
+use super::two_factor_enabled;
 use crate::{
-    auth::Claims,
-    crypto::{generate_salt, hash_password_for_storage},
+    auth::{jwt_time_options, Claims},
+    config::Config,
+    crypto::{generate_api_key, generate_salt, hash_password_for_storage},
     db,
+    env::Env,
     error::AppError,
-    handlers::{attachments, sends},
+    handlers::{attachments, events, organizations, sends},
+    mail::{self, templates},
     models::{
         cipher::CipherData,
         device::Device,
+        event::EventType,
+        invitation::Invitation,
+        organization::Membership,
         sync::Profile,
         user::{
             AvatarData, ChangeKdfRequest, ChangePasswordRequest, MasterPasswordUnlockData,
             PasswordHintRequest, PasswordOrOtpData, PreloginResponse, ProfileData, RegisterRequest,
-            RotateKeyRequest, User,
+            RotateKeyRequest, SendVerificationEmailRequest, User, VerifyEmailTokenRequest,
         },
+        webauthn_credential::WebAuthnCredential,
     },
     notifications::{self, UpdateType},
-    push,
+    push, BaseUrl,
 };
 
 const KDF_TYPE_PBKDF2: i32 = 0;
 const KDF_TYPE_ARGON2ID: i32 = 1;
 const MIN_PBKDF2_ITERATIONS: i32 = 100_000;
 const DEFAULT_PBKDF2_ITERATIONS: i32 = 600_000;
+const REGISTER_VERIFY_TOKEN_ISSUER: &str = "warden-worker-register-verify";
+const EMAIL_VERIFY_TOKEN_ISSUER: &str = "warden-worker-email-verify";
+
+/// Lifetime of the links sent by `send_verification_email` and `post_verify_email`.
+const EMAIL_TOKEN_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RegisterVerifyClaims {
+    pub sub: String, // Email address
+    pub iss: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmailVerifyClaims {
+    pub sub: String,   // User ID
+    pub email: String, // Address the link was sent to
+    pub iss: String,
+}
 
 fn ensure_supported_kdf(
     kdf_type: i32,
@@ -189,6 +222,8 @@ pub async fn prelogin(
 #[worker::send]
 pub async fn register(
     State(env): State<Arc<Env>>,
+    config: Config,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
     headers: HeaderMap,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<Json<Value>, AppError> {
@@ -208,19 +243,23 @@ pub async fn register(
         }
     }
 
-    let allowed_emails = env
-        .secret("ALLOWED_EMAILS")
-        .map_err(|_| AppError::Internal)?;
-    let allowed_emails = allowed_emails
-        .as_ref()
-        .as_string()
-        .ok_or_else(|| AppError::Internal)?;
-    if !allowed_emails
-        .split(',')
-        .any(|pattern| glob_match(pattern.trim(), &payload.email))
-    {
-        return Err(AppError::Unauthorized("Not allowed to signup".to_string()));
-    }
+    let db = db::get_db(&env)?;
+    ensure_signup_allowed(&env, &db, &payload.email).await?;
+
+    // Signup links carry a token proving the address was reached; the legacy
+    // register endpoint does not send one. Without mail the token was handed
+    // straight back to the client, so it proves nothing about the address.
+    let email_verified = match payload.email_verification_token.as_deref() {
+        Some(token) => {
+            if !is_valid_register_token(&env, token, &payload.email.to_lowercase())? {
+                return Err(AppError::BadRequest(
+                    "Invalid or expired email verification token".to_string(),
+                ));
+            }
+            mail::mail_enabled(&env)
+        }
+        None => false,
+    };
 
     ensure_supported_kdf(
         payload.kdf,
@@ -231,7 +270,7 @@ pub async fn register(
 
     // Generate salt and hash the password with server-side PBKDF2
     let password_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = config.password_iterations as i32;
     let hashed_password = hash_password_for_storage(
         &payload.master_password_hash,
         &password_salt,
@@ -239,7 +278,6 @@ pub async fn register(
     )
     .await?;
 
-    let db = db::get_db(&env)?;
     let now = db::now_string();
 
     // Only store kdf_memory and kdf_parallelism for Argon2id, clear for PBKDF2
@@ -254,7 +292,7 @@ pub async fn register(
         name: payload.name,
         avatar_color: None,
         email: payload.email.to_lowercase(),
-        email_verified: false,
+        email_verified,
         master_password_hash: hashed_password,
         master_password_hint: payload.master_password_hint,
         password_salt: Some(password_salt),
@@ -270,14 +308,17 @@ pub async fn register(
         equivalent_domains: "[]".to_string(),
         excluded_globals: "[]".to_string(),
         totp_recover: None,
+        last_verifying_at: None,
+        api_key: None,
+        enabled: true,
         created_at: now.clone(),
         updated_at: now,
     };
 
     d1_query!(
         &db,
-        "INSERT INTO users (id, name, email, master_password_hash, master_password_hint, password_salt, password_iterations, key, private_key, public_key, kdf_type, kdf_iterations, kdf_memory, kdf_parallelism, security_stamp, equivalent_domains, excluded_globals, totp_recover, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+        "INSERT INTO users (id, name, email, master_password_hash, master_password_hint, password_salt, password_iterations, key, private_key, public_key, kdf_type, kdf_iterations, kdf_memory, kdf_parallelism, security_stamp, equivalent_domains, excluded_globals, totp_recover, created_at, updated_at, email_verified)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)",
          user.id,
          user.name,
          user.email,
@@ -297,7 +338,8 @@ pub async fn register(
          user.excluded_globals,
          user.totp_recover,
          user.created_at,
-         user.updated_at
+         user.updated_at,
+         user.email_verified as i32
     ).map_err(|_|{
         AppError::Database
     })?
@@ -307,18 +349,288 @@ pub async fn register(
         AppError::Database
     })?;
 
+    Invitation::delete(&db, &user.email).await?;
+
+    mail::send_in_background((*env).clone(), templates::welcome(&user.email, &base_url));
+
     Ok(Json(json!({})))
 }
 
+/// POST /identity/accounts/register/send-verification-email
+///
+/// Issues the signed token that `register/finish` expects. When mail is configured the token is
+/// emailed as a signup link (and nothing is returned); otherwise it is returned directly so the
+/// client can continue the signup immediately.
 #[worker::send]
-pub async fn send_verification_email() -> Result<Json<String>, AppError> {
-    Ok(Json("fixed-token-to-mock".to_string()))
+pub async fn send_verification_email(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    headers: HeaderMap,
+    Json(payload): Json<SendVerificationEmailRequest>,
+) -> Result<Response, AppError> {
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let ip = headers
+            .get("cf-connecting-ip")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown");
+        let rate_limit_key = format!("register:{}", ip);
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many requests. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+
+    let email = payload.email.trim().to_lowercase();
+    let db = db::get_db(&env)?;
+    ensure_signup_allowed(&env, &db, &email).await?;
+
+    let token = generate_register_token(&env, &email)?;
+    if !mail::mail_enabled(&env) {
+        return Ok(Json(token).into_response());
+    }
+
+    // Do not reveal whether the address is already registered.
+    if User::find_by_email(&db, &email).await?.is_none() {
+        let link = signup_link(&base_url, &email, &token);
+        mail::send_in_background((*env).clone(), templates::verify_email(&email, &link));
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Link to the web vault's `finish-signup` page for a token from `generate_register_token`.
+fn signup_link(base_url: &str, email: &str, token: &str) -> String {
+    mail::web_vault_link(
+        base_url,
+        "/finish-signup",
+        &[("email", email), ("token", token), ("fromEmail", "true")],
+    )
+}
+
+/// Invite `email` to sign up, bypassing `ALLOWED_EMAILS`.
+///
+/// The signup link is emailed when mail is configured; it is returned either way so the
+/// operator can pass it on.
+pub(crate) async fn invite_user(
+    env: &Env,
+    db: &crate::db::Db,
+    base_url: &str,
+    email: &str,
+) -> Result<String, AppError> {
+    Invitation::create(db, email).await?;
+
+    let token = generate_register_token(env, email)?;
+    let link = signup_link(base_url, email, &token);
+    mail::send_in_background(env.clone(), templates::invite(email, &link));
+
+    Ok(link)
+}
+
+/// Reject signups for addresses that are neither matched by the `ALLOWED_EMAILS` glob list
+/// nor invited through the admin API.
+async fn ensure_signup_allowed(env: &Env, db: &crate::db::Db, email: &str) -> Result<(), AppError> {
+    let allowed_emails = env
+        .secret("ALLOWED_EMAILS")
+        .map_err(|_| AppError::Internal)?
+        .to_string();
+    if allowed_emails
+        .split(',')
+        .any(|pattern| glob_match(pattern.trim(), email))
+    {
+        return Ok(());
+    }
+    if Invitation::exists(db, &email.to_lowercase()).await? {
+        return Ok(());
+    }
+    Err(AppError::Unauthorized("Not allowed to signup".to_string()))
+}
+
+/// Sign a token for an emailed link (HS256 with `JWT_SECRET`, like attachment URLs).
+pub(crate) fn sign_email_token<T: Serialize>(
+    env: &Env,
+    custom: T,
+    valid_for: Duration,
+) -> Result<String, AppError> {
+    let time_options = jwt_time_options();
+    let claims = JwtClaims::new(custom)
+        .set_duration_and_issuance(&time_options, valid_for)
+        .set_not_before(Utc::now());
+
+    let secret = attachments::jwt_secret(env)?;
+    let key = Hs256Key::new(secret.as_bytes());
+    jwt_compact::alg::Hs256
+        .token(&Header::empty(), &claims, &key)
+        .map_err(|_| AppError::Crypto("Failed to create verification token".to_string()))
+}
+
+/// Decode a token from `sign_email_token`; `None` if it is malformed, forged or expired.
+pub(crate) fn decode_email_token<T: DeserializeOwned>(
+    env: &Env,
+    raw_token: &str,
+) -> Result<Option<T>, AppError> {
+    let secret = attachments::jwt_secret(env)?;
+    let key = Hs256Key::new(secret.as_bytes());
+    let Ok(token) = UntrustedToken::new(raw_token) else {
+        return Ok(None);
+    };
+    let Ok(token) = jwt_compact::alg::Hs256
+        .validator::<T>(&key)
+        .validate(&token)
+    else {
+        return Ok(None);
+    };
+    let time_options = jwt_time_options();
+    if token.claims().validate_expiration(&time_options).is_err()
+        || token.claims().validate_maturity(&time_options).is_err()
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(token.into_parts().1.custom))
+}
+
+fn generate_register_token(env: &Env, email: &str) -> Result<String, AppError> {
+    sign_email_token(
+        env,
+        RegisterVerifyClaims {
+            sub: email.to_string(),
+            iss: REGISTER_VERIFY_TOKEN_ISSUER.to_string(),
+        },
+        Duration::hours(EMAIL_TOKEN_HOURS),
+    )
+}
+
+/// Whether `raw_token` was issued by `send_verification_email` for `email` and is still valid.
+fn is_valid_register_token(env: &Env, raw_token: &str, email: &str) -> Result<bool, AppError> {
+    Ok(decode_email_token::<RegisterVerifyClaims>(env, raw_token)?
+        .is_some_and(|claims| claims.iss == REGISTER_VERIFY_TOKEN_ISSUER && claims.sub == email))
+}
+
+/// Email a verification link to the user, unless one was sent within
+/// `SIGNUPS_VERIFY_RESEND_SECS`. Returns whether a link was sent.
+pub(crate) async fn send_email_verification(
+    env: &Env,
+    config: &Config,
+    db: &crate::db::Db,
+    base_url: &str,
+    user: &User,
+) -> Result<bool, AppError> {
+    let now = Utc::now();
+    let last_sent = user
+        .last_verifying_at
+        .as_deref()
+        .and_then(|ts| chrono::DateTime::parse_from_rfc3339(ts).ok());
+    if let Some(last_sent) = last_sent {
+        if (now - last_sent.to_utc()).num_seconds() < config.signups_verify_resend_secs {
+            return Ok(false);
+        }
+    }
+
+    d1_query!(
+        db,
+        "UPDATE users SET last_verifying_at = ?1 WHERE id = ?2",
+        db::now_string(),
+        &user.id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    let token = sign_email_token(
+        env,
+        EmailVerifyClaims {
+            sub: user.id.clone(),
+            email: user.email.clone(),
+            iss: EMAIL_VERIFY_TOKEN_ISSUER.to_string(),
+        },
+        Duration::hours(EMAIL_TOKEN_HOURS),
+    )?;
+    let link = mail::web_vault_link(
+        base_url,
+        "/verify-email",
+        &[("userId", &user.id), ("token", &token)],
+    );
+    mail::send_in_background(env.clone(), templates::verify_email(&user.email, &link));
+
+    Ok(true)
+}
+
+/// POST /api/accounts/verify-email - Send a verification link to the current address
+#[worker::send]
+pub async fn post_verify_email(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    config: Config,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+) -> Result<Json<Value>, AppError> {
+    if !mail::mail_enabled(&env) {
+        return Err(AppError::BadRequest(
+            "Mail is not configured on this server".to_string(),
+        ));
+    }
+
+    let db = db::get_db(&env)?;
+    let user = User::find_by_id(&db, &claims.sub)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if user.email_verified {
+        return Err(AppError::BadRequest(
+            "Email is already verified".to_string(),
+        ));
+    }
+
+    if !send_email_verification(&env, &config, &db, &base_url, &user).await? {
+        return Err(AppError::TooManyRequests(
+            "A verification email was sent recently. Please check your inbox.".to_string(),
+        ));
+    }
+
+    Ok(Json(json!({})))
+}
+
+/// POST /api/accounts/verify-email-token - Mark the address verified from an emailed link
+#[worker::send]
+pub async fn post_verify_email_token(
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<VerifyEmailTokenRequest>,
+) -> Result<Json<Value>, AppError> {
+    let invalid = || AppError::BadRequest("Invalid or expired verification link".to_string());
+
+    let claims = decode_email_token::<EmailVerifyClaims>(&env, &payload.token)?
+        .filter(|c| c.iss == EMAIL_VERIFY_TOKEN_ISSUER && c.sub == payload.user_id)
+        .ok_or_else(invalid)?;
+
+    let db = db::get_db(&env)?;
+    let user = User::find_by_id(&db, &claims.sub)
+        .await?
+        .ok_or_else(invalid)?;
+    // A link sent before an address change must not verify the new address.
+    if user.email != claims.email {
+        return Err(invalid());
+    }
+
+    d1_query!(
+        &db,
+        "UPDATE users SET email_verified = 1, last_verifying_at = NULL, updated_at = ?1 WHERE id = ?2",
+        db::now_string(),
+        &user.id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(Json(json!({})))
 }
 
 /// POST /api/accounts/password-hint
 ///
-/// Bitwarden normally sends the master password hint via email. This project does not implement
-/// email delivery, so we return the hint directly.
+/// When mail is configured the hint is emailed to the address and the response is the same
+/// whether or not an account exists. Otherwise the hint is returned directly in the error body.
 #[worker::send]
 pub async fn password_hint(
     State(env): State<Arc<Env>>,
@@ -346,12 +658,10 @@ pub async fn password_hint(
     let db = db::get_db(&env)?;
     let email = payload.email.to_lowercase();
 
-    let hint: Option<String> = db
-        .prepare("SELECT master_password_hint FROM users WHERE email = ?1")
-        .bind(&[email.into()])?
-        .first(Some("master_password_hint"))
-        .await
-        .map_err(|_| AppError::Database)?;
+    let user = User::find_by_email(&db, &email).await?;
+    let hint = user
+        .as_ref()
+        .and_then(|user| user.master_password_hint.clone());
 
     let hint = hint.and_then(|h| {
         let trimmed = h.trim();
@@ -362,6 +672,16 @@ pub async fn password_hint(
         }
     });
 
+    if mail::mail_enabled(&env) {
+        if user.is_some() {
+            mail::send_in_background(
+                (*env).clone(),
+                templates::password_hint(&email, hint.as_deref()),
+            );
+        }
+        return Ok(Json(json!({})));
+    }
+
     if let Some(hint) = hint {
         return Err(AppError::BadRequest(format!(
             "Your password hint is: {hint}"
@@ -423,7 +743,8 @@ pub async fn get_profile(
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
     let two_factor_enabled = two_factor_enabled(&db, &user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let mut profile = Profile::from_user(user, two_factor_enabled, mail::mail_enabled(&env))?;
+    profile.organizations = organizations::profile_organizations(&db, &user_id).await?;
 
     Ok(Json(profile))
 }
@@ -470,7 +791,7 @@ pub async fn post_profile(
     .map_err(|_| AppError::Database)?;
 
     let two_factor_enabled = two_factor_enabled(&db, user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let profile = Profile::from_user(user, two_factor_enabled, mail::mail_enabled(&env))?;
 
     notifications::publish_user_update(
         (*env).clone(),
@@ -537,7 +858,7 @@ pub async fn put_avatar(
     .map_err(|_| AppError::Database)?;
 
     let two_factor_enabled = two_factor_enabled(&db, user_id).await?;
-    let profile = Profile::from_user(user, two_factor_enabled)?;
+    let profile = Profile::from_user(user, two_factor_enabled, mail::mail_enabled(&env))?;
 
     notifications::publish_user_update(
         (*env).clone(),
@@ -580,35 +901,62 @@ pub async fn delete_account(
         return Err(AppError::Unauthorized("Invalid password".to_string()));
     }
 
-    push::unregister_push_devices_by_user(&env, user_id).await;
+    delete_user(&env, &db, &user).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// Delete a user with their vault, sends and stored files.
+///
+/// Refuses while the user is the only owner of an organization.
+pub(crate) async fn delete_user(
+    env: &Env,
+    db: &crate::db::Db,
+    user: &User,
+) -> Result<(), AppError> {
+    let user_id = &user.id;
+
+    if !Membership::list_orgs_solely_owned_by(db, user_id)
+        .await?
+        .is_empty()
+    {
+        return Err(AppError::BadRequest(
+            "You are the only owner of an organization. Transfer ownership or delete the organization first."
+                .to_string(),
+        ));
+    }
+
+    push::unregister_push_devices_by_user(env, user_id).await;
 
-    if attachments::attachments_enabled(env.as_ref()) {
-        let keys = attachments::list_attachment_keys_for_user(&db, user_id).await?;
-        attachments::delete_storage_objects(env.as_ref(), &keys).await?;
+    if attachments::attachments_enabled(env) {
+        let keys = attachments::list_attachment_keys_for_user(db, user_id).await?;
+        attachments::delete_storage_objects(env, &keys).await?;
     }
 
     // Delete all user's sends and associated storage objects
-    sends::delete_user_sends(&db, env.as_ref(), user_id).await?;
+    sends::delete_user_sends(db, env, user_id).await?;
 
     // Delete all user's ciphers
-    d1_query!(&db, "DELETE FROM ciphers WHERE user_id = ?1", user_id)
+    d1_query!(db, "DELETE FROM ciphers WHERE user_id = ?1", user_id)
         .map_err(|_| AppError::Database)?
         .run()
         .await?;
 
     // Delete all user's folders
-    d1_query!(&db, "DELETE FROM folders WHERE user_id = ?1", user_id)
+    d1_query!(db, "DELETE FROM folders WHERE user_id = ?1", user_id)
         .map_err(|_| AppError::Database)?
         .run()
         .await?;
 
     // Delete the user
-    d1_query!(&db, "DELETE FROM users WHERE id = ?1", user_id)
+    d1_query!(db, "DELETE FROM users WHERE id = ?1", user_id)
         .map_err(|_| AppError::Database)?
         .run()
         .await?;
 
-    Ok(Json(json!({})))
+    mail::send_in_background(env.clone(), templates::account_deleted(&user.email));
+
+    Ok(())
 }
 
 /// POST /accounts/password - Change master password
@@ -616,6 +964,8 @@ pub async fn delete_account(
 pub async fn post_password(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    config: Config,
     Json(payload): Json<ChangePasswordRequest>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
@@ -642,7 +992,7 @@ pub async fn post_password(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = config.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         &payload.new_master_password_hash,
         &new_salt,
@@ -671,6 +1021,7 @@ pub async fn post_password(
     .run()
     .await?;
 
+    events::log_user_event(&db, EventType::UserChangedPassword, user_id, &headers).await;
     notifications::publish_user_logout((*env).clone(), claims.sub, now, Some(claims.device));
 
     Ok(Json(json!({})))
@@ -681,11 +1032,13 @@ pub async fn post_password(
 pub async fn post_rotatekey(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    config: Config,
     Json(payload): Json<RotateKeyRequest>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
     let user_id = &claims.sub;
-    let batch_size = get_batch_size(&env);
+    let batch_size = config.import_batch_size;
 
     // Get the user from the database
     let user: Value = db
@@ -831,7 +1184,7 @@ pub async fn post_rotatekey(
 
     // Update all folders with new encrypted names (batch operation)
     // Skip null folder IDs (Bitwarden client bug: https://github.com/bitwarden/clients/issues/8453)
-    let mut folder_statements: Vec<D1PreparedStatement> =
+    let mut folder_statements: Vec<Statement> =
         Vec::with_capacity(payload.account_data.folders.len());
     for folder in &payload.account_data.folders {
         // Skip null folder id entries
@@ -853,9 +1206,8 @@ pub async fn post_rotatekey(
 
     // Update all ciphers with new encrypted data (batch operation)
     // Only update personal ciphers (organization_id is None)
-    let mut cipher_statements: Vec<D1PreparedStatement> =
-        Vec::with_capacity(personal_ciphers.len());
-    let mut attachment_statements: Vec<D1PreparedStatement> = Vec::new();
+    let mut cipher_statements: Vec<Statement> = Vec::with_capacity(personal_ciphers.len());
+    let mut attachment_statements: Vec<Statement> = Vec::new();
     for cipher in personal_ciphers {
         // id is guaranteed to exist (validated above)
         let cipher_id = cipher.id.as_ref().unwrap();
@@ -898,6 +1250,36 @@ pub async fn post_rotatekey(
     db::execute_in_batches(&db, cipher_statements, batch_size).await?;
     db::execute_in_batches(&db, attachment_statements, batch_size).await?;
 
+    // Re-encrypt the user key for every emergency access contact
+    let mut emergency_statements: Vec<Statement> = Vec::with_capacity(
+        payload
+            .account_unlock_data
+            .emergency_access_unlock_data
+            .len(),
+    );
+    for access in &payload.account_unlock_data.emergency_access_unlock_data {
+        let stmt = d1_query!(
+            &db,
+            "UPDATE emergency_access SET key_encrypted = ?1, updated_at = ?2 WHERE id = ?3 AND grantor_id = ?4",
+            access.key_encrypted,
+            now,
+            access.id,
+            user_id
+        )
+        .map_err(|_| AppError::Database)?;
+        emergency_statements.push(stmt);
+    }
+    db::execute_in_batches(&db, emergency_statements, batch_size).await?;
+
+    // Re-wrap the user key for every passkey that can unlock the vault
+    let passkey_statements = WebAuthnCredential::rotate_keys_statements(
+        &db,
+        user_id,
+        &payload.account_unlock_data.passkey_unlock_data,
+        &now,
+    )?;
+    db::execute_in_batches(&db, passkey_statements, batch_size).await?;
+
     // Rotate sends
     sends::rotate_user_sends(
         &db,
@@ -911,7 +1293,7 @@ pub async fn post_rotatekey(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = config.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         &unlock_data.master_key_authentication_hash,
         &new_salt,
@@ -950,6 +1332,7 @@ pub async fn post_rotatekey(
     .run()
     .await?;
 
+    events::log_user_event(&db, EventType::UserChangedPassword, user_id, &headers).await;
     notifications::publish_user_logout((*env).clone(), claims.sub, now, Some(claims.device));
 
     Ok(Json(json!({})))
@@ -960,6 +1343,8 @@ pub async fn post_rotatekey(
 pub async fn post_kdf(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    config: Config,
     Json(payload): Json<ChangeKdfRequest>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
@@ -1008,7 +1393,7 @@ pub async fn post_kdf(
 
     // Generate new salt and hash the new password
     let new_salt = generate_salt()?;
-    let password_iterations = server_password_iterations(&env) as i32;
+    let password_iterations = config.password_iterations as i32;
     let new_hashed_password = hash_password_for_storage(
         &auth_data.master_password_authentication_hash,
         &new_salt,
@@ -1046,6 +1431,7 @@ pub async fn post_kdf(
     .run()
     .await?;
 
+    events::log_user_event(&db, EventType::UserChangedPassword, user_id, &headers).await;
     notifications::publish_user_logout((*env).clone(), claims.sub, now, Some(claims.device));
 
     Ok(Json(json!({})))
@@ -1080,17 +1466,28 @@ pub async fn post_sstamp(
         return Err(AppError::Unauthorized("Invalid password".to_string()));
     }
 
-    push::unregister_push_devices_by_user(&env, user_id).await;
+    deauthorize_user(&env, &db, user_id).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// Log a user out everywhere: drop all devices and rotate the security stamp.
+pub(crate) async fn deauthorize_user(
+    env: &Env,
+    db: &crate::db::Db,
+    user_id: &str,
+) -> Result<(), AppError> {
+    push::unregister_push_devices_by_user(env, user_id).await;
 
     // Delete all device rows — this revokes every refresh token and 2FA-remember token
-    Device::delete_all_by_user(&db, user_id).await?;
+    Device::delete_all_by_user(db, user_id).await?;
 
     // Rotate the security stamp so all existing access tokens become invalid immediately
     let new_security_stamp = Uuid::new_v4().to_string();
     let now = db::now_string();
 
     d1_query!(
-        &db,
+        db,
         "UPDATE users SET security_stamp = ?1, updated_at = ?2 WHERE id = ?3",
         new_security_stamp,
         now,
@@ -1103,7 +1500,222 @@ pub async fn post_sstamp(
     // Known issue: Logout push for mobile devices will be skiped since the records of devices are deleted.
     // Notifications are sent in background via waitUntil,
     // so putting it ahead of device deletion is not guaranteed to send the logout push before the deletion.
-    notifications::publish_user_logout((*env).clone(), claims.sub, now, None);
+    notifications::publish_user_logout(env.clone(), user_id.to_string(), now, None);
 
-    Ok(Json(json!({})))
+    Ok(())
+}
+
+/// POST /api/accounts/api-key - Returns the personal API key, creating it on first use
+#[worker::send]
+pub async fn post_api_key(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    api_key_response(&env, &claims.sub, payload, false).await
+}
+
+/// POST /api/accounts/rotate-api-key - Replaces the personal API key
+#[worker::send]
+pub async fn post_rotate_api_key(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    api_key_response(&env, &claims.sub, payload, true).await
+}
+
+async fn api_key_response(
+    env: &Env,
+    user_id: &str,
+    payload: PasswordOrOtpData,
+    rotate: bool,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(env)?;
+    let user = User::find_by_id(&db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    // Require master password hash (OTP not supported)
+    let provided_hash = payload
+        .master_password_hash
+        .ok_or_else(|| AppError::BadRequest("Missing master password hash".to_string()))?;
+    let verification = user.verify_master_password(&provided_hash).await?;
+    if !verification.is_valid() {
+        return Err(AppError::Unauthorized("Invalid password".to_string()));
+    }
+
+    let (api_key, revision_date) = match user.api_key {
+        Some(api_key) if !rotate && !api_key.is_empty() => (api_key, user.updated_at),
+        _ => {
+            let api_key = generate_api_key()?;
+            let now = db::now_string();
+            d1_query!(
+                &db,
+                "UPDATE users SET api_key = ?1, updated_at = ?2 WHERE id = ?3",
+                &api_key,
+                &now,
+                user_id
+            )
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+            (api_key, now)
+        }
+    };
+
+    Ok(Json(json!({
+        "apiKey": api_key,
+        "revisionDate": revision_date,
+        "object": "apiKey",
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::testing::{block_on, Env, TestClient, BASE_URL};
+
+    /// The query of the last `verify-email` link mailed to `email`.
+    fn verify_link_params(env: &Env, email: &str) -> Vec<(String, String)> {
+        let mail = env.sent_mail();
+        let message = mail
+            .iter()
+            .rev()
+            .find(|message| message.to == email)
+            .expect("a verification link was mailed");
+        let prefix = format!("{BASE_URL}/#/verify-email?");
+        let start = message.text.find(&prefix).expect("the mail has a link") + prefix.len();
+        let query = message.text[start..].split_whitespace().next().unwrap();
+        form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    fn param<'a>(params: &'a [(String, String)], name: &str) -> &'a str {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("the link has {name}"))
+    }
+
+    /// Register, log in and request a verification link. Returns the access token, the user
+    /// id and the mailed token.
+    fn requested_link(env: &Env, client: &TestClient) -> (String, String, String) {
+        let user = client.register();
+        let access = client.login(&user);
+        let sent = client.post("/api/accounts/verify-email", Some(&access), json!({}));
+        assert_eq!(sent.status, StatusCode::OK, "{}", sent.body);
+        let params = verify_link_params(env, &user.email);
+        let profile = client.get("/api/accounts/profile", Some(&access));
+        assert_eq!(param(&params, "userId"), profile.body["id"]);
+        let user_id = param(&params, "userId").to_string();
+        (access, user_id, param(&params, "token").to_string())
+    }
+
+    fn verify(client: &TestClient, user_id: &str, token: &str) -> StatusCode {
+        client
+            .post(
+                "/api/accounts/verify-email-token",
+                None,
+                json!({ "userId": user_id, "token": token }),
+            )
+            .status
+    }
+
+    fn email_verified(client: &TestClient, access: &str) -> bool {
+        let profile = client.get("/api/accounts/profile", Some(access));
+        profile.body["emailVerified"].as_bool().unwrap()
+    }
+
+    #[test]
+    fn the_mailed_link_verifies_the_address() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let (access, user_id, token) = requested_link(&env, &client);
+        assert!(!email_verified(&client, &access));
+
+        // A second link is not sent within SIGNUPS_VERIFY_RESEND_SECS.
+        let again = client.post("/api/accounts/verify-email", Some(&access), json!({}));
+        assert_eq!(again.status, StatusCode::TOO_MANY_REQUESTS);
+
+        assert_eq!(verify(&client, &user_id, &token), StatusCode::OK);
+        assert!(email_verified(&client, &access));
+        let verified = client.post("/api/accounts/verify-email", Some(&access), json!({}));
+        assert_eq!(verified.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn expired_links_are_rejected() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let (access, user_id, _) = requested_link(&env, &client);
+        let email = client.get("/api/accounts/profile", Some(&access)).body["email"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let expired = sign_email_token(
+            &env,
+            EmailVerifyClaims {
+                sub: user_id.clone(),
+                email,
+                iss: EMAIL_VERIFY_TOKEN_ISSUER.to_string(),
+            },
+            Duration::hours(-EMAIL_TOKEN_HOURS),
+        )
+        .unwrap();
+        assert_eq!(verify(&client, &user_id, &expired), StatusCode::BAD_REQUEST);
+        assert!(!email_verified(&client, &access));
+    }
+
+    #[test]
+    fn links_only_verify_the_user_and_address_they_were_sent_for() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let (access, user_id, token) = requested_link(&env, &client);
+        let (other_access, other_id, _) = requested_link(&env, &client);
+
+        assert_eq!(verify(&client, &other_id, &token), StatusCode::BAD_REQUEST);
+        assert!(!email_verified(&client, &other_access));
+
+        // A signup token for the same address is not a verification token.
+        let email = client.get("/api/accounts/profile", Some(&access)).body["email"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let signup_token = generate_register_token(&env, &email).unwrap();
+        assert_eq!(
+            verify(&client, &user_id, &signup_token),
+            StatusCode::BAD_REQUEST
+        );
+
+        // A link sent before an address change does not verify the new address.
+        let db = db::get_db(&env).unwrap();
+        block_on(async {
+            d1_query!(
+                &db,
+                "UPDATE users SET email = ?1 WHERE id = ?2",
+                "changed@example.com",
+                &user_id
+            )
+            .unwrap()
+            .run()
+            .await
+        })
+        .unwrap();
+        assert_eq!(verify(&client, &user_id, &token), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn signup_links_open_finish_signup_in_the_web_vault() {
+        assert_eq!(
+            signup_link(BASE_URL, "new+user@example.com", "a.b.c"),
+            format!("{BASE_URL}/#/finish-signup?email=new%2Buser%40example.com&token=a.b.c&fromEmail=true")
+        );
+    }
 }
@@ -0,0 +1,539 @@
+//! Admin API for instance operators, authenticated with the `ADMIN_TOKEN` secret.
+
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::d1_query;
+use crate::{
+    auth::AdminAuth,
+    backup,
+    config::{save_config_value, Config, CONFIG_KEYS},
+    db,
+    env::Env,
+    error::AppError,
+    handlers::accounts,
+    migrations,
+    models::{attachment::display_size, user::User},
+    outbox, BaseUrl,
+};
+
+/// Per-user overview returned by `GET /admin/users`.
+#[derive(Debug, Deserialize)]
+struct UserOverviewRow {
+    id: String,
+    name: Option<String>,
+    email: String,
+    email_verified: i64,
+    enabled: i64,
+    created_at: String,
+    last_active: Option<String>,
+    two_factor_enabled: i64,
+    cipher_count: i64,
+    attachment_count: i64,
+    attachment_size: i64,
+    send_count: i64,
+    send_size: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteData {
+    pub email: String,
+}
+
+async fn load_user(db: &db::Db, user_id: &str) -> Result<User, AppError> {
+    User::find_by_id(db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// GET /admin/users
+///
+/// Lists every user with item counts and the bytes used by attachments and file Sends.
+#[worker::send]
+pub async fn list_users(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let rows: Vec<UserOverviewRow> = db
+        .prepare(
+            "SELECT u.id, u.name, u.email, u.email_verified, u.enabled, u.created_at,
+                (SELECT MAX(d.updated_at) FROM devices d WHERE d.user_id = u.id) AS last_active,
+                EXISTS(SELECT 1 FROM twofactor t WHERE t.user_uuid = u.id AND t.enabled = 1 AND t.atype < 1000) AS two_factor_enabled,
+                (SELECT COUNT(*) FROM ciphers c WHERE c.user_id = u.id) AS cipher_count,
+                (SELECT COUNT(*) FROM attachments a JOIN ciphers c ON a.cipher_id = c.id WHERE c.user_id = u.id) AS attachment_count,
+                (SELECT COALESCE(SUM(a.file_size), 0) FROM attachments a JOIN ciphers c ON a.cipher_id = c.id WHERE c.user_id = u.id) AS attachment_size,
+                (SELECT COUNT(*) FROM sends s WHERE s.user_id = u.id) AS send_count,
+                (SELECT COALESCE(SUM(CAST(json_extract(s.data, '$.size') AS INTEGER)), 0) FROM sends s WHERE s.user_id = u.id AND s.type = 1) AS send_size
+             FROM users u
+             ORDER BY u.email",
+        )
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+    let users: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            let storage_used = row.attachment_size + row.send_size;
+            json!({
+                "id": row.id,
+                "name": row.name,
+                "email": row.email,
+                "emailVerified": row.email_verified != 0,
+                "enabled": row.enabled != 0,
+                "createdAt": row.created_at,
+                "lastActive": row.last_active,
+                "twoFactorEnabled": row.two_factor_enabled != 0,
+                "cipherCount": row.cipher_count,
+                "attachmentCount": row.attachment_count,
+                "sendCount": row.send_count,
+                "storageUsed": storage_used,
+                "storageUsedName": display_size(storage_used),
+                "object": "adminUser",
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "data": users,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// POST /admin/users/{id}/disable - Blocks logins and ends existing sessions
+#[worker::send]
+pub async fn disable_user(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_user(&db, &user_id).await?;
+
+    set_user_enabled(&db, &user.id, false).await?;
+    accounts::deauthorize_user(&env, &db, &user.id).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /admin/users/{id}/enable
+#[worker::send]
+pub async fn enable_user(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_user(&db, &user_id).await?;
+
+    set_user_enabled(&db, &user.id, true).await?;
+
+    Ok(Json(json!({})))
+}
+
+async fn set_user_enabled(db: &db::Db, user_id: &str, enabled: bool) -> Result<(), AppError> {
+    d1_query!(
+        db,
+        "UPDATE users SET enabled = ?1, updated_at = ?2 WHERE id = ?3",
+        enabled as i32,
+        db::now_string(),
+        user_id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+    Ok(())
+}
+
+/// POST /admin/users/{id}/delete - Deletes the user like `DELETE /api/accounts` would
+#[worker::send]
+pub async fn delete_user(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_user(&db, &user_id).await?;
+
+    accounts::delete_user(&env, &db, &user).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /admin/users/{id}/remove-2fa - Removes every two-step login provider and the recovery code
+#[worker::send]
+pub async fn remove_2fa(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_user(&db, &user_id).await?;
+
+    d1_query!(&db, "DELETE FROM twofactor WHERE user_uuid = ?1", &user.id)
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+    d1_query!(
+        &db,
+        "UPDATE users SET totp_recover = NULL, updated_at = ?1 WHERE id = ?2",
+        db::now_string(),
+        &user.id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /admin/users/{id}/deauth - Logs the user out on every device
+#[worker::send]
+pub async fn deauth_user(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_user(&db, &user_id).await?;
+
+    accounts::deauthorize_user(&env, &db, &user.id).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /admin/invite - Lets an address sign up even if `ALLOWED_EMAILS` does not match it
+#[worker::send]
+pub async fn invite_user(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(payload): Json<InviteData>,
+) -> Result<Json<Value>, AppError> {
+    let email = payload.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(AppError::BadRequest("Invalid email address".to_string()));
+    }
+
+    let db = db::get_db(&env)?;
+    if User::find_by_email(&db, &email).await?.is_some() {
+        return Err(AppError::BadRequest("User already exists".to_string()));
+    }
+
+    let link = accounts::invite_user(&env, &db, &base_url, &email).await?;
+
+    Ok(Json(json!({
+        "email": email,
+        "signupLink": link,
+        "object": "invitation",
+    })))
+}
+
+/// GET /admin/config - Lists every runtime setting with its effective source
+#[worker::send]
+pub async fn get_config(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    Ok(Json(Config::load(&env).await?.to_json()))
+}
+
+/// POST /admin/config
+///
+/// Takes an object of setting name to value. A `null` value removes the stored value so the
+/// environment variable or the default applies again. Nothing is saved unless every value is valid.
+#[worker::send]
+pub async fn update_config(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<Map<String, Value>>,
+) -> Result<Json<Value>, AppError> {
+    let mut updates: Vec<(&str, Option<String>)> = Vec::with_capacity(payload.len());
+    for (key, value) in &payload {
+        let key = CONFIG_KEYS
+            .iter()
+            .copied()
+            .find(|known| known.eq_ignore_ascii_case(key))
+            .ok_or_else(|| AppError::BadRequest(format!("Unknown setting {key}")))?;
+        let value = match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => {
+                return Err(AppError::BadRequest(format!(
+                    "{key} must be a string, number, boolean or null"
+                )))
+            }
+        };
+        if let Some(value) = &value {
+            Config::validate(key, value)
+                .map_err(|e| AppError::BadRequest(format!("Invalid {key}: {e}")))?;
+        }
+        updates.push((key, value));
+    }
+
+    let db = db::get_db(&env)?;
+    for (key, value) in &updates {
+        save_config_value(&db, key, value.as_deref()).await?;
+    }
+
+    Ok(Json(Config::load(&env).await?.to_json()))
+}
+
+/// GET /admin/schema - Reports the schema version and the migrations applied to D1
+#[worker::send]
+pub async fn schema_status(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    Ok(Json(migrations::schema_status(&env).await?))
+}
+
+/// GET /admin/outbox - Reports queued and dead-lettered notification deliveries
+#[worker::send]
+pub async fn outbox_status(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    Ok(Json(outbox::status(&env).await?))
+}
+
+/// GET /admin/backups - Lists the backups in `BACKUP_BUCKET`, newest first
+#[worker::send]
+pub async fn list_backups(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let backups: Vec<Value> = backup::list_backups(&env)
+        .await?
+        .iter()
+        .map(backup::BackupInfo::to_json)
+        .collect();
+    Ok(Json(json!({ "data": backups, "object": "list" })))
+}
+
+/// POST /admin/backups - Takes a backup now, outside the cron schedule
+#[worker::send]
+pub async fn create_backup(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    Ok(Json(backup::create_backup(&env).await?.to_json()))
+}
+
+/// GET /admin/backups/{name} - Downloads the archive as stored
+#[worker::send]
+pub async fn download_backup(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let data = backup::read_backup(&env, &name).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}\""),
+            ),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+/// POST /admin/backups/{name}/restore
+///
+/// Loads a backup into a database that has the schema but no users yet.
+#[worker::send]
+pub async fn restore_backup(
+    _admin: AdminAuth,
+    State(env): State<Arc<Env>>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let tables: Map<String, Value> = backup::restore_backup(&env, &name)
+        .await?
+        .into_iter()
+        .map(|(table, rows)| (table, json!(rows)))
+        .collect();
+    Ok(Json(json!({ "name": name, "tables": tables })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::crypto::hash_password_for_storage;
+    use crate::testing::{block_on, TestClient, TestResponse, TestUser};
+
+    const ADMIN_SECRET: &str = "correct horse battery staple";
+
+    /// A client whose server has `ADMIN_TOKEN` set to the hash of [`ADMIN_SECRET`].
+    fn admin_client() -> TestClient {
+        let salt = "c2FsdHNhbHRzYWx0";
+        let hash = block_on(hash_password_for_storage(ADMIN_SECRET, salt, 1000)).unwrap();
+        let admin_token = format!("pbkdf2-sha256$1000${salt}${hash}");
+        TestClient::with_env(Env::with_vars(&[("ADMIN_TOKEN", &admin_token)]))
+    }
+
+    fn admin_post(client: &TestClient, path: &str, body: Value) -> TestResponse {
+        client.post(path, Some(ADMIN_SECRET), body)
+    }
+
+    /// Log in and return the access and refresh tokens.
+    fn session(client: &TestClient, user: &TestUser) -> (String, String) {
+        let response = client.token(user, &[]);
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let token = |name: &str| response.body[name].as_str().unwrap().to_string();
+        (token("access_token"), token("refresh_token"))
+    }
+
+    fn refresh(client: &TestClient, refresh_token: &str) -> StatusCode {
+        client
+            .post_form(
+                "/identity/connect/token",
+                &[
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", refresh_token),
+                    ("client_id", "web"),
+                ],
+            )
+            .status
+    }
+
+    fn user_id(client: &TestClient, access_token: &str) -> String {
+        let profile = client.get("/api/accounts/profile", Some(access_token));
+        profile.body["id"].as_str().unwrap().to_string()
+    }
+
+    fn setting<'a>(config: &'a Value, key: &str) -> &'a Value {
+        config["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["key"] == key)
+            .unwrap_or_else(|| panic!("{key} is listed"))
+    }
+
+    #[test]
+    fn the_admin_api_needs_the_admin_token() {
+        let without = TestClient::new();
+        assert_eq!(
+            without.get("/admin/users", Some(ADMIN_SECRET)).status,
+            StatusCode::NOT_FOUND
+        );
+
+        let client = admin_client();
+        let user_token = client.login(&client.register());
+        for token in [None, Some("wrong"), Some(user_token.as_str())] {
+            assert_eq!(
+                client.get("/admin/users", token).status,
+                StatusCode::UNAUTHORIZED,
+                "{token:?}"
+            );
+        }
+        let users = client.get("/admin/users", Some(ADMIN_SECRET));
+        assert_eq!(users.status, StatusCode::OK, "{}", users.body);
+        assert_eq!(users.body["data"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn disabled_users_cannot_log_in_until_enabled_again() {
+        let client = admin_client();
+        let user = client.register();
+        let (access, refresh_token) = session(&client, &user);
+        let id = user_id(&client, &access);
+
+        let disabled = admin_post(&client, &format!("/admin/users/{id}/disable"), json!({}));
+        assert_eq!(disabled.status, StatusCode::OK, "{}", disabled.body);
+        assert_eq!(
+            client.get("/api/accounts/profile", Some(&access)).status,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(refresh(&client, &refresh_token), StatusCode::BAD_REQUEST);
+        let login = client.token(&user, &[]);
+        assert_eq!(login.status, StatusCode::UNAUTHORIZED, "{}", login.body);
+
+        admin_post(&client, &format!("/admin/users/{id}/enable"), json!({}));
+        session(&client, &user);
+    }
+
+    #[test]
+    fn deauth_ends_every_session_but_allows_new_logins() {
+        let client = admin_client();
+        let user = client.register();
+        let (access, refresh_token) = session(&client, &user);
+        let id = user_id(&client, &access);
+
+        let deauthed = admin_post(&client, &format!("/admin/users/{id}/deauth"), json!({}));
+        assert_eq!(deauthed.status, StatusCode::OK, "{}", deauthed.body);
+        assert_eq!(
+            client.get("/api/accounts/profile", Some(&access)).status,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(refresh(&client, &refresh_token), StatusCode::BAD_REQUEST);
+
+        let (access, _) = session(&client, &user);
+        assert_eq!(
+            client.get("/api/accounts/profile", Some(&access)).status,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn config_updates_are_saved_all_or_nothing() {
+        let client = admin_client();
+
+        for rejected in [
+            json!({ "TRASH_AUTO_DELETE_DAYS": "10", "PASSWORD_ITERATIONS": "1" }),
+            json!({ "TRASH_AUTO_DELETE_DAYS": "10", "NOT_A_SETTING": "1" }),
+            json!({ "TRASH_AUTO_DELETE_DAYS": "10", "SIGNUPS_VERIFY": ["yes"] }),
+        ] {
+            let response = admin_post(&client, "/admin/config", rejected.clone());
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "{rejected}");
+        }
+        let config = client.get("/admin/config", Some(ADMIN_SECRET)).body;
+        assert_eq!(
+            setting(&config, "TRASH_AUTO_DELETE_DAYS")["source"],
+            "default"
+        );
+
+        let saved = admin_post(
+            &client,
+            "/admin/config",
+            json!({ "trash_auto_delete_days": 10, "SIGNUPS_VERIFY": true }),
+        );
+        assert_eq!(saved.status, StatusCode::OK, "{}", saved.body);
+        let trash = setting(&saved.body, "TRASH_AUTO_DELETE_DAYS");
+        assert_eq!(trash["source"], "database");
+        assert_eq!(trash["value"], "10");
+        assert_eq!(setting(&saved.body, "SIGNUPS_VERIFY")["value"], "true");
+
+        let reset = admin_post(
+            &client,
+            "/admin/config",
+            json!({ "TRASH_AUTO_DELETE_DAYS": null }),
+        );
+        assert_eq!(
+            setting(&reset.body, "TRASH_AUTO_DELETE_DAYS")["source"],
+            "default"
+        );
+        assert_eq!(setting(&reset.body, "SIGNUPS_VERIFY")["source"], "database");
+    }
+}
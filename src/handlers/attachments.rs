@@ -12,54 +12,24 @@ use log;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
-use worker::{wasm_bindgen::JsValue, Env, HttpMetadata};
 
 use crate::d1_query;
 
 use crate::{
     auth::{Claims, JWT_VALIDATION_LEEWAY_SECS},
+    config::Config,
     db::{self, touch_user_updated_at},
+    env::Env,
     error::AppError,
     models::{
         attachment::{AttachmentDB, AttachmentResponse},
         cipher::{Cipher, CipherDBModel},
     },
     notifications::{self, UpdateType},
+    storage::{self, is_kv_backend, KV_MAX_VALUE_BYTES},
     BaseUrl,
 };
 
-const ATTACHMENTS_BUCKET: &str = "ATTACHMENTS_BUCKET";
-const ATTACHMENTS_KV: &str = "ATTACHMENTS_KV";
-
-const DEFAULT_ATTACHMENT_TTL_SECS: i64 = 300; // 5 minutes
-const KV_MAX_VALUE_BYTES: i64 = 25 * 1024 * 1024; // 25 MiB (KV hard limit)
-
-/// Storage backend for attachments
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub(crate) enum StorageBackend {
-    /// Cloudflare KV - no credit card required, 25MB limit per value
-    KV,
-    /// Cloudflare R2 - requires credit card, no practical size limit
-    R2,
-}
-
-/// Detect which storage backend is available.
-/// Priority: R2 if bound, otherwise KV.
-pub(crate) fn get_storage_backend(env: &Env) -> Option<StorageBackend> {
-    if env.bucket(ATTACHMENTS_BUCKET).is_ok() {
-        Some(StorageBackend::R2)
-    } else if env.kv(ATTACHMENTS_KV).is_ok() {
-        Some(StorageBackend::KV)
-    } else {
-        None
-    }
-}
-
-/// Check if using KV backend (for behavior differences)
-pub(crate) fn is_kv_backend(env: &Env) -> bool {
-    get_storage_backend(env) == Some(StorageBackend::KV)
-}
-
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AttachmentCreateRequest {
@@ -154,6 +124,7 @@ pub(crate) async fn touch_cipher_updated_at(
 pub async fn create_attachment_v2(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
     Path(cipher_id): Path<String>,
     Json(payload): Json<AttachmentCreateRequest>,
@@ -185,6 +156,7 @@ pub async fn create_attachment_v2(
     enforce_limits(
         &db,
         &env,
+        &config,
         &claims.sub,
         declared_size,
         None, /* exclude_attachment */
@@ -213,6 +185,7 @@ pub async fn create_attachment_v2(
     // Return upload URL pointing to local upload endpoint
     let token = build_upload_download_token(
         &env,
+        &config,
         &claims.sub,
         &claims.device,
         &cipher_id,
@@ -329,6 +302,7 @@ pub async fn upload_attachment_v2_data(
 pub async fn upload_attachment_legacy(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Path(cipher_id): Path<String>,
     mut multipart: Multipart,
 ) -> Result<Json<Cipher>, AppError> {
@@ -354,7 +328,7 @@ pub async fn upload_attachment_legacy(
     }
 
     // Validate capacity limits
-    enforce_limits(&db, &env, &claims.sub, actual_size, None).await?;
+    enforce_limits(&db, &env, &config, &claims.sub, actual_size, None).await?;
 
     let attachment_id = Uuid::new_v4().to_string();
     let now = db::now_string();
@@ -408,6 +382,7 @@ pub async fn upload_attachment_legacy(
 pub async fn get_attachment(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
     Path((cipher_id, attachment_id)): Path<(String, String)>,
 ) -> Result<Json<AttachmentResponse>, AppError> {
@@ -429,6 +404,7 @@ pub async fn get_attachment(
 
     let token = build_upload_download_token(
         &env,
+        &config,
         &claims.sub,
         &claims.device,
         &cipher_id,
@@ -528,43 +504,14 @@ pub async fn hydrate_cipher_attachments(
 }
 
 pub(crate) fn attachments_enabled(env: &Env) -> bool {
-    get_storage_backend(env).is_some()
-}
-
-fn is_not_found_error(err: &worker::Error) -> bool {
-    let msg = err.to_string();
-    msg.contains("NoSuchKey") || msg.contains("404") || msg.contains("NotFound")
+    storage::file_storage(env).is_some()
 }
 
-/// Delete objects from storage (KV or R2 based on configured backend)
+/// Delete objects from storage; a no-op when attachments are not enabled.
 pub(crate) async fn delete_storage_objects(env: &Env, keys: &[String]) -> Result<(), AppError> {
-    match get_storage_backend(env) {
-        Some(StorageBackend::KV) => {
-            let kv = env.kv(ATTACHMENTS_KV).map_err(|_| AppError::Internal)?;
-            for key in keys {
-                // KV delete is idempotent - no error if key doesn't exist
-                if let Err(e) = kv.delete(key).await {
-                    log::error!("KV delete error for key '{}': {:?}", key, e);
-                    return Err(AppError::Internal);
-                }
-            }
-            Ok(())
-        }
-        Some(StorageBackend::R2) => {
-            let bucket = env
-                .bucket(ATTACHMENTS_BUCKET)
-                .map_err(|_| AppError::Internal)?;
-            for key in keys {
-                if let Err(err) = bucket.delete(key).await {
-                    if !is_not_found_error(&err) {
-                        log::error!("R2 delete error for key '{}': {:?}", key, err);
-                        return Err(AppError::Worker(err));
-                    }
-                }
-            }
-            Ok(())
-        }
-        None => Ok(()), // No-op if attachments not enabled
+    match storage::file_storage(env) {
+        Some(storage) => storage.delete(keys).await,
+        None => Ok(()),
     }
 }
 
@@ -584,7 +531,7 @@ pub(crate) async fn list_attachment_keys_for_cipher_ids_json(
     user_id: Option<&str>,
 ) -> Result<Vec<String>, AppError> {
     let mut sql = "SELECT a.cipher_id, a.id FROM attachments a JOIN ciphers c ON a.cipher_id = c.id WHERE c.id IN (SELECT value FROM json_each(?1, ?2))".to_string();
-    let mut params: Vec<worker::wasm_bindgen::JsValue> =
+    let mut params: Vec<db::BindValue> =
         vec![json_body.to_owned().into(), ids_path.to_owned().into()];
 
     if let Some(uid) = user_id {
@@ -733,42 +680,12 @@ fn build_attachment_map(
 pub(crate) async fn upload_to_storage(
     env: &Env,
     key: &str,
-    _content_type: Option<String>,
+    content_type: Option<String>,
     data: Vec<u8>,
 ) -> Result<(), AppError> {
-    match get_storage_backend(env) {
-        Some(StorageBackend::KV) => {
-            let kv = env.kv(ATTACHMENTS_KV).map_err(|_| AppError::Internal)?;
-            // KV put_bytes stores raw binary data
-            if let Err(e) = kv
-                .put_bytes(key, &data)
-                .map_err(|_| AppError::Internal)?
-                .execute()
-                .await
-            {
-                log::error!("KV put error for key '{}': {:?}", key, e);
-                return Err(AppError::Internal);
-            }
-            Ok(())
-        }
-        Some(StorageBackend::R2) => {
-            let bucket = env
-                .bucket(ATTACHMENTS_BUCKET)
-                .map_err(|_| AppError::Internal)?;
-            let mut builder = bucket.put(key, data);
-            if let Some(ct) = _content_type {
-                builder = builder.http_metadata(HttpMetadata {
-                    content_type: Some(ct),
-                    ..Default::default()
-                });
-            }
-            builder.execute().await.map_err(AppError::Worker)?;
-            Ok(())
-        }
-        None => Err(AppError::BadRequest(
-            "Attachments are not enabled".to_string(),
-        )),
-    }
+    let storage = storage::file_storage(env)
+        .ok_or_else(|| AppError::BadRequest("Attachments are not enabled".to_string()))?;
+    storage.put(key, content_type.as_deref(), data).await
 }
 
 async fn read_multipart(
@@ -813,12 +730,13 @@ async fn read_multipart(
 
 fn build_upload_download_token(
     env: &Env,
+    config: &Config,
     user_id: &str,
     device: &str,
     cipher_id: &str,
     attachment_id: &str,
 ) -> Result<String, AppError> {
-    let ttl_secs = download_ttl_secs(env)?;
+    let ttl_secs = config.attachment_ttl_secs;
     let now = Utc::now().timestamp();
     let exp = now
         .checked_add(ttl_secs)
@@ -857,29 +775,10 @@ pub(crate) fn jwt_secret(env: &Env) -> Result<String, AppError> {
     Ok(env.secret("JWT_SECRET")?.to_string())
 }
 
-fn download_ttl_secs(env: &Env) -> Result<i64, AppError> {
-    match env.var("ATTACHMENT_TTL_SECS") {
-        Ok(v) => {
-            let raw = v.to_string();
-            let ttl = raw.parse::<i64>().map_err(|err| {
-                log::error!("Invalid ATTACHMENT_TTL_SECS '{}': {}", raw, err);
-                AppError::Internal
-            })?;
-
-            if ttl <= 0 {
-                log::error!("ATTACHMENT_TTL_SECS '{}' must be positive", raw);
-                return Err(AppError::Internal);
-            }
-
-            Ok(ttl)
-        }
-        Err(_) => Ok(DEFAULT_ATTACHMENT_TTL_SECS),
-    }
-}
-
 async fn enforce_limits(
     db: &crate::db::Db,
     env: &Env,
+    config: &Config,
     user_id: &str,
     new_size: i64,
     exclude_attachment: Option<&str>,
@@ -898,8 +797,7 @@ async fn enforce_limits(
         )));
     }
 
-    let max_bytes = attachment_max_bytes(env)?;
-    if let Some(max_bytes) = max_bytes {
+    if let Some(max_bytes) = config.attachment_max_bytes {
         if new_size as u64 > max_bytes {
             return Err(AppError::BadRequest(
                 "Attachment size exceeds limit".to_string(),
@@ -908,8 +806,7 @@ async fn enforce_limits(
     }
 
     // Check total storage limit
-    let limit_bytes = total_limit_bytes(env)?;
-    if let Some(limit_bytes) = limit_bytes {
+    if let Some(limit_bytes) = config.attachment_total_limit_bytes() {
         let used = user_attachment_usage(db, user_id, exclude_attachment).await?;
         let limit = limit_bytes as i64;
         let new_total = used
@@ -926,48 +823,12 @@ async fn enforce_limits(
     Ok(())
 }
 
-fn attachment_max_bytes(env: &Env) -> Result<Option<u64>, AppError> {
-    match env.var("ATTACHMENT_MAX_BYTES") {
-        Ok(v) => {
-            let raw = v.to_string();
-            raw.parse::<u64>().map(Some).map_err(|err| {
-                log::error!("Invalid ATTACHMENT_MAX_BYTES '{}': {}", raw, err);
-                AppError::Internal
-            })
-        }
-        Err(_) => Ok(None),
-    }
-}
-
-fn total_limit_bytes(env: &Env) -> Result<Option<u64>, AppError> {
-    match env.var("ATTACHMENT_TOTAL_LIMIT_KB") {
-        Ok(v) => {
-            let raw = v.to_string();
-            let kb = raw.parse::<u64>().map_err(|err| {
-                log::error!("Invalid ATTACHMENT_TOTAL_LIMIT_KB '{}': {}", raw, err);
-                AppError::Internal
-            })?;
-
-            let bytes = kb.checked_mul(1024).ok_or_else(|| {
-                log::error!(
-                    "ATTACHMENT_TOTAL_LIMIT_KB '{}' overflowed when converting to bytes",
-                    raw
-                );
-                AppError::Internal
-            })?;
-
-            Ok(Some(bytes))
-        }
-        Err(_) => Ok(None),
-    }
-}
-
 async fn user_attachment_usage(
     db: &crate::db::Db,
     user_id: &str,
     exclude_attachment: Option<&str>,
 ) -> Result<i64, AppError> {
-    let (query_str, bindings): (String, Vec<JsValue>) = if let Some(id) = exclude_attachment {
+    let (query_str, bindings): (String, Vec<db::BindValue>) = if let Some(id) = exclude_attachment {
         (
             "SELECT COALESCE(SUM(file_size), 0) as total FROM (
                 SELECT a.file_size AS file_size
@@ -981,7 +842,7 @@ async fn user_attachment_usage(
                 WHERE c2.user_id = ?1 AND p.id != ?2
             ) AS files"
                 .to_string(),
-            vec![JsValue::from_str(user_id), JsValue::from_str(id)],
+            vec![user_id.into(), id.into()],
         )
     } else {
         (
@@ -997,7 +858,7 @@ async fn user_attachment_usage(
                 WHERE c2.user_id = ?1
             ) AS files"
                 .to_string(),
-            vec![JsValue::from_str(user_id)],
+            vec![user_id.into()],
         )
     };
 
@@ -1015,3 +876,123 @@ async fn user_attachment_usage(
 
     Ok(total)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::testing::{enc_string, Env, TestClient};
+
+    fn create_cipher(client: &TestClient, token: &str) -> String {
+        let created = client.post(
+            "/api/ciphers",
+            Some(token),
+            json!({ "type": 2, "name": enc_string(), "secureNote": { "type": 0 } }),
+        );
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        created.body["id"].as_str().unwrap().to_string()
+    }
+
+    fn synced_attachments(client: &TestClient, token: &str) -> Value {
+        let sync = client.get("/api/sync", Some(token));
+        sync.body["ciphers"][0]["attachments"].clone()
+    }
+
+    #[test]
+    fn attachments_are_disabled_without_file_storage() {
+        let client = TestClient::new();
+        let token = client.login(&client.register());
+        let id = create_cipher(&client, &token);
+
+        let created = client.post(
+            &format!("/api/ciphers/{id}/attachment/v2"),
+            Some(&token),
+            json!({ "key": enc_string(), "fileName": enc_string(), "fileSize": 5 }),
+        );
+        assert_eq!(created.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn uploaded_attachments_are_stored_listed_and_deleted() {
+        let env = Env::new().with_file_storage();
+        let files = env.file_storage().unwrap();
+        let client = TestClient::with_env(env);
+        let token = client.login(&client.register());
+        let id = create_cipher(&client, &token);
+
+        let file_name = enc_string();
+        let created = client.post(
+            &format!("/api/ciphers/{id}/attachment/v2"),
+            Some(&token),
+            json!({ "key": enc_string(), "fileName": file_name, "fileSize": 5 }),
+        );
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        let attachment_id = created.body["attachmentId"].as_str().unwrap();
+        let path = format!("/api/ciphers/{id}/attachment/{attachment_id}");
+
+        let short = client.post_multipart(&path, Some(&token), &[("data", Some("f"), b"abc")]);
+        assert_eq!(short.status, StatusCode::BAD_REQUEST);
+        assert!(files.keys().is_empty());
+
+        // The failed upload dropped the pending attachment; declare it again.
+        let created = client.post(
+            &format!("/api/ciphers/{id}/attachment/v2"),
+            Some(&token),
+            json!({ "key": enc_string(), "fileName": file_name, "fileSize": 5 }),
+        );
+        let attachment_id = created.body["attachmentId"].as_str().unwrap();
+        let path = format!("/api/ciphers/{id}/attachment/{attachment_id}");
+        let uploaded = client.post_multipart(&path, Some(&token), &[("data", Some("f"), b"hello")]);
+        assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+
+        let key = format!("{id}/{attachment_id}");
+        assert_eq!(
+            files.get(&key),
+            Some((
+                Some("application/octet-stream".to_string()),
+                b"hello".to_vec()
+            ))
+        );
+        let listed = synced_attachments(&client, &token);
+        assert_eq!(listed[0]["id"], attachment_id);
+        assert_eq!(listed[0]["fileName"], file_name.as_str());
+        assert_eq!(listed[0]["size"], "5");
+
+        let deleted = client.delete(&path, Some(&token));
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+        assert!(files.get(&key).is_none());
+        assert!(synced_attachments(&client, &token).is_null());
+    }
+
+    #[test]
+    fn deleting_a_cipher_deletes_its_attachment_files() {
+        let env = Env::new().with_file_storage();
+        let files = env.file_storage().unwrap();
+        let client = TestClient::with_env(env);
+        let token = client.login(&client.register());
+        let id = create_cipher(&client, &token);
+        let other = create_cipher(&client, &token);
+
+        for cipher_id in [&id, &other] {
+            let key = enc_string();
+            let uploaded = client.post_multipart(
+                &format!("/api/ciphers/{cipher_id}/attachment"),
+                Some(&token),
+                &[
+                    ("key", None, key.as_bytes()),
+                    ("data", Some("legacy.txt"), b"contents"),
+                ],
+            );
+            assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+            assert_eq!(uploaded.body["attachments"][0]["fileName"], "legacy.txt");
+        }
+        assert_eq!(files.keys().len(), 2);
+
+        let deleted = client.delete(&format!("/api/ciphers/{id}"), Some(&token));
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+        let remaining = files.keys();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].starts_with(&format!("{other}/")));
+    }
+}
@@ -6,12 +6,12 @@ use axum::{
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::Env;
 
 use crate::{
     auth::Claims,
     client_context::{request_device_type_from_headers, request_ip_from_headers},
     db,
+    env::Env,
     error::AppError,
     models::{auth_request::AuthRequest, device::Device, user::User},
     notifications, BaseUrl,
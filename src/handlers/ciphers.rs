@@ -1,23 +1,27 @@
 use crate::d1_query;
-use axum::extract::Path;
-use axum::http::header;
+use crate::env::Env;
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap};
 use axum::response::{IntoResponse, Response};
 use axum::{extract::State, Extension, Json};
 use chrono::{DateTime, Utc};
 use log; // Used for warning logs on parse failures
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{wasm_bindgen::JsValue, Env};
 
 use crate::auth::Claims;
+use crate::config::Config;
 use crate::db;
 use crate::error::AppError;
-use crate::handlers::attachments;
+use crate::handlers::{attachments, events};
 use crate::models::cipher::{
-    Cipher, CipherDBModel, CipherData, CipherRequestData, CreateCipherRequest, PartialCipherData,
+    Cipher, CipherCollectionsData, CipherDBModel, CipherData, CipherRequestData,
+    CreateCipherRequest, PartialCipherData, ShareCiphersBulkRequest,
 };
+use crate::models::event::EventType;
+use crate::models::organization::Membership;
 use crate::models::user::{PasswordOrOtpData, User};
 use crate::notifications::{self, UpdateType};
 use crate::BaseUrl;
@@ -32,11 +36,63 @@ impl IntoResponse for RawJson {
     }
 }
 
-/// Helper to fetch a cipher by id for a user or return NotFound.
+/// SQL predicate matching the ciphers (aliased `c`) readable by the user bound to `user_param`:
+/// their personal ciphers plus organization ciphers granted through the `cipher_access` view.
+pub(crate) fn cipher_read_access_sql(user_param: &str) -> String {
+    format!(
+        "(c.user_id = {user_param} OR c.id IN (SELECT ca.cipher_id FROM cipher_access ca WHERE ca.user_id = {user_param}))"
+    )
+}
+
+/// Like [`cipher_read_access_sql`], but organization ciphers need a grant that is not read-only.
+pub(crate) fn cipher_write_access_sql(user_param: &str) -> String {
+    format!(
+        "(c.user_id = {user_param} OR c.id IN (SELECT ca.cipher_id FROM cipher_access ca WHERE ca.user_id = {user_param} AND ca.read_only = 0))"
+    )
+}
+
+/// Helper to fetch a cipher readable by a user or return NotFound.
 async fn fetch_cipher_for_user(
     db: &crate::db::Db,
     cipher_id: &str,
     user_id: &str,
+) -> Result<CipherDBModel, AppError> {
+    let sql = format!(
+        "SELECT c.* FROM ciphers c WHERE c.id = ?1 AND {}",
+        cipher_read_access_sql("?2")
+    );
+    db.prepare(&sql)
+        .bind(&[cipher_id.to_string().into(), user_id.to_string().into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))
+}
+
+/// Helper to fetch a cipher writable by a user or return NotFound.
+async fn fetch_writable_cipher_for_user(
+    db: &crate::db::Db,
+    cipher_id: &str,
+    user_id: &str,
+) -> Result<CipherDBModel, AppError> {
+    let sql = format!(
+        "SELECT c.* FROM ciphers c WHERE c.id = ?1 AND {}",
+        cipher_write_access_sql("?2")
+    );
+    db.prepare(&sql)
+        .bind(&[cipher_id.to_string().into(), user_id.to_string().into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))
+}
+
+/// Helper to fetch a personal (non-organization) cipher of a user or return NotFound.
+/// Folders, favorites and archiving are stored on the cipher row, so they only apply to these.
+async fn fetch_personal_cipher_for_user(
+    db: &crate::db::Db,
+    cipher_id: &str,
+    user_id: &str,
 ) -> Result<CipherDBModel, AppError> {
     db.prepare("SELECT * FROM ciphers WHERE id = ?1 AND user_id = ?2")
         .bind(&[cipher_id.to_string().into(), user_id.to_string().into()])?
@@ -46,15 +102,265 @@ async fn fetch_cipher_for_user(
         .ok_or_else(|| AppError::NotFound("Cipher not found".to_string()))
 }
 
+#[derive(Deserialize)]
+struct CipherAccessRow {
+    collection_id: Option<String>,
+    read_only: i32,
+    hide_passwords: i32,
+}
+
+/// Fill in the per-user organization fields (`edit`, `viewPassword`, `collectionIds`)
+/// of a cipher response. Personal ciphers keep their defaults.
+async fn hydrate_cipher_access(
+    db: &crate::db::Db,
+    cipher: &mut Cipher,
+    user_id: &str,
+) -> Result<(), AppError> {
+    if cipher.organization_id.is_none() {
+        return Ok(());
+    }
+
+    let rows: Vec<CipherAccessRow> = d1_query!(
+        db,
+        "SELECT collection_id, read_only, hide_passwords FROM cipher_access WHERE cipher_id = ?1 AND user_id = ?2",
+        &cipher.id,
+        user_id
+    )
+    .map_err(|_| AppError::Database)?
+    .all()
+    .await
+    .map_err(|_| AppError::Database)?
+    .results()
+    .map_err(|_| AppError::Database)?;
+
+    let mut collection_ids: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.collection_id.clone())
+        .collect();
+    collection_ids.sort();
+    collection_ids.dedup();
+
+    cipher.organization_use_totp = true;
+    cipher.edit = rows.iter().any(|row| row.read_only == 0);
+    cipher.view_password = rows.iter().any(|row| row.hide_passwords == 0);
+    cipher.collection_ids = Some(collection_ids);
+    Ok(())
+}
+
+/// Bump revision dates and notify everyone who can see a cipher: the owner of a
+/// personal cipher, or every confirmed member of the cipher's organization.
+async fn publish_cipher_change(
+    env: &Arc<Env>,
+    db: &crate::db::Db,
+    claims: &Claims,
+    organization_id: Option<&str>,
+    update_type: UpdateType,
+    cipher_id: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    let Some(org_id) = organization_id else {
+        db::touch_user_updated_at(db, &claims.sub, now).await?;
+        notifications::publish_cipher_update(
+            (**env).clone(),
+            claims.sub.clone(),
+            update_type,
+            cipher_id.to_string(),
+            now.to_string(),
+            Some(claims.device.clone()),
+        );
+        return Ok(());
+    };
+
+    Membership::touch_members_updated_at(db, org_id, now).await?;
+    for user_id in Membership::list_confirmed_user_ids(db, org_id).await? {
+        let context_id = (user_id == claims.sub).then(|| claims.device.clone());
+        notifications::publish_cipher_update(
+            (**env).clone(),
+            user_id,
+            update_type,
+            cipher_id.to_string(),
+            now.to_string(),
+            context_id,
+        );
+    }
+    Ok(())
+}
+
+/// Organizations owning any of the ciphers listed at `$.ids` of a JSON request body.
+async fn organization_ids_for_cipher_ids_json(
+    db: &crate::db::Db,
+    body: &str,
+) -> Result<Vec<String>, AppError> {
+    let rows: Vec<Value> = d1_query!(
+        db,
+        "SELECT DISTINCT organization_id FROM ciphers
+         WHERE organization_id IS NOT NULL AND id IN (SELECT value FROM json_each(?1, '$.ids'))",
+        body
+    )
+    .map_err(|_| AppError::Database)?
+    .all()
+    .await
+    .map_err(db::map_d1_json_error)?
+    .results()
+    .map_err(|_| AppError::Database)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| row.get("organization_id")?.as_str().map(str::to_owned))
+        .collect())
+}
+
+/// Bulk counterpart of [`publish_cipher_change`]: resync the acting user and the
+/// members of every organization touched by the operation.
+async fn publish_bulk_cipher_change(
+    env: &Arc<Env>,
+    db: &crate::db::Db,
+    claims: &Claims,
+    organization_ids: &[String],
+    now: &str,
+) -> Result<(), AppError> {
+    db::touch_user_updated_at(db, &claims.sub, now).await?;
+    notifications::publish_user_update(
+        (**env).clone(),
+        claims.sub.clone(),
+        UpdateType::SyncCiphers,
+        now.to_string(),
+        Some(claims.device.clone()),
+    );
+
+    for org_id in organization_ids {
+        Membership::touch_members_updated_at(db, org_id, now).await?;
+        for user_id in Membership::list_confirmed_user_ids(db, org_id).await? {
+            if user_id == claims.sub {
+                continue;
+            }
+            notifications::publish_user_update(
+                (**env).clone(),
+                user_id,
+                UpdateType::SyncCiphers,
+                now.to_string(),
+                None,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check that `user_id` may add ciphers to `collection_ids` of `org_id`.
+/// Members with full access may leave a cipher without collections; everybody else
+/// must pick at least one collection they can write to.
+async fn ensure_org_collections_writable(
+    db: &crate::db::Db,
+    user_id: &str,
+    org_id: &str,
+    collection_ids: &[String],
+) -> Result<(), AppError> {
+    let membership = Membership::find_by_user_and_org(db, user_id, org_id)
+        .await?
+        .filter(Membership::is_confirmed)
+        .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+    if collection_ids.is_empty() {
+        if membership.has_full_access() {
+            return Ok(());
+        }
+        return Err(AppError::BadRequest(
+            "You must select at least one collection.".to_string(),
+        ));
+    }
+
+    let ids_json = serde_json::to_string(collection_ids).map_err(|_| AppError::Internal)?;
+    let writable: Option<i64> = if membership.has_full_access() {
+        d1_query!(
+            db,
+            "SELECT COUNT(*) AS cnt FROM collections
+             WHERE org_id = ?1 AND id IN (SELECT value FROM json_each(?2))",
+            org_id,
+            ids_json
+        )
+    } else {
+        d1_query!(
+            db,
+            "SELECT COUNT(*) AS cnt FROM collections col
+             JOIN users_collections uc ON uc.collection_id = col.id AND uc.user_id = ?3
+             WHERE col.org_id = ?1 AND col.id IN (SELECT value FROM json_each(?2)) AND uc.read_only = 0",
+            org_id,
+            ids_json,
+            user_id
+        )
+    }
+    .map_err(|_| AppError::Database)?
+    .first(Some("cnt"))
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    let mut unique_ids = collection_ids.to_vec();
+    unique_ids.sort();
+    unique_ids.dedup();
+    if writable.unwrap_or(0) != unique_ids.len() as i64 {
+        return Err(AppError::BadRequest(
+            "Collection not found or you don't have write access to it.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Statements replacing the collections of an organization cipher.
+fn replace_cipher_collections_statements(
+    db: &crate::db::Db,
+    cipher_id: &str,
+    collection_ids: &[String],
+) -> Result<Vec<db::Statement>, AppError> {
+    let mut statements = Vec::with_capacity(collection_ids.len() + 1);
+    statements.push(
+        d1_query!(
+            db,
+            "DELETE FROM ciphers_collections WHERE cipher_id = ?1",
+            cipher_id
+        )
+        .map_err(|_| AppError::Database)?,
+    );
+    for collection_id in collection_ids {
+        statements.push(
+            d1_query!(
+                db,
+                "INSERT OR IGNORE INTO ciphers_collections (cipher_id, collection_id) VALUES (?1, ?2)",
+                cipher_id,
+                collection_id
+            )
+            .map_err(|_| AppError::Database)?,
+        );
+    }
+    Ok(statements)
+}
+
 #[worker::send]
 pub async fn create_cipher(
     claims: Claims,
     State(env): State<Arc<Env>>,
     Json(payload): Json<CreateCipherRequest>,
 ) -> Result<Json<Cipher>, AppError> {
-    let db = db::get_db(&env)?;
+    insert_new_cipher(&env, &claims, payload.cipher, payload.collection_ids)
+        .await
+        .map(Json)
+}
+
+/// Shared implementation of the cipher creation endpoints.
+/// Organization ciphers have no owning user and are linked to `collection_ids`;
+/// folders and favorites only apply to personal ciphers.
+async fn insert_new_cipher(
+    env: &Arc<Env>,
+    claims: &Claims,
+    cipher_data_req: CipherRequestData,
+    collection_ids: Vec<String>,
+) -> Result<Cipher, AppError> {
+    let db = db::get_db(env)?;
     let now = db::now_string();
-    let cipher_data_req = payload.cipher;
+
+    let is_org_cipher = cipher_data_req.organization_id.is_some();
+    if let Some(org_id) = cipher_data_req.organization_id.as_deref() {
+        ensure_org_collections_writable(&db, &claims.sub, org_id, &collection_ids).await?;
+    }
 
     let cipher_data = CipherData::new(
         cipher_data_req.name,
@@ -66,12 +372,12 @@ pub async fn create_cipher(
 
     let mut cipher = Cipher {
         id: Uuid::new_v4().to_string(),
-        user_id: Some(claims.sub.clone()),
+        user_id: (!is_org_cipher).then(|| claims.sub.clone()),
         organization_id: cipher_data_req.organization_id.clone(),
         r#type: cipher_data_req.r#type,
         data: data_value,
-        favorite: cipher_data_req.favorite.unwrap_or(false),
-        folder_id: cipher_data_req.folder_id.clone(),
+        favorite: !is_org_cipher && cipher_data_req.favorite.unwrap_or(false),
+        folder_id: cipher_data_req.folder_id.filter(|_| !is_org_cipher),
         deleted_at: None,
         archived_at: None,
         created_at: now.clone(),
@@ -80,17 +386,13 @@ pub async fn create_cipher(
         organization_use_totp: false,
         edit: true,
         view_password: true,
-        collection_ids: if payload.collection_ids.is_empty() {
-            None
-        } else {
-            Some(payload.collection_ids)
-        },
+        collection_ids: None,
         attachments: None,
     };
 
     let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
 
-    d1_query!(
+    let mut statements = vec![d1_query!(
         &db,
         "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
@@ -100,27 +402,34 @@ pub async fn create_cipher(
          cipher.r#type,
          data,
          cipher.favorite,
-
          cipher.folder_id,
          cipher.created_at,
          cipher.updated_at,
-    ).map_err(|_|AppError::Database)?
-    .run()
-    .await?;
+    ).map_err(|_|AppError::Database)?];
+    if is_org_cipher {
+        statements.extend(replace_cipher_collections_statements(
+            &db,
+            &cipher.id,
+            &collection_ids,
+        )?);
+    }
+    db.batch(statements).await?;
 
+    hydrate_cipher_access(&db, &mut cipher, &claims.sub).await?;
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub, &cipher.updated_at).await?;
 
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
+    publish_cipher_change(
+        env,
+        &db,
+        claims,
+        cipher.organization_id.as_deref(),
         UpdateType::SyncCipherCreate,
-        cipher.id.clone(),
-        cipher.updated_at.clone(),
-        Some(claims.device),
-    );
+        &cipher.id,
+        &cipher.updated_at,
+    )
+    .await?;
 
-    Ok(Json(cipher))
+    Ok(cipher)
 }
 
 #[worker::send]
@@ -134,10 +443,20 @@ pub async fn update_cipher(
     let db = db::get_db(&env)?;
     let now = db::now_string();
 
-    let existing_cipher = fetch_cipher_for_user(&db, &id, &claims.sub).await?;
+    let existing_cipher = fetch_writable_cipher_for_user(&db, &id, &claims.sub).await?;
+
+    // Moving a cipher into (or out of) an organization goes through the share endpoints.
+    if payload.organization_id.is_some()
+        && payload.organization_id != existing_cipher.organization_id
+    {
+        return Err(AppError::BadRequest(
+            "Use the share endpoint to move a cipher into an organization".to_string(),
+        ));
+    }
+    let is_org_cipher = existing_cipher.organization_id.is_some();
 
     // Validate folder ownership if provided
-    if let Some(ref folder_id) = payload.folder_id {
+    if let Some(folder_id) = payload.folder_id.as_ref().filter(|_| !is_org_cipher) {
         let folder_exists: Option<serde_json::Value> = db
             .prepare("SELECT id FROM folders WHERE id = ?1 AND user_id = ?2")
             .bind(&[folder_id.clone().into(), claims.sub.clone().into()])?
@@ -177,14 +496,15 @@ pub async fn update_cipher(
 
     let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
 
+    // Folders and favorites of organization ciphers are not tracked per member.
     let mut cipher = Cipher {
         id: id.clone(),
-        user_id: Some(claims.sub.clone()),
-        organization_id: payload.organization_id.clone(),
+        user_id: existing_cipher.user_id.clone(),
+        organization_id: existing_cipher.organization_id.clone(),
         r#type: payload.r#type,
         data: data_value,
-        favorite: payload.favorite.unwrap_or(false),
-        folder_id: payload.folder_id.clone(),
+        favorite: !is_org_cipher && payload.favorite.unwrap_or(false),
+        folder_id: payload.folder_id.clone().filter(|_| !is_org_cipher),
         deleted_at: None,
         archived_at: existing_cipher.archived_at,
         created_at: existing_cipher.created_at,
@@ -201,15 +521,13 @@ pub async fn update_cipher(
 
     d1_query!(
         &db,
-        "UPDATE ciphers SET organization_id = ?1, type = ?2, data = ?3, favorite = ?4, folder_id = ?5, updated_at = ?6 WHERE id = ?7 AND user_id = ?8",
-        cipher.organization_id,
+        "UPDATE ciphers SET type = ?1, data = ?2, favorite = ?3, folder_id = ?4, updated_at = ?5 WHERE id = ?6",
         cipher.r#type,
         data,
         cipher.favorite,
         cipher.folder_id,
         cipher.updated_at,
         id,
-        claims.sub,
     ).map_err(|_|AppError::Database)?
     .run()
     .await?;
@@ -240,17 +558,19 @@ pub async fn update_cipher(
         }
     }
 
+    hydrate_cipher_access(&db, &mut cipher, &claims.sub).await?;
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub, &cipher.updated_at).await?;
 
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
+    publish_cipher_change(
+        &env,
+        &db,
+        &claims,
+        cipher.organization_id.as_deref(),
         UpdateType::SyncCipherUpdate,
-        cipher.id.clone(),
-        cipher.updated_at.clone(),
-        Some(claims.device),
-    );
+        &cipher.id,
+        &cipher.updated_at,
+    )
+    .await?;
 
     Ok(Json(cipher))
 }
@@ -260,12 +580,18 @@ pub async fn update_cipher(
 pub async fn list_ciphers(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
+    let where_clause = format!(
+        "WHERE {} AND c.deleted_at IS NULL",
+        cipher_read_access_sql("?1")
+    );
     build_cipher_list_response(
         &db,
         env.as_ref(),
-        "WHERE c.user_id = ?1 AND c.deleted_at IS NULL",
+        &config,
+        &where_clause,
         &[claims.sub.clone().into()],
         "ORDER BY c.updated_at DESC",
     )
@@ -283,6 +609,7 @@ pub async fn get_cipher(
     let cipher = fetch_cipher_for_user(&db, &id, &claims.sub).await?;
     let mut cipher: Cipher = cipher.into();
 
+    hydrate_cipher_access(&db, &mut cipher, &claims.sub).await?;
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
 
     Ok(Json(cipher))
@@ -298,6 +625,260 @@ pub async fn get_cipher_details(
     get_cipher(claims, state, id).await
 }
 
+/// Move a personal cipher into an organization.
+/// The client re-encrypts the cipher with the organization key before sending it.
+async fn share_cipher_inner(
+    env: &Arc<Env>,
+    db: &crate::db::Db,
+    claims: &Claims,
+    id: &str,
+    payload: CipherRequestData,
+    collection_ids: &[String],
+) -> Result<Cipher, AppError> {
+    let existing = fetch_personal_cipher_for_user(db, id, &claims.sub).await?;
+    if existing.organization_id.is_some() {
+        return Err(AppError::BadRequest(
+            "Cipher is already shared with an organization".to_string(),
+        ));
+    }
+
+    let org_id = payload
+        .organization_id
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("Organization id not provided".to_string()))?;
+    if collection_ids.is_empty() {
+        return Err(AppError::BadRequest(
+            "You must select at least one collection.".to_string(),
+        ));
+    }
+    ensure_org_collections_writable(db, &claims.sub, &org_id, collection_ids).await?;
+
+    let attachment_count: Option<i64> = d1_query!(
+        db,
+        "SELECT COUNT(*) AS cnt FROM attachments WHERE cipher_id = ?1",
+        id
+    )
+    .map_err(|_| AppError::Database)?
+    .first(Some("cnt"))
+    .await
+    .map_err(|_| AppError::Database)?;
+    if attachment_count.unwrap_or(0) > 0 {
+        return Err(AppError::BadRequest(
+            "Sharing ciphers with attachments is not supported".to_string(),
+        ));
+    }
+
+    let now = db::now_string();
+    let cipher_data = CipherData::new(payload.name, payload.notes, payload.type_fields);
+    let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
+    let data = serde_json::to_string(&data_value).map_err(|_| AppError::Internal)?;
+
+    let mut statements = vec![d1_query!(
+        db,
+        "UPDATE ciphers
+         SET user_id = NULL, organization_id = ?1, type = ?2, data = ?3, favorite = 0,
+             folder_id = NULL, archived_at = NULL, updated_at = ?4
+         WHERE id = ?5 AND user_id = ?6",
+        &org_id,
+        payload.r#type,
+        data,
+        &now,
+        id,
+        &claims.sub
+    )
+    .map_err(|_| AppError::Database)?];
+    statements.extend(replace_cipher_collections_statements(
+        db,
+        id,
+        collection_ids,
+    )?);
+    db.batch(statements).await?;
+
+    // The previous owner no longer sees the cipher as a personal item.
+    db::touch_user_updated_at(db, &claims.sub, &now).await?;
+    publish_cipher_change(
+        env,
+        db,
+        claims,
+        Some(&org_id),
+        UpdateType::SyncCipherUpdate,
+        id,
+        &now,
+    )
+    .await?;
+
+    let mut cipher: Cipher = fetch_cipher_for_user(db, id, &claims.sub).await?.into();
+    hydrate_cipher_access(db, &mut cipher, &claims.sub).await?;
+    attachments::hydrate_cipher_attachments(db, env.as_ref(), &mut cipher).await?;
+    Ok(cipher)
+}
+
+/// POST/PUT /api/ciphers/{id}/share
+#[worker::send]
+pub async fn share_cipher(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateCipherRequest>,
+) -> Result<Json<Cipher>, AppError> {
+    let db = db::get_db(&env)?;
+    share_cipher_inner(
+        &env,
+        &db,
+        &claims,
+        &id,
+        payload.cipher,
+        &payload.collection_ids,
+    )
+    .await
+    .map(Json)
+}
+
+/// PUT /api/ciphers/share - share several personal ciphers into the same collections
+#[worker::send]
+pub async fn share_ciphers_bulk(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<ShareCiphersBulkRequest>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut shared = Vec::with_capacity(payload.ciphers.len());
+    for cipher in payload.ciphers {
+        let id = cipher
+            .id
+            .clone()
+            .ok_or_else(|| AppError::BadRequest("Request missing ids field".to_string()))?;
+        shared.push(
+            share_cipher_inner(&env, &db, &claims, &id, cipher, &payload.collection_ids).await?,
+        );
+    }
+
+    Ok(Json(json!({
+        "data": shared,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// Replace the collections of an organization cipher.
+/// Only members with access to every collection of the organization may do this,
+/// so assignments invisible to the caller can never be dropped by accident.
+async fn update_cipher_collections_inner(
+    env: &Arc<Env>,
+    claims: &Claims,
+    id: &str,
+    collection_ids: &[String],
+) -> Result<Cipher, AppError> {
+    let db = db::get_db(env)?;
+    let existing = fetch_writable_cipher_for_user(&db, id, &claims.sub).await?;
+    let org_id = existing
+        .organization_id
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("Cipher is not in an organization".to_string()))?;
+
+    let membership = Membership::find_by_user_and_org(&db, &claims.sub, &org_id)
+        .await?
+        .filter(Membership::has_full_access)
+        .ok_or_else(|| {
+            AppError::Unauthorized(
+                "Only members with access to all collections can change them".to_string(),
+            )
+        })?;
+    ensure_org_collections_writable(&db, &membership.user_id, &org_id, collection_ids).await?;
+
+    let now = db::now_string();
+    let mut statements = replace_cipher_collections_statements(&db, id, collection_ids)?;
+    statements.push(
+        d1_query!(
+            &db,
+            "UPDATE ciphers SET updated_at = ?1 WHERE id = ?2",
+            &now,
+            id
+        )
+        .map_err(|_| AppError::Database)?,
+    );
+    db.batch(statements).await?;
+
+    publish_cipher_change(
+        env,
+        &db,
+        claims,
+        Some(&org_id),
+        UpdateType::SyncCipherUpdate,
+        id,
+        &now,
+    )
+    .await?;
+
+    let mut cipher: Cipher = fetch_cipher_for_user(&db, id, &claims.sub).await?.into();
+    hydrate_cipher_access(&db, &mut cipher, &claims.sub).await?;
+    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
+    Ok(cipher)
+}
+
+/// PUT/POST /api/ciphers/{id}/collections
+#[worker::send]
+pub async fn update_cipher_collections(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<CipherCollectionsData>,
+) -> Result<Json<Cipher>, AppError> {
+    update_cipher_collections_inner(&env, &claims, &id, &payload.collection_ids)
+        .await
+        .map(Json)
+}
+
+/// PUT/POST /api/ciphers/{id}/collections_v2
+#[worker::send]
+pub async fn update_cipher_collections_v2(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<CipherCollectionsData>,
+) -> Result<Json<Value>, AppError> {
+    let cipher =
+        update_cipher_collections_inner(&env, &claims, &id, &payload.collection_ids).await?;
+
+    Ok(Json(json!({
+        "cipher": cipher,
+        "unavailable": false,
+        "object": "optionalCipherDetails",
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationIdQuery {
+    pub organization_id: String,
+}
+
+/// GET /api/ciphers/organization-details?organizationId= - all ciphers of an organization,
+/// used by the admin console
+#[worker::send]
+pub async fn list_organization_ciphers(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    config: Config,
+    Query(query): Query<OrganizationIdQuery>,
+) -> Result<RawJson, AppError> {
+    let db = db::get_db(&env)?;
+    Membership::find_by_user_and_org(&db, &claims.sub, &query.organization_id)
+        .await?
+        .filter(Membership::has_full_access)
+        .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+    build_cipher_list_response(
+        &db,
+        env.as_ref(),
+        &config,
+        "WHERE c.organization_id = ?2",
+        &[claims.sub.into(), query.organization_id.into()],
+        "ORDER BY c.updated_at DESC",
+    )
+    .await
+}
+
 /// PUT/POST /api/ciphers/{id}/partial
 #[worker::send]
 pub async fn update_cipher_partial(
@@ -325,7 +906,12 @@ pub async fn update_cipher_partial(
     }
 
     // Ensure cipher exists and belongs to user
-    fetch_cipher_for_user(&db, &id, user_id).await?;
+    let existing = fetch_cipher_for_user(&db, &id, user_id).await?;
+    if existing.organization_id.is_some() {
+        return Err(AppError::BadRequest(
+            "Folders and favorites are not supported for organization items".to_string(),
+        ));
+    }
 
     let now = db::now_string();
 
@@ -359,33 +945,41 @@ pub async fn update_cipher_partial(
 pub async fn soft_delete_cipher(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
     Path(id): Path<String>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
-    fetch_cipher_for_user(&db, &id, &claims.sub).await?;
+    let existing = fetch_writable_cipher_for_user(&db, &id, &claims.sub).await?;
     let now = db::now_string();
 
     d1_query!(
         &db,
-        "UPDATE ciphers SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
+        "UPDATE ciphers SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
         now,
-        id,
-        claims.sub
+        id
     )
     .map_err(|_| AppError::Database)?
     .run()
     .await?;
+    events::log_cipher_event(
+        &db,
+        EventType::CipherSoftDeleted,
+        &existing,
+        &claims.sub,
+        &headers,
+    )
+    .await;
 
-    db::touch_user_updated_at(&db, &claims.sub, &now).await?;
-
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
+    publish_cipher_change(
+        &env,
+        &db,
+        &claims,
+        existing.organization_id.as_deref(),
         UpdateType::SyncCipherUpdate,
-        id,
-        now,
-        Some(claims.device),
-    );
+        &id,
+        &now,
+    )
+    .await?;
 
     Ok(Json(()))
 }
@@ -397,32 +991,37 @@ pub async fn soft_delete_cipher(
 pub async fn soft_delete_ciphers_bulk(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
     body: String,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
     let now = db::now_string();
+    let organization_ids = organization_ids_for_cipher_ids_json(&db, &body).await?;
 
-    d1_query!(
+    let sql = format!(
+        "UPDATE ciphers AS c SET deleted_at = ?1, updated_at = ?1 WHERE {} AND c.id IN (SELECT value FROM json_each(?3, '$.ids'))",
+        cipher_write_access_sql("?2")
+    );
+    db.prepare(&sql)
+        .bind(&[
+            now.clone().into(),
+            claims.sub.clone().into(),
+            body.clone().into(),
+        ])?
+        .run()
+        .await
+        .map_err(db::map_d1_json_error)?;
+    events::log_cipher_events_bulk(
         &db,
-        "UPDATE ciphers SET deleted_at = ?1, updated_at = ?1 WHERE user_id = ?2 AND id IN (SELECT value FROM json_each(?3, '$.ids'))",
-        now,
-        claims.sub,
-        body
+        EventType::CipherSoftDeleted,
+        &cipher_write_access_sql("?2"),
+        &claims.sub,
+        &body,
+        &headers,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await
-    .map_err(db::map_d1_json_error)?;
-
-    db::touch_user_updated_at(&db, &claims.sub, &now).await?;
+    .await;
 
-    notifications::publish_user_update(
-        (*env).clone(),
-        claims.sub,
-        UpdateType::SyncCiphers,
-        now,
-        Some(claims.device),
-    );
+    publish_bulk_cipher_change(&env, &db, &claims, &organization_ids, &now).await?;
 
     Ok(Json(()))
 }
@@ -433,10 +1032,11 @@ pub async fn soft_delete_ciphers_bulk(
 pub async fn hard_delete_cipher(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
     Path(id): Path<String>,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
-    fetch_cipher_for_user(&db, &id, &claims.sub).await?;
+    let existing = fetch_writable_cipher_for_user(&db, &id, &claims.sub).await?;
     let now = db::now_string();
 
     if attachments::attachments_enabled(env.as_ref()) {
@@ -451,26 +1051,29 @@ pub async fn hard_delete_cipher(
         attachments::delete_storage_objects(env.as_ref(), &keys).await?;
     }
 
-    d1_query!(
+    d1_query!(&db, "DELETE FROM ciphers WHERE id = ?1", id)
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await?;
+    events::log_cipher_event(
         &db,
-        "DELETE FROM ciphers WHERE id = ?1 AND user_id = ?2",
-        id,
-        claims.sub
+        EventType::CipherDeleted,
+        &existing,
+        &claims.sub,
+        &headers,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await?;
+    .await;
 
-    db::touch_user_updated_at(&db, &claims.sub, &now).await?;
-
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
+    publish_cipher_change(
+        &env,
+        &db,
+        &claims,
+        existing.organization_id.as_deref(),
         UpdateType::SyncLoginDelete,
-        id,
-        now,
-        Some(claims.device),
-    );
+        &id,
+        &now,
+    )
+    .await?;
 
     Ok(Json(()))
 }
@@ -482,6 +1085,7 @@ pub async fn hard_delete_cipher(
 pub async fn hard_delete_ciphers_bulk(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
     body: String,
 ) -> Result<Json<()>, AppError> {
     let db = db::get_db(&env)?;
@@ -498,26 +1102,28 @@ pub async fn hard_delete_ciphers_bulk(
         attachments::delete_storage_objects(env.as_ref(), &keys).await?;
     }
 
-    d1_query!(
+    let organization_ids = organization_ids_for_cipher_ids_json(&db, &body).await?;
+    // Recorded first: the events are built from the rows about to be deleted.
+    events::log_cipher_events_bulk(
         &db,
-        "DELETE FROM ciphers WHERE user_id = ?1 AND id IN (SELECT value FROM json_each(?2, '$.ids'))",
-        claims.sub,
-        body
+        EventType::CipherDeleted,
+        &cipher_write_access_sql("?2"),
+        &claims.sub,
+        &body,
+        &headers,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await
-    .map_err(db::map_d1_json_error)?;
-
-    db::touch_user_updated_at(&db, &claims.sub, &now).await?;
-
-    notifications::publish_user_update(
-        (*env).clone(),
-        claims.sub,
-        UpdateType::SyncCiphers,
-        now,
-        Some(claims.device),
+    .await;
+    let sql = format!(
+        "DELETE FROM ciphers AS c WHERE {} AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
+        cipher_write_access_sql("?1")
     );
+    db.prepare(&sql)
+        .bind(&[claims.sub.clone().into(), body.into()])?
+        .run()
+        .await
+        .map_err(db::map_d1_json_error)?;
+
+    publish_bulk_cipher_change(&env, &db, &claims, &organization_ids, &now).await?;
 
     Ok(Json(()))
 }
@@ -528,38 +1134,47 @@ pub async fn hard_delete_ciphers_bulk(
 pub async fn restore_cipher(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
     Path(id): Path<String>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    fetch_cipher_for_user(&db, &id, &claims.sub).await?;
+    let existing = fetch_writable_cipher_for_user(&db, &id, &claims.sub).await?;
     let now = db::now_string();
 
     // Update the cipher to clear deleted_at
     d1_query!(
         &db,
-        "UPDATE ciphers SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
+        "UPDATE ciphers SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
         now,
-        id,
-        claims.sub
+        id
     )
     .map_err(|_| AppError::Database)?
     .run()
     .await?;
+    events::log_cipher_event(
+        &db,
+        EventType::CipherRestored,
+        &existing,
+        &claims.sub,
+        &headers,
+    )
+    .await;
 
     let restored = fetch_cipher_for_user(&db, &id, &claims.sub).await?;
     let mut cipher: Cipher = restored.into();
+    hydrate_cipher_access(&db, &mut cipher, &claims.sub).await?;
     attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
 
-    db::touch_user_updated_at(&db, &claims.sub, &cipher.updated_at).await?;
-
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
+    publish_cipher_change(
+        &env,
+        &db,
+        &claims,
+        cipher.organization_id.as_deref(),
         UpdateType::SyncCipherUpdate,
-        cipher.id.clone(),
-        cipher.updated_at.clone(),
-        Some(claims.device),
-    );
+        &cipher.id,
+        &cipher.updated_at,
+    )
+    .await?;
 
     Ok(Json(cipher))
 }
@@ -571,38 +1186,50 @@ pub async fn restore_cipher(
 pub async fn restore_ciphers_bulk(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    config: Config,
     body: String,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
     let now = db::now_string();
 
+    let organization_ids = organization_ids_for_cipher_ids_json(&db, &body).await?;
+
     // Single bulk UPDATE using json_each() with path
-    d1_query!(
+    let sql = format!(
+        "UPDATE ciphers AS c SET deleted_at = NULL, updated_at = ?1 WHERE {} AND c.id IN (SELECT value FROM json_each(?3, '$.ids'))",
+        cipher_write_access_sql("?2")
+    );
+    db.prepare(&sql)
+        .bind(&[
+            now.clone().into(),
+            claims.sub.clone().into(),
+            body.clone().into(),
+        ])?
+        .run()
+        .await
+        .map_err(db::map_d1_json_error)?;
+    events::log_cipher_events_bulk(
         &db,
-        "UPDATE ciphers SET deleted_at = NULL, updated_at = ?1 WHERE user_id = ?2 AND id IN (SELECT value FROM json_each(?3, '$.ids'))",
-        now,
-        claims.sub,
-        body
+        EventType::CipherRestored,
+        &cipher_write_access_sql("?2"),
+        &claims.sub,
+        &body,
+        &headers,
     )
-    .map_err(|_| AppError::Database)?
-    .run()
-    .await
-    .map_err(db::map_d1_json_error)?;
+    .await;
 
-    db::touch_user_updated_at(&db, &claims.sub, &now).await?;
+    publish_bulk_cipher_change(&env, &db, &claims, &organization_ids, &now).await?;
 
-    notifications::publish_user_update(
-        (*env).clone(),
-        claims.sub.clone(),
-        UpdateType::SyncCiphers,
-        now,
-        Some(claims.device),
+    let where_clause = format!(
+        "WHERE {} AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
+        cipher_read_access_sql("?1")
     );
-
     build_cipher_list_response(
         &db,
         env.as_ref(),
-        "WHERE c.user_id = ?1 AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
+        &config,
+        &where_clause,
         &[claims.sub.into(), body.into()],
         "",
     )
@@ -617,7 +1244,7 @@ pub async fn archive_cipher(
     Path(id): Path<String>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    fetch_cipher_for_user(&db, &id, &claims.sub).await.map_err(|_| {
+    fetch_personal_cipher_for_user(&db, &id, &claims.sub).await.map_err(|_| {
         AppError::BadRequest(
             "Cipher was not archived. Ensure the provided ID is correct and you have permission to archive it.".to_string(),
         )
@@ -661,7 +1288,7 @@ pub async fn unarchive_cipher(
     Path(id): Path<String>,
 ) -> Result<Json<Cipher>, AppError> {
     let db = db::get_db(&env)?;
-    fetch_cipher_for_user(&db, &id, &claims.sub).await.map_err(|_| {
+    fetch_personal_cipher_for_user(&db, &id, &claims.sub).await.map_err(|_| {
         AppError::BadRequest(
             "Cipher was not unarchived. Ensure the provided ID is correct and you have permission to unarchive it.".to_string(),
         )
@@ -703,6 +1330,7 @@ pub async fn unarchive_cipher(
 pub async fn archive_ciphers_bulk(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     body: String,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
@@ -733,6 +1361,7 @@ pub async fn archive_ciphers_bulk(
     build_cipher_list_response(
         &db,
         env.as_ref(),
+        &config,
         "WHERE c.user_id = ?1 AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
         &[claims.sub.into(), body.into()],
         "",
@@ -746,6 +1375,7 @@ pub async fn archive_ciphers_bulk(
 pub async fn unarchive_ciphers_bulk(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     body: String,
 ) -> Result<RawJson, AppError> {
     let db = db::get_db(&env)?;
@@ -776,6 +1406,7 @@ pub async fn unarchive_ciphers_bulk(
     build_cipher_list_response(
         &db,
         env.as_ref(),
+        &config,
         "WHERE c.user_id = ?1 AND c.id IN (SELECT value FROM json_each(?2, '$.ids'))",
         &[claims.sub.into(), body.into()],
         "",
@@ -792,64 +1423,9 @@ pub async fn create_cipher_simple(
     State(env): State<Arc<Env>>,
     Json(payload): Json<CipherRequestData>,
 ) -> Result<Json<Cipher>, AppError> {
-    let db = db::get_db(&env)?;
-    let now = db::now_string();
-    let cipher_data = CipherData::new(payload.name, payload.notes, payload.type_fields);
-
-    let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
-
-    let mut cipher = Cipher {
-        id: Uuid::new_v4().to_string(),
-        user_id: Some(claims.sub.clone()),
-        organization_id: payload.organization_id.clone(),
-        r#type: payload.r#type,
-        data: data_value,
-        favorite: payload.favorite.unwrap_or(false),
-        folder_id: payload.folder_id.clone(),
-        deleted_at: None,
-        archived_at: None,
-        created_at: now.clone(),
-        updated_at: now.clone(),
-        object: "cipher".to_string(),
-        organization_use_totp: false,
-        edit: true,
-        view_password: true,
-        collection_ids: None,
-        attachments: None,
-    };
-
-    let data = serde_json::to_string(&cipher.data).map_err(|_| AppError::Internal)?;
-
-    d1_query!(
-        &db,
-        "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-         cipher.id,
-         cipher.user_id,
-         cipher.organization_id,
-         cipher.r#type,
-         data,
-         cipher.favorite,
-         cipher.folder_id,
-         cipher.created_at,
-         cipher.updated_at,
-    ).map_err(|_| AppError::Database)?
-    .run()
-    .await?;
-
-    attachments::hydrate_cipher_attachments(&db, env.as_ref(), &mut cipher).await?;
-    db::touch_user_updated_at(&db, &claims.sub, &cipher.updated_at).await?;
-
-    notifications::publish_cipher_update(
-        (*env).clone(),
-        claims.sub,
-        UpdateType::SyncCipherCreate,
-        cipher.id.clone(),
-        cipher.updated_at.clone(),
-        Some(claims.device),
-    );
-
-    Ok(Json(cipher))
+    insert_new_cipher(&env, &claims, payload, Vec::new())
+        .await
+        .map(Json)
 }
 
 /// Move selected ciphers to a folder (POST/PUT /api/ciphers/move)
@@ -985,7 +1561,14 @@ struct CipherJsonArrayRow {
 }
 
 /// Build the SQL expression for a single cipher as JSON.
+/// The per-user organization fields are computed for the user bound to `?1`.
 fn cipher_json_expr(attachments_enabled: bool) -> String {
+    let edit_expr = "CASE WHEN c.organization_id IS NULL OR EXISTS(
+                SELECT 1 FROM cipher_access ca WHERE ca.cipher_id = c.id AND ca.user_id = ?1 AND ca.read_only = 0
+            ) THEN json('true') ELSE json('false') END";
+    let view_password_expr = "CASE WHEN c.organization_id IS NULL OR EXISTS(
+                SELECT 1 FROM cipher_access ca WHERE ca.cipher_id = c.id AND ca.user_id = ?1 AND ca.hide_passwords = 0
+            ) THEN json('true') ELSE json('false') END";
     let attachments_expr = if attachments_enabled {
         "
             (
@@ -1024,11 +1607,15 @@ fn cipher_json_expr(attachments_enabled: bool) -> String {
             'folderId', c.folder_id,
             'type', c.type,
             'favorite', CASE WHEN c.favorite THEN json('true') ELSE json('false') END,
-            'edit', json('true'),
-            'viewPassword', json('true'),
-            'permissions', json_object('delete', json('true'), 'restore', json('true')),
-            'organizationUseTotp', json('false'),
-            'collectionIds', json('[]'),
+            'edit', {edit_expr},
+            'viewPassword', {view_password_expr},
+            'permissions', json_object('delete', {edit_expr}, 'restore', {edit_expr}),
+            'organizationUseTotp', CASE WHEN c.organization_id IS NULL THEN json('false') ELSE json('true') END,
+            'collectionIds', json(COALESCE((
+                SELECT json_group_array(DISTINCT ca.collection_id)
+                FROM cipher_access ca
+                WHERE ca.cipher_id = c.id AND ca.user_id = ?1 AND ca.collection_id IS NOT NULL
+            ), '[]')),
             'revisionDate', c.updated_at,
             'creationDate', c.created_at,
             'deletedDate', c.deleted_at,
@@ -1047,6 +1634,8 @@ fn cipher_json_expr(attachments_enabled: bool) -> String {
             'key', json_extract(c.data, '$.key')
         )",
         attachments_expr = attachments_expr,
+        edit_expr = edit_expr,
+        view_password_expr = view_password_expr,
     )
 }
 
@@ -1099,12 +1688,13 @@ fn is_sqlite_toobig(err: &worker::Error) -> bool {
 async fn build_cipher_list_response(
     db: &crate::db::Db,
     env: &Env,
+    config: &Config,
     where_clause: &str,
-    params: &[JsValue],
+    params: &[db::BindValue],
     order_clause: &str,
 ) -> Result<RawJson, AppError> {
     let include_attachments = attachments::attachments_enabled(env);
-    let force_row_query = super::ciphers_default_row_query(env);
+    let force_row_query = config.ciphers_default_row_query;
     let mut response = String::new();
     response.push_str("{\"data\":");
     append_cipher_json_array_raw(
@@ -1123,12 +1713,13 @@ async fn build_cipher_list_response(
 
 /// Append ciphers JSON array to an existing buffer.
 /// This avoids JSON parsing in Rust, significantly reducing CPU time.
+/// `?1` must be bound to the id of the user the ciphers are rendered for.
 pub(crate) async fn append_cipher_json_array_raw(
     out: &mut String,
     db: &crate::db::Db,
     attachments_enabled: bool,
     where_clause: &str,
-    params: &[JsValue],
+    params: &[db::BindValue],
     order_clause: &str,
     force_row_query: bool,
 ) -> Result<(), AppError> {
@@ -1177,51 +1768,29 @@ pub(crate) async fn append_cipher_json_array_raw(
 /// Append ciphers JSON array to an existing buffer row by row.
 /// This avoids JSON array exceeding the maximum size that can be returned in a single string.
 ///
-/// Uses `raw_js_value()` to bypass Serde deserialization entirely, which should reduce
-/// CPU time for large payloads. Each row from `raw_js_value()` is a JS array `[cipher_json]`
-/// where the first element is the JSON string we need.
+/// Rows are read with `db::for_each_first_column`, which bypasses Serde deserialization
+/// entirely and should reduce CPU time for large payloads.
 pub(crate) async fn append_from_rows(
     out: &mut String,
     db: &crate::db::Db,
     attachments_enabled: bool,
     where_clause: &str,
-    params: &[JsValue],
+    params: &[db::BindValue],
     order_clause: &str,
 ) -> Result<(), AppError> {
-    use js_sys::Array;
-    use wasm_bindgen::JsCast;
-
     let sql = cipher_json_rows_sql(attachments_enabled, where_clause, order_clause);
 
-    // Use raw_js_value() to get Vec<JsValue> without Serde deserialization.
-    // Each JsValue is a JS array: [cipher_json_string]
-    let raw_rows: Vec<JsValue> = db
-        .prepare(&sql)
-        .bind(params)?
-        .raw_js_value()
-        .await
-        .map_err(db::map_d1_json_error)?;
-
-    if raw_rows.is_empty() {
-        out.push_str("[]");
-        return Ok(());
-    }
-
     out.push('[');
-    for (idx, row_js) in raw_rows.iter().enumerate() {
-        if idx > 0 {
+    let mut first = true;
+    db::for_each_first_column(db.prepare(&sql).bind(params)?, |cipher_json| {
+        if !first {
             out.push(',');
         }
-        // Each row is a JS array [column0, column1, ...]. We only select one column (cipher_json).
-        let row_array = row_js
-            .dyn_ref::<Array>()
-            .ok_or_else(|| AppError::Internal)?;
-        let cipher_json_js = row_array.get(0);
-        let cipher_json = cipher_json_js
-            .as_string()
-            .ok_or_else(|| AppError::Internal)?;
-        out.push_str(&cipher_json);
-    }
+        first = false;
+        out.push_str(cipher_json);
+    })
+    .await
+    .map_err(db::map_d1_json_error)?;
     out.push(']');
     Ok(())
 }
@@ -1,22 +1,26 @@
-use axum::{extract::State, Extension, Json};
-use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::Env;
 
-use crate::BaseUrl;
+use axum::{extract::State, Extension, Json};
+use serde_json::{json, Map, Value};
+
+use crate::{config::Config, env::Env, webpush, BaseUrl};
 
-/// Get the disable_user_registration setting from environment variable.
-/// Defaults to true if not set. Only "false" will disable it.
-fn get_disable_user_registration(env: &Env) -> bool {
-    env.var("DISABLE_USER_REGISTRATION")
-        .ok()
-        .map(|v| v.to_string().to_lowercase() != "false")
-        .unwrap_or(true)
-}
+/// `pushTechnology` values understood by the clients.
+const PUSH_TECHNOLOGY_SIGNALR: i32 = 0;
+const PUSH_TECHNOLOGY_WEB_PUSH: i32 = 1;
+
+/// Feature flags reported to the clients unless overridden by `EXPERIMENTAL_CLIENT_FEATURE_FLAGS`.
+const DEFAULT_FEATURE_STATES: &[(&str, bool)] = &[
+    ("pm-19051-send-email-verification", false),
+    ("pm-19148-innovation-archive", true),
+    ("cxp-import-mobile", true),
+    ("cxp-export-mobile", true),
+];
 
 #[worker::send]
 pub async fn config(
     State(env): State<Arc<Env>>,
+    config: Config,
     Extension(BaseUrl(domain)): Extension<BaseUrl>,
 ) -> Json<Value> {
     // Official available feature flags can be found here:
@@ -24,15 +28,20 @@ pub async fn config(
     // Client (web-v2025.6.1): https://github.com/bitwarden/clients/blob/747c2fd6a1c348a57a76e4a7de8128466ffd3c01/libs/common/src/enums/feature-flag.enum.ts#L12
     // Android (v2025.6.0): https://github.com/bitwarden/android/blob/b5b022caaad33390c31b3021b2c1205925b0e1a2/app/src/main/kotlin/com/x8bit/bitwarden/data/platform/manager/model/FlagKey.kt#L22
     // iOS (v2025.6.0): https://github.com/bitwarden/ios/blob/ff06d9c6cc8da89f78f37f376495800201d7261a/BitwardenShared/Core/Platform/Models/Enum/FeatureFlag.swift#L7
-    // let mut feature_states =
-    //     parse_experimental_client_feature_flags(&crate::CONFIG.experimental_client_feature_flags());
-    // feature_states.insert("duo-redirect".to_string(), true);
-    // feature_states.insert("email-verification".to_string(), true);
-    // feature_states.insert("unauth-ui-refresh".to_string(), true);
-    // feature_states.insert("enable-pm-flight-recorder".to_string(), true);
-    // feature_states.insert("mobile-error-reporting".to_string(), true);
-
-    let disable_user_registration = get_disable_user_registration(&env);
+    let mut feature_states: Map<String, Value> = DEFAULT_FEATURE_STATES
+        .iter()
+        .map(|(flag, enabled)| (flag.to_string(), Value::Bool(*enabled)))
+        .collect();
+    for flag in &config.experimental_client_feature_flags {
+        feature_states.insert(flag.clone(), Value::Bool(true));
+    }
+    // Browser clients switch from the WebSocket hub to Web Push when a VAPID key is offered.
+    let vapid_public_key = webpush::vapid_public_key(&env).await;
+    let push_technology = if vapid_public_key.is_some() {
+        PUSH_TECHNOLOGY_WEB_PUSH
+    } else {
+        PUSH_TECHNOLOGY_SIGNALR
+    };
 
     Json(json!({
         // Note: The clients use this version to handle backwards compatibility concerns
@@ -48,7 +57,7 @@ pub async fn config(
           "url": "https://github.com/dani-garcia/vaultwarden"
         },
         "settings": {
-            "disableUserRegistration": disable_user_registration,
+            "disableUserRegistration": config.disable_user_registration,
         },
         "environment": {
           "vault": domain,
@@ -60,15 +69,10 @@ pub async fn config(
         },
         // Bitwarden uses this for the self-hosted servers to indicate the default push technology
         "push": {
-          "pushTechnology": 0,
-          "vapidPublicKey": null
-        },
-        "featureStates": {
-            "pm-19051-send-email-verification": false,
-            "pm-19148-innovation-archive": true,
-            "cxp-import-mobile": true,
-            "cxp-export-mobile": true,
+          "pushTechnology": push_technology,
+          "vapidPublicKey": vapid_public_key
         },
+        "featureStates": feature_states,
         "object": "config",
     }))
 }
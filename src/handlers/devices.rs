@@ -7,11 +7,10 @@ use base64::{engine::general_purpose, Engine as _};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::Env;
 
 use crate::{
-    auth::Claims, db, error::AppError, models::auth_request::AuthRequest, models::device::Device,
-    push,
+    auth::Claims, db, env::Env, error::AppError, models::auth_request::AuthRequest,
+    models::device::Device, models::web_push_subscription::WebPushSubscription, push, webpush,
 };
 
 fn required_header(headers: &HeaderMap, name: &str) -> Result<String, AppError> {
@@ -201,6 +200,7 @@ async fn clear_device_token(
     let mut device = current_device(&db, &claims, &device_id).await?;
 
     device.set_push_token(&db, None).await?;
+    WebPushSubscription::delete(&db, &device.identifier, &device.user_id).await?;
 
     if let Some(cfg) = push::push_config(&env)? {
         push::unregister_push_device(&cfg, device.push_uuid.as_deref()).await?;
@@ -228,3 +228,69 @@ pub async fn post_clear_device_token(
 ) -> Result<Json<Value>, AppError> {
     clear_device_token(env, claims, device_id).await
 }
+
+/// A browser's `PushSubscription`, flattened as the clients send it.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebPushAuth {
+    endpoint: String,
+    p256dh: String,
+    auth: String,
+}
+
+async fn upsert_web_push_auth(
+    env: Arc<Env>,
+    claims: Claims,
+    device_id: String,
+    data: WebPushAuth,
+) -> Result<Json<Value>, AppError> {
+    if !webpush::web_push_enabled(&env) {
+        return Err(AppError::BadRequest(
+            "Web push is disabled on this server".to_string(),
+        ));
+    }
+    let db = db::get_db(&env)?;
+    let device = current_device(&db, &claims, &device_id).await?;
+    if !device.is_web_push_device() {
+        return Err(AppError::BadRequest(
+            "Web push is only available to browser extensions and the web vault".to_string(),
+        ));
+    }
+    let endpoint = data.endpoint.trim().to_string();
+    webpush::validate_subscription(&endpoint, &data.p256dh, &data.auth)
+        .map_err(AppError::BadRequest)?;
+
+    WebPushSubscription::new(
+        device.identifier,
+        device.user_id,
+        endpoint,
+        data.p256dh,
+        data.auth,
+    )
+    .save(&db)
+    .await?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /devices/identifier/{device_id}/web-push-auth
+#[worker::send]
+pub async fn post_web_push_auth(
+    State(env): State<Arc<Env>>,
+    claims: Claims,
+    Path(device_id): Path<String>,
+    Json(data): Json<WebPushAuth>,
+) -> Result<Json<Value>, AppError> {
+    upsert_web_push_auth(env, claims, device_id, data).await
+}
+
+/// PUT /devices/identifier/{device_id}/web-push-auth
+#[worker::send]
+pub async fn put_web_push_auth(
+    State(env): State<Arc<Env>>,
+    claims: Claims,
+    Path(device_id): Path<String>,
+    Json(data): Json<WebPushAuth>,
+) -> Result<Json<Value>, AppError> {
+    upsert_web_push_auth(env, claims, device_id, data).await
+}
@@ -3,7 +3,6 @@ use log::warn;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::Env;
 
 use crate::d1_query;
 
@@ -11,6 +10,7 @@ use crate::handlers::ciphers::RawJson;
 use crate::{
     auth::Claims,
     db,
+    env::Env,
     error::AppError,
     notifications::{self, UpdateType},
 };
@@ -1,32 +1,1009 @@
-use axum::Json;
+//! Emergency access handlers
+//!
+//! A grantor invites a trusted contact (grantee) by email. Once the grantee has
+//! accepted and the grantor has confirmed them with the user key encrypted to the
+//! grantee's public key, the grantee may initiate recovery. Recovery is approved
+//! either explicitly by the grantor or automatically by the `scheduled` job once
+//! the configured wait time has elapsed, after which the grantee can view the
+//! grantor's vault or take over the account depending on the access type.
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
 
-/// GET /emergency-access/trusted
-///
-/// Returns the list of trusted emergency access contacts (grantees) for the current user.
-/// This is a stub implementation that always returns an empty list since emergency access
-/// is not supported in this minimal Bitwarden-compatible implementation.
-///
-/// In vaultwarden, when `emergency_access_allowed` is disabled, it returns an empty list.
-/// We follow the same pattern here.
-#[worker::send]
-pub async fn get_trusted_contacts() -> Json<Value> {
+use crate::d1_query;
+use crate::{
+    auth::Claims,
+    config::Config,
+    crypto::{generate_salt, hash_password_for_storage},
+    db,
+    env::Env,
+    error::AppError,
+    handlers::{
+        accounts::{decode_email_token, sign_email_token},
+        attachments,
+        ciphers::RawJson,
+    },
+    mail::{self, templates},
+    models::{
+        device::Device,
+        emergency_access::{
+            AcceptData, ConfirmData, EmergencyAccess, EmergencyAccessInviteData,
+            EmergencyAccessPasswordData, EmergencyAccessStatus, EmergencyAccessType,
+            EmergencyAccessUpdateData,
+        },
+        user::User,
+    },
+    notifications::{self, UpdateType},
+    push, BaseUrl,
+};
+
+pub(crate) const EMERGENCY_INVITE_ISSUER: &str = "warden-worker|emergencyaccessinvite";
+/// How long an emailed invitation link stays valid.
+const INVITE_TOKEN_DAYS: i64 = 5;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct EmergencyAccessInviteClaims {
+    pub email: String,
+    pub emer_id: String,
+    pub grantor_id: String,
+    pub iss: String,
+}
+
+fn not_found() -> AppError {
+    AppError::NotFound("Emergency access not valid.".to_string())
+}
+
+fn list_response(data: Vec<Value>) -> Json<Value> {
     Json(json!({
-        "data": [],
+        "data": data,
         "object": "list",
         "continuationToken": null
     }))
 }
 
+fn parse_access_type(value: i32) -> Result<EmergencyAccessType, AppError> {
+    EmergencyAccessType::from_i32(value)
+        .ok_or_else(|| AppError::BadRequest("Invalid emergency access type.".to_string()))
+}
+
+fn validate_wait_time_days(value: i32) -> Result<i32, AppError> {
+    if value < 1 {
+        return Err(AppError::BadRequest(
+            "Wait time days must be positive.".to_string(),
+        ));
+    }
+    Ok(value)
+}
+
+/// Load an emergency access record owned by `grantor_id`.
+async fn find_as_grantor(
+    db: &crate::db::Db,
+    id: &str,
+    grantor_id: &str,
+) -> Result<EmergencyAccess, AppError> {
+    EmergencyAccess::find_by_id(db, id)
+        .await?
+        .filter(|access| access.grantor_id == grantor_id)
+        .ok_or_else(not_found)
+}
+
+/// Load an emergency access record granted to `grantee_id`.
+async fn find_as_grantee(
+    db: &crate::db::Db,
+    id: &str,
+    grantee_id: &str,
+) -> Result<EmergencyAccess, AppError> {
+    EmergencyAccess::find_by_id(db, id)
+        .await?
+        .filter(|access| access.grantee_id.as_deref() == Some(grantee_id))
+        .ok_or_else(not_found)
+}
+
+/// Load an approved emergency access record of the given type for `grantee_id`.
+async fn find_approved_as_grantee(
+    db: &crate::db::Db,
+    id: &str,
+    grantee_id: &str,
+    atype: EmergencyAccessType,
+) -> Result<EmergencyAccess, AppError> {
+    let access = find_as_grantee(db, id, grantee_id).await?;
+    if !access.has_status(EmergencyAccessStatus::RecoveryApproved) || !access.has_type(atype) {
+        return Err(AppError::BadRequest(
+            "Emergency access not valid.".to_string(),
+        ));
+    }
+    Ok(access)
+}
+
+/// Tell the other party of an emergency access record that something changed.
+fn notify_contact(env: &Env, user_id: String, date: String) {
+    notifications::publish_user_update(env.clone(), user_id, UpdateType::SyncSettings, date, None);
+}
+
+/// Mark an invitation as accepted by `grantee`. Only `accept_invite` calls this, with a
+/// token from the invitation email, so the grantee always consents.
+async fn accept_for_grantee(
+    db: &crate::db::Db,
+    access: &mut EmergencyAccess,
+    grantee: &User,
+) -> Result<(), AppError> {
+    let now = db::now_string();
+    access.grantee_id = Some(grantee.id.clone());
+    access.email = None;
+    access.set_status(EmergencyAccessStatus::Accepted, &now);
+    access.update(db).await
+}
+
+/// Decode an invitation token from the `accept-emergency` link.
+fn decode_invite_token(
+    env: &Env,
+    raw_token: &str,
+) -> Result<EmergencyAccessInviteClaims, AppError> {
+    decode_email_token::<EmergencyAccessInviteClaims>(env, raw_token)?
+        .filter(|claims| claims.iss == EMERGENCY_INVITE_ISSUER)
+        .ok_or_else(|| AppError::BadRequest("Invalid claim".to_string()))
+}
+
+/// Email `access.email` a link to the web vault's `accept-emergency` page.
+///
+/// Invitations can only be accepted through that link, so they need outgoing mail.
+async fn send_invite_email(
+    env: &Env,
+    db: &crate::db::Db,
+    base_url: &str,
+    access: &EmergencyAccess,
+) -> Result<(), AppError> {
+    if !mail::mail_enabled(env) {
+        return Err(AppError::BadRequest(
+            "Emergency access invitations need outgoing mail, which is not configured on this server."
+                .to_string(),
+        ));
+    }
+    let email = access.email.clone().ok_or_else(not_found)?;
+    let grantor = User::find_by_id(db, &access.grantor_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let grantor_name = grantor
+        .name
+        .clone()
+        .unwrap_or_else(|| grantor.email.clone());
+
+    let token = sign_email_token(
+        env,
+        EmergencyAccessInviteClaims {
+            email: email.clone(),
+            emer_id: access.id.clone(),
+            grantor_id: access.grantor_id.clone(),
+            iss: EMERGENCY_INVITE_ISSUER.to_string(),
+        },
+        Duration::days(INVITE_TOKEN_DAYS),
+    )?;
+    let link = mail::web_vault_link(
+        base_url,
+        "/accept-emergency",
+        &[
+            ("id", &access.id),
+            ("name", &grantor_name),
+            ("email", &email),
+            ("token", &token),
+        ],
+    );
+
+    mail::send(
+        env,
+        &templates::emergency_access_invite(&email, &grantor_name, &link),
+    )
+    .await
+}
+
+/// GET /emergency-access/trusted
+///
+/// Returns the emergency access contacts (grantees) of the current user.
+#[worker::send]
+pub async fn get_trusted_contacts(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let data = EmergencyAccess::list_by_grantor(&db, &claims.sub).await?;
+    Ok(list_response(
+        data.iter()
+            .map(|details| details.to_json_grantee_details())
+            .collect(),
+    ))
+}
+
 /// GET /emergency-access/granted
 ///
-/// Returns the list of emergency access grants where the current user is a grantee.
-/// This is a stub implementation that always returns an empty list.
+/// Returns the emergency access grants where the current user is the grantee.
 #[worker::send]
-pub async fn get_granted_access() -> Json<Value> {
-    Json(json!({
-        "data": [],
-        "object": "list",
-        "continuationToken": null
-    }))
+pub async fn get_granted_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let data = EmergencyAccess::list_by_grantee(&db, &claims.sub).await?;
+    Ok(list_response(
+        data.iter()
+            .map(|details| details.to_json_grantor_details())
+            .collect(),
+    ))
+}
+
+/// GET /emergency-access/{id}
+#[worker::send]
+pub async fn get_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let details = EmergencyAccess::list_by_grantor(&db, &claims.sub)
+        .await?
+        .into_iter()
+        .find(|details| details.access.id == id)
+        .ok_or_else(not_found)?;
+    Ok(Json(details.to_json_grantee_details()))
+}
+
+/// PUT/POST /emergency-access/{id}
+#[worker::send]
+pub async fn update_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<EmergencyAccessUpdateData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    let atype = parse_access_type(payload.r#type)?;
+    let now = db::now_string();
+    access.atype = atype as i32;
+    access.wait_time_days = validate_wait_time_days(payload.wait_time_days)?;
+    if let Some(key_encrypted) = payload.key_encrypted.filter(|key| !key.is_empty()) {
+        access.key_encrypted = Some(key_encrypted);
+    }
+    access.updated_at = now;
+    access.update(&db).await?;
+
+    Ok(Json(access.to_json()))
+}
+
+/// DELETE /emergency-access/{id}
+/// POST /emergency-access/{id}/delete
+///
+/// Either the grantor or the grantee may remove the relationship.
+#[worker::send]
+pub async fn delete_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = EmergencyAccess::find_by_id(&db, &id)
+        .await?
+        .ok_or_else(not_found)?;
+
+    let other_party = if access.grantor_id == claims.sub {
+        access.grantee_id.clone()
+    } else if access.grantee_id.as_deref() == Some(claims.sub.as_str()) {
+        Some(access.grantor_id.clone())
+    } else {
+        return Err(not_found());
+    };
+
+    access.delete(&db).await?;
+
+    if let Some(user_id) = other_party {
+        notify_contact(&env, user_id, db::now_string());
+    }
+
+    Ok(Json(()))
+}
+
+/// POST /emergency-access/invite
+#[worker::send]
+pub async fn send_invite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(payload): Json<EmergencyAccessInviteData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let email = payload.email.trim().to_lowercase();
+    let atype = parse_access_type(payload.r#type)?;
+    let wait_time_days = validate_wait_time_days(payload.wait_time_days)?;
+
+    if email == claims.email.to_lowercase() {
+        return Err(AppError::BadRequest(
+            "You can not set yourself as an emergency contact.".to_string(),
+        ));
+    }
+
+    if User::find_by_email(&db, &email).await?.is_none() {
+        return Err(AppError::BadRequest(
+            "Grantee user does not exist.".to_string(),
+        ));
+    }
+
+    if EmergencyAccess::find_by_grantor_and_email(&db, &claims.sub, &email)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest(
+            "Emergency contact already invited.".to_string(),
+        ));
+    }
+
+    let access = EmergencyAccess::new(claims.sub.clone(), None, email, atype, wait_time_days);
+    access.insert(&db).await?;
+    if let Err(e) = send_invite_email(&env, &db, &base_url, &access).await {
+        access.delete(&db).await?;
+        return Err(e);
+    }
+
+    Ok(Json(()))
+}
+
+/// POST /emergency-access/{id}/reinvite
+#[worker::send]
+pub async fn resend_invite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Path(id): Path<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    if !access.has_status(EmergencyAccessStatus::Invited) {
+        return Err(AppError::BadRequest(
+            "The grantee has already accepted or been confirmed for this emergency access."
+                .to_string(),
+        ));
+    }
+
+    send_invite_email(&env, &db, &base_url, &access).await?;
+
+    Ok(Json(()))
+}
+
+/// POST /emergency-access/{id}/accept
+#[worker::send]
+pub async fn accept_invite(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<AcceptData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let invite = decode_invite_token(&env, &payload.token)?;
+
+    if invite.emer_id != id || invite.email.to_lowercase() != claims.email.to_lowercase() {
+        return Err(AppError::BadRequest(
+            "Claim email does not match current users email".to_string(),
+        ));
+    }
+
+    let mut access = EmergencyAccess::find_by_id(&db, &id)
+        .await?
+        .filter(|access| access.grantor_id == invite.grantor_id)
+        .ok_or_else(not_found)?;
+
+    if !access.has_status(EmergencyAccessStatus::Invited) {
+        return Err(AppError::BadRequest(
+            "Emergency access already accepted.".to_string(),
+        ));
+    }
+
+    let grantee = User::find_by_id(&db, &claims.sub)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    accept_for_grantee(&db, &mut access, &grantee).await?;
+
+    notify_contact(&env, access.grantor_id, access.updated_at);
+
+    Ok(Json(()))
+}
+
+/// POST /emergency-access/{id}/confirm
+#[worker::send]
+pub async fn confirm_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(payload): Json<ConfirmData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    if !access.has_status(EmergencyAccessStatus::Accepted) {
+        return Err(AppError::BadRequest(
+            "Emergency access not valid.".to_string(),
+        ));
+    }
+    let grantee_id = access.grantee_id.clone().ok_or_else(not_found)?;
+
+    let now = db::now_string();
+    access.key_encrypted = Some(payload.key);
+    access.email = None;
+    access.set_status(EmergencyAccessStatus::Confirmed, &now);
+    access.update(&db).await?;
+
+    notify_contact(&env, grantee_id, now);
+
+    Ok(Json(access.to_json()))
+}
+
+/// POST /emergency-access/{id}/initiate
+#[worker::send]
+pub async fn initiate_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantee(&db, &id, &claims.sub).await?;
+
+    if !access.has_status(EmergencyAccessStatus::Confirmed) {
+        return Err(AppError::BadRequest(
+            "Emergency access not valid.".to_string(),
+        ));
+    }
+
+    let now = db::now_string();
+    access.recovery_initiated_at = Some(now.clone());
+    access.last_notification_at = Some(now.clone());
+    access.set_status(EmergencyAccessStatus::RecoveryInitiated, &now);
+    access.update(&db).await?;
+
+    notify_contact(&env, access.grantor_id.clone(), now);
+
+    Ok(Json(access.to_json()))
+}
+
+/// POST /emergency-access/{id}/approve
+#[worker::send]
+pub async fn approve_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    if !access.has_status(EmergencyAccessStatus::RecoveryInitiated) {
+        return Err(AppError::BadRequest(
+            "Emergency access not valid.".to_string(),
+        ));
+    }
+    let grantee_id = access.grantee_id.clone().ok_or_else(not_found)?;
+
+    let now = db::now_string();
+    access.set_status(EmergencyAccessStatus::RecoveryApproved, &now);
+    access.update(&db).await?;
+
+    notify_contact(&env, grantee_id, now);
+
+    Ok(Json(access.to_json()))
+}
+
+/// POST /emergency-access/{id}/reject
+#[worker::send]
+pub async fn reject_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let mut access = find_as_grantor(&db, &id, &claims.sub).await?;
+
+    if !access.has_status(EmergencyAccessStatus::RecoveryInitiated)
+        && !access.has_status(EmergencyAccessStatus::RecoveryApproved)
+    {
+        return Err(AppError::BadRequest(
+            "Emergency access not valid.".to_string(),
+        ));
+    }
+    let grantee_id = access.grantee_id.clone().ok_or_else(not_found)?;
+
+    let now = db::now_string();
+    access.recovery_initiated_at = None;
+    access.set_status(EmergencyAccessStatus::Confirmed, &now);
+    access.update(&db).await?;
+
+    notify_contact(&env, grantee_id, now);
+
+    Ok(Json(access.to_json()))
+}
+
+/// POST /emergency-access/{id}/view
+///
+/// Returns the grantor's (non-deleted) ciphers together with the user key
+/// encrypted for the grantee.
+#[worker::send]
+pub async fn view_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    config: Config,
+    Path(id): Path<String>,
+) -> Result<RawJson, AppError> {
+    let db = db::get_db(&env)?;
+    let access = find_approved_as_grantee(&db, &id, &claims.sub, EmergencyAccessType::View).await?;
+    let key_encrypted =
+        serde_json::to_string(&access.key_encrypted).map_err(|_| AppError::Internal)?;
+
+    let include_attachments = attachments::attachments_enabled(env.as_ref());
+    let force_row_query = config.ciphers_default_row_query;
+    let mut response = String::new();
+    response.push_str("{\"ciphers\":");
+    super::ciphers::append_cipher_json_array_raw(
+        &mut response,
+        &db,
+        include_attachments,
+        "WHERE c.user_id = ?1 AND c.deleted_at IS NULL",
+        &[access.grantor_id.into()],
+        "",
+        force_row_query,
+    )
+    .await?;
+    response.push_str(",\"keyEncrypted\":");
+    response.push_str(&key_encrypted);
+    response.push_str(",\"object\":\"emergencyAccessView\"}");
+
+    Ok(RawJson(response))
+}
+
+/// POST /emergency-access/{id}/takeover
+///
+/// Returns the grantor's KDF settings and the user key encrypted for the grantee,
+/// so the grantee's client can derive a new master password for the grantor.
+#[worker::send]
+pub async fn takeover_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let access =
+        find_approved_as_grantee(&db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    let grantor = User::find_by_id(&db, &access.grantor_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Grantor user not found.".to_string()))?;
+
+    Ok(Json(json!({
+        "kdf": grantor.kdf_type,
+        "kdfIterations": grantor.kdf_iterations,
+        "kdfMemory": grantor.kdf_memory,
+        "kdfParallelism": grantor.kdf_parallelism,
+        "keyEncrypted": access.key_encrypted,
+        "object": "emergencyAccessTakeover",
+    })))
+}
+
+/// POST /emergency-access/{id}/password
+///
+/// Sets a new master password for the grantor. Two-factor providers and devices
+/// of the grantor are removed, since they would otherwise block the new login.
+#[worker::send]
+pub async fn password_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    config: Config,
+    Path(id): Path<String>,
+    Json(payload): Json<EmergencyAccessPasswordData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let access =
+        find_approved_as_grantee(&db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    let grantor_id = access.grantor_id;
+
+    let new_salt = generate_salt()?;
+    let password_iterations = config.password_iterations as i32;
+    let new_hashed_password = hash_password_for_storage(
+        &payload.new_master_password_hash,
+        &new_salt,
+        password_iterations as u32,
+    )
+    .await?;
+    let new_security_stamp = Uuid::new_v4().to_string();
+    let now = db::now_string();
+
+    d1_query!(
+        &db,
+        "UPDATE users SET master_password_hash = ?1, password_salt = ?2, password_iterations = ?3, key = ?4, security_stamp = ?5, totp_recover = NULL, updated_at = ?6 WHERE id = ?7",
+        new_hashed_password,
+        new_salt,
+        password_iterations,
+        payload.key,
+        new_security_stamp,
+        now,
+        grantor_id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await?;
+
+    d1_query!(
+        &db,
+        "DELETE FROM twofactor WHERE user_uuid = ?1",
+        grantor_id
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await?;
+
+    push::unregister_push_devices_by_user(&env, &grantor_id).await;
+    Device::delete_all_by_user(&db, &grantor_id).await?;
+
+    notifications::publish_user_logout((*env).clone(), grantor_id, now, None);
+
+    Ok(Json(()))
+}
+
+/// GET /emergency-access/{id}/policies
+///
+/// Organization policies are not supported, so the list is always empty.
+#[worker::send]
+pub async fn policies_emergency_access(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    find_approved_as_grantee(&db, &id, &claims.sub, EmergencyAccessType::Takeover).await?;
+    Ok(list_response(Vec::new()))
+}
+
+/// Approve recovery requests whose wait time has elapsed.
+///
+/// Called from the `scheduled` cron handler. Returns the number of approved requests.
+pub async fn emergency_request_timeout_job(env: &Env) -> Result<u32, worker::Error> {
+    let db = db::get_db(env).map_err(|e| worker::Error::RustError(e.to_string()))?;
+    let pending = EmergencyAccess::list_recovery_initiated(&db)
+        .await
+        .map_err(|e| worker::Error::RustError(e.to_string()))?;
+
+    let mut approved = 0u32;
+    for mut access in pending {
+        if !access.is_wait_time_elapsed() {
+            continue;
+        }
+
+        let now = db::now_string();
+        access.last_notification_at = Some(now.clone());
+        access.set_status(EmergencyAccessStatus::RecoveryApproved, &now);
+        access
+            .update(&db)
+            .await
+            .map_err(|e| worker::Error::RustError(e.to_string()))?;
+        approved += 1;
+
+        notify_contact(env, access.grantor_id.clone(), now.clone());
+        if let Some(grantee_id) = access.grantee_id {
+            notify_contact(env, grantee_id, now);
+        }
+    }
+
+    if approved > 0 {
+        log::info!("Approved {} emergency access request(s)", approved);
+    }
+
+    Ok(approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::testing::{block_on, enc_string, Env, TestClient, TestUser, BASE_URL};
+
+    fn invite_claims() -> EmergencyAccessInviteClaims {
+        EmergencyAccessInviteClaims {
+            email: "grantee@example.com".to_string(),
+            emer_id: "access-1".to_string(),
+            grantor_id: "grantor-1".to_string(),
+            iss: EMERGENCY_INVITE_ISSUER.to_string(),
+        }
+    }
+
+    fn sign(env: &Env, claims: EmergencyAccessInviteClaims) -> String {
+        sign_email_token(env, claims, Duration::days(INVITE_TOKEN_DAYS)).unwrap()
+    }
+
+    #[test]
+    fn wait_time_must_be_at_least_one_day() {
+        assert!(validate_wait_time_days(0).is_err());
+        assert!(validate_wait_time_days(-3).is_err());
+        assert_eq!(validate_wait_time_days(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn invite_tokens_round_trip_only_with_the_signing_secret() {
+        let token = sign(&Env::new(), invite_claims());
+        let claims = decode_invite_token(&Env::new(), &token).unwrap();
+        assert_eq!(claims.emer_id, "access-1");
+        assert_eq!(claims.email, "grantee@example.com");
+        let other = Env::with_vars(&[("JWT_SECRET", "other secret")]);
+        assert!(decode_invite_token(&other, &token).is_err());
+    }
+
+    #[test]
+    fn invite_tokens_need_the_invite_issuer() {
+        let env = Env::new();
+        let mut claims = invite_claims();
+        claims.iss = "warden-worker|somethingelse".to_string();
+        assert!(decode_invite_token(&env, &sign(&env, claims)).is_err());
+    }
+
+    /// The login tokens of a grantor and a grantee, and the id of their emergency access.
+    struct Grant {
+        grantor: String,
+        grantee: String,
+        id: String,
+    }
+
+    /// The query of the last `accept-emergency` link mailed to `email`.
+    fn invite_link_params(env: &Env, email: &str) -> Vec<(String, String)> {
+        let mail = env.sent_mail();
+        let message = mail
+            .iter()
+            .rev()
+            .find(|message| message.to == email)
+            .expect("an invitation was mailed");
+        let prefix = format!("{BASE_URL}/#/accept-emergency?");
+        let start = message.text.find(&prefix).expect("the mail has a link") + prefix.len();
+        let query = message.text[start..].split_whitespace().next().unwrap();
+        form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    fn param<'a>(params: &'a [(String, String)], name: &str) -> &'a str {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("the link has {name}"))
+    }
+
+    fn invite(client: &TestClient, grantor: &str, grantee: &TestUser, atype: i32) -> String {
+        let invited = client.post(
+            "/api/emergency-access/invite",
+            Some(grantor),
+            json!({ "email": grantee.email, "type": atype, "waitTimeDays": 1 }),
+        );
+        assert_eq!(invited.status, StatusCode::OK, "{}", invited.body);
+        let trusted = client.get("/api/emergency-access/trusted", Some(grantor));
+        trusted.body["data"][0]["id"].as_str().unwrap().to_string()
+    }
+
+    fn status(client: &TestClient, grant: &Grant) -> Value {
+        let access = client.get(
+            &format!("/api/emergency-access/{}", grant.id),
+            Some(&grant.grantor),
+        );
+        access.body["status"].clone()
+    }
+
+    fn action(client: &TestClient, token: &str, id: &str, action: &str) -> StatusCode {
+        client
+            .post(
+                &format!("/api/emergency-access/{id}/{action}"),
+                Some(token),
+                json!({}),
+            )
+            .status
+    }
+
+    /// Invite a new account with a one-day wait, accept the mailed link and confirm it.
+    fn confirmed_grant(env: &Env, client: &TestClient, atype: i32) -> Grant {
+        let grantor = client.login(&client.register());
+        let grantee_user = client.register();
+        let grantee = client.login(&grantee_user);
+        let id = invite(client, &grantor, &grantee_user, atype);
+
+        let params = invite_link_params(env, &grantee_user.email);
+        assert_eq!(param(&params, "id"), id);
+        assert_eq!(param(&params, "email"), grantee_user.email);
+        let accepted = client.post(
+            &format!("/api/emergency-access/{id}/accept"),
+            Some(&grantee),
+            json!({ "token": param(&params, "token") }),
+        );
+        assert_eq!(accepted.status, StatusCode::OK, "{}", accepted.body);
+
+        let confirmed = client.post(
+            &format!("/api/emergency-access/{id}/confirm"),
+            Some(&grantor),
+            json!({ "key": enc_string() }),
+        );
+        assert_eq!(confirmed.status, StatusCode::OK, "{}", confirmed.body);
+        let grant = Grant {
+            grantor,
+            grantee,
+            id,
+        };
+        assert_eq!(
+            status(client, &grant),
+            EmergencyAccessStatus::Confirmed as i32
+        );
+        grant
+    }
+
+    #[test]
+    fn invites_are_accepted_only_by_the_invited_account() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let grantor = client.login(&client.register());
+        let grantee = client.register();
+        let id = invite(&client, &grantor, &grantee, 0);
+        let token = param(&invite_link_params(&env, &grantee.email), "token").to_string();
+
+        let stranger = client.login(&client.register());
+        let stolen = client.post(
+            &format!("/api/emergency-access/{id}/accept"),
+            Some(&stranger),
+            json!({ "token": token }),
+        );
+        assert_eq!(stolen.status, StatusCode::BAD_REQUEST);
+        let forged = client.post(
+            &format!("/api/emergency-access/{id}/accept"),
+            Some(&client.login(&grantee)),
+            json!({ "token": sign(&Env::with_vars(&[("JWT_SECRET", "other")]), invite_claims()) }),
+        );
+        assert_eq!(forged.status, StatusCode::BAD_REQUEST);
+
+        // A grant that was accepted can't be re-sent.
+        let accepted = client.post(
+            &format!("/api/emergency-access/{id}/accept"),
+            Some(&client.login(&grantee)),
+            json!({ "token": token }),
+        );
+        assert_eq!(accepted.status, StatusCode::OK, "{}", accepted.body);
+        let resent = client.post(
+            &format!("/api/emergency-access/{id}/reinvite"),
+            Some(&grantor),
+            json!({}),
+        );
+        assert_eq!(resent.status, StatusCode::BAD_REQUEST);
+        assert!(resent.body.to_string().contains("emergency access"));
+    }
+
+    #[test]
+    fn invitations_need_mail() {
+        let client = TestClient::new();
+        let grantor = client.login(&client.register());
+        let grantee = client.register();
+        let invited = client.post(
+            "/api/emergency-access/invite",
+            Some(&grantor),
+            json!({ "email": grantee.email, "type": 0, "waitTimeDays": 1 }),
+        );
+        assert_eq!(invited.status, StatusCode::BAD_REQUEST);
+        let trusted = client.get("/api/emergency-access/trusted", Some(&grantor));
+        assert_eq!(trusted.body["data"], json!([]));
+    }
+
+    #[test]
+    fn the_grantor_approves_or_rejects_recovery() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let grant = confirmed_grant(&env, &client, EmergencyAccessType::View as i32);
+        let view = |client: &TestClient| action(client, &grant.grantee, &grant.id, "view");
+
+        // Only the grantee initiates, and nothing is viewable before approval.
+        assert_eq!(
+            action(&client, &grant.grantor, &grant.id, "initiate"),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "initiate"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&client, &grant),
+            EmergencyAccessStatus::RecoveryInitiated as i32
+        );
+        assert_eq!(view(&client), StatusCode::BAD_REQUEST);
+
+        // Only the grantor approves or rejects.
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "approve"),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            action(&client, &grant.grantor, &grant.id, "reject"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&client, &grant),
+            EmergencyAccessStatus::Confirmed as i32
+        );
+        assert_eq!(view(&client), StatusCode::BAD_REQUEST);
+
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "initiate"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            action(&client, &grant.grantor, &grant.id, "approve"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&client, &grant),
+            EmergencyAccessStatus::RecoveryApproved as i32
+        );
+        let viewed = client.post(
+            &format!("/api/emergency-access/{}/view", grant.id),
+            Some(&grant.grantee),
+            json!({}),
+        );
+        assert_eq!(viewed.status, StatusCode::OK, "{}", viewed.body);
+        assert_eq!(viewed.body["object"], "emergencyAccessView");
+        // A view grant does not allow a takeover.
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "takeover"),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn recovery_is_approved_once_the_wait_time_has_elapsed() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let grant = confirmed_grant(&env, &client, EmergencyAccessType::Takeover as i32);
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "initiate"),
+            StatusCode::OK
+        );
+
+        // Less than the one-day wait has passed.
+        assert_eq!(block_on(emergency_request_timeout_job(&env)).unwrap(), 0);
+        assert_eq!(
+            action(&client, &grant.grantee, &grant.id, "takeover"),
+            StatusCode::BAD_REQUEST
+        );
+
+        let db = db::get_db(&env).unwrap();
+        let day_ago = (chrono::Utc::now() - Duration::hours(25))
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string();
+        block_on(async {
+            d1_query!(
+                &db,
+                "UPDATE emergency_access SET recovery_initiated_at = ?1 WHERE id = ?2",
+                day_ago,
+                grant.id
+            )
+            .unwrap()
+            .run()
+            .await
+        })
+        .unwrap();
+
+        assert_eq!(block_on(emergency_request_timeout_job(&env)).unwrap(), 1);
+        assert_eq!(
+            status(&client, &grant),
+            EmergencyAccessStatus::RecoveryApproved as i32
+        );
+        let takeover = client.post(
+            &format!("/api/emergency-access/{}/takeover", grant.id),
+            Some(&grant.grantee),
+            json!({}),
+        );
+        assert_eq!(takeover.status, StatusCode::OK, "{}", takeover.body);
+        assert_eq!(takeover.body["object"], "emergencyAccessTakeover");
+        // Already approved requests are not approved again.
+        assert_eq!(block_on(emergency_request_timeout_job(&env)).unwrap(), 0);
+    }
 }
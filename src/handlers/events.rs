@@ -0,0 +1,440 @@
+//! Audit log of security-relevant actions.
+//!
+//! Handlers record events through the `log_*` helpers below. Recording never fails the
+//! request it describes: errors are logged and swallowed.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::{
+    auth::Claims,
+    client_context::{request_device_type_from_headers, request_ip_from_headers},
+    db,
+    env::Env,
+    error::AppError,
+    handlers::ciphers::cipher_read_access_sql,
+    models::{
+        cipher::CipherDBModel,
+        event::{Event, EventType},
+    },
+};
+
+/// Maximum number of events returned per page.
+const EVENTS_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventRange {
+    start: Option<String>,
+    end: Option<String>,
+    continuation_token: Option<String>,
+}
+
+/// Where a page of events ends: events dated before `date`, or dated `date` with an id that
+/// sorts before `id`. An empty `id` excludes everything dated `date`.
+struct PageEnd {
+    date: String,
+    id: String,
+}
+
+impl PageEnd {
+    fn as_bounds(&self) -> (&str, &str) {
+        (&self.date, &self.id)
+    }
+}
+
+impl EventRange {
+    /// Resolve the query into the start date and the end of the page, in the format stored
+    /// in D1. A continuation token is `{date}|{id}` of the last event of the previous page.
+    fn bounds(&self) -> Result<(String, PageEnd), AppError> {
+        let start = match self.start.as_deref() {
+            Some(start) => parse_date(start, "start")?,
+            None => "0000-01-01T00:00:00.000Z".to_string(),
+        };
+        let end = match (self.continuation_token.as_deref(), self.end.as_deref()) {
+            (Some(token), _) => {
+                let (date, id) = token
+                    .split_once('|')
+                    .filter(|(_, id)| !id.is_empty())
+                    .ok_or_else(|| AppError::BadRequest("Invalid continuationToken".to_string()))?;
+                PageEnd {
+                    date: parse_date(date, "continuationToken")?,
+                    id: id.to_string(),
+                }
+            }
+            (None, end) => PageEnd {
+                date: match end {
+                    Some(end) => parse_date(end, "end")?,
+                    None => "9999-12-31T23:59:59.999Z".to_string(),
+                },
+                id: String::new(),
+            },
+        };
+        Ok((start, end))
+    }
+}
+
+fn parse_date(raw: &str, field: &str) -> Result<String, AppError> {
+    DateTime::parse_from_rfc3339(raw)
+        .map(|date| {
+            date.with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+        })
+        .map_err(|_| AppError::BadRequest(format!("Invalid {field} date")))
+}
+
+fn events_response(events: Vec<Event>) -> Value {
+    let continuation_token = if events.len() as i64 == EVENTS_PAGE_SIZE {
+        events
+            .last()
+            .map(|event| format!("{}|{}", event.event_date, event.id))
+    } else {
+        None
+    };
+    let data: Vec<Value> = events.iter().map(Event::to_json).collect();
+    json!({
+        "data": data,
+        "continuationToken": continuation_token,
+        "object": "list",
+    })
+}
+
+/// GET /api/events - Events about the current user's account and personal ciphers
+#[worker::send]
+pub async fn get_user_events(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Query(range): Query<EventRange>,
+) -> Result<Json<Value>, AppError> {
+    let (start, end) = range.bounds()?;
+    let db = db::get_db(&env)?;
+    let events =
+        Event::find_by_user(&db, &claims.sub, &start, end.as_bounds(), EVENTS_PAGE_SIZE).await?;
+    Ok(Json(events_response(events)))
+}
+
+/// GET /api/ciphers/{id}/events
+#[worker::send]
+pub async fn get_cipher_events(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(cipher_id): Path<String>,
+    Query(range): Query<EventRange>,
+) -> Result<Json<Value>, AppError> {
+    let (start, end) = range.bounds()?;
+    let db = db::get_db(&env)?;
+
+    let sql = format!(
+        "SELECT c.id FROM ciphers c WHERE c.id = ?1 AND {}",
+        cipher_read_access_sql("?2")
+    );
+    let readable: Option<String> = db
+        .prepare(&sql)
+        .bind(&[cipher_id.clone().into(), claims.sub.clone().into()])?
+        .first(Some("id"))
+        .await
+        .map_err(|_| AppError::Database)?;
+    if readable.is_none() {
+        return Err(AppError::NotFound("Cipher not found".to_string()));
+    }
+
+    let events =
+        Event::find_by_cipher(&db, &cipher_id, &start, end.as_bounds(), EVENTS_PAGE_SIZE).await?;
+    Ok(Json(events_response(events)))
+}
+
+fn event_from_headers(event_type: EventType, headers: &HeaderMap) -> Event {
+    Event::new(
+        event_type,
+        request_device_type_from_headers(headers),
+        request_ip_from_headers(headers),
+    )
+}
+
+async fn save_event(db: &db::Db, event: Event) {
+    if let Err(e) = event.save(db).await {
+        log::warn!("Failed to record event {}: {e}", event.event_type);
+    }
+}
+
+/// Record an event about the account of `user_id`, performed by that user.
+pub(crate) async fn log_user_event(
+    db: &db::Db,
+    event_type: EventType,
+    user_id: &str,
+    headers: &HeaderMap,
+) {
+    let mut event = event_from_headers(event_type, headers);
+    event.user_id = Some(user_id.to_string());
+    event.acting_user_id = Some(user_id.to_string());
+    save_event(db, event).await;
+}
+
+/// Record an event about `cipher`, performed by `acting_user_id`.
+pub(crate) async fn log_cipher_event(
+    db: &db::Db,
+    event_type: EventType,
+    cipher: &CipherDBModel,
+    acting_user_id: &str,
+    headers: &HeaderMap,
+) {
+    let mut event = event_from_headers(event_type, headers);
+    event.user_id = cipher.user_id.clone();
+    event.organization_id = cipher.organization_id.clone();
+    event.cipher_id = Some(cipher.id.clone());
+    event.acting_user_id = Some(acting_user_id.to_string());
+    save_event(db, event).await;
+}
+
+#[derive(Deserialize)]
+struct CipherOwnerRow {
+    id: String,
+    user_id: Option<String>,
+    organization_id: Option<String>,
+}
+
+/// Record an event for each cipher listed in `body` (`{"ids": [...]}`) that matches
+/// `access_sql`, a predicate over ciphers `c` with the acting user bound to `?2`.
+pub(crate) async fn log_cipher_events_bulk(
+    db: &db::Db,
+    event_type: EventType,
+    access_sql: &str,
+    acting_user_id: &str,
+    body: &str,
+    headers: &HeaderMap,
+) {
+    let sql = format!(
+        "SELECT c.id, c.user_id, c.organization_id FROM ciphers c
+         WHERE {access_sql} AND c.id IN (SELECT value FROM json_each(?1, '$.ids'))"
+    );
+    let result = async {
+        let ciphers: Vec<CipherOwnerRow> = db
+            .prepare(&sql)
+            .bind(&[body.into(), acting_user_id.into()])?
+            .all()
+            .await?
+            .results()?;
+        let statements = ciphers
+            .into_iter()
+            .map(|cipher| {
+                let mut event = event_from_headers(event_type, headers);
+                event.user_id = cipher.user_id;
+                event.organization_id = cipher.organization_id;
+                event.cipher_id = Some(cipher.id);
+                event.acting_user_id = Some(acting_user_id.to_string());
+                event.insert_statement(db)
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        if !statements.is_empty() {
+            db.batch(statements).await?;
+        }
+        Ok::<(), AppError>(())
+    };
+    if let Err(e) = result.await {
+        log::warn!("Failed to record event {}: {e}", event_type as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::testing::{block_on, enc_string, Env, TestClient};
+
+    /// Record `count` login events for `user_id` at `date`.
+    fn record(env: &Env, user_id: &str, date: &str, count: usize) {
+        let db = db::get_db(env).unwrap();
+        for _ in 0..count {
+            let mut event = Event::new(EventType::UserLoggedIn, 9, "127.0.0.1".to_string());
+            event.user_id = Some(user_id.to_string());
+            event.event_date = date.to_string();
+            block_on(event.save(&db)).unwrap();
+        }
+    }
+
+    fn user_id(client: &TestClient, token: &str) -> String {
+        let profile = client.get("/api/accounts/profile", Some(token));
+        profile.body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Follow the continuation tokens from `path` and return every page.
+    fn all_pages(client: &TestClient, token: &str, path: &str) -> Vec<Value> {
+        let mut pages = Vec::new();
+        let mut next = path.to_string();
+        loop {
+            let page = client.get(&next, Some(token));
+            assert_eq!(page.status, StatusCode::OK, "{}", page.body);
+            let continuation = page.body["continuationToken"].as_str().map(str::to_string);
+            pages.push(page.body);
+            match continuation {
+                Some(continuation) => {
+                    let continuation: String =
+                        form_urlencoded::byte_serialize(continuation.as_bytes()).collect();
+                    next = format!("{path}&continuationToken={continuation}");
+                }
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_keep_events_that_share_a_timestamp_across_the_boundary() {
+        let env = Env::new();
+        let client = TestClient::with_env(env.clone());
+        let token = client.login(&client.register());
+        let user = user_id(&client, &token);
+        record(&env, &user, "2020-01-03T00:00:00.000Z", 30);
+        // 140 events at one instant straddle the first and second page.
+        record(&env, &user, "2020-01-02T00:00:00.000Z", 140);
+        record(&env, &user, "2020-01-01T00:00:00.000Z", 50);
+
+        let pages = all_pages(
+            &client,
+            &token,
+            "/api/events?start=2020-01-01T00:00:00Z&end=2020-02-01T00:00:00Z",
+        );
+        assert_eq!(pages.len(), 3);
+        let events: Vec<&Value> = pages
+            .iter()
+            .flat_map(|page| page["data"].as_array().unwrap())
+            .collect();
+        assert_eq!(events.len(), 220);
+        let dates: Vec<&str> = events
+            .iter()
+            .map(|event| event["date"].as_str().unwrap())
+            .collect();
+        assert!(dates.windows(2).all(|pair| pair[0] >= pair[1]));
+        let per_date = |date: &str| dates.iter().filter(|d| **d == date).count();
+        assert_eq!(per_date("2020-01-03T00:00:00.000Z"), 30);
+        assert_eq!(per_date("2020-01-02T00:00:00.000Z"), 140);
+        assert_eq!(per_date("2020-01-01T00:00:00.000Z"), 50);
+        let ids: HashSet<String> = block_on(Event::find_by_user(
+            &db::get_db(&env).unwrap(),
+            &user,
+            "2020-01-01T00:00:00.000Z",
+            ("2020-02-01T00:00:00.000Z", ""),
+            1000,
+        ))
+        .unwrap()
+        .into_iter()
+        .map(|event| event.id)
+        .collect();
+        assert_eq!(ids.len(), 220);
+    }
+
+    #[test]
+    fn events_are_filtered_by_date_range() {
+        let env = Env::new();
+        let client = TestClient::with_env(env.clone());
+        let token = client.login(&client.register());
+        let user = user_id(&client, &token);
+        record(&env, &user, "2020-01-01T00:00:00.000Z", 1);
+        record(&env, &user, "2020-01-02T12:00:00.000Z", 2);
+        record(&env, &user, "2020-01-03T00:00:00.000Z", 1);
+        let other = client.login(&client.register());
+        record(
+            &env,
+            &user_id(&client, &other),
+            "2020-01-02T12:00:00.000Z",
+            1,
+        );
+
+        // `start` is inclusive, `end` exclusive, and other offsets are converted to UTC.
+        let page = client.get(
+            "/api/events?start=2020-01-01T00:00:00Z&end=2020-01-03T01:00:00%2B01:00",
+            Some(&token),
+        );
+        assert_eq!(page.status, StatusCode::OK, "{}", page.body);
+        let dates: Vec<&str> = page.body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["date"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            dates,
+            [
+                "2020-01-02T12:00:00.000Z",
+                "2020-01-02T12:00:00.000Z",
+                "2020-01-01T00:00:00.000Z"
+            ]
+        );
+        assert!(page.body["continuationToken"].is_null());
+
+        for bad in [
+            "/api/events?start=yesterday",
+            "/api/events?continuationToken=2020-01-02T12:00:00.000Z",
+            "/api/events?continuationToken=soon%7Cabc",
+        ] {
+            assert_eq!(
+                client.get(bad, Some(&token)).status,
+                StatusCode::BAD_REQUEST,
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn bulk_actions_record_one_event_per_writable_cipher() {
+        let env = Env::new();
+        let client = TestClient::with_env(env.clone());
+        let token = client.login(&client.register());
+        let other = client.login(&client.register());
+        let cipher = |token: &str| {
+            let created = client.post(
+                "/api/ciphers",
+                Some(token),
+                json!({ "type": 2, "name": enc_string(), "secureNote": { "type": 0 } }),
+            );
+            created.body["id"].as_str().unwrap().to_string()
+        };
+        let own = [cipher(&token), cipher(&token)];
+        let foreign = cipher(&other);
+
+        let deleted = client.put(
+            "/api/ciphers/delete",
+            Some(&token),
+            json!({ "ids": [own[0], own[1], foreign] }),
+        );
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+
+        for id in &own {
+            let events = client.get(&format!("/api/ciphers/{id}/events"), Some(&token));
+            assert_eq!(events.status, StatusCode::OK, "{}", events.body);
+            let data = events.body["data"].as_array().unwrap();
+            assert_eq!(data.len(), 1);
+            assert_eq!(data[0]["type"], EventType::CipherSoftDeleted as i32);
+            assert_eq!(data[0]["cipherId"], id.as_str());
+        }
+        let foreign_events = client.get(&format!("/api/ciphers/{foreign}/events"), Some(&other));
+        assert_eq!(foreign_events.body["data"], json!([]));
+
+        let env_events: Vec<Event> = block_on(Event::find_by_user(
+            &db::get_db(&env).unwrap(),
+            &user_id(&client, &token),
+            "0000-01-01T00:00:00.000Z",
+            ("9999-01-01T00:00:00.000Z", ""),
+            100,
+        ))
+        .unwrap();
+        let ids: Vec<&str> = env_events
+            .iter()
+            .filter(|event| event.event_type == EventType::CipherSoftDeleted as i32)
+            .map(|event| event.id.as_str())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| Uuid::parse_str(id).is_ok()));
+    }
+}
@@ -0,0 +1,312 @@
+use axum::extract::State;
+use axum::{Extension, Json};
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::auth::Claims;
+use crate::config::Config;
+use crate::converters::{self, CxfAccount};
+use crate::db;
+use crate::env::Env;
+use crate::error::AppError;
+use crate::handlers::attachments;
+use crate::handlers::ciphers::{self, RawJson};
+use crate::models::folder::Folder;
+use crate::BaseUrl;
+
+/// Personal, non-deleted ciphers. `?1` is the user id.
+const EXPORT_WHERE: &str =
+    "WHERE c.user_id = ?1 AND c.organization_id IS NULL AND c.deleted_at IS NULL";
+/// Ciphers are listed in this order, so indexes in `folderRelationships` refer to it.
+const EXPORT_ORDER: &str = "ORDER BY c.created_at, c.id";
+
+#[derive(Deserialize)]
+struct FolderLinkRow {
+    cipher_index: usize,
+    folder_id: String,
+}
+
+/// GET /api/accounts/export
+///
+/// Exports the user's personal vault in the shape accepted by `POST /api/ciphers/import`:
+/// `ciphers`, `folders` and `folderRelationships`. Cipher and folder fields stay encrypted with
+/// the user key, and each cipher carries its attachment metadata. Re-importing restores folders,
+/// favorites and archived items, but not attachments: their files are not included.
+#[worker::send]
+pub async fn export_data(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    config: Config,
+) -> Result<RawJson, AppError> {
+    let user_id = claims.sub;
+    let db = db::get_db(&env)?;
+
+    let folders: Vec<Folder> = db
+        .prepare("SELECT * FROM folders WHERE user_id = ?1 ORDER BY created_at, id")
+        .bind(&[user_id.clone().into()])?
+        .all()
+        .await?
+        .results()?;
+    let folder_indexes: HashMap<&str, usize> = folders
+        .iter()
+        .enumerate()
+        .map(|(index, folder)| (folder.id.as_str(), index))
+        .collect();
+
+    // Compute the cipher -> folder links in SQL so the ciphers themselves never need parsing.
+    let links: Vec<FolderLinkRow> = db
+        .prepare(format!(
+            "SELECT cipher_index, folder_id FROM (
+                SELECT ROW_NUMBER() OVER ({EXPORT_ORDER}) - 1 AS cipher_index, c.folder_id
+                FROM ciphers c
+                {EXPORT_WHERE}
+            ) WHERE folder_id IS NOT NULL"
+        ))
+        .bind(&[user_id.clone().into()])?
+        .all()
+        .await?
+        .results()?;
+    let relationships: Vec<_> = links
+        .iter()
+        .filter_map(|link| {
+            folder_indexes
+                .get(link.folder_id.as_str())
+                .map(|folder| json!({ "key": link.cipher_index, "value": folder }))
+        })
+        .collect();
+
+    let folders_json: Vec<_> = folders
+        .iter()
+        .map(|folder| json!({ "id": folder.id, "name": folder.name }))
+        .collect();
+    let folders_json = serde_json::to_string(&folders_json).map_err(|_| AppError::Internal)?;
+    let relationships_json =
+        serde_json::to_string(&relationships).map_err(|_| AppError::Internal)?;
+
+    let mut response = String::new();
+    response.push_str("{\"encrypted\":true,\"folders\":");
+    response.push_str(&folders_json);
+    response.push_str(",\"folderRelationships\":");
+    response.push_str(&relationships_json);
+    response.push_str(",\"ciphers\":");
+    ciphers::append_cipher_json_array_raw(
+        &mut response,
+        &db,
+        attachments::attachments_enabled(env.as_ref()),
+        EXPORT_WHERE,
+        &[user_id.into()],
+        EXPORT_ORDER,
+        config.ciphers_default_row_query,
+    )
+    .await?;
+    response.push('}');
+
+    Ok(RawJson(response))
+}
+
+/// POST /api/accounts/export/cxf
+///
+/// Converts the client's unencrypted JSON export into the FIDO Credential Exchange Format,
+/// passkeys included. The server cannot decrypt the vault, so the plaintext export is the
+/// input; like plaintext imports this is refused unless `ALLOW_PLAINTEXT_CONVERSION` is enabled.
+#[worker::send]
+pub async fn export_cxf(
+    claims: Claims,
+    config: Config,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    body: Bytes,
+) -> Result<Json<Value>, AppError> {
+    if !config.allow_plaintext_conversion {
+        return Err(AppError::BadRequest(
+            "Plaintext conversion is disabled on this server".to_string(),
+        ));
+    }
+    let rp_id = worker::Url::parse(&base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let account = CxfAccount {
+        id: &claims.sub,
+        username: &claims.name,
+        email: &claims.email,
+    };
+    let (header, warnings) =
+        converters::export_cxf(&body, &account, &rp_id).map_err(AppError::BadRequest)?;
+
+    Ok(Json(json!({
+        "data": header,
+        "warnings": warnings,
+        "object": "exportConversion",
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::testing::{enc_string, Env, TestClient};
+
+    fn create_cipher(client: &TestClient, token: &str, extra: Value) -> String {
+        let mut cipher = json!({
+            "type": 1,
+            "name": enc_string(),
+            "notes": enc_string(),
+            "login": { "username": enc_string(), "password": enc_string() },
+        });
+        cipher
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        let created = client.post("/api/ciphers", Some(token), cipher);
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        created.body["id"].as_str().unwrap().to_string()
+    }
+
+    fn export(client: &TestClient, token: &str) -> Value {
+        let exported = client.get("/api/accounts/export", Some(token));
+        assert_eq!(exported.status, StatusCode::OK, "{}", exported.body);
+        exported.body
+    }
+
+    /// The exported ciphers without server-assigned ids and dates or attachments, each with the
+    /// name of its folder, ordered by name.
+    fn comparable(export: &Value) -> Vec<Value> {
+        let folders = export["folders"].as_array().unwrap();
+        let mut ciphers: Vec<Value> = export["ciphers"].as_array().unwrap().clone();
+        for relationship in export["folderRelationships"].as_array().unwrap() {
+            let cipher = relationship["key"].as_u64().unwrap() as usize;
+            let folder = relationship["value"].as_u64().unwrap() as usize;
+            ciphers[cipher]["folder"] = folders[folder]["name"].clone();
+        }
+        for cipher in &mut ciphers {
+            let fields = cipher.as_object_mut().unwrap();
+            for volatile in [
+                "id",
+                "userId",
+                "folderId",
+                "revisionDate",
+                "creationDate",
+                "attachments",
+            ] {
+                fields.remove(volatile);
+            }
+        }
+        ciphers.sort_by_key(|cipher| cipher["name"].as_str().unwrap().to_string());
+        ciphers
+    }
+
+    #[test]
+    fn an_export_imports_back_into_the_same_export() {
+        let env = Env::new().with_file_storage();
+        let client = TestClient::with_env(env);
+        let token = client.login(&client.register());
+
+        let folder = client.post(
+            "/api/folders",
+            Some(&token),
+            json!({ "name": enc_string() }),
+        );
+        let folder_id = folder.body["id"].as_str().unwrap();
+        create_cipher(
+            &client,
+            &token,
+            json!({ "folderId": folder_id, "favorite": true }),
+        );
+        let archived = create_cipher(&client, &token, json!({}));
+        let response = client.put(
+            &format!("/api/ciphers/{archived}/archive"),
+            Some(&token),
+            json!({}),
+        );
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let with_file = create_cipher(&client, &token, json!({ "folderId": folder_id }));
+        let file_key = enc_string();
+        let uploaded = client.post_multipart(
+            &format!("/api/ciphers/{with_file}/attachment"),
+            Some(&token),
+            &[
+                ("key", None, file_key.as_bytes()),
+                ("data", Some("file.txt"), b"contents"),
+            ],
+        );
+        assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+
+        let first = export(&client, &token);
+        assert_eq!(first["ciphers"].as_array().unwrap().len(), 3);
+        let exported_file = first["ciphers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|cipher| cipher["id"] == with_file.as_str())
+            .map(|cipher| cipher["attachments"][0].clone())
+            .unwrap();
+        assert_eq!(exported_file["fileName"], "file.txt");
+        assert_eq!(exported_file["key"], file_key.as_str());
+        assert_eq!(exported_file["size"], "8");
+
+        let other = client.login(&client.register());
+        let imported = client.post(
+            "/api/ciphers/import",
+            Some(&other),
+            json!({
+                "ciphers": first["ciphers"],
+                "folders": first["folders"],
+                "folderRelationships": first["folderRelationships"],
+            }),
+        );
+        assert_eq!(imported.status, StatusCode::OK, "{}", imported.body);
+
+        let second = export(&client, &other);
+        assert_eq!(second["folders"].as_array().unwrap().len(), 1);
+        assert_eq!(second["folders"][0]["name"], first["folders"][0]["name"]);
+        assert_ne!(second["folders"][0]["id"], first["folders"][0]["id"]);
+        let round_tripped = comparable(&second);
+        assert_eq!(round_tripped, comparable(&first));
+        assert_eq!(
+            round_tripped
+                .iter()
+                .filter(|cipher| cipher["archivedDate"].is_string())
+                .count(),
+            1
+        );
+        assert_eq!(
+            round_tripped
+                .iter()
+                .filter(|cipher| cipher["favorite"] == true)
+                .count(),
+            1
+        );
+        // Attachment files are not part of the export, so their metadata is not imported.
+        assert!(second["ciphers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|cipher| cipher["attachments"].is_null()));
+    }
+
+    #[test]
+    fn imports_reject_an_invalid_archived_date() {
+        let client = TestClient::new();
+        let token = client.login(&client.register());
+
+        let imported = client.post(
+            "/api/ciphers/import",
+            Some(&token),
+            json!({
+                "ciphers": [{
+                    "type": 2,
+                    "name": enc_string(),
+                    "secureNote": { "type": 0 },
+                    "archivedDate": "yesterday",
+                }],
+                "folders": [],
+            }),
+        );
+        assert_eq!(imported.status, StatusCode::BAD_REQUEST);
+        assert_eq!(export(&client, &token)["ciphers"], json!([]));
+    }
+}
@@ -3,9 +3,9 @@ use axum::Json;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::Env;
 
 use crate::d1_query;
+use crate::env::Env;
 
 use crate::auth::Claims;
 use crate::db::{self, touch_user_updated_at};
@@ -0,0 +1,585 @@
+//! Website icons for vault items (`GET /icons/{domain}/icon.png`).
+//!
+//! The icon is taken from the site's `<link rel="icon">` tags, falling back to `/favicon.ico`.
+//! Only public domain names are fetched, redirects are followed by hand so every hop is checked,
+//! and responses are size-limited and must look like a raster image. Results, including
+//! failures, are cached in the optional `ICON_CACHE` KV namespace, or failing that in the
+//! optional `ICON_BUCKET` R2 bucket. Downloads are rate limited per IP address by
+//! `ICON_RATE_LIMITER`.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{
+    future::{select, Either},
+    StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use worker::{
+    kv::KvStore, Bucket, Delay, Fetch, Headers, Method, Request, RequestInit, RequestRedirect, Url,
+};
+
+use crate::{client_context::request_ip_from_headers, config::Config, env::Env, error::AppError};
+
+const ICON_CACHE_BINDING: &str = "ICON_CACHE";
+const ICON_BUCKET_BINDING: &str = "ICON_BUCKET";
+const ICON_RATE_LIMITER_BINDING: &str = "ICON_RATE_LIMITER";
+const ICON_CACHE_PREFIX: &str = "icon:";
+const ICON_BUCKET_PREFIX: &str = "icons/";
+
+/// Only the start of a page is searched for `<link>` tags.
+const MAX_HTML_BYTES: usize = 256 * 1024;
+const MAX_ICON_BYTES: usize = 512 * 1024;
+const MAX_REDIRECTS: usize = 5;
+/// `<link>` candidates tried before falling back to `/favicon.ico`.
+const MAX_LINK_CANDIDATES: usize = 3;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const USER_AGENT: &str = "Mozilla/5.0 (compatible; warden-worker icon fetcher)";
+
+/// Suffixes that never resolve to a public site.
+const BLOCKED_SUFFIXES: &[&str] = &[
+    "localhost",
+    "local",
+    "localdomain",
+    "internal",
+    "intranet",
+    "lan",
+    "home",
+    "home.arpa",
+    "corp",
+    "private",
+    "arpa",
+    "test",
+    "invalid",
+    "example",
+    "onion",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IconCacheMetadata {
+    /// `None` marks a cached failure.
+    content_type: Option<String>,
+}
+
+struct Icon {
+    content_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+/// A cached lookup: the icon's content type and bytes, or `None` for a site without one.
+type CachedIcon = Option<(String, Vec<u8>)>;
+
+/// Where lookups are cached. KV expires entries by itself; R2 has no per-object TTL, so the
+/// expiry is kept in the object's metadata and stale objects are replaced on the next lookup.
+enum IconCache {
+    Kv(KvStore),
+    R2(Bucket),
+}
+
+impl IconCache {
+    fn from_env(env: &Env) -> Option<Self> {
+        env.kv(ICON_CACHE_BINDING)
+            .map(Self::Kv)
+            .or_else(|_| env.bucket(ICON_BUCKET_BINDING).map(Self::R2))
+            .ok()
+    }
+
+    async fn get(&self, domain: &str) -> worker::Result<Option<CachedIcon>> {
+        match self {
+            Self::Kv(kv) => {
+                let (bytes, metadata) = kv
+                    .get(&format!("{ICON_CACHE_PREFIX}{domain}"))
+                    .bytes_with_metadata::<IconCacheMetadata>()
+                    .await?;
+                Ok(bytes.zip(metadata).map(|(bytes, metadata)| {
+                    metadata
+                        .content_type
+                        .map(|content_type| (content_type, bytes))
+                }))
+            }
+            Self::R2(bucket) => {
+                let Some(object) = bucket
+                    .get(format!("{ICON_BUCKET_PREFIX}{domain}"))
+                    .execute()
+                    .await?
+                else {
+                    return Ok(None);
+                };
+                let metadata = object.custom_metadata()?;
+                let expires = metadata
+                    .get("expires")
+                    .and_then(|expires| expires.parse::<i64>().ok())
+                    .unwrap_or_default();
+                if expires <= chrono::Utc::now().timestamp() {
+                    return Ok(None);
+                }
+                let content_type = metadata
+                    .get("contentType")
+                    .filter(|content_type| !content_type.is_empty());
+                Ok(Some(match (content_type, object.body()) {
+                    (Some(content_type), Some(body)) => {
+                        Some((content_type.clone(), body.bytes().await?))
+                    }
+                    _ => None,
+                }))
+            }
+        }
+    }
+
+    async fn put(&self, domain: &str, icon: Option<&Icon>, ttl: u64) -> worker::Result<()> {
+        let bytes = icon.map(|icon| icon.bytes.clone()).unwrap_or_default();
+        let content_type = icon.map(|icon| icon.content_type);
+        match self {
+            Self::Kv(kv) => {
+                kv.put_bytes(&format!("{ICON_CACHE_PREFIX}{domain}"), &bytes)?
+                    .metadata(IconCacheMetadata {
+                        content_type: content_type.map(str::to_string),
+                    })?
+                    .expiration_ttl(ttl)
+                    .execute()
+                    .await?;
+                Ok(())
+            }
+            Self::R2(bucket) => {
+                let expires = chrono::Utc::now().timestamp() + ttl as i64;
+                let metadata = HashMap::from([
+                    (
+                        "contentType".to_string(),
+                        content_type.unwrap_or_default().to_string(),
+                    ),
+                    ("expires".to_string(), expires.to_string()),
+                ]);
+                bucket
+                    .put(format!("{ICON_BUCKET_PREFIX}{domain}"), bytes)
+                    .custom_metadata(metadata)
+                    .execute()
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+}
+
+/// GET /icons/{domain}/icon.png
+#[worker::send]
+pub async fn get_icon(
+    State(env): State<Arc<Env>>,
+    config: Config,
+    headers: HeaderMap,
+    Path(domain): Path<String>,
+) -> Result<Response, AppError> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    if !is_public_domain(&domain) {
+        return Ok(not_found(config.icon_cache_negttl_secs));
+    }
+
+    let cache = IconCache::from_env(&env);
+    if let Some(cache) = &cache {
+        match cache.get(&domain).await {
+            Ok(Some(Some((content_type, bytes)))) => {
+                return Ok(icon_response(
+                    &content_type,
+                    bytes,
+                    config.icon_cache_ttl_secs,
+                ))
+            }
+            Ok(Some(None)) => return Ok(not_found(config.icon_cache_negttl_secs)),
+            Ok(None) => {}
+            Err(e) => log::warn!("Icon cache read failed: {e}"),
+        }
+    }
+
+    if config.disable_icon_download {
+        return Ok(not_found(config.icon_cache_negttl_secs));
+    }
+
+    // Cached icons are free; only lookups that reach out to other sites count.
+    if let Ok(rate_limiter) = env.rate_limiter(ICON_RATE_LIMITER_BINDING) {
+        let rate_limit_key = format!("icons:{}", request_ip_from_headers(&headers));
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many requests. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+
+    let icon = fetch_icon(&domain).await;
+
+    if let Some(cache) = &cache {
+        let ttl = match &icon {
+            Some(_) => config.icon_cache_ttl_secs,
+            None => config.icon_cache_negttl_secs,
+        };
+        if let Err(e) = cache.put(&domain, icon.as_ref(), ttl).await {
+            log::warn!("Icon cache write failed: {e}");
+        }
+    }
+
+    Ok(match icon {
+        Some(icon) => icon_response(icon.content_type, icon.bytes, config.icon_cache_ttl_secs),
+        None => not_found(config.icon_cache_negttl_secs),
+    })
+}
+
+fn icon_response(content_type: &str, bytes: Vec<u8>, max_age: u64) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CACHE_CONTROL, format!("public, max-age={max_age}")),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+fn not_found(max_age: u64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        [(header::CACHE_CONTROL, format!("public, max-age={max_age}"))],
+    )
+        .into_response()
+}
+
+/// Whether `host` is a domain name that can be fetched: no IP literals, ports, single labels or
+/// private-use suffixes.
+pub(crate) fn is_public_domain(host: &str) -> bool {
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < 2 {
+        return false;
+    }
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    });
+    if !valid_labels {
+        return false;
+    }
+    // An alphabetic top-level label rules out IPv4 literals in every notation.
+    let tld = labels[labels.len() - 1];
+    if !(tld.chars().all(|c| c.is_ascii_lowercase()) || tld.starts_with("xn--")) {
+        return false;
+    }
+    !BLOCKED_SUFFIXES
+        .iter()
+        .any(|suffix| host == *suffix || host.ends_with(&format!(".{suffix}")))
+}
+
+fn is_allowed_url(url: &Url) -> bool {
+    matches!(url.scheme(), "https" | "http")
+        && url.port().is_none()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && url.host_str().is_some_and(is_public_domain)
+}
+
+async fn fetch_icon(domain: &str) -> Option<Icon> {
+    let page_url = Url::parse(&format!("https://{domain}/")).ok()?;
+
+    let mut candidates = Vec::new();
+    if let Some((final_url, html)) = fetch_limited(page_url.clone(), MAX_HTML_BYTES, true).await {
+        let html = String::from_utf8_lossy(&html);
+        candidates = icon_candidates(&html, &final_url);
+    }
+    candidates.truncate(MAX_LINK_CANDIDATES);
+    if let Ok(favicon) = page_url.join("/favicon.ico") {
+        candidates.push(favicon);
+    }
+
+    for candidate in candidates {
+        if let Some((_, bytes)) = fetch_limited(candidate, MAX_ICON_BYTES, false).await {
+            if let Some(content_type) = sniff_image(&bytes) {
+                return Some(Icon {
+                    content_type,
+                    bytes,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// GET `url`, following redirects to allowed URLs only. Returns the final URL and the body,
+/// or `None` on any failure. Bodies larger than `max_bytes` are cut short when `truncate` is
+/// set and rejected otherwise.
+async fn fetch_limited(mut url: Url, max_bytes: usize, truncate: bool) -> Option<(Url, Vec<u8>)> {
+    for _ in 0..=MAX_REDIRECTS {
+        if !is_allowed_url(&url) {
+            return None;
+        }
+
+        let headers = Headers::new();
+        headers.set("User-Agent", USER_AGENT).ok()?;
+        let mut init = RequestInit::new();
+        init.with_method(Method::Get)
+            .with_headers(headers)
+            .with_redirect(RequestRedirect::Manual);
+        let request = Request::new_with_init(url.as_str(), &init).ok()?;
+
+        let fetch = Fetch::Request(request);
+        let mut response = match select(Box::pin(fetch.send()), Delay::from(FETCH_TIMEOUT)).await {
+            Either::Left((Ok(response), _)) => response,
+            Either::Left((Err(e), _)) => {
+                log::debug!("Icon fetch of {url} failed: {e}");
+                return None;
+            }
+            Either::Right(_) => {
+                log::debug!("Icon fetch of {url} timed out");
+                return None;
+            }
+        };
+
+        match response.status_code() {
+            200 => {}
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response.headers().get("Location").ok()??;
+                url = url.join(&location).ok()?;
+                continue;
+            }
+            _ => return None,
+        }
+
+        let declared = response
+            .headers()
+            .get("Content-Length")
+            .ok()
+            .flatten()
+            .and_then(|len| len.parse::<usize>().ok());
+        if !truncate && declared.is_some_and(|len| len > max_bytes) {
+            return None;
+        }
+
+        let mut body = Vec::new();
+        let mut stream = response.stream().ok()?;
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.ok()?);
+            if body.len() > max_bytes {
+                if !truncate {
+                    return None;
+                }
+                body.truncate(max_bytes);
+                break;
+            }
+        }
+        return Some((url, body));
+    }
+    None
+}
+
+/// Icon URLs declared by `<link>` tags in `html`, best first. Relative links are resolved
+/// against `<base href>` or `page_url`.
+pub(crate) fn icon_candidates(html: &str, page_url: &Url) -> Vec<Url> {
+    let mut base = page_url.clone();
+    let mut links: Vec<(u8, String)> = Vec::new();
+
+    for (name, attrs) in tags(html) {
+        match name.as_str() {
+            "base" => {
+                if let Some(href) = attribute(&attrs, "href") {
+                    if let Ok(url) = page_url.join(&href) {
+                        base = url;
+                    }
+                }
+            }
+            "link" => {
+                let Some(href) = attribute(&attrs, "href") else {
+                    continue;
+                };
+                let rel = attribute(&attrs, "rel")
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                let rank = match rel.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
+                    ["icon"] | ["shortcut", "icon"] => 0,
+                    ["apple-touch-icon"] | ["apple-touch-icon-precomposed"] => 1,
+                    rels if rels.contains(&"icon") => 2,
+                    _ => continue,
+                };
+                let is_svg = attribute(&attrs, "type").is_some_and(|t| t.contains("svg"))
+                    || href
+                        .split(['?', '#'])
+                        .next()
+                        .unwrap_or("")
+                        .ends_with(".svg");
+                if is_svg || href.starts_with("data:") {
+                    continue;
+                }
+                links.push((rank, href));
+            }
+            _ => {}
+        }
+    }
+
+    links.sort_by_key(|(rank, _)| *rank);
+    links
+        .into_iter()
+        .filter_map(|(_, href)| base.join(&href).ok())
+        .collect()
+}
+
+/// Opening tags in `html` as lowercase name and raw attribute text, up to `</head>`.
+fn tags(html: &str) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        if name == "/head" || name == "body" {
+            break;
+        }
+        tags.push((name, tag[name_end..].to_string()));
+    }
+    tags
+}
+
+/// Value of attribute `name` in the raw attribute text of a tag.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            return None;
+        }
+        let key_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+
+        let mut value = None;
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (raw, remaining) = match after_eq.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &after_eq[1..];
+                    let close = inner.find(quote).unwrap_or(inner.len());
+                    (&inner[..close], inner.get(close + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let close = after_eq
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(after_eq.len());
+                    (&after_eq[..close], &after_eq[close..])
+                }
+            };
+            value = Some(raw.trim().replace("&amp;", "&"));
+            rest = remaining;
+        }
+
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value.unwrap_or_default());
+        }
+    }
+}
+
+/// Content type of a supported raster image, detected from its first bytes.
+/// SVG is refused: it can carry scripts and would be served from the vault's origin.
+pub(crate) fn sniff_image(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0, 0, 1, 0]) {
+        Some("image/x-icon")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_domains_are_fetched() {
+        assert!(is_public_domain("example.com"));
+        assert!(is_public_domain("login.bank.co.uk"));
+        assert!(is_public_domain("xn--bcher-kva.xn--tckwe"));
+
+        assert!(!is_public_domain("localhost"));
+        assert!(!is_public_domain("nas.local"));
+        assert!(!is_public_domain("router.home.arpa"));
+        assert!(!is_public_domain("metadata.google.internal"));
+        assert!(!is_public_domain("127.0.0.1"));
+        assert!(!is_public_domain("0x7f.1"));
+        assert!(!is_public_domain("intranet"));
+        assert!(!is_public_domain("exa_mple.com"));
+        assert!(!is_public_domain("-bad.com"));
+    }
+
+    #[test]
+    fn links_are_ranked_and_resolved() {
+        let page = Url::parse("https://example.com/login").unwrap();
+        let html = r#"<html><head>
+            <link rel="apple-touch-icon" href="/touch.png">
+            <link rel="stylesheet" href="/site.css">
+            <LINK REL='shortcut icon' HREF='static/fav.ico?v=1&amp;x=2'>
+            <link rel="icon" type="image/svg+xml" href="/icon.svg">
+            </head><body><link rel="icon" href="/late.png"></body></html>"#;
+
+        let candidates: Vec<String> = icon_candidates(html, &page)
+            .iter()
+            .map(Url::to_string)
+            .collect();
+
+        assert_eq!(
+            candidates,
+            vec![
+                "https://example.com/static/fav.ico?v=1&x=2",
+                "https://example.com/touch.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn base_href_changes_resolution() {
+        let page = Url::parse("https://example.com/").unwrap();
+        let html = r#"<base href="https://cdn.example.com/assets/"><link rel=icon href=i.png>"#;
+
+        let candidates = icon_candidates(html, &page);
+
+        assert_eq!(
+            candidates[0].as_str(),
+            "https://cdn.example.com/assets/i.png"
+        );
+    }
+
+    #[test]
+    fn only_raster_images_are_served() {
+        assert_eq!(sniff_image(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff_image(&[0, 0, 1, 0, 1, 0]), Some("image/x-icon"));
+        assert_eq!(
+            sniff_image(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"),
+            None
+        );
+        assert_eq!(sniff_image(b"<!DOCTYPE html>"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use axum::{extract::State, http::HeaderMap, Form, Json};
+use axum::{extract::State, http::HeaderMap, Extension, Form, Json};
 use chrono::{Duration, Utc};
 use constant_time_eq::constant_time_eq;
 use jwt_compact::AlgorithmExt;
@@ -6,29 +6,39 @@ use jwt_compact::{alg::Hs256Key, Claims as JwtClaims, Header, UntrustedToken};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
-use worker::Env;
 
 use crate::d1_query;
 use crate::{
     auth::{jwt_time_options, Claims},
     client_context::{parse_required_device_type, request_ip_from_headers},
+    config::Config,
     crypto::{ct_eq, generate_salt, hash_password_for_storage, validate_totp},
     db,
+    env::Env,
     error::AppError,
     handlers::{
-        allow_totp_drift, server_password_iterations,
-        twofactor::{is_twofactor_enabled, list_user_twofactors},
+        accounts, events,
+        twofactor::{
+            enabled_twofactor_providers, generate_webauthn_login, list_user_twofactors,
+            send_email_login_token, validate_email_login, validate_webauthn_login,
+        },
+        webauth::authenticate_webauthn_login,
     },
+    mail::{self, templates},
     models::{
         auth_request::AuthRequest,
         device::{Device, DeviceType},
-        twofactor::{TwoFactor, TwoFactorType},
+        event::EventType,
+        twofactor::{obscure_email, EmailTokenData, TwoFactor, TwoFactorType},
         user::User,
     },
     push,
+    webauthn::RelyingParty,
+    BaseUrl,
 };
 
 const PASSWORD_SCOPE: &str = "api offline_access";
+const API_KEY_SCOPE: &str = "api";
 const REMEMBER_TOKEN_ISSUER: &str = "warden-worker-device-remember";
 
 /// Deserialize an Option<i32> that may have trailing/leading whitespace.
@@ -63,9 +73,15 @@ pub struct TokenRequest {
     refresh_token: Option<String>,
     #[serde(rename = "client_id", alias = "clientId")]
     client_id: Option<String>,
+    #[serde(rename = "client_secret", alias = "clientSecret")]
+    client_secret: Option<String>, // Personal API key (`client_credentials` grant)
     scope: Option<String>,
     #[serde(rename = "authrequest", alias = "authRequest")]
     auth_request: Option<String>,
+    // WebAuthn (passkey) grant fields
+    token: Option<String>,
+    #[serde(rename = "deviceResponse", alias = "device_response")]
+    device_response: Option<String>,
     // 2FA fields
     #[serde(rename = "twoFactorToken")]
     two_factor_token: Option<String>,
@@ -148,6 +164,8 @@ pub struct UserDecryptionOptions {
     pub has_master_password: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub master_password_unlock: Option<serde_json::Value>,
+    #[serde(rename = "WebAuthnPrfOption", skip_serializing_if = "Option::is_none")]
+    pub web_authn_prf_option: Option<serde_json::Value>,
     pub object: String,
 }
 
@@ -155,11 +173,17 @@ pub struct UserDecryptionOptions {
 #[serde(rename_all = "snake_case")]
 enum RefreshAuthMethod {
     Password,
+    #[serde(rename = "webauthn")]
+    WebAuthn,
+    ApiKey,
 }
 
 impl RefreshAuthMethod {
     fn scope(self) -> &'static str {
-        PASSWORD_SCOPE
+        match self {
+            RefreshAuthMethod::Password | RefreshAuthMethod::WebAuthn => PASSWORD_SCOPE,
+            RefreshAuthMethod::ApiKey => API_KEY_SCOPE,
+        }
     }
 
     fn scope_vec(self) -> Vec<String> {
@@ -199,18 +223,21 @@ fn optional_field(value: Option<&str>) -> Option<String> {
         .map(str::to_owned)
 }
 
-fn validate_password_scope(value: Option<&str>, required: bool) -> Result<(), AppError> {
+fn validate_scope(value: Option<&str>, expected: &str, required: bool) -> Result<(), AppError> {
     let scope = optional_field(value);
     match scope {
-        Some(scope) if scope == PASSWORD_SCOPE => Ok(()),
+        Some(scope) if scope == expected => Ok(()),
         Some(scope) => Err(AppError::BadRequest(format!("Unsupported scope: {scope}"))),
         None if required => Err(AppError::BadRequest("Missing scope".to_string())),
         None => Ok(()),
     }
 }
 
-fn parse_password_device_request(payload: &TokenRequest) -> Result<DeviceAuthRequest, AppError> {
-    validate_password_scope(payload.scope.as_deref(), true)?;
+fn parse_device_request(
+    payload: &TokenRequest,
+    expected_scope: &str,
+) -> Result<DeviceAuthRequest, AppError> {
+    validate_scope(payload.scope.as_deref(), expected_scope, true)?;
 
     Ok(DeviceAuthRequest {
         client_id: required_field(payload.client_id.as_deref(), "client_id")?,
@@ -227,7 +254,7 @@ async fn authenticate_password_grant(
     username: &str,
 ) -> Result<PasswordGrantAuthContext, AppError> {
     let password_hash = required_field(payload.password.as_deref(), "password")?;
-    let device_request = parse_password_device_request(payload)?;
+    let device_request = parse_device_request(payload, PASSWORD_SCOPE)?;
     let user = User::find_by_email(db, &username.to_lowercase())
         .await?
         .ok_or_else(|| AppError::Unauthorized("Invalid credentials".to_string()))?;
@@ -265,6 +292,7 @@ async fn authenticate_password_grant(
 
     let verification = user.verify_master_password(&password_hash).await?;
     if !verification.is_valid() {
+        events::log_user_event(db, EventType::UserFailedLogIn, &user.id, headers).await;
         return Err(AppError::Unauthorized("Invalid credentials".to_string()));
     }
 
@@ -290,12 +318,12 @@ async fn load_user_by_id(db: &crate::db::Db, user_id: &str) -> Result<User, AppE
 
 async fn maybe_upgrade_password_hash(
     db: &crate::db::Db,
-    env: &Env,
+    config: &Config,
     user: User,
     password_hash: &str,
     needs_migration: bool,
 ) -> Result<User, AppError> {
-    let desired_iterations = server_password_iterations(env) as i32;
+    let desired_iterations = config.password_iterations as i32;
     let needs_upgrade = needs_migration || user.password_iterations < desired_iterations;
 
     if !needs_upgrade {
@@ -348,52 +376,46 @@ fn generate_remember_token(env: &Env, user: &User, device: &Device) -> Result<St
         .map_err(|_| AppError::Crypto("Failed to create remember token".to_string()))
 }
 
-fn validate_remember_token(
+/// Whether `raw_token` is a valid remember-device token for this user and device.
+fn is_valid_remember_token(
     env: &Env,
     user: &User,
     device: &Device,
     raw_token: &str,
-    twofactor_ids: &[i32],
-) -> Result<(), AppError> {
+) -> Result<bool, AppError> {
     let secret = env.secret("JWT_REFRESH_SECRET")?.to_string();
     let key = Hs256Key::new(secret.as_bytes());
-    let token = UntrustedToken::new(raw_token)
-        .map_err(|_| AppError::TwoFactorRequired(json_err_twofactor(twofactor_ids)))?;
-    let token = jwt_compact::alg::Hs256
+    let Ok(token) = UntrustedToken::new(raw_token) else {
+        return Ok(false);
+    };
+    let Ok(token) = jwt_compact::alg::Hs256
         .validator::<RememberJwtClaims>(&key)
         .validate(&token)
-        .map_err(|_| AppError::TwoFactorRequired(json_err_twofactor(twofactor_ids)))?;
+    else {
+        return Ok(false);
+    };
     let time_options = jwt_time_options();
-    token
-        .claims()
-        .validate_expiration(&time_options)
-        .map_err(|_| AppError::TwoFactorRequired(json_err_twofactor(twofactor_ids)))?;
-    token
-        .claims()
-        .validate_maturity(&time_options)
-        .map_err(|_| AppError::TwoFactorRequired(json_err_twofactor(twofactor_ids)))?;
+    if token.claims().validate_expiration(&time_options).is_err()
+        || token.claims().validate_maturity(&time_options).is_err()
+    {
+        return Ok(false);
+    }
 
     let remember_claims = token.into_parts().1.custom;
     if remember_claims.iss != REMEMBER_TOKEN_ISSUER
         || remember_claims.sub.as_str() != device.identifier.as_str()
         || remember_claims.user_uuid.as_str() != user.id.as_str()
     {
-        return Err(AppError::TwoFactorRequired(json_err_twofactor(
-            twofactor_ids,
-        )));
+        return Ok(false);
     }
 
-    let stored_token = device
-        .twofactor_remember
-        .as_deref()
-        .ok_or_else(|| AppError::TwoFactorRequired(json_err_twofactor(twofactor_ids)))?;
-    if !constant_time_eq(stored_token.as_bytes(), raw_token.as_bytes()) {
-        return Err(AppError::TwoFactorRequired(json_err_twofactor(
-            twofactor_ids,
-        )));
-    }
-
-    Ok(())
+    let Some(stored_token) = device.twofactor_remember.as_deref() else {
+        return Ok(false);
+    };
+    Ok(constant_time_eq(
+        stored_token.as_bytes(),
+        raw_token.as_bytes(),
+    ))
 }
 
 fn generate_tokens_and_response(
@@ -402,11 +424,12 @@ fn generate_tokens_and_response(
     client_id: &str,
     env: &Arc<Env>,
     two_factor_token: Option<String>,
+    auth_method: RefreshAuthMethod,
+    web_authn_prf_option: Option<Value>,
 ) -> Result<Json<TokenResponse>, AppError> {
     let now = Utc::now();
     let expires_in = Duration::hours(1);
     let time_options = jwt_time_options();
-    let auth_method = RefreshAuthMethod::Password;
 
     let access_claims = JwtClaims::new(Claims {
         sub: user.id.clone(),
@@ -490,6 +513,7 @@ fn generate_tokens_and_response(
         user_decryption_options: UserDecryptionOptions {
             has_master_password,
             master_password_unlock,
+            web_authn_prf_option,
             object: "userDecryptionOptions".to_string(),
         },
         account_keys,
@@ -500,6 +524,8 @@ fn generate_tokens_and_response(
 #[worker::send]
 pub async fn token(
     State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    config: Config,
     headers: HeaderMap,
     Form(payload): Form<TokenRequest>,
 ) -> Result<Json<TokenResponse>, AppError> {
@@ -508,18 +534,7 @@ pub async fn token(
     match payload.grant_type.as_str() {
         "password" => {
             let username = required_field(payload.username.as_deref(), "username")?;
-
-            // Check rate limit using email as key to prevent brute force attacks.
-            if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
-                let rate_limit_key = format!("login:{}", username.to_lowercase());
-                if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
-                    if !outcome.success {
-                        return Err(AppError::TooManyRequests(
-                            "Too many login attempts. Please try again later.".to_string(),
-                        ));
-                    }
-                }
-            }
+            check_login_rate_limit(&env, &username).await?;
 
             let PasswordGrantAuthContext {
                 user,
@@ -527,6 +542,19 @@ pub async fn token(
                 password_hash,
                 needs_migration,
             } = authenticate_password_grant(&db, &headers, &payload, &username).await?;
+            ensure_user_enabled(&user)?;
+
+            if config.signups_verify_enabled(&env) && !user.email_verified {
+                // Resend the link (subject to the throttle) so the user has something to act on.
+                if let Err(e) =
+                    accounts::send_email_verification(&env, &config, &db, &base_url, &user).await
+                {
+                    log::warn!("Failed to send verification email: {e}");
+                }
+                return Err(AppError::BadRequest(
+                    "Please verify your email before trying again.".to_string(),
+                ));
+            }
 
             let mut device = Device::get_or_create(
                 &db,
@@ -536,110 +564,149 @@ pub async fn token(
                 device_request.r#type,
             )
             .await?;
+            // Devices are stored before 2FA runs, so "never touched" means no completed login yet.
+            let is_new_device = device.created_at == device.updated_at;
 
             let twofactors: Vec<TwoFactor> = list_user_twofactors(&db, &user.id).await?;
-            let twofactor_ids = vec![TwoFactorType::Authenticator as i32];
+            let twofactor_ids = enabled_twofactor_providers(&twofactors);
             let mut should_issue_remember = false;
 
-            if is_twofactor_enabled(&twofactors) {
+            if !twofactor_ids.is_empty() {
+                let rp = RelyingParty::from_base_url(&base_url)?;
                 let selected_id = payload.two_factor_provider.unwrap_or(twofactor_ids[0]);
-                let twofactor_code = payload.two_factor_token.as_deref().ok_or_else(|| {
-                    AppError::TwoFactorRequired(json_err_twofactor(&twofactor_ids))
-                })?;
-
-                match TwoFactorType::from_i32(selected_id) {
-                    Some(TwoFactorType::Authenticator) => {
-                        let tf = twofactors
-                            .iter()
-                            .find(|tf| {
-                                tf.enabled && tf.atype == TwoFactorType::Authenticator as i32
-                            })
-                            .ok_or_else(|| {
-                                AppError::BadRequest("TOTP not configured".to_string())
-                            })?;
-
-                        let allow_drift = allow_totp_drift(&env);
-                        let new_last_used =
-                            validate_totp(twofactor_code, &tf.data, tf.last_used, allow_drift)
-                                .await?;
-
-                        d1_query!(
-                            &db,
-                            "UPDATE twofactor SET last_used = ?1 WHERE uuid = ?2",
-                            new_last_used,
-                            &tf.uuid
-                        )
-                        .map_err(|_| AppError::Database)?
-                        .run()
-                        .await
-                        .map_err(|_| AppError::Database)?;
-
-                        should_issue_remember = payload.two_factor_remember == Some(1);
-                    }
-                    Some(TwoFactorType::Remember) => {
-                        validate_remember_token(
-                            env.as_ref(),
-                            &user,
-                            &device,
-                            twofactor_code,
-                            &twofactor_ids,
-                        )?;
-                        should_issue_remember = payload.two_factor_remember == Some(1);
-                    }
-                    Some(TwoFactorType::RecoveryCode) => {
-                        if let Some(ref stored_code) = user.totp_recover {
-                            if !ct_eq(&stored_code.to_uppercase(), &twofactor_code.to_uppercase()) {
-                                return Err(AppError::BadRequest(
-                                    "Recovery code is incorrect".to_string(),
-                                ));
-                            }
+                let Some(twofactor_code) = payload.two_factor_token.as_deref() else {
+                    return Err(
+                        json_err_twofactor(&env, &db, &rp, &twofactors, &twofactor_ids).await?,
+                    );
+                };
+
+                // Wrong codes are recorded; a rejected remember token only asks for 2FA again.
+                let verified: Result<(), AppError> = async {
+                    match TwoFactorType::from_i32(selected_id) {
+                        Some(TwoFactorType::Authenticator) => {
+                            let tf = twofactors
+                                .iter()
+                                .find(|tf| {
+                                    tf.enabled && tf.atype == TwoFactorType::Authenticator as i32
+                                })
+                                .ok_or_else(|| {
+                                    AppError::BadRequest("TOTP not configured".to_string())
+                                })?;
+
+                            let allow_drift = !config.authenticator_disable_time_drift;
+                            let new_last_used =
+                                validate_totp(twofactor_code, &tf.data, tf.last_used, allow_drift)
+                                    .await?;
 
-                            d1_query!(&db, "DELETE FROM twofactor WHERE user_uuid = ?1", &user.id)
-                                .map_err(|_| AppError::Database)?
-                                .run()
-                                .await
-                                .map_err(|_| AppError::Database)?;
                             d1_query!(
                                 &db,
-                                "UPDATE users SET totp_recover = NULL WHERE id = ?1",
-                                &user.id
+                                "UPDATE twofactor SET last_used = ?1 WHERE uuid = ?2",
+                                new_last_used,
+                                &tf.uuid
                             )
                             .map_err(|_| AppError::Database)?
                             .run()
                             .await
                             .map_err(|_| AppError::Database)?;
-                            d1_query!(
-                                &db,
-                                "UPDATE devices SET twofactor_remember = NULL WHERE user_id = ?1",
-                                &user.id
-                            )
-                            .map_err(|_| AppError::Database)?
-                            .run()
-                            .await
-                            .map_err(|_| AppError::Database)?;
-                        } else {
+
+                            should_issue_remember = payload.two_factor_remember == Some(1);
+                        }
+                        Some(TwoFactorType::Email) => {
+                            validate_email_login(&db, &twofactors, twofactor_code).await?;
+                            should_issue_remember = payload.two_factor_remember == Some(1);
+                        }
+                        Some(TwoFactorType::Webauthn) => {
+                            validate_webauthn_login(&db, &rp, &twofactors, twofactor_code).await?;
+                            should_issue_remember = payload.two_factor_remember == Some(1);
+                        }
+                        Some(TwoFactorType::Remember) => {
+                            if !is_valid_remember_token(env.as_ref(), &user, &device, twofactor_code)? {
+                                return Err(json_err_twofactor(
+                                    &env,
+                                    &db,
+                                    &rp,
+                                    &twofactors,
+                                    &twofactor_ids,
+                                )
+                                .await?);
+                            }
+                            should_issue_remember = payload.two_factor_remember == Some(1);
+                        }
+                        Some(TwoFactorType::RecoveryCode) => {
+                            if let Some(ref stored_code) = user.totp_recover {
+                                if !ct_eq(&stored_code.to_uppercase(), &twofactor_code.to_uppercase()) {
+                                    return Err(AppError::BadRequest(
+                                        "Recovery code is incorrect".to_string(),
+                                    ));
+                                }
+
+                                d1_query!(&db, "DELETE FROM twofactor WHERE user_uuid = ?1", &user.id)
+                                    .map_err(|_| AppError::Database)?
+                                    .run()
+                                    .await
+                                    .map_err(|_| AppError::Database)?;
+                                d1_query!(
+                                    &db,
+                                    "UPDATE users SET totp_recover = NULL WHERE id = ?1",
+                                    &user.id
+                                )
+                                .map_err(|_| AppError::Database)?
+                                .run()
+                                .await
+                                .map_err(|_| AppError::Database)?;
+                                d1_query!(
+                                    &db,
+                                    "UPDATE devices SET twofactor_remember = NULL WHERE user_id = ?1",
+                                    &user.id
+                                )
+                                .map_err(|_| AppError::Database)?
+                                .run()
+                                .await
+                                .map_err(|_| AppError::Database)?;
+
+                                mail::send_in_background(
+                                    (*env).clone(),
+                                    templates::twofactor_disabled(&user.email, None),
+                                );
+                                events::log_user_event(
+                                    &db,
+                                    EventType::UserRecovered2fa,
+                                    &user.id,
+                                    &headers,
+                                )
+                                .await;
+                            } else {
+                                return Err(AppError::BadRequest(
+                                    "Recovery code is incorrect".to_string(),
+                                ));
+                            }
+                        }
+                        _ => {
                             return Err(AppError::BadRequest(
-                                "Recovery code is incorrect".to_string(),
+                                "Invalid two factor provider".to_string(),
                             ));
                         }
                     }
-                    _ => {
-                        return Err(AppError::BadRequest(
-                            "Invalid two factor provider".to_string(),
-                        ));
+                    Ok(())
+                }
+                .await;
+                if let Err(e) = verified {
+                    if matches!(e, AppError::BadRequest(_) | AppError::Unauthorized(_)) {
+                        events::log_user_event(
+                            &db,
+                            EventType::UserFailedLogIn2fa,
+                            &user.id,
+                            &headers,
+                        )
+                        .await;
                     }
+                    return Err(e);
                 }
             }
 
             let user = if let Some(password_hash) = password_hash {
-                maybe_upgrade_password_hash(
-                    &db,
-                    env.as_ref(),
-                    user,
-                    &password_hash,
-                    needs_migration,
-                )
-                .await?
+                maybe_upgrade_password_hash(&db, &config, user, &password_hash, needs_migration)
+                    .await?
             } else {
                 user
             };
@@ -654,22 +721,11 @@ pub async fn token(
                 device.touch(&db).await?;
             }
 
-            if device.push_token.is_some() && device.is_push_device() {
-                if let Ok(Some(cfg)) = push::push_config(&env) {
-                    match push::register_push_device(&cfg, &mut device).await {
-                        Ok(push_uuid_created) => {
-                            if push_uuid_created {
-                                if let Err(e) = device.persist_push_uuid(&db).await {
-                                    log::warn!("Push uuid persistence on login failed: {e}");
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::warn!("Push re-registration on login failed: {e}");
-                        }
-                    }
-                }
+            refresh_push_registration(&env, &db, &mut device).await;
+            if is_new_device {
+                send_new_device_email(&env, &headers, &user, &device);
             }
+            events::log_user_event(&db, EventType::UserLoggedIn, &user.id, &headers).await;
 
             generate_tokens_and_response(
                 user,
@@ -677,6 +733,102 @@ pub async fn token(
                 &device_request.client_id,
                 &env,
                 two_factor_remember_token,
+                RefreshAuthMethod::Password,
+                None,
+            )
+        }
+        "webauthn" => {
+            let token = required_field(payload.token.as_deref(), "token")?;
+            let device_response =
+                required_field(payload.device_response.as_deref(), "deviceResponse")?;
+            let device_request = parse_device_request(&payload, PASSWORD_SCOPE)?;
+
+            // A user-verified passkey is already multi-factor, so 2FA is not requested.
+            let credential =
+                authenticate_webauthn_login(&db, &base_url, &token, &device_response).await?;
+            let user = load_user_by_id(&db, &credential.user_id).await?;
+            ensure_user_enabled(&user)?;
+
+            let mut device = Device::get_or_create(
+                &db,
+                device_request.identifier,
+                user.id.clone(),
+                device_request.name,
+                device_request.r#type,
+            )
+            .await?;
+            let is_new_device = device.created_at == device.updated_at;
+            device.touch(&db).await?;
+            refresh_push_registration(&env, &db, &mut device).await;
+            if is_new_device {
+                send_new_device_email(&env, &headers, &user, &device);
+            }
+            events::log_user_event(&db, EventType::UserLoggedIn, &user.id, &headers).await;
+
+            generate_tokens_and_response(
+                user,
+                &device,
+                &device_request.client_id,
+                &env,
+                None,
+                RefreshAuthMethod::WebAuthn,
+                credential.to_prf_decryption_option(),
+            )
+        }
+        "client_credentials" => {
+            let client_id = required_field(payload.client_id.as_deref(), "client_id")?;
+            check_login_rate_limit(&env, &client_id).await?;
+
+            let client_secret = required_field(payload.client_secret.as_deref(), "client_secret")?;
+            let device_request = parse_device_request(&payload, API_KEY_SCOPE)?;
+            let user_id = client_id
+                .strip_prefix("user.")
+                .ok_or_else(|| AppError::BadRequest("Invalid client_id".to_string()))?;
+            let user = User::find_by_id(&db, user_id)
+                .await?
+                .ok_or_else(|| AppError::Unauthorized("Invalid client_id".to_string()))?;
+            // Users that never generated a key cannot log in with one.
+            let api_key = user.api_key.as_deref().unwrap_or_default();
+            if api_key.is_empty() || !ct_eq(api_key, &client_secret) {
+                return Err(AppError::Unauthorized("Invalid client_secret".to_string()));
+            }
+            ensure_user_enabled(&user)?;
+
+            if config.signups_verify_enabled(&env) && !user.email_verified {
+                if let Err(e) =
+                    accounts::send_email_verification(&env, &config, &db, &base_url, &user).await
+                {
+                    log::warn!("Failed to send verification email: {e}");
+                }
+                return Err(AppError::BadRequest(
+                    "Please verify your email before trying again.".to_string(),
+                ));
+            }
+
+            // The API key replaces both the password and two-step login, as in Bitwarden.
+            let mut device = Device::get_or_create(
+                &db,
+                device_request.identifier,
+                user.id.clone(),
+                device_request.name,
+                device_request.r#type,
+            )
+            .await?;
+            let is_new_device = device.created_at == device.updated_at;
+            device.touch(&db).await?;
+            if is_new_device {
+                send_new_device_email(&env, &headers, &user, &device);
+            }
+            events::log_user_event(&db, EventType::UserLoggedIn, &user.id, &headers).await;
+
+            generate_tokens_and_response(
+                user,
+                &device,
+                &client_id,
+                &env,
+                None,
+                RefreshAuthMethod::ApiKey,
+                None,
             )
         }
         "refresh_token" => {
@@ -686,8 +838,6 @@ pub async fn token(
             // https://github.com/bitwarden/clients/blob/2ee158e720a5e7dbe3641caf80b569e97a1dd91b/libs/common/src/services/api.service.ts#L1786-L1797
             let refresh_token = required_field(payload.refresh_token.as_deref(), "refresh_token")
                 .map_err(|_| AppError::BadRequest("invalid_grant".to_string()))?;
-            validate_password_scope(payload.scope.as_deref(), false)
-                .map_err(|_| AppError::BadRequest("invalid_grant".to_string()))?;
 
             let jwt_refresh_secret = env.secret("JWT_REFRESH_SECRET")?.to_string();
             let refresh_key = Hs256Key::new(jwt_refresh_secret.as_bytes());
@@ -708,15 +858,19 @@ pub async fn token(
                 .map_err(|_| AppError::BadRequest("invalid_grant".to_string()))?;
 
             let refresh_claims = token.into_parts().1.custom;
+            validate_scope(payload.scope.as_deref(), refresh_claims.sub.scope(), false)
+                .map_err(|_| AppError::BadRequest("invalid_grant".to_string()))?;
             let mut device = Device::find_by_refresh_token(&db, &refresh_claims.device_token)
                 .await?
                 .ok_or_else(|| AppError::BadRequest("invalid_grant".to_string()))?;
             let user = load_user_by_id(&db, &device.user_id).await?;
 
-            if !constant_time_eq(
-                refresh_claims.sstamp.as_bytes(),
-                user.security_stamp.as_bytes(),
-            ) {
+            if !user.enabled
+                || !constant_time_eq(
+                    refresh_claims.sstamp.as_bytes(),
+                    user.security_stamp.as_bytes(),
+                )
+            {
                 return Err(AppError::BadRequest("invalid_grant".to_string()));
             }
 
@@ -724,14 +878,94 @@ pub async fn token(
 
             let client_id = optional_field(payload.client_id.as_deref())
                 .unwrap_or_else(|| "undefined".to_string());
-            generate_tokens_and_response(user, &device, &client_id, &env, None)
+            generate_tokens_and_response(
+                user,
+                &device,
+                &client_id,
+                &env,
+                None,
+                refresh_claims.sub,
+                None,
+            )
         }
         _ => Err(AppError::BadRequest("Unsupported grant_type".to_string())),
     }
 }
 
-/// Generates the JSON error response for 2FA required
-fn json_err_twofactor(providers: &[i32]) -> Value {
+/// Refuse logins for users disabled through the admin API.
+fn ensure_user_enabled(user: &User) -> Result<(), AppError> {
+    if !user.enabled {
+        return Err(AppError::Unauthorized(
+            "This user has been disabled".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check the login rate limit keyed by username (email or API key `client_id`)
+/// to prevent brute force attacks.
+async fn check_login_rate_limit(env: &Env, username: &str) -> Result<(), AppError> {
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let rate_limit_key = format!("login:{}", username.to_lowercase());
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many login attempts. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Re-register a push-capable device with the relay after login.
+/// Failures are logged only; they must not block the login.
+async fn refresh_push_registration(env: &Env, db: &crate::db::Db, device: &mut Device) {
+    if device.push_token.is_none() || !device.is_push_device() {
+        return;
+    }
+    let Ok(Some(cfg)) = push::push_config(env) else {
+        return;
+    };
+    match push::register_push_device(&cfg, device).await {
+        Ok(push_uuid_created) => {
+            if push_uuid_created {
+                if let Err(e) = device.persist_push_uuid(db).await {
+                    log::warn!("Push uuid persistence on login failed: {e}");
+                }
+            }
+        }
+        Err(e) => {
+            log::warn!("Push re-registration on login failed: {e}");
+        }
+    }
+}
+
+/// Alert the user about the first successful login from a device.
+fn send_new_device_email(env: &Env, headers: &HeaderMap, user: &User, device: &Device) {
+    let date = Utc::now().format("%A, %B %-d, %Y %H:%M UTC").to_string();
+    mail::send_in_background(
+        env.clone(),
+        templates::new_device_logged_in(
+            &user.email,
+            &device.name,
+            DeviceType::from_i32(device.r#type).display_name(),
+            &request_ip_from_headers(headers),
+            &date,
+        ),
+    );
+}
+
+/// Builds the "two factor required" error for the given providers.
+/// Security keys get a fresh login challenge embedded under their provider id, and
+/// an email code is sent right away when email is the only provider.
+async fn json_err_twofactor(
+    env: &Env,
+    db: &crate::db::Db,
+    rp: &RelyingParty,
+    twofactors: &[TwoFactor],
+    providers: &[i32],
+) -> Result<AppError, AppError> {
     let mut result = serde_json::json!({
         "error": "invalid_grant",
         "error_description": "Two factor required.",
@@ -743,8 +977,187 @@ fn json_err_twofactor(providers: &[i32]) -> Value {
     });
 
     for provider in providers {
-        result["TwoFactorProviders2"][provider.to_string()] = Value::Null;
+        let details = match TwoFactorType::from_i32(*provider) {
+            Some(TwoFactorType::Email) => {
+                if providers.len() == 1 {
+                    send_email_login_token(env, db, twofactors).await?;
+                }
+                twofactors
+                    .iter()
+                    .find(|tf| tf.enabled && tf.atype == TwoFactorType::Email as i32)
+                    .map(|tf| EmailTokenData::from_json(&tf.data))
+                    .transpose()?
+                    .map(|data| serde_json::json!({ "Email": obscure_email(&data.email) }))
+                    .unwrap_or(Value::Null)
+            }
+            Some(TwoFactorType::Webauthn) => generate_webauthn_login(db, rp, twofactors)
+                .await?
+                .unwrap_or(Value::Null),
+            _ => Value::Null,
+        };
+        result["TwoFactorProviders2"][provider.to_string()] = details;
+    }
+
+    Ok(AppError::TwoFactorRequired(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{block_on, Env, TestClient, TestResponse};
+
+    /// Register an account and return its `client_id` and personal API key.
+    fn api_key(client: &TestClient) -> (String, String) {
+        let user = client.register();
+        let token = client.login(&user);
+        let key = client.post(
+            "/api/accounts/api-key",
+            Some(&token),
+            json!({ "masterPasswordHash": user.master_password_hash }),
+        );
+        assert_eq!(key.status, StatusCode::OK, "{}", key.body);
+        let profile = client.get("/api/accounts/profile", Some(&token));
+        (
+            format!("user.{}", profile.body["id"].as_str().unwrap()),
+            key.body["apiKey"].as_str().unwrap().to_string(),
+        )
+    }
+
+    fn client_credentials(
+        client: &TestClient,
+        client_id: &str,
+        client_secret: &str,
+        scope: Option<&str>,
+    ) -> TestResponse {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("deviceType", "8"),
+            ("deviceIdentifier", "5b2b6f1e-3d0c-4f7e-9a54-0e6c1d2f3a4b"),
+            ("deviceName", "linux"),
+        ];
+        if let Some(scope) = scope {
+            form.push(("scope", scope));
+        }
+        client.post_form("/identity/connect/token", &form)
+    }
+
+    #[test]
+    fn a_valid_api_key_logs_in_with_the_api_scope() {
+        let client = TestClient::new();
+        let (client_id, secret) = api_key(&client);
+
+        let login = client_credentials(&client, &client_id, &secret, Some(API_KEY_SCOPE));
+        assert_eq!(login.status, StatusCode::OK, "{}", login.body);
+        assert_eq!(login.body["scope"], API_KEY_SCOPE);
+        let access = login.body["access_token"].as_str().unwrap();
+        let profile = client.get("/api/accounts/profile", Some(access));
+        assert_eq!(profile.status, StatusCode::OK, "{}", profile.body);
+        assert_eq!(
+            format!("user.{}", profile.body["id"].as_str().unwrap()),
+            client_id
+        );
+
+        // The refresh token keeps the API key scope and cannot widen it.
+        let refresh_token = login.body["refresh_token"].as_str().unwrap();
+        let refresh = |scope: Option<&str>| {
+            let mut form = vec![
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", client_id.as_str()),
+            ];
+            if let Some(scope) = scope {
+                form.push(("scope", scope));
+            }
+            client.post_form("/identity/connect/token", &form)
+        };
+        assert_eq!(refresh(None).body["scope"], API_KEY_SCOPE);
+        assert_eq!(refresh(Some(API_KEY_SCOPE)).status, StatusCode::OK);
+        assert_eq!(
+            refresh(Some(PASSWORD_SCOPE)).status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn api_key_logins_need_the_api_scope() {
+        let client = TestClient::new();
+        let (client_id, secret) = api_key(&client);
+        for scope in [None, Some(PASSWORD_SCOPE), Some("api.organization")] {
+            let login = client_credentials(&client, &client_id, &secret, scope);
+            assert_eq!(login.status, StatusCode::BAD_REQUEST, "{scope:?}");
+        }
     }
 
-    result
+    #[test]
+    fn api_key_logins_reject_a_wrong_secret_or_client_id() {
+        let client = TestClient::new();
+        let (client_id, secret) = api_key(&client);
+        let (other_id, other_secret) = api_key(&client);
+        let login = |id: &str, secret: &str| {
+            client_credentials(&client, id, secret, Some(API_KEY_SCOPE)).status
+        };
+
+        assert_eq!(login(&client_id, "wrong"), StatusCode::UNAUTHORIZED);
+        assert_eq!(login(&client_id, &other_secret), StatusCode::UNAUTHORIZED);
+        assert_eq!(login(&other_id, &secret), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            login("user.00000000-0000-0000-0000-000000000000", &secret),
+            StatusCode::UNAUTHORIZED
+        );
+        let bare_id = client_id.strip_prefix("user.").unwrap();
+        assert_eq!(login(bare_id, &secret), StatusCode::BAD_REQUEST);
+
+        // Rotating the key retires the old one.
+        let user = client.register();
+        let token = client.login(&user);
+        let password = json!({ "masterPasswordHash": user.master_password_hash });
+        let first = client.post("/api/accounts/api-key", Some(&token), password.clone());
+        let rotated = client.post("/api/accounts/rotate-api-key", Some(&token), password);
+        let id = format!(
+            "user.{}",
+            client.get("/api/accounts/profile", Some(&token)).body["id"]
+                .as_str()
+                .unwrap()
+        );
+        assert_eq!(
+            login(&id, first.body["apiKey"].as_str().unwrap()),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            login(&id, rotated.body["apiKey"].as_str().unwrap()),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn disabled_users_cannot_log_in_with_their_api_key() {
+        let env = Env::new();
+        let client = TestClient::with_env(env.clone());
+        let (client_id, secret) = api_key(&client);
+        let db = db::get_db(&env).unwrap();
+        block_on(async {
+            d1_query!(
+                &db,
+                "UPDATE users SET enabled = 0 WHERE id = ?1",
+                client_id.strip_prefix("user.").unwrap()
+            )
+            .unwrap()
+            .run()
+            .await
+        })
+        .unwrap();
+
+        let login = client_credentials(&client, &client_id, &secret, Some(API_KEY_SCOPE));
+        assert_eq!(login.status, StatusCode::UNAUTHORIZED, "{}", login.body);
+        assert!(
+            login.body.to_string().contains("disabled"),
+            "{}",
+            login.body
+        );
+    }
 }
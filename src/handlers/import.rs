@@ -1,12 +1,21 @@
-use axum::{extract::State, Json};
+use crate::db::Statement;
+use crate::env::Env;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use uuid::Uuid;
-use worker::{D1PreparedStatement, Env};
 
 use crate::d1_query;
 
 use crate::auth::Claims;
+use crate::config::Config;
+use crate::converters::{self, Format};
 use crate::db::{self, touch_user_updated_at};
 use crate::error::AppError;
 use crate::models::cipher::{Cipher, CipherData};
@@ -14,19 +23,35 @@ use crate::models::folder::Folder;
 use crate::models::import::ImportRequest;
 use crate::notifications::{self, UpdateType};
 
-use super::get_batch_size;
-
 /// Import ciphers and folders.
 /// Aligned with vaultwarden's POST /ciphers/import implementation.
 #[worker::send]
 pub async fn import_data(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Json(data): Json<ImportRequest>,
 ) -> Result<Json<()>, AppError> {
+    // Reject malformed passkeys before anything is written. This endpoint imports into the
+    // personal vault only; organization items must go through the organization's own checks.
+    for (index, cipher) in data.ciphers.iter().enumerate() {
+        if cipher.organization_id.is_some() {
+            return Err(AppError::BadRequest(format!(
+                "Cipher {index}: organization items cannot be imported into a personal vault"
+            )));
+        }
+        cipher
+            .type_fields
+            .validate_fido2_credentials()
+            .map_err(|e| AppError::BadRequest(format!("Cipher {index}: {e}")))?;
+        if let Some(date) = cipher.archived_date.as_deref() {
+            archived_at(index, date)?;
+        }
+    }
+
     let db = db::get_db(&env)?;
     let now = db::now_string();
-    let batch_size = get_batch_size(&env);
+    let batch_size = config.import_batch_size;
 
     // Get existing folders for this user
     let existing_folder_rows = d1_query!(
@@ -43,18 +68,16 @@ pub async fn import_data(
         existing_folder_rows.into_iter().map(|row| row.id).collect();
 
     // Process folders and build the folder_id list
-    let mut folder_statements: Vec<D1PreparedStatement> = Vec::new();
+    let mut folder_statements: Vec<Statement> = Vec::new();
     let mut folders: Vec<String> = Vec::with_capacity(data.folders.len());
 
     for import_folder in data.folders {
-        let folder_id = if let Some(ref id) = import_folder.id {
-            if existing_folders.contains(id) {
-                // Folder already exists, use existing ID
-                id.clone()
-            } else {
-                // Folder doesn't exist, create new one with provided ID
+        // Folder ids the user doesn't own (e.g. from another account's export) get a new id.
+        let folder_id = match import_folder.id {
+            Some(id) if existing_folders.contains(&id) => id,
+            _ => {
                 let folder = Folder {
-                    id: id.clone(),
+                    id: Uuid::new_v4().to_string(),
                     user_id: claims.sub.clone(),
                     name: import_folder.name.clone(),
                     created_at: now.clone(),
@@ -73,32 +96,8 @@ pub async fn import_data(
                 .map_err(|_| AppError::Database)?;
 
                 folder_statements.push(stmt);
-                id.clone()
+                folder.id
             }
-        } else {
-            // No ID provided, create new folder with generated UUID
-            let new_id = Uuid::new_v4().to_string();
-            let folder = Folder {
-                id: new_id.clone(),
-                user_id: claims.sub.clone(),
-                name: import_folder.name.clone(),
-                created_at: now.clone(),
-                updated_at: now.clone(),
-            };
-
-            let stmt = d1_query!(
-                &db,
-                "INSERT INTO folders (id, user_id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-                folder.id,
-                folder.user_id,
-                folder.name,
-                folder.created_at,
-                folder.updated_at
-            )
-            .map_err(|_| AppError::Database)?;
-
-            folder_statements.push(stmt);
-            new_id
         };
 
         folders.push(folder_id);
@@ -118,7 +117,7 @@ pub async fn import_data(
     }
 
     // Prepare all cipher insert statements
-    let mut cipher_statements: Vec<D1PreparedStatement> = Vec::with_capacity(data.ciphers.len());
+    let mut cipher_statements: Vec<Statement> = Vec::with_capacity(data.ciphers.len());
 
     for (index, import_cipher) in data.ciphers.into_iter().enumerate() {
         // Determine folder_id from folder_relationships
@@ -126,24 +125,36 @@ pub async fn import_data(
             .get(&index)
             .and_then(|folder_idx| folders.get(*folder_idx).cloned());
 
+        let passkeys = import_cipher.type_fields.fido2_credentials().cloned();
+        let archived_at = import_cipher
+            .archived_date
+            .as_deref()
+            .map(|date| archived_at(index, date))
+            .transpose()?;
+
         let cipher_data = CipherData::new(
             import_cipher.name,
             import_cipher.notes,
             import_cipher.type_fields,
         );
+        // Passkeys cannot be recreated from anything else, so never store a cipher that lost them.
+        if cipher_data.type_fields.fido2_credentials() != passkeys.as_ref() {
+            log::error!("Import normalization changed the passkeys of cipher {index}");
+            return Err(AppError::Internal);
+        }
 
         let data_value = serde_json::to_value(&cipher_data).map_err(|_| AppError::Internal)?;
 
         let cipher = Cipher {
             id: Uuid::new_v4().to_string(),
             user_id: Some(claims.sub.clone()),
-            organization_id: import_cipher.organization_id,
+            organization_id: None,
             r#type: import_cipher.r#type,
             data: data_value,
             favorite: import_cipher.favorite.unwrap_or(false),
             folder_id,
             deleted_at: None,
-            archived_at: None,
+            archived_at,
             created_at: now.clone(),
             updated_at: now.clone(),
             object: "cipher".to_string(),
@@ -158,8 +169,8 @@ pub async fn import_data(
 
         let stmt = d1_query!(
             &db,
-            "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO ciphers (id, user_id, organization_id, type, data, favorite, folder_id, archived_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
              cipher.id,
              cipher.user_id,
              cipher.organization_id,
@@ -167,6 +178,7 @@ pub async fn import_data(
              data,
              cipher.favorite,
              cipher.folder_id,
+             cipher.archived_at,
              cipher.created_at,
              cipher.updated_at,
         ).map_err(|_| AppError::Database)?;
@@ -192,6 +204,44 @@ pub async fn import_data(
     Ok(Json(()))
 }
 
+/// Normalize the `archivedDate` of imported cipher `index` to the stored timestamp format.
+fn archived_at(index: usize, date: &str) -> Result<String, AppError> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| {
+            date.with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+                .to_string()
+        })
+        .map_err(|_| AppError::BadRequest(format!("Cipher {index}: invalid archivedDate")))
+}
+
+/// Convert a plaintext export from another password manager into Bitwarden's unencrypted JSON
+/// export, which the client then imports and encrypts like any other Bitwarden export.
+///
+/// The whole vault passes through the server in the clear, so this is refused unless
+/// `ALLOW_PLAINTEXT_CONVERSION` is enabled. Nothing is stored or logged.
+#[worker::send]
+pub async fn convert_import(
+    _claims: Claims,
+    config: Config,
+    Path(format): Path<String>,
+    body: Bytes,
+) -> Result<Json<Value>, AppError> {
+    if !config.allow_plaintext_conversion {
+        return Err(AppError::BadRequest(
+            "Plaintext conversion is disabled on this server".to_string(),
+        ));
+    }
+    let format: Format = format.parse().map_err(AppError::BadRequest)?;
+    let conversion = converters::convert(format, &body).map_err(AppError::BadRequest)?;
+
+    Ok(Json(json!({
+        "data": conversion.export_json(),
+        "warnings": conversion.warnings,
+        "object": "importConversion",
+    })))
+}
+
 /// Helper struct for querying existing folder IDs
 #[derive(serde::Deserialize)]
 struct FolderIdRow {
@@ -1,11 +1,19 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    Json,
+};
 use chrono::{SecondsFormat, Utc};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use worker::Env;
 
-use crate::{db, error::AppError};
+use crate::{
+    auth::Claims,
+    db,
+    env::Env,
+    error::AppError,
+    hibp::{self, BreachCache},
+};
 
 /// GET /api/now
 ///
@@ -40,15 +48,40 @@ pub async fn version() -> Json<&'static str> {
 
 #[derive(Debug, Deserialize)]
 pub struct HibpBreachQuery {
-    #[allow(dead_code)] // stub endpoint doesn't use it yet
     pub username: String,
 }
 
 /// GET /api/hibp/breach?username=...
 ///
-/// Vaultwarden can proxy HaveIBeenPwned if configured. This minimal server doesn't.
-/// We return an empty array to indicate "no breach data" without surfacing an error in clients.
+/// Proxies HaveIBeenPwned when `HIBP_API_KEY` is set. Without it we return an empty array to
+/// indicate "no breach data" without surfacing an error in clients.
 #[worker::send]
-pub async fn hibp_breach(_query: axum::extract::Query<HibpBreachQuery>) -> Json<Value> {
-    Json(json!([]))
+pub async fn hibp_breach(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Query(query): Query<HibpBreachQuery>,
+) -> Result<Json<Value>, AppError> {
+    let Some(client) = hibp::breach_client(&env) else {
+        return Ok(Json(json!([])));
+    };
+
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let rate_limit_key = format!("hibp:{}", claims.sub);
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many breach report requests. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+
+    let cache = hibp::breach_cache(&env);
+    let breaches = hibp::lookup_breaches(
+        &client,
+        cache.as_ref().map(|cache| cache as &dyn BreachCache),
+        &query.username,
+    )
+    .await?;
+    Ok(Json(breaches))
 }
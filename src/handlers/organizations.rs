@@ -0,0 +1,1447 @@
+//! Organization handlers
+//!
+//! A minimal organization model for sharing ciphers among a small team. Members
+//! are invited by email and must already have an account; they join by accepting
+//! the emailed invitation link. An owner or admin then confirms the
+//! member by wrapping the organization key with the member's public key. Owners,
+//! admins and members with `accessAll` see every collection of the organization;
+//! everyone else only sees the collections assigned to them, optionally read-only
+//! or with hidden passwords.
+
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::d1_query;
+use crate::{
+    auth::Claims,
+    db,
+    env::Env,
+    error::AppError,
+    handlers::accounts::{decode_email_token, sign_email_token},
+    mail::{self, templates},
+    models::{
+        collection::{Collection, CollectionUser},
+        organization::{
+            AcceptMemberData, BulkConfirmData, CollectionAccessData, CollectionData,
+            ConfirmMemberData, InviteData, MemberIdsData, Membership, MembershipStatus,
+            MembershipType, MembershipUpdateData, Organization, OrganizationCreateData,
+            OrganizationUpdateData,
+        },
+        user::{PasswordOrOtpData, User},
+    },
+    notifications::{self, UpdateType},
+    BaseUrl,
+};
+
+const MEMBER_INVITE_ISSUER: &str = "warden-worker|organizationinvite";
+/// How long an emailed invitation link stays valid.
+const MEMBER_INVITE_DAYS: i64 = 5;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MemberInviteClaims {
+    sub: String, // Membership ID
+    org_id: String,
+    email: String,
+    iss: String,
+}
+
+fn list_response(data: Vec<Value>) -> Json<Value> {
+    Json(json!({
+        "data": data,
+        "object": "list",
+        "continuationToken": null
+    }))
+}
+
+fn org_not_found() -> AppError {
+    AppError::NotFound("Organization not found".to_string())
+}
+
+fn parse_membership_type(value: i32) -> Result<MembershipType, AppError> {
+    MembershipType::from_i32(value)
+        .ok_or_else(|| AppError::BadRequest("Invalid membership type".to_string()))
+}
+
+async fn find_org(db: &crate::db::Db, org_id: &str) -> Result<Organization, AppError> {
+    Organization::find_by_id(db, org_id)
+        .await?
+        .ok_or_else(org_not_found)
+}
+
+/// Load the confirmed membership of `user_id` in `org_id`.
+async fn require_member(
+    db: &crate::db::Db,
+    user_id: &str,
+    org_id: &str,
+) -> Result<Membership, AppError> {
+    Membership::find_by_user_and_org(db, user_id, org_id)
+        .await?
+        .filter(Membership::is_confirmed)
+        .ok_or_else(org_not_found)
+}
+
+/// Load the membership of an owner or admin of `org_id`.
+async fn require_admin(
+    db: &crate::db::Db,
+    user_id: &str,
+    org_id: &str,
+) -> Result<Membership, AppError> {
+    let membership = require_member(db, user_id, org_id).await?;
+    if !membership.is_admin() {
+        return Err(AppError::Unauthorized(
+            "You need to be an owner or admin of this organization".to_string(),
+        ));
+    }
+    Ok(membership)
+}
+
+/// Load the membership of an owner of `org_id`.
+async fn require_owner(
+    db: &crate::db::Db,
+    user_id: &str,
+    org_id: &str,
+) -> Result<Membership, AppError> {
+    let membership = require_member(db, user_id, org_id).await?;
+    if !membership.is_owner() {
+        return Err(AppError::Unauthorized(
+            "You need to be an owner of this organization".to_string(),
+        ));
+    }
+    Ok(membership)
+}
+
+async fn find_member(
+    db: &crate::db::Db,
+    member_id: &str,
+    org_id: &str,
+) -> Result<Membership, AppError> {
+    Membership::find_by_id_and_org(db, member_id, org_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("The specified user isn't a member".to_string()))
+}
+
+async fn find_collection(
+    db: &crate::db::Db,
+    collection_id: &str,
+    org_id: &str,
+) -> Result<Collection, AppError> {
+    Collection::find_by_id_and_org(db, collection_id, org_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Collection not found".to_string()))
+}
+
+/// Refuse to demote or remove the last confirmed owner of an organization.
+async fn ensure_not_last_owner(db: &crate::db::Db, member: &Membership) -> Result<(), AppError> {
+    if member.is_owner()
+        && member.is_confirmed()
+        && Membership::count_confirmed_owners(db, &member.org_id).await? <= 1
+    {
+        return Err(AppError::BadRequest(
+            "Can't remove or demote the last owner".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Email `email` a link to the web vault's `accept-organization` page for `member_id`.
+fn send_member_invite(
+    env: &Env,
+    base_url: &str,
+    org: &Organization,
+    member_id: &str,
+    email: &str,
+) -> Result<(), AppError> {
+    let token = sign_email_token(
+        env,
+        MemberInviteClaims {
+            sub: member_id.to_string(),
+            org_id: org.id.clone(),
+            email: email.to_string(),
+            iss: MEMBER_INVITE_ISSUER.to_string(),
+        },
+        Duration::days(MEMBER_INVITE_DAYS),
+    )?;
+    let link = mail::web_vault_link(
+        base_url,
+        "/accept-organization",
+        &[
+            ("organizationId", &org.id),
+            ("organizationUserId", member_id),
+            ("email", email),
+            ("organizationName", &org.name),
+            ("token", &token),
+            ("initOrganization", "false"),
+            ("orgUserHasExistingUser", "true"),
+        ],
+    );
+    mail::send_in_background(
+        env.clone(),
+        templates::organization_invite(email, &org.name, &link),
+    );
+    Ok(())
+}
+
+/// Invitations can only be accepted through the emailed link.
+fn require_mail(env: &Env) -> Result<(), AppError> {
+    if mail::mail_enabled(env) {
+        return Ok(());
+    }
+    Err(AppError::BadRequest(
+        "Organization invitations need outgoing mail, which is not configured on this server."
+            .to_string(),
+    ))
+}
+
+/// Tell a single member to refresh their organization keys and vault.
+async fn notify_member(
+    env: &Env,
+    db: &crate::db::Db,
+    user_id: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    db::touch_user_updated_at(db, user_id, now).await?;
+    notifications::publish_user_update(
+        env.clone(),
+        user_id.to_string(),
+        UpdateType::SyncOrgKeys,
+        now.to_string(),
+        None,
+    );
+    Ok(())
+}
+
+/// Tell every confirmed member of an organization to resync their vault.
+async fn notify_org_members(
+    env: &Env,
+    db: &crate::db::Db,
+    org_id: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    Membership::touch_members_updated_at(db, org_id, now).await?;
+    for user_id in Membership::list_confirmed_user_ids(db, org_id).await? {
+        notifications::publish_user_update(
+            env.clone(),
+            user_id,
+            UpdateType::SyncVault,
+            now.to_string(),
+            None,
+        );
+    }
+    Ok(())
+}
+
+/// The `organizations` array of the profile returned by `/api/sync` and `/api/accounts/profile`.
+pub(crate) async fn profile_organizations(
+    db: &crate::db::Db,
+    user_id: &str,
+) -> Result<Vec<Value>, AppError> {
+    Ok(Membership::list_with_org_by_user(db, user_id)
+        .await?
+        .iter()
+        .map(|membership| membership.to_json_profile())
+        .collect())
+}
+
+/// Group the `users_collections` rows of an organization by user id.
+fn collection_access_by_user(rows: &[CollectionUser]) -> HashMap<&str, Vec<CollectionAccessData>> {
+    let mut by_user: HashMap<&str, Vec<CollectionAccessData>> = HashMap::new();
+    for row in rows {
+        by_user
+            .entry(row.user_id.as_str())
+            .or_default()
+            .push(CollectionAccessData {
+                id: row.collection_id.clone(),
+                read_only: row.read_only != 0,
+                hide_passwords: row.hide_passwords != 0,
+                manage: row.manage != 0,
+            });
+    }
+    by_user
+}
+
+/// Members assigned to a collection, keyed by membership id as the clients expect.
+fn collection_users_json(
+    collection_id: &str,
+    access: &[CollectionUser],
+    membership_ids: &HashMap<&str, &str>,
+) -> Vec<Value> {
+    access
+        .iter()
+        .filter(|row| row.collection_id == collection_id)
+        .filter_map(|row| {
+            let membership_id = membership_ids.get(row.user_id.as_str())?;
+            Some(json!({
+                "id": membership_id,
+                "readOnly": row.read_only != 0,
+                "hidePasswords": row.hide_passwords != 0,
+                "manage": row.manage != 0,
+            }))
+        })
+        .collect()
+}
+
+/// A collection as shown in the admin console, including the viewer's own access
+/// and the members assigned to it.
+fn collection_access_details_json(
+    collection: &Collection,
+    viewer: &Membership,
+    access: &[CollectionUser],
+    membership_ids: &HashMap<&str, &str>,
+) -> Value {
+    let own = access
+        .iter()
+        .find(|row| row.collection_id == collection.id && row.user_id == viewer.user_id);
+    let full_access = viewer.has_full_access();
+
+    json!({
+        "id": collection.id,
+        "organizationId": collection.org_id,
+        "name": collection.name,
+        "externalId": collection.external_id,
+        "assigned": full_access || own.is_some(),
+        "readOnly": !full_access && own.is_some_and(|row| row.read_only != 0),
+        "hidePasswords": !full_access && own.is_some_and(|row| row.hide_passwords != 0),
+        "manage": viewer.is_admin() || own.is_some_and(|row| row.manage != 0),
+        "users": collection_users_json(&collection.id, access, membership_ids),
+        "groups": [],
+        "object": "collectionAccessDetails",
+    })
+}
+
+/// Access details of a single collection, loading the organization-wide lookups it needs.
+async fn load_collection_access_details(
+    db: &crate::db::Db,
+    collection: &Collection,
+    viewer: &Membership,
+) -> Result<Value, AppError> {
+    let members = Membership::list_by_org(db, &collection.org_id).await?;
+    let membership_ids: HashMap<&str, &str> = members
+        .iter()
+        .map(|member| (member.user_id.as_str(), member.id.as_str()))
+        .collect();
+    let access = Collection::list_users_by_org(db, &collection.org_id).await?;
+
+    Ok(collection_access_details_json(
+        collection,
+        viewer,
+        &access,
+        &membership_ids,
+    ))
+}
+
+/// GET /api/plans - the web vault requires a plan list to create an organization
+#[worker::send]
+pub async fn get_plans() -> Result<Json<Value>, AppError> {
+    Ok(list_response(vec![json!({
+        "object": "plan",
+        "type": 0,
+        "product": 0,
+        "name": "Free",
+        "nameLocalizationKey": "planNameFree",
+        "bitwardenProduct": 0,
+        "maxUsers": 0,
+        "descriptionLocalizationKey": "planDescFree",
+    })]))
+}
+
+/// POST /api/organizations
+#[worker::send]
+pub async fn create_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Json(payload): Json<OrganizationCreateData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let (private_key, public_key) = match payload.keys {
+        Some(keys) => (Some(keys.encrypted_private_key), Some(keys.public_key)),
+        None => (None, None),
+    };
+    let org = Organization::new(
+        payload.name,
+        payload.billing_email.to_lowercase(),
+        private_key,
+        public_key,
+    );
+    org.insert(&db).await?;
+
+    let mut owner = Membership::new(
+        claims.sub.clone(),
+        org.id.clone(),
+        MembershipType::Owner,
+        true,
+    );
+    owner.akey = Some(payload.key);
+    owner.set_status(MembershipStatus::Confirmed, &org.created_at);
+    owner.insert(&db).await?;
+
+    Collection::new(org.id.clone(), payload.collection_name, None)
+        .insert(&db)
+        .await?;
+
+    notify_member(&env, &db, &claims.sub, &org.created_at).await?;
+
+    Ok(Json(org.to_json()))
+}
+
+/// GET /api/organizations/{org_id}
+#[worker::send]
+pub async fn get_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+
+    Ok(Json(find_org(&db, &org_id).await?.to_json()))
+}
+
+/// PUT/POST /api/organizations/{org_id}
+#[worker::send]
+pub async fn update_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<OrganizationUpdateData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_owner(&db, &claims.sub, &org_id).await?;
+
+    let mut org = find_org(&db, &org_id).await?;
+    org.name = payload.name;
+    if let Some(billing_email) = payload.billing_email {
+        org.billing_email = billing_email.to_lowercase();
+    }
+    org.updated_at = db::now_string();
+    org.update(&db).await?;
+
+    notify_org_members(&env, &db, &org.id, &org.updated_at).await?;
+
+    Ok(Json(org.to_json()))
+}
+
+/// DELETE /api/organizations/{org_id} and POST /api/organizations/{org_id}/delete
+#[worker::send]
+pub async fn delete_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<PasswordOrOtpData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    require_owner(&db, &claims.sub, &org_id).await?;
+
+    let user = User::find_by_id(&db, &claims.sub)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let provided_hash = payload
+        .master_password_hash
+        .ok_or_else(|| AppError::BadRequest("Missing master password hash".to_string()))?;
+    if !user
+        .verify_master_password(&provided_hash)
+        .await?
+        .is_valid()
+    {
+        return Err(AppError::Unauthorized("Invalid password".to_string()));
+    }
+
+    let org = find_org(&db, &org_id).await?;
+    let member_ids = Membership::list_confirmed_user_ids(&db, &org.id).await?;
+    org.delete(&db).await?;
+
+    let now = db::now_string();
+    for user_id in member_ids {
+        notify_member(&env, &db, &user_id, &now).await?;
+    }
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{org_id}/leave
+#[worker::send]
+pub async fn leave_organization(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = Membership::find_by_user_and_org(&db, &claims.sub, &org_id)
+        .await?
+        .ok_or_else(org_not_found)?;
+    ensure_not_last_owner(&db, &membership).await?;
+
+    membership.delete(&db).await?;
+    notify_member(&env, &db, &claims.sub, &db::now_string()).await?;
+
+    Ok(Json(()))
+}
+
+/// GET /api/organizations/{org_id}/keys
+#[worker::send]
+pub async fn get_organization_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_member(&db, &claims.sub, &org_id).await?;
+    let org = find_org(&db, &org_id).await?;
+
+    Ok(Json(json!({
+        "object": "organizationKeys",
+        "publicKey": org.public_key,
+        "privateKey": org.private_key,
+    })))
+}
+
+/// GET /api/organizations/{org_id}/public-key
+#[worker::send]
+pub async fn get_organization_public_key(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_member(&db, &claims.sub, &org_id).await?;
+    let org = find_org(&db, &org_id).await?;
+
+    Ok(Json(json!({
+        "object": "organizationPublicKey",
+        "publicKey": org.public_key,
+    })))
+}
+
+/// GET /api/organizations/{org_id}/policies and /groups
+/// Policies and groups are not implemented, so both lists are always empty.
+#[worker::send]
+pub async fn list_empty(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_member(&db, &claims.sub, &org_id).await?;
+
+    Ok(list_response(Vec::new()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MembersQuery {
+    #[serde(rename = "includeCollections", default)]
+    pub include_collections: bool,
+}
+
+/// GET /api/organizations/{org_id}/users
+#[worker::send]
+pub async fn list_members(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Query(query): Query<MembersQuery>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+
+    let members = Membership::list_details_by_org(&db, &org_id).await?;
+    let access = if query.include_collections {
+        Collection::list_users_by_org(&db, &org_id).await?
+    } else {
+        Vec::new()
+    };
+    let access_by_user = collection_access_by_user(&access);
+
+    let data = members
+        .iter()
+        .map(|member| {
+            let collections = query.include_collections.then(|| {
+                access_by_user
+                    .get(member.membership.user_id.as_str())
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+            });
+            member.to_json_user_details(collections)
+        })
+        .collect();
+
+    Ok(list_response(data))
+}
+
+/// GET /api/organizations/{org_id}/users/{member_id}
+#[worker::send]
+pub async fn get_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, member_id)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+
+    let member = Membership::list_details_by_org(&db, &org_id)
+        .await?
+        .into_iter()
+        .find(|member| member.membership.id == member_id)
+        .ok_or_else(|| AppError::NotFound("The specified user isn't a member".to_string()))?;
+    let access = Collection::list_users_by_org(&db, &org_id).await?;
+    let access_by_user = collection_access_by_user(&access);
+    let collections = access_by_user
+        .get(member.membership.user_id.as_str())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    Ok(Json(member.to_json_details(collections)))
+}
+
+/// POST /api/organizations/{org_id}/users/invite
+///
+/// Invitees must already have an account and join by accepting the emailed link. Addresses
+/// without an account are skipped, with the same response, so admins cannot probe for accounts.
+#[worker::send]
+pub async fn invite_members(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<InviteData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+    require_mail(&env)?;
+
+    let atype = parse_membership_type(payload.r#type)?;
+    if atype == MembershipType::Owner && !actor.is_owner() {
+        return Err(AppError::Unauthorized(
+            "Only owners can invite owners".to_string(),
+        ));
+    }
+    let org = find_org(&db, &org_id).await?;
+
+    let now = db::now_string();
+    for email in &payload.emails {
+        let email = email.trim().to_lowercase();
+        let Some(user) = User::find_by_email(&db, &email).await? else {
+            log::info!("Not inviting {email} to organization {org_id}: no such account");
+            continue;
+        };
+        if Membership::find_by_user_and_org(&db, &user.id, &org_id)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::BadRequest(format!(
+                "User already in organization: {email}"
+            )));
+        }
+
+        let membership =
+            Membership::new(user.id.clone(), org_id.clone(), atype, payload.access_all);
+        membership.insert(&db).await?;
+
+        if !payload.access_all {
+            if let Some(collections) = payload.collections.as_deref() {
+                db.batch(Collection::replace_user_access_statements(
+                    &db,
+                    &org_id,
+                    &user.id,
+                    collections,
+                )?)
+                .await?;
+            }
+        }
+
+        send_member_invite(&env, &base_url, &org, &membership.id, &email)?;
+        notify_member(&env, &db, &user.id, &now).await?;
+    }
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{org_id}/users/{member_id}/reinvite
+#[worker::send]
+pub async fn reinvite_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Path((org_id, member_id)): Path<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+    require_mail(&env)?;
+
+    let member = find_member(&db, &member_id, &org_id).await?;
+    if !member.has_status(MembershipStatus::Invited) {
+        return Err(AppError::BadRequest(
+            "User already accepted the invitation".to_string(),
+        ));
+    }
+    let user = User::find_by_id(&db, &member.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let org = find_org(&db, &org_id).await?;
+    send_member_invite(&env, &base_url, &org, &member.id, &user.email)?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{org_id}/users/{member_id}/accept
+///
+/// Accepts an invitation with the token from the emailed link.
+#[worker::send]
+pub async fn accept_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, member_id)): Path<(String, String)>,
+    Json(payload): Json<AcceptMemberData>,
+) -> Result<Json<()>, AppError> {
+    let invalid = || AppError::BadRequest("Invalid invitation token".to_string());
+    let invite = decode_email_token::<MemberInviteClaims>(&env, &payload.token)?
+        .filter(|invite| {
+            invite.iss == MEMBER_INVITE_ISSUER
+                && invite.sub == member_id
+                && invite.org_id == org_id
+                && invite.email.eq_ignore_ascii_case(&claims.email)
+        })
+        .ok_or_else(invalid)?;
+
+    let db = db::get_db(&env)?;
+    let mut member = find_member(&db, &invite.sub, &org_id).await?;
+    if member.user_id != claims.sub {
+        return Err(invalid());
+    }
+    if !member.has_status(MembershipStatus::Invited) {
+        return Err(AppError::BadRequest(
+            "User already accepted the invitation".to_string(),
+        ));
+    }
+
+    let now = db::now_string();
+    member.set_status(MembershipStatus::Accepted, &now);
+    member.update(&db).await?;
+    notify_member(&env, &db, &claims.sub, &now).await?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{org_id}/users/public-keys
+#[worker::send]
+pub async fn members_public_keys(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<MemberIdsData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+
+    let ids_json = serde_json::to_string(&payload.ids).map_err(|_| AppError::Internal)?;
+    let rows: Vec<Value> = d1_query!(
+        &db,
+        "SELECT uo.id, uo.user_id, u.public_key
+         FROM users_organizations uo
+         JOIN users u ON u.id = uo.user_id
+         WHERE uo.org_id = ?1 AND uo.id IN (SELECT value FROM json_each(?2))",
+        &org_id,
+        ids_json
+    )
+    .map_err(|_| AppError::Database)?
+    .all()
+    .await
+    .map_err(|_| AppError::Database)?
+    .results()
+    .map_err(|_| AppError::Database)?;
+
+    let data = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "id": row.get("id"),
+                "userId": row.get("user_id"),
+                "key": row.get("public_key"),
+                "object": "organizationUserPublicKeyResponseModel",
+            })
+        })
+        .collect();
+
+    Ok(list_response(data))
+}
+
+/// Confirm an accepted member by storing the organization key wrapped for them.
+async fn confirm_one(
+    env: &Env,
+    db: &crate::db::Db,
+    actor: &Membership,
+    member_id: &str,
+    key: String,
+) -> Result<(), AppError> {
+    let mut member = find_member(db, member_id, &actor.org_id).await?;
+    if !member.has_status(MembershipStatus::Accepted) {
+        return Err(AppError::BadRequest("User in invalid state".to_string()));
+    }
+    if member.is_owner() && !actor.is_owner() {
+        return Err(AppError::Unauthorized(
+            "Only owners can confirm owners".to_string(),
+        ));
+    }
+
+    let now = db::now_string();
+    member.akey = Some(key);
+    member.set_status(MembershipStatus::Confirmed, &now);
+    member.update(db).await?;
+
+    notify_member(env, db, &member.user_id, &now).await
+}
+
+/// POST /api/organizations/{org_id}/users/{member_id}/confirm
+#[worker::send]
+pub async fn confirm_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, member_id)): Path<(String, String)>,
+    Json(payload): Json<ConfirmMemberData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    confirm_one(&env, &db, &actor, &member_id, payload.key).await?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/organizations/{org_id}/users/confirm
+#[worker::send]
+pub async fn bulk_confirm_members(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<BulkConfirmData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    let mut data = Vec::with_capacity(payload.keys.len());
+    for entry in payload.keys {
+        let error = match confirm_one(&env, &db, &actor, &entry.id, entry.key).await {
+            Ok(()) => String::new(),
+            Err(err) => err.to_string(),
+        };
+        data.push(json!({
+            "id": entry.id,
+            "error": error,
+            "object": "OrganizationBulkConfirmResponseModel",
+        }));
+    }
+
+    Ok(list_response(data))
+}
+
+/// PUT/POST /api/organizations/{org_id}/users/{member_id}
+#[worker::send]
+pub async fn update_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, member_id)): Path<(String, String)>,
+    Json(payload): Json<MembershipUpdateData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    let atype = parse_membership_type(payload.r#type)?;
+    let mut member = find_member(&db, &member_id, &org_id).await?;
+    if (member.is_owner() || atype == MembershipType::Owner) && !actor.is_owner() {
+        return Err(AppError::Unauthorized(
+            "Only owners can grant and remove owner rights".to_string(),
+        ));
+    }
+    if atype != MembershipType::Owner {
+        ensure_not_last_owner(&db, &member).await?;
+    }
+
+    let now = db::now_string();
+    member.atype = atype as i32;
+    member.access_all = i32::from(payload.access_all);
+    member.updated_at = now.clone();
+    member.update(&db).await?;
+
+    let collections = if payload.access_all {
+        &[][..]
+    } else {
+        payload.collections.as_deref().unwrap_or_default()
+    };
+    db.batch(Collection::replace_user_access_statements(
+        &db,
+        &org_id,
+        &member.user_id,
+        collections,
+    )?)
+    .await?;
+
+    notify_member(&env, &db, &member.user_id, &now).await?;
+
+    Ok(Json(()))
+}
+
+/// DELETE /api/organizations/{org_id}/users/{member_id} and POST .../delete
+#[worker::send]
+pub async fn delete_member(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, member_id)): Path<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    let member = find_member(&db, &member_id, &org_id).await?;
+    if member.is_owner() && !actor.is_owner() {
+        return Err(AppError::Unauthorized(
+            "Only owners can remove other owners".to_string(),
+        ));
+    }
+    ensure_not_last_owner(&db, &member).await?;
+
+    member.delete(&db).await?;
+    notify_member(&env, &db, &member.user_id, &db::now_string()).await?;
+
+    Ok(Json(()))
+}
+
+/// GET /api/organizations/{org_id}/collections
+#[worker::send]
+pub async fn list_org_collections(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let membership = require_member(&db, &claims.sub, &org_id).await?;
+
+    let data = if membership.has_full_access() {
+        Collection::list_by_org(&db, &org_id)
+            .await?
+            .iter()
+            .map(Collection::to_json)
+            .collect()
+    } else {
+        Collection::list_for_user(&db, &claims.sub)
+            .await?
+            .iter()
+            .filter(|entry| entry.collection.org_id == org_id)
+            .map(|entry| entry.collection.to_json())
+            .collect()
+    };
+
+    Ok(list_response(data))
+}
+
+/// GET /api/organizations/{org_id}/collections/details
+#[worker::send]
+pub async fn list_org_collections_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let viewer = require_member(&db, &claims.sub, &org_id).await?;
+
+    let members = Membership::list_by_org(&db, &org_id).await?;
+    let membership_ids: HashMap<&str, &str> = members
+        .iter()
+        .map(|member| (member.user_id.as_str(), member.id.as_str()))
+        .collect();
+    let access = Collection::list_users_by_org(&db, &org_id).await?;
+
+    let data = Collection::list_by_org(&db, &org_id)
+        .await?
+        .iter()
+        .map(|collection| {
+            collection_access_details_json(collection, &viewer, &access, &membership_ids)
+        })
+        .filter(|details| details["assigned"] == json!(true))
+        .collect();
+
+    Ok(list_response(data))
+}
+
+/// POST /api/organizations/{org_id}/collections
+#[worker::send]
+pub async fn create_collection(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<CollectionData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    let collection = Collection::new(org_id.clone(), payload.name, payload.external_id);
+    collection.insert(&db).await?;
+    db.batch(collection.replace_collection_access_statements(&db, &payload.users)?)
+        .await?;
+
+    notify_org_members(&env, &db, &org_id, &collection.updated_at).await?;
+
+    Ok(Json(
+        load_collection_access_details(&db, &collection, &actor).await?,
+    ))
+}
+
+/// GET /api/organizations/{org_id}/collections/{collection_id}/details
+#[worker::send]
+pub async fn get_collection_details(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, collection_id)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+    let collection = find_collection(&db, &collection_id, &org_id).await?;
+
+    Ok(Json(
+        load_collection_access_details(&db, &collection, &actor).await?,
+    ))
+}
+
+/// GET /api/organizations/{org_id}/collections/{collection_id}/users
+#[worker::send]
+pub async fn get_collection_users(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, collection_id)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+    let collection = find_collection(&db, &collection_id, &org_id).await?;
+
+    let members = Membership::list_by_org(&db, &org_id).await?;
+    let membership_ids: HashMap<&str, &str> = members
+        .iter()
+        .map(|member| (member.user_id.as_str(), member.id.as_str()))
+        .collect();
+    let access = Collection::list_users_by_org(&db, &org_id).await?;
+
+    Ok(Json(json!(collection_users_json(
+        &collection.id,
+        &access,
+        &membership_ids
+    ))))
+}
+
+/// PUT/POST /api/organizations/{org_id}/collections/{collection_id}
+#[worker::send]
+pub async fn update_collection(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, collection_id)): Path<(String, String)>,
+    Json(payload): Json<CollectionData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let actor = require_admin(&db, &claims.sub, &org_id).await?;
+
+    let mut collection = find_collection(&db, &collection_id, &org_id).await?;
+    collection.name = payload.name;
+    collection.external_id = payload.external_id.filter(|id| !id.is_empty());
+    collection.updated_at = db::now_string();
+    collection.update(&db).await?;
+    db.batch(collection.replace_collection_access_statements(&db, &payload.users)?)
+        .await?;
+
+    notify_org_members(&env, &db, &org_id, &collection.updated_at).await?;
+
+    Ok(Json(
+        load_collection_access_details(&db, &collection, &actor).await?,
+    ))
+}
+
+/// DELETE /api/organizations/{org_id}/collections/{collection_id} and POST .../delete
+#[worker::send]
+pub async fn delete_collection(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path((org_id, collection_id)): Path<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    require_admin(&db, &claims.sub, &org_id).await?;
+
+    let collection = find_collection(&db, &collection_id, &org_id).await?;
+    collection.delete(&db).await?;
+
+    notify_org_members(&env, &db, &org_id, &db::now_string()).await?;
+
+    Ok(Json(()))
+}
+
+/// GET /api/collections - collections of every organization the user belongs to
+#[worker::send]
+pub async fn list_user_collections(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let data = Collection::list_for_user(&db, &claims.sub)
+        .await?
+        .iter()
+        .map(|entry| entry.to_json_details())
+        .collect();
+
+    Ok(list_response(data))
+}
+
+/// GET /api/users/{user_id}/public-key - used to wrap keys for another user
+#[worker::send]
+pub async fn get_user_public_key(
+    _claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = User::find_by_id(&db, &user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User doesn't exist".to_string()))?;
+
+    Ok(Json(json!({
+        "userId": user.id,
+        "publicKey": user.public_key,
+        "object": "userKey",
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::testing::{enc_string, Env, TestClient, TestUser, BASE_URL};
+
+    struct Org {
+        id: String,
+        collection_id: String,
+        owner: String,
+    }
+
+    fn create_org(client: &TestClient) -> Org {
+        let owner = client.login(&client.register());
+        let created = client.post(
+            "/api/organizations",
+            Some(&owner),
+            json!({
+                "name": "Team",
+                "billingEmail": "billing@example.com",
+                "collectionName": enc_string(),
+                "key": enc_string(),
+                "keys": { "publicKey": "public-key", "encryptedPrivateKey": enc_string() },
+            }),
+        );
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        let id = created.body["id"].as_str().unwrap().to_string();
+        let collections = client.get(
+            &format!("/api/organizations/{id}/collections"),
+            Some(&owner),
+        );
+        let collection_id = collections.body["data"][0]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        Org {
+            id,
+            collection_id,
+            owner,
+        }
+    }
+
+    /// The query of the `accept-organization` link in the last invitation mailed to `email`.
+    fn invite_link_params(env: &Env, email: &str) -> Vec<(String, String)> {
+        let mail = env.sent_mail();
+        let message = mail
+            .iter()
+            .rev()
+            .find(|message| message.to == email)
+            .expect("an invitation was mailed");
+        let prefix = format!("{BASE_URL}/#/accept-organization?");
+        let start = message.text.find(&prefix).expect("the mail has a link") + prefix.len();
+        let query = message.text[start..].split_whitespace().next().unwrap();
+        form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    fn param<'a>(params: &'a [(String, String)], name: &str) -> &'a str {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("the link has {name}"))
+    }
+
+    fn member_status(client: &TestClient, org: &Org, member_id: &str) -> Value {
+        let members = client.get(
+            &format!("/api/organizations/{}/users", org.id),
+            Some(&org.owner),
+        );
+        members.body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|member| member["id"] == member_id)
+            .map(|member| member["status"].clone())
+            .unwrap_or(Value::Null)
+    }
+
+    /// Invite a new account with `collections` access, accept and confirm it.
+    /// Returns the membership id and the member's access token.
+    fn add_member(
+        client: &TestClient,
+        env: &Env,
+        org: &Org,
+        collections: Value,
+    ) -> (String, String) {
+        let user: TestUser = client.register();
+        let token = client.login(&user);
+        let invited = client.post(
+            &format!("/api/organizations/{}/users/invite", org.id),
+            Some(&org.owner),
+            json!({ "emails": [user.email], "type": 2, "collections": collections }),
+        );
+        assert_eq!(invited.status, StatusCode::OK, "{}", invited.body);
+
+        let params = invite_link_params(env, &user.email);
+        let member_id = param(&params, "organizationUserId").to_string();
+        let accepted = client.post(
+            &format!("/api/organizations/{}/users/{member_id}/accept", org.id),
+            Some(&token),
+            json!({ "token": param(&params, "token") }),
+        );
+        assert_eq!(accepted.status, StatusCode::OK, "{}", accepted.body);
+
+        let confirmed = client.post(
+            &format!("/api/organizations/{}/users/{member_id}/confirm", org.id),
+            Some(&org.owner),
+            json!({ "key": enc_string() }),
+        );
+        assert_eq!(confirmed.status, StatusCode::OK, "{}", confirmed.body);
+        (member_id, token)
+    }
+
+    fn org_cipher(org: &Org) -> Value {
+        json!({
+            "type": 1,
+            "organizationId": org.id,
+            "name": enc_string(),
+            "notes": null,
+            "login": { "username": enc_string(), "password": enc_string() },
+        })
+    }
+
+    fn create_org_cipher(client: &TestClient, org: &Org) -> String {
+        let created = client.post(
+            "/api/ciphers/create",
+            Some(&org.owner),
+            json!({ "cipher": org_cipher(org), "collectionIds": [org.collection_id] }),
+        );
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        created.body["id"].as_str().unwrap().to_string()
+    }
+
+    fn synced_cipher(client: &TestClient, token: &str, id: &str) -> Option<Value> {
+        let sync = client.get("/api/sync", Some(token));
+        sync.body["ciphers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|cipher| cipher["id"] == id)
+            .cloned()
+    }
+
+    #[test]
+    fn invited_members_accept_the_mailed_link_and_are_confirmed() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let org = create_org(&client);
+        let user = client.register();
+        let token = client.login(&user);
+
+        let invited = client.post(
+            &format!("/api/organizations/{}/users/invite", org.id),
+            Some(&org.owner),
+            json!({ "emails": [user.email.to_uppercase()], "type": 2, "accessAll": true }),
+        );
+        assert_eq!(invited.status, StatusCode::OK, "{}", invited.body);
+        let params = invite_link_params(&env, &user.email);
+        assert_eq!(param(&params, "organizationId"), org.id);
+        assert_eq!(param(&params, "organizationName"), "Team");
+        let member_id = param(&params, "organizationUserId").to_string();
+        assert_eq!(member_status(&client, &org, &member_id), 0);
+
+        let accept_path = format!("/api/organizations/{}/users/{member_id}/accept", org.id);
+        let other = client.login(&client.register());
+        let stolen = client.post(
+            &accept_path,
+            Some(&other),
+            json!({ "token": param(&params, "token") }),
+        );
+        assert_eq!(stolen.status, StatusCode::BAD_REQUEST);
+        let forged = client.post(&accept_path, Some(&token), json!({ "token": "forged" }));
+        assert_eq!(forged.status, StatusCode::BAD_REQUEST);
+
+        let accepted = client.post(
+            &accept_path,
+            Some(&token),
+            json!({ "token": param(&params, "token") }),
+        );
+        assert_eq!(accepted.status, StatusCode::OK, "{}", accepted.body);
+        assert_eq!(member_status(&client, &org, &member_id), 1);
+        // Accepted members can't use the organization until they are confirmed.
+        let collections_path = format!("/api/organizations/{}/collections", org.id);
+        assert_eq!(
+            client.get(&collections_path, Some(&token)).status,
+            StatusCode::NOT_FOUND
+        );
+
+        let confirm_path = format!("/api/organizations/{}/users/{member_id}/confirm", org.id);
+        let confirmed = client.post(
+            &confirm_path,
+            Some(&org.owner),
+            json!({ "key": enc_string() }),
+        );
+        assert_eq!(confirmed.status, StatusCode::OK, "{}", confirmed.body);
+        assert_eq!(member_status(&client, &org, &member_id), 2);
+        let collections = client.get(&collections_path, Some(&token));
+        assert_eq!(collections.status, StatusCode::OK, "{}", collections.body);
+        assert_eq!(collections.body["data"][0]["id"], org.collection_id);
+
+        let again = client.post(
+            &confirm_path,
+            Some(&org.owner),
+            json!({ "key": enc_string() }),
+        );
+        assert_eq!(again.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn invitations_need_mail() {
+        let client = TestClient::new();
+        let org = create_org(&client);
+        let user = client.register();
+
+        let invited = client.post(
+            &format!("/api/organizations/{}/users/invite", org.id),
+            Some(&org.owner),
+            json!({ "emails": [user.email], "type": 2, "accessAll": true }),
+        );
+        assert_eq!(invited.status, StatusCode::BAD_REQUEST);
+        assert!(member_status(&client, &org, "").is_null());
+    }
+
+    #[test]
+    fn read_only_members_cannot_edit_or_delete_collection_ciphers() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let org = create_org(&client);
+        let id = create_org_cipher(&client, &org);
+        let (_, member) = add_member(
+            &client,
+            &env,
+            &org,
+            json!([{ "id": org.collection_id, "readOnly": true }]),
+        );
+        let path = format!("/api/ciphers/{id}");
+
+        let fetched = client.get(&path, Some(&member));
+        assert_eq!(fetched.status, StatusCode::OK, "{}", fetched.body);
+        assert_eq!(fetched.body["edit"], false);
+        assert_eq!(synced_cipher(&client, &member, &id).unwrap()["edit"], false);
+
+        assert_eq!(
+            client.put(&path, Some(&member), org_cipher(&org)).status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            client
+                .put(&format!("{path}/delete"), Some(&member), json!({}))
+                .status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            client.delete(&path, Some(&member)).status,
+            StatusCode::NOT_FOUND
+        );
+        // Bulk endpoints skip the ciphers the member can't write to.
+        let bulk = client.put("/api/ciphers/delete", Some(&member), json!({ "ids": [id] }));
+        assert_eq!(bulk.status, StatusCode::OK, "{}", bulk.body);
+
+        let unchanged = client.get(&path, Some(&org.owner));
+        assert_eq!(unchanged.body["name"], fetched.body["name"]);
+        assert!(unchanged.body["deletedDate"].is_null());
+        assert_eq!(unchanged.body["edit"], true);
+        assert_eq!(
+            client.put(&path, Some(&org.owner), org_cipher(&org)).status,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn hidden_passwords_are_not_viewable_by_the_member() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let org = create_org(&client);
+        let id = create_org_cipher(&client, &org);
+        let (_, member) = add_member(
+            &client,
+            &env,
+            &org,
+            json!([{ "id": org.collection_id, "hidePasswords": true }]),
+        );
+
+        let fetched = client.get(&format!("/api/ciphers/{id}"), Some(&member));
+        assert_eq!(fetched.status, StatusCode::OK, "{}", fetched.body);
+        assert_eq!(fetched.body["viewPassword"], false);
+        assert_eq!(fetched.body["edit"], true);
+        assert_eq!(
+            synced_cipher(&client, &member, &id).unwrap()["viewPassword"],
+            false
+        );
+        assert_eq!(
+            synced_cipher(&client, &org.owner, &id).unwrap()["viewPassword"],
+            true
+        );
+    }
+
+    #[test]
+    fn removed_members_lose_access_to_organization_ciphers() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let org = create_org(&client);
+        let id = create_org_cipher(&client, &org);
+        let (member_id, member) =
+            add_member(&client, &env, &org, json!([{ "id": org.collection_id }]));
+        let path = format!("/api/ciphers/{id}");
+        assert_eq!(client.get(&path, Some(&member)).status, StatusCode::OK);
+        assert!(synced_cipher(&client, &member, &id).is_some());
+
+        let removed = client.delete(
+            &format!("/api/organizations/{}/users/{member_id}", org.id),
+            Some(&org.owner),
+        );
+        assert_eq!(removed.status, StatusCode::OK, "{}", removed.body);
+
+        assert_eq!(
+            client.get(&path, Some(&member)).status,
+            StatusCode::NOT_FOUND
+        );
+        assert!(synced_cipher(&client, &member, &id).is_none());
+        assert_eq!(
+            client
+                .get(
+                    &format!("/api/organizations/{}/collections", org.id),
+                    Some(&member)
+                )
+                .status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(client.get(&path, Some(&org.owner)).status, StatusCode::OK);
+    }
+}
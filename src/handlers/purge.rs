@@ -4,34 +4,26 @@
 //! soft-deleted (marked with deleted_at) for longer than the configured
 //! retention period.
 
+use crate::config::Config;
 use crate::db::now_string;
+use crate::env::Env;
 use crate::handlers::attachments::{
     attachments_enabled, delete_storage_objects, list_attachment_keys_for_soft_deleted_before,
 };
 use crate::models::auth_request::AuthRequest;
+use crate::models::event::Event;
 use crate::models::send::SendDB;
 use crate::notifications::{self, UpdateType};
 use chrono::{Duration, Utc};
 
 use std::collections::HashSet;
-use worker::Env;
 
 use crate::d1_query;
-/// Default number of days to keep soft-deleted items before purging
-const DEFAULT_PURGE_DAYS: i64 = 30;
 /// Retain pending attachments for at most this many days before cleanup
 const PENDING_RETENTION_DAYS: i64 = 1;
 /// Retain auth requests for at most this many minutes before cleanup
 const AUTH_REQUEST_RETENTION_MINUTES: i64 = 15;
 
-/// Get the purge threshold days from environment variable or use default
-fn get_purge_days(env: &Env) -> i64 {
-    env.var("TRASH_AUTO_DELETE_DAYS")
-        .ok()
-        .and_then(|v| v.to_string().parse::<i64>().ok())
-        .unwrap_or(DEFAULT_PURGE_DAYS)
-}
-
 /// Purge pending attachments older than the configured retention window.
 pub async fn purge_stale_pending_attachments(env: &Env) -> Result<u32, worker::Error> {
     let db = crate::db::get_db(env).map_err(|e| worker::Error::RustError(e.to_string()))?;
@@ -74,14 +66,17 @@ pub async fn purge_stale_pending_attachments(env: &Env) -> Result<u32, worker::E
 /// Purge soft-deleted ciphers that are older than the configured threshold.
 ///
 /// This function:
-/// 1. Calculates the cutoff timestamp based on TRASH_AUTO_DELETE_DAYS env var (default: 30 days)
+/// 1. Calculates the cutoff timestamp based on the TRASH_AUTO_DELETE_DAYS setting (default: 30 days)
 /// 2. Deletes all ciphers where deleted_at is not null and older than the cutoff
 /// 3. Updates the affected users' updated_at to trigger client sync
 /// 4. If TRASH_AUTO_DELETE_DAYS is set to 0 or negative, skips purging (disabled)
 ///
 /// Returns the number of purged records on success.
 pub async fn purge_deleted_ciphers(env: &Env) -> Result<u32, worker::Error> {
-    let purge_days = get_purge_days(env);
+    let purge_days = Config::load(env)
+        .await
+        .map_err(|e| worker::Error::RustError(e.to_string()))?
+        .trash_auto_delete_days;
 
     // If purge_days is 0 or negative, auto-purge is disabled
     if purge_days <= 0 {
@@ -103,10 +98,14 @@ pub async fn purge_deleted_ciphers(env: &Env) -> Result<u32, worker::Error> {
         cutoff_str
     );
 
-    // First, get the list of affected user IDs before deletion
+    // First, get the list of affected user IDs (owners and organization members) before deletion
     let affected_users_result: Vec<AffectedUser> = d1_query!(
         &db,
-        "SELECT DISTINCT user_id FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1 AND user_id IS NOT NULL",
+        "SELECT user_id FROM ciphers WHERE deleted_at IS NOT NULL AND deleted_at < ?1 AND user_id IS NOT NULL
+         UNION
+         SELECT ca.user_id FROM cipher_access ca
+         JOIN ciphers c ON c.id = ca.cipher_id
+         WHERE c.deleted_at IS NOT NULL AND c.deleted_at < ?1",
         cutoff_str
     )
     .map_err(|e| worker::Error::RustError(e.to_string()))?
@@ -289,6 +288,40 @@ pub async fn purge_stale_pending_sends(env: &Env) -> Result<u32, worker::Error>
     Ok(count)
 }
 
+/// Purge audit events older than `EVENTS_DAYS_RETAIN` days.
+pub async fn purge_old_events(env: &Env) -> Result<u32, worker::Error> {
+    let retain_days = Config::load(env)
+        .await
+        .map_err(|e| worker::Error::RustError(e.to_string()))?
+        .events_days_retain;
+
+    if retain_days <= 0 {
+        log::info!("Event pruning is disabled (EVENTS_DAYS_RETAIN <= 0)");
+        return Ok(0);
+    }
+
+    let db = crate::db::get_db(env).map_err(|e| worker::Error::RustError(e.to_string()))?;
+    let cutoff = (Utc::now() - Duration::days(retain_days))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+
+    let count = Event::delete_older_than(&db, &cutoff)
+        .await
+        .map_err(|e| worker::Error::RustError(e.to_string()))?;
+
+    if count > 0 {
+        log::info!(
+            "Purged {} event(s) older than {} day(s)",
+            count,
+            retain_days
+        );
+    } else {
+        log::info!("No old events to purge");
+    }
+
+    Ok(count)
+}
+
 /// Helper struct for affected user query result
 #[derive(serde::Deserialize)]
 struct AffectedUser {
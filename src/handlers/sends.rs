@@ -10,29 +10,23 @@ use jwt_compact::AlgorithmExt;
 use jwt_compact::{alg::Hs256Key, Claims as JwtClaims, Header};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use worker::Env;
 
 use crate::d1_query;
 
 use crate::{
     auth::{Claims, JWT_VALIDATION_LEEWAY_SECS},
+    config::Config,
     db,
+    env::Env,
     error::AppError,
-    handlers::attachments::{
-        attachments_enabled, delete_storage_objects, is_kv_backend, upload_to_storage,
-    },
-    handlers::get_env_usize,
+    handlers::attachments::{attachments_enabled, delete_storage_objects, upload_to_storage},
     models::attachment::display_size,
     models::send::{validate_send_dates, SendDB, SendRequestData, SendType, SEND_INACCESSIBLE_MSG},
     notifications::{self, UpdateType},
+    storage::{is_kv_backend, KV_MAX_VALUE_BYTES},
     BaseUrl,
 };
 
-const DEFAULT_SEND_TTL_SECS: i64 = 300;
-const DEFAULT_SEND_MAX_BYTES: i64 = 100 * 1024 * 1024; // 100 MiB
-const DEFAULT_SEND_TEXT_MAX_BYTES: usize = 1_887_436; // ~1.8 MiB
-const KV_MAX_VALUE_BYTES: i64 = 25 * 1024 * 1024;
-
 // ── Token claims ────────────────────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,43 +54,17 @@ pub struct SendFileUploadResponse {
     send_response: Value,
 }
 
-// ── Config helpers ──────────────────────────────────────────────────
-
-fn send_ttl_secs(env: &Env) -> i64 {
-    env.var("SEND_TTL_SECS")
-        .ok()
-        .and_then(|v| v.to_string().parse::<i64>().ok())
-        .unwrap_or(DEFAULT_SEND_TTL_SECS)
-}
-
-fn send_max_bytes(env: &Env) -> i64 {
-    env.var("SEND_MAX_BYTES")
-        .ok()
-        .and_then(|v| v.to_string().parse::<i64>().ok())
-        .unwrap_or(DEFAULT_SEND_MAX_BYTES)
-}
-
-fn send_text_max_bytes(env: &Env) -> usize {
-    get_env_usize(env, "SEND_TEXT_MAX_BYTES", DEFAULT_SEND_TEXT_MAX_BYTES)
-}
-
-fn user_send_limit_bytes(env: &Env) -> Option<i64> {
-    env.var("USER_SEND_LIMIT_KB")
-        .ok()
-        .and_then(|v| v.to_string().parse::<i64>().ok())
-        .map(|kb| kb * 1024)
-}
-
 // ── JWT helpers ─────────────────────────────────────────────────────
 
 fn build_upload_token(
     env: &Env,
+    config: &Config,
     user_id: &str,
     device: &str,
     send_id: &str,
     file_id: &str,
 ) -> Result<String, AppError> {
-    let ttl = send_ttl_secs(env);
+    let ttl = config.send_ttl_secs;
     let now = Utc::now().timestamp();
     let exp = now
         .checked_add(ttl)
@@ -122,8 +90,13 @@ fn build_upload_token(
         .map_err(|_| AppError::Crypto("Failed to create send upload token".into()))
 }
 
-pub fn build_download_token(env: &Env, send_id: &str, file_id: &str) -> Result<String, AppError> {
-    let ttl = send_ttl_secs(env);
+pub fn build_download_token(
+    env: &Env,
+    config: &Config,
+    send_id: &str,
+    file_id: &str,
+) -> Result<String, AppError> {
+    let ttl = config.send_ttl_secs;
     let now = Utc::now().timestamp();
     let exp = now
         .checked_add(ttl)
@@ -267,6 +240,7 @@ pub async fn get_send(
 pub async fn create_text_send(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Json(payload): Json<SendRequestData>,
 ) -> Result<Json<Value>, AppError> {
     if payload.send_type != SendType::Text as i32 {
@@ -279,7 +253,7 @@ pub async fn create_text_send(
         validate_send_dates(&payload.deletion_date, payload.expiration_date.as_deref())?;
 
     let data = prepare_send_data(&payload)?;
-    let text_limit = send_text_max_bytes(&env);
+    let text_limit = config.send_text_max_bytes;
     if data.len() > text_limit {
         return Err(AppError::BadRequest(format!(
             "Text send data exceeds limit ({text_limit} bytes). Use file send for larger content."
@@ -312,6 +286,7 @@ pub async fn create_text_send(
 pub async fn create_file_send_v2(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
     Json(payload): Json<SendRequestData>,
 ) -> Result<Json<SendFileUploadResponse>, AppError> {
@@ -336,7 +311,7 @@ pub async fn create_file_send_v2(
         return Err(AppError::BadRequest("Send size can't be negative".into()));
     }
 
-    let max = send_max_bytes(&env);
+    let max = config.send_max_bytes;
     if declared_size > max {
         return Err(AppError::BadRequest("File size exceeds send limit".into()));
     }
@@ -350,7 +325,7 @@ pub async fn create_file_send_v2(
 
     let db = db::get_db(&env)?;
 
-    if let Some(limit) = user_send_limit_bytes(&env) {
+    if let Some(limit) = config.user_send_limit_bytes() {
         let used = SendDB::file_usage_by_user(&db, &claims.sub).await?;
         if used + declared_size > limit {
             return Err(AppError::BadRequest("Send storage limit reached".into()));
@@ -375,7 +350,14 @@ pub async fn create_file_send_v2(
     send.set_password(payload.password.as_deref()).await?;
     send.insert_pending(&db).await?;
 
-    let token = build_upload_token(&env, &claims.sub, &claims.device, &send.id, &file_id)?;
+    let token = build_upload_token(
+        &env,
+        &config,
+        &claims.sub,
+        &claims.device,
+        &send.id,
+        &file_id,
+    )?;
     let url = format!(
         "{base_url}/api/sends/{}/file/{file_id}/azure-upload?token={token}",
         send.id
@@ -397,6 +379,7 @@ pub async fn create_file_send_v2(
 pub async fn create_file_send_legacy(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     mut multipart: Multipart,
 ) -> Result<Json<Value>, AppError> {
     if !attachments_enabled(&env) {
@@ -448,14 +431,14 @@ pub async fn create_file_send_legacy(
     let file_bytes = file_bytes.ok_or_else(|| AppError::BadRequest("Missing file data".into()))?;
     let actual_size = file_bytes.len() as i64;
 
-    let max = send_max_bytes(&env);
+    let max = config.send_max_bytes;
     if actual_size > max {
         return Err(AppError::BadRequest("File size exceeds send limit".into()));
     }
 
     let db = db::get_db(&env)?;
 
-    if let Some(limit) = user_send_limit_bytes(&env) {
+    if let Some(limit) = config.user_send_limit_bytes() {
         let used = SendDB::file_usage_by_user(&db, &claims.sub).await?;
         if used + actual_size > limit {
             return Err(AppError::BadRequest("Send storage limit reached".into()));
@@ -578,6 +561,7 @@ pub async fn upload_file_send_direct(
 pub async fn update_send(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Path(send_id): Path<String>,
     Json(payload): Json<SendRequestData>,
 ) -> Result<Json<Value>, AppError> {
@@ -595,7 +579,7 @@ pub async fn update_send(
 
     if payload.send_type == SendType::Text as i32 {
         let data = prepare_send_data(&payload)?;
-        let text_limit = send_text_max_bytes(&env);
+        let text_limit = config.send_text_max_bytes;
         if data.len() > text_limit {
             return Err(AppError::BadRequest(format!(
                 "Text send data exceeds limit ({text_limit} bytes)"
@@ -750,6 +734,7 @@ pub async fn access_send(
 #[worker::send]
 pub async fn access_file_send(
     State(env): State<Arc<Env>>,
+    config: Config,
     Path((send_id, file_id)): Path<(String, String)>,
     Extension(BaseUrl(base_url)): Extension<BaseUrl>,
     Json(payload): Json<SendAccessRequest>,
@@ -787,7 +772,7 @@ pub async fn access_file_send(
         None,
     );
 
-    let token = build_download_token(&env, &send_id, &file_id)?;
+    let token = build_download_token(&env, &config, &send_id, &file_id)?;
     let url = format!("{base_url}/api/sends/{send_id}/{file_id}?t={token}");
 
     Ok(Json(serde_json::json!({
@@ -885,3 +870,91 @@ pub async fn append_sends_json_array(
     out.push_str(&json);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use chrono::{Duration, Utc};
+    use serde_json::{json, Value};
+
+    use crate::testing::{enc_string, Env, TestClient};
+
+    fn file_send(size: usize) -> Value {
+        json!({
+            "type": 1,
+            "key": enc_string(),
+            "name": enc_string(),
+            "notes": null,
+            "file": { "fileName": enc_string() },
+            "fileLength": size,
+            "deletionDate": (Utc::now() + Duration::days(7)).to_rfc3339(),
+            "disabled": false,
+            "hideEmail": false,
+            "password": null,
+        })
+    }
+
+    #[test]
+    fn file_sends_upload_to_storage_and_are_deleted_with_the_send() {
+        let env = Env::new().with_file_storage();
+        let files = env.file_storage().unwrap();
+        let client = TestClient::with_env(env);
+        let token = client.login(&client.register());
+
+        let created = client.post("/api/sends/file/v2", Some(&token), file_send(4));
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        let send_id = created.body["sendResponse"]["id"].as_str().unwrap();
+        let file_id = created.body["sendResponse"]["file"]["id"].as_str().unwrap();
+        let upload_path = format!("/api/sends/{send_id}/file/{file_id}");
+
+        let wrong_size =
+            client.post_multipart(&upload_path, Some(&token), &[("data", Some("f"), b"abc")]);
+        assert_eq!(wrong_size.status, StatusCode::BAD_REQUEST);
+        assert!(files.keys().is_empty());
+
+        let uploaded =
+            client.post_multipart(&upload_path, Some(&token), &[("data", Some("f"), b"file")]);
+        assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.body);
+        let key = format!("sends/{send_id}/{file_id}");
+        assert_eq!(files.get(&key).unwrap().1, b"file");
+
+        let deleted = client.delete(&format!("/api/sends/{send_id}"), Some(&token));
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+        assert!(files.keys().is_empty());
+    }
+
+    #[test]
+    fn legacy_file_sends_are_stored_in_one_request() {
+        let env = Env::new().with_file_storage();
+        let files = env.file_storage().unwrap();
+        let client = TestClient::with_env(env);
+        let token = client.login(&client.register());
+
+        let model = file_send(0).to_string();
+        let created = client.post_multipart(
+            "/api/sends/file",
+            Some(&token),
+            &[
+                ("model", None, model.as_bytes()),
+                ("data", Some("f"), b"legacy"),
+            ],
+        );
+        assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+        assert_eq!(created.body["file"]["size"], "6");
+        let key = format!(
+            "sends/{}/{}",
+            created.body["id"].as_str().unwrap(),
+            created.body["file"]["id"].as_str().unwrap()
+        );
+        assert_eq!(files.keys(), vec![key]);
+    }
+
+    #[test]
+    fn file_sends_need_file_storage() {
+        let client = TestClient::new();
+        let token = client.login(&client.register());
+
+        let created = client.post("/api/sends/file/v2", Some(&token), file_send(4));
+        assert_eq!(created.status, StatusCode::BAD_REQUEST);
+    }
+}
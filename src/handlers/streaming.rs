@@ -1,23 +1,20 @@
 use jwt_compact::{alg::Hs256Key, AlgorithmExt, UntrustedToken};
 use serde::{Deserialize, Serialize};
 use web_sys::ReadableStream;
-use worker::{Env, Headers, HttpMetadata, Method, Request, Response, Url};
+use worker::{Headers, HttpMetadata, Method, Request, Response, Url};
 
 use crate::{
     auth::jwt_time_options,
     db::{self, touch_user_updated_at},
+    env::Env,
     error::AppError,
-    handlers::attachments::{
-        self, get_storage_backend, jwt_secret, AttachmentClaims, StorageBackend,
-    },
+    handlers::attachments::{self, jwt_secret, AttachmentClaims},
     handlers::sends::{SendDownloadClaims, SendUploadClaims},
     models::send::SendDB,
     notifications::{self, UpdateType},
+    storage::{get_storage_backend, StorageBackend, ATTACHMENTS_BUCKET, ATTACHMENTS_KV},
 };
 
-const ATTACHMENTS_BUCKET: &str = "ATTACHMENTS_BUCKET";
-const ATTACHMENTS_KV: &str = "ATTACHMENTS_KV";
-
 // ── KV metadata ─────────────────────────────────────────────────────
 
 #[derive(Serialize, Deserialize)]
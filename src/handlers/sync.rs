@@ -1,16 +1,16 @@
 use axum::extract::{Query, State};
 use std::sync::Arc;
-use worker::Env;
 
 use crate::{
     auth::Claims,
+    config::Config,
     db,
+    env::Env,
     error::AppError,
-    handlers::{
-        attachments, ciphers, ciphers_default_row_query, domains, sends,
-        sync_response_prealloc_bytes, two_factor_enabled,
-    },
+    handlers::{attachments, ciphers, domains, organizations, sends, two_factor_enabled},
+    mail,
     models::{
+        collection::Collection,
         folder::{Folder, FolderResponse},
         sync::Profile,
         user::User,
@@ -32,6 +32,7 @@ pub struct SyncQuery {
 pub async fn get_sync_data(
     claims: Claims,
     State(env): State<Arc<Env>>,
+    config: Config,
     Query(query): Query<SyncQuery>,
 ) -> Result<RawJson, AppError> {
     let user_id = claims.sub;
@@ -82,15 +83,22 @@ pub async fn get_sync_data(
 
     // Fetch ciphers as raw JSON array string (no parsing in Rust!)
     let include_attachments = attachments::attachments_enabled(env.as_ref());
-    let force_row_query = ciphers_default_row_query(env.as_ref());
+    let force_row_query = config.ciphers_default_row_query;
 
     // Serialize profile and folders (small data, acceptable CPU cost)
-    let mut profile = Profile::from_user(user, two_factor_enabled)?;
+    let mut profile = Profile::from_user(user, two_factor_enabled, mail::mail_enabled(&env))?;
     // Match vaultwarden semantics: `_status` is `Invited` when no master password is set.
-    // We don't implement org invitations, but this helps clients interpret the account state.
+    // This helps clients interpret the account state.
     profile.status = if has_master_password { 0 } else { 1 };
+    profile.organizations = organizations::profile_organizations(&db, &user_id).await?;
+    let collections: Vec<Value> = Collection::list_for_user(&db, &user_id)
+        .await?
+        .iter()
+        .map(|entry| entry.to_json_details())
+        .collect();
     let profile_json = serde_json::to_string(&profile).map_err(|_| AppError::Internal)?;
     let folders_json = serde_json::to_string(&folders).map_err(|_| AppError::Internal)?;
+    let collections_json = serde_json::to_string(&collections).map_err(|_| AppError::Internal)?;
 
     // Build response JSON via string concatenation (ciphers already raw JSON)
     let user_decryption_json = serde_json::to_string(&json!({
@@ -100,7 +108,8 @@ pub async fn get_sync_data(
 
     const DEFAULT_SYNC_RESPONSE_PREALLOC_BYTES: usize = 1024 * 1024;
 
-    let capacity = sync_response_prealloc_bytes(env.as_ref())
+    let capacity = config
+        .sync_response_prealloc_bytes
         .filter(|v| *v > 0)
         .unwrap_or(DEFAULT_SYNC_RESPONSE_PREALLOC_BYTES);
 
@@ -108,7 +117,7 @@ pub async fn get_sync_data(
     // {
     //   "profile": {...},
     //   "folders": [...],
-    //   "collections": [],
+    //   "collections": [...],
     //   "policies": [],
     //   "ciphers": [...],
     //   "domains": {...} | null, // null when excludeDomains=true
@@ -123,12 +132,15 @@ pub async fn get_sync_data(
     response.push_str(&profile_json);
     response.push_str(",\"folders\":");
     response.push_str(&folders_json);
-    response.push_str(",\"collections\":[],\"policies\":[],\"ciphers\":");
+    response.push_str(",\"collections\":");
+    response.push_str(&collections_json);
+    response.push_str(",\"policies\":[],\"ciphers\":");
+    let where_clause = format!("WHERE {}", ciphers::cipher_read_access_sql("?1"));
     ciphers::append_cipher_json_array_raw(
         &mut response,
         &db,
         include_attachments,
-        "WHERE c.user_id = ?1",
+        &where_clause,
         &[user_id.clone().into()],
         "",
         force_row_query,
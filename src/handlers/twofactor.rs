@@ -1,22 +1,48 @@
-use axum::{extract::State, Json};
+use axum::{extract::State, http::HeaderMap, Extension, Json};
+use chrono::Utc;
 use serde_json::Value;
 use std::sync::Arc;
-use worker::Env;
 
 use crate::d1_query;
 use crate::{
     auth::AuthUser,
-    crypto::{base32_decode, ct_eq, generate_recovery_code, generate_totp_secret, validate_totp},
+    config::Config,
+    crypto::{
+        base32_decode, ct_eq, generate_numeric_token, generate_recovery_code, generate_totp_secret,
+        validate_totp,
+    },
     db,
+    env::Env,
     error::AppError,
-    handlers::allow_totp_drift,
+    handlers::events,
+    mail::{self, templates},
+    models::auth_request::AuthRequest,
+    models::event::EventType,
     models::twofactor::{
-        DisableAuthenticatorData, DisableTwoFactorData, EnableAuthenticatorData, TwoFactor,
-        TwoFactorType,
+        DeleteWebauthnData, DisableAuthenticatorData, DisableTwoFactorData, EmailTokenData,
+        EnableAuthenticatorData, EnableEmailData, EnableWebauthnData, SendEmailData,
+        SendEmailLoginData, TwoFactor, TwoFactorType, WebauthnRegistration,
     },
     models::user::{PasswordOrOtpData, User},
+    webauthn::{self, CredentialKind, PublicKeyCredential, RelyingParty, StoredCredential},
+    BaseUrl,
 };
 
+/// Number of security key slots offered by the clients.
+const MAX_WEBAUTHN_KEYS: i32 = 5;
+
+/// Seconds a pending WebAuthn challenge stays valid.
+const WEBAUTHN_CHALLENGE_TTL_SECS: i64 = 300;
+
+/// Number of digits in an emailed 2FA code.
+const EMAIL_TOKEN_DIGITS: u32 = 6;
+
+/// Seconds an emailed 2FA code stays valid.
+const EMAIL_TOKEN_TTL_SECS: i64 = 600;
+
+/// Wrong guesses allowed before an emailed 2FA code is discarded.
+const EMAIL_TOKEN_MAX_ATTEMPTS: u32 = 3;
+
 /// List all 2FA records for a user (excludes atype >= 1000).
 pub(crate) async fn list_user_twofactors(
     db: &crate::db::Db,
@@ -31,14 +57,26 @@ pub(crate) async fn list_user_twofactors(
         .map_err(|_| AppError::Database)
 }
 
-/// Whether the user has 2FA enabled.
-///
-/// For now, we intentionally only treat Authenticator (TOTP) as a real 2FA provider.
+/// 2FA providers that can complete a login on their own.
 /// Remember-device tokens are never considered a 2FA method by themselves.
-pub(crate) fn is_twofactor_enabled(twofactors: &[TwoFactor]) -> bool {
-    twofactors
+const LOGIN_PROVIDERS: [TwoFactorType; 3] = [
+    TwoFactorType::Authenticator,
+    TwoFactorType::Email,
+    TwoFactorType::Webauthn,
+];
+
+/// Enabled login providers of the user, in the order they are offered to clients.
+pub(crate) fn enabled_twofactor_providers(twofactors: &[TwoFactor]) -> Vec<i32> {
+    LOGIN_PROVIDERS
         .iter()
-        .any(|tf| tf.enabled && tf.atype == TwoFactorType::Authenticator as i32)
+        .map(|provider| *provider as i32)
+        .filter(|atype| twofactors.iter().any(|tf| tf.enabled && tf.atype == *atype))
+        .collect()
+}
+
+/// Whether the user has 2FA enabled.
+pub(crate) fn is_twofactor_enabled(twofactors: &[TwoFactor]) -> bool {
+    !enabled_twofactor_providers(twofactors).is_empty()
 }
 
 /// GET /api/two-factor - Get all enabled 2FA providers for current user
@@ -111,6 +149,8 @@ pub async fn get_authenticator(
 pub async fn activate_authenticator(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
+    headers: HeaderMap,
+    config: Config,
     Json(data): Json<EnableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
@@ -159,7 +199,7 @@ pub async fn activate_authenticator(
     let previous_last_used = existing.as_ref().map(|tf| tf.last_used).unwrap_or(0);
 
     // Validate TOTP code and capture time step for replay protection
-    let allow_drift = allow_totp_drift(&env);
+    let allow_drift = !config.authenticator_disable_time_drift;
     let last_used_step = validate_totp(&data.token, &key, previous_last_used, allow_drift).await?;
 
     // Delete existing TOTP and any remember-device tokens bound to it to avoid stale bypass
@@ -197,6 +237,8 @@ pub async fn activate_authenticator(
     // Generate recovery code if not exists
     generate_recovery_code_for_user(&db, &user_id).await?;
 
+    events::log_user_event(&db, EventType::UserUpdated2fa, &user_id, &headers).await;
+
     Ok(Json(serde_json::json!({
         "enabled": true,
         "key": key,
@@ -209,9 +251,11 @@ pub async fn activate_authenticator(
 pub async fn activate_authenticator_put(
     state: State<Arc<Env>>,
     auth_user: AuthUser,
+    headers: HeaderMap,
+    config: Config,
     json: Json<EnableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
-    activate_authenticator(state, auth_user, json).await
+    activate_authenticator(state, auth_user, headers, config, json).await
 }
 
 /// POST /api/two-factor/disable - Disable a 2FA method
@@ -219,6 +263,7 @@ pub async fn activate_authenticator_put(
 pub async fn disable_twofactor(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
+    headers: HeaderMap,
     Json(data): Json<DisableTwoFactorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
@@ -257,9 +302,17 @@ pub async fn disable_twofactor(
     .map_err(|_| AppError::Database)?;
 
     log::info!("User {} disabled 2FA type {}", user_id, type_);
+    events::log_user_event(&db, EventType::UserDisabled2fa, &user_id, &headers).await;
 
     clear_recovery_if_no_twofactor(&db, &user_id).await?;
 
+    if let Some(provider) = TwoFactorType::from_i32(type_).filter(|t| LOGIN_PROVIDERS.contains(t)) {
+        mail::send_in_background(
+            (*env).clone(),
+            templates::twofactor_disabled(&user.email, Some(provider.display_name())),
+        );
+    }
+
     Ok(Json(serde_json::json!({
         "enabled": false,
         "type": type_,
@@ -272,6 +325,7 @@ pub async fn disable_twofactor(
 pub async fn disable_authenticator(
     State(env): State<Arc<Env>>,
     AuthUser(user_id, _): AuthUser,
+    headers: HeaderMap,
     Json(data): Json<DisableAuthenticatorData>,
 ) -> Result<Json<Value>, AppError> {
     let db = db::get_db(&env)?;
@@ -331,9 +385,18 @@ pub async fn disable_authenticator(
         user_id,
         data.r#type
     );
+    events::log_user_event(&db, EventType::UserDisabled2fa, &user_id, &headers).await;
 
     clear_recovery_if_no_twofactor(&db, &user_id).await?;
 
+    mail::send_in_background(
+        (*env).clone(),
+        templates::twofactor_disabled(
+            &user.email,
+            Some(TwoFactorType::Authenticator.display_name()),
+        ),
+    );
+
     Ok(Json(serde_json::json!({
         "enabled": false,
         "type": data.r#type,
@@ -346,9 +409,10 @@ pub async fn disable_authenticator(
 pub async fn disable_twofactor_put(
     state: State<Arc<Env>>,
     auth_user: AuthUser,
+    headers: HeaderMap,
     json: Json<DisableTwoFactorData>,
 ) -> Result<Json<Value>, AppError> {
-    disable_twofactor(state, auth_user, json).await
+    disable_twofactor(state, auth_user, headers, json).await
 }
 
 /// POST /api/two-factor/get-recover - Get recovery code
@@ -378,8 +442,734 @@ pub async fn get_recover(
     })))
 }
 
+/// POST /api/two-factor/get-webauthn - List registered security keys
+#[worker::send]
+pub async fn get_webauthn(
+    State(env): State<Arc<Env>>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(&db, &user_id, &data).await?;
+
+    let registrations = load_webauthn_registrations(&db, &user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "enabled": !registrations.is_empty(),
+        "keys": webauthn_keys_json(&registrations),
+        "object": "twoFactorWebAuthn"
+    })))
+}
+
+/// POST /api/two-factor/get-webauthn-challenge - Start registering a security key
+#[worker::send]
+pub async fn get_webauthn_challenge(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_verified_user(&db, &user_id, &data).await?;
+
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let existing: Vec<StoredCredential> = load_webauthn_registrations(&db, &user_id)
+        .await?
+        .into_iter()
+        .map(|reg| reg.credential)
+        .collect();
+
+    let challenge = webauthn::generate_challenge()?;
+    store_webauthn_challenge(
+        &db,
+        &user_id,
+        TwoFactorType::WebauthnRegisterChallenge,
+        &challenge,
+    )
+    .await?;
+
+    let mut options = webauthn::creation_options(
+        &rp,
+        &challenge,
+        &user.id,
+        &user.email,
+        user.name.as_deref().unwrap_or(&user.email),
+        &existing,
+        CredentialKind::SecurityKey,
+    );
+    options["status"] = "ok".into();
+    options["errorMessage"] = "".into();
+
+    Ok(Json(options))
+}
+
+/// POST /api/two-factor/webauthn - Finish registering a security key
+#[worker::send]
+pub async fn activate_webauthn(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<EnableWebauthnData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(
+        &db,
+        &user_id,
+        &PasswordOrOtpData {
+            master_password_hash: data.master_password_hash,
+            otp: data.otp,
+        },
+    )
+    .await?;
+
+    if !(1..=MAX_WEBAUTHN_KEYS).contains(&data.id) {
+        return Err(AppError::BadRequest("Invalid key id".to_string()));
+    }
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Key name is required".to_string()));
+    }
+
+    let challenge =
+        take_webauthn_challenge(&db, &user_id, TwoFactorType::WebauthnRegisterChallenge)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("Can't recover challenge".to_string()))?;
+
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let credential = webauthn::verify_registration(
+        &rp,
+        &challenge,
+        &data.device_response,
+        CredentialKind::SecurityKey,
+    )?;
+
+    let mut registrations = load_webauthn_registrations(&db, &user_id).await?;
+    registrations.retain(|reg| reg.id != data.id);
+    if registrations
+        .iter()
+        .any(|reg| reg.credential.cred_id == credential.cred_id)
+    {
+        return Err(AppError::BadRequest(
+            "This security key is already registered".to_string(),
+        ));
+    }
+    registrations.push(WebauthnRegistration {
+        id: data.id,
+        name: name.to_string(),
+        migrated: false,
+        credential,
+    });
+    registrations.sort_by_key(|reg| reg.id);
+
+    save_webauthn_registrations(&db, &user_id, &registrations).await?;
+    generate_recovery_code_for_user(&db, &user_id).await?;
+
+    log::info!("User {} registered WebAuthn key {}", user_id, data.id);
+
+    Ok(Json(serde_json::json!({
+        "enabled": true,
+        "keys": webauthn_keys_json(&registrations),
+        "object": "twoFactorU2f"
+    })))
+}
+
+/// PUT /api/two-factor/webauthn - Same as POST
+#[worker::send]
+pub async fn activate_webauthn_put(
+    state: State<Arc<Env>>,
+    base_url: Extension<BaseUrl>,
+    auth_user: AuthUser,
+    json: Json<EnableWebauthnData>,
+) -> Result<Json<Value>, AppError> {
+    activate_webauthn(state, base_url, auth_user, json).await
+}
+
+/// DELETE /api/two-factor/webauthn - Remove a security key
+#[worker::send]
+pub async fn delete_webauthn(
+    State(env): State<Arc<Env>>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<DeleteWebauthnData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_verified_user(
+        &db,
+        &user_id,
+        &PasswordOrOtpData {
+            master_password_hash: data.master_password_hash,
+            otp: data.otp,
+        },
+    )
+    .await?;
+
+    let mut registrations = load_webauthn_registrations(&db, &user_id).await?;
+    let before = registrations.len();
+    registrations.retain(|reg| reg.id != data.id);
+    if registrations.len() == before {
+        return Err(AppError::BadRequest("Webauthn entry not found".to_string()));
+    }
+
+    if registrations.is_empty() {
+        d1_query!(
+            &db,
+            "DELETE FROM twofactor WHERE user_uuid = ?1 AND atype = ?2",
+            &user_id,
+            TwoFactorType::Webauthn as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+        clear_recovery_if_no_twofactor(&db, &user_id).await?;
+        mail::send_in_background(
+            (*env).clone(),
+            templates::twofactor_disabled(
+                &user.email,
+                Some(TwoFactorType::Webauthn.display_name()),
+            ),
+        );
+    } else {
+        save_webauthn_registrations(&db, &user_id, &registrations).await?;
+    }
+
+    log::info!("User {} removed WebAuthn key {}", user_id, data.id);
+
+    Ok(Json(serde_json::json!({
+        "enabled": !registrations.is_empty(),
+        "keys": webauthn_keys_json(&registrations),
+        "object": "twoFactorU2f"
+    })))
+}
+
+/// POST /api/two-factor/get-email - Get the email provider status
+#[worker::send]
+pub async fn get_email(
+    State(env): State<Arc<Env>>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(&db, &user_id, &data).await?;
+
+    let existing = find_twofactor(&db, &user_id, TwoFactorType::Email).await?;
+    let (enabled, email) = match existing {
+        Some(tf) => (tf.enabled, Some(EmailTokenData::from_json(&tf.data)?.email)),
+        None => (false, None),
+    };
+
+    Ok(Json(serde_json::json!({
+        "email": email,
+        "enabled": enabled,
+        "object": "twoFactorEmail"
+    })))
+}
+
+/// POST /api/two-factor/send-email - Send a setup code to the address being configured
+#[worker::send]
+pub async fn send_email(
+    State(env): State<Arc<Env>>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<SendEmailData>,
+) -> Result<Json<()>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(
+        &db,
+        &user_id,
+        &PasswordOrOtpData {
+            master_password_hash: data.master_password_hash,
+            otp: data.otp,
+        },
+    )
+    .await?;
+
+    let email = data.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(AppError::BadRequest("Invalid email address".to_string()));
+    }
+
+    let token = generate_numeric_token(EMAIL_TOKEN_DIGITS)?;
+    let token_data = EmailTokenData::new(email.clone(), token.clone(), Utc::now().timestamp());
+    save_email_twofactor(
+        &db,
+        &user_id,
+        TwoFactorType::EmailVerificationChallenge,
+        &token_data,
+    )
+    .await?;
+
+    mail::send(&env, &templates::twofactor_token(&email, &token)).await?;
+
+    Ok(Json(()))
+}
+
+/// POST /api/two-factor/email - Activate the email provider with the setup code
+#[worker::send]
+pub async fn activate_email(
+    State(env): State<Arc<Env>>,
+    AuthUser(user_id, _): AuthUser,
+    Json(data): Json<EnableEmailData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(
+        &db,
+        &user_id,
+        &PasswordOrOtpData {
+            master_password_hash: data.master_password_hash,
+            otp: data.otp,
+        },
+    )
+    .await?;
+
+    let pending = find_twofactor(&db, &user_id, TwoFactorType::EmailVerificationChallenge)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("Two factor not found".to_string()))?;
+    let mut token_data = EmailTokenData::from_json(&pending.data)?;
+
+    if token_data.email != data.email.trim().to_lowercase() {
+        return Err(AppError::BadRequest(
+            "Email does not match the address the code was sent to".to_string(),
+        ));
+    }
+
+    if let Err(e) = check_email_token(&mut token_data, &data.token) {
+        save_email_twofactor(
+            &db,
+            &user_id,
+            TwoFactorType::EmailVerificationChallenge,
+            &token_data,
+        )
+        .await?;
+        return Err(e);
+    }
+
+    // Replace the pending challenge and any remember-device tokens with the active provider
+    d1_query!(
+        &db,
+        "DELETE FROM twofactor WHERE user_uuid = ?1 AND atype IN (?2, ?3)",
+        &user_id,
+        TwoFactorType::EmailVerificationChallenge as i32,
+        TwoFactorType::Remember as i32
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+    save_email_twofactor(&db, &user_id, TwoFactorType::Email, &token_data).await?;
+    generate_recovery_code_for_user(&db, &user_id).await?;
+
+    log::info!("User {} enabled email 2FA", user_id);
+
+    Ok(Json(serde_json::json!({
+        "email": token_data.email,
+        "enabled": true,
+        "object": "twoFactorEmail"
+    })))
+}
+
+/// PUT /api/two-factor/email - Same as POST
+#[worker::send]
+pub async fn activate_email_put(
+    state: State<Arc<Env>>,
+    auth_user: AuthUser,
+    json: Json<EnableEmailData>,
+) -> Result<Json<Value>, AppError> {
+    activate_email(state, auth_user, json).await
+}
+
+/// POST /api/two-factor/send-email-login - Send a login code during the 2FA step
+///
+/// Unauthenticated: the caller proves the first factor again with the master
+/// password hash, or with the access code of an approved login-with-device request.
+#[worker::send]
+pub async fn send_email_login(
+    State(env): State<Arc<Env>>,
+    Json(data): Json<SendEmailLoginData>,
+) -> Result<Json<()>, AppError> {
+    let email = data.email.trim().to_lowercase();
+
+    if let Ok(rate_limiter) = env.rate_limiter("LOGIN_RATE_LIMITER") {
+        let rate_limit_key = format!("send-email-login:{}", email);
+        if let Ok(outcome) = rate_limiter.limit(rate_limit_key).await {
+            if !outcome.success {
+                return Err(AppError::TooManyRequests(
+                    "Too many requests. Please try again later.".to_string(),
+                ));
+            }
+        }
+    }
+
+    let db = db::get_db(&env)?;
+    let user = User::find_by_email(&db, &email)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Username or password is incorrect".to_string()))?;
+
+    match (
+        data.auth_request_id.as_deref(),
+        data.auth_request_access_code.as_deref(),
+        data.master_password_hash.as_deref(),
+    ) {
+        (Some(auth_request_id), Some(access_code), _) => {
+            let auth_request = AuthRequest::find_by_id_and_user(&db, auth_request_id, &user.id)
+                .await?
+                .ok_or_else(|| {
+                    AppError::BadRequest("Auth request not found. Try again.".to_string())
+                })?;
+            let device_matches = data
+                .device_identifier
+                .as_deref()
+                .is_none_or(|id| id == auth_request.request_device_identifier);
+            if !auth_request.is_approved()
+                || auth_request.is_expired()
+                || !device_matches
+                || !auth_request.check_access_code(access_code)
+            {
+                return Err(AppError::Unauthorized(
+                    "Username or access code is incorrect".to_string(),
+                ));
+            }
+        }
+        (_, _, Some(password_hash)) => {
+            if !user.verify_master_password(password_hash).await?.is_valid() {
+                return Err(AppError::Unauthorized(
+                    "Username or password is incorrect".to_string(),
+                ));
+            }
+        }
+        _ => {
+            return Err(AppError::BadRequest(
+                "No password or access code provided".to_string(),
+            ));
+        }
+    }
+
+    let twofactors = list_user_twofactors(&db, &user.id).await?;
+    send_email_login_token(&env, &db, &twofactors).await?;
+
+    Ok(Json(()))
+}
+
+/// Issue a login challenge for the user's security keys.
+/// Returns the `PublicKeyCredentialRequestOptions` to embed in the 2FA-required response,
+/// or `None` when the user has no keys registered.
+pub(crate) async fn generate_webauthn_login(
+    db: &crate::db::Db,
+    rp: &RelyingParty,
+    twofactors: &[TwoFactor],
+) -> Result<Option<Value>, AppError> {
+    let Some(tf) = twofactors
+        .iter()
+        .find(|tf| tf.enabled && tf.atype == TwoFactorType::Webauthn as i32)
+    else {
+        return Ok(None);
+    };
+    let credentials: Vec<StoredCredential> = parse_webauthn_registrations(&tf.data)?
+        .into_iter()
+        .map(|reg| reg.credential)
+        .collect();
+    if credentials.is_empty() {
+        return Ok(None);
+    }
+
+    let challenge = webauthn::generate_challenge()?;
+    store_webauthn_challenge(
+        db,
+        &tf.user_uuid,
+        TwoFactorType::WebauthnLoginChallenge,
+        &challenge,
+    )
+    .await?;
+
+    Ok(Some(webauthn::request_options(
+        rp,
+        &challenge,
+        &credentials,
+        CredentialKind::SecurityKey,
+    )))
+}
+
+/// Verify the assertion sent as `twoFactorToken` during login.
+/// The pending challenge is consumed whether or not verification succeeds.
+pub(crate) async fn validate_webauthn_login(
+    db: &crate::db::Db,
+    rp: &RelyingParty,
+    twofactors: &[TwoFactor],
+    token: &str,
+) -> Result<(), AppError> {
+    let tf = twofactors
+        .iter()
+        .find(|tf| tf.enabled && tf.atype == TwoFactorType::Webauthn as i32)
+        .ok_or_else(|| AppError::BadRequest("WebAuthn not configured".to_string()))?;
+
+    let challenge =
+        take_webauthn_challenge(db, &tf.user_uuid, TwoFactorType::WebauthnLoginChallenge)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("Can't recover login challenge".to_string()))?;
+
+    let assertion: PublicKeyCredential = serde_json::from_str(token)
+        .map_err(|_| AppError::BadRequest("Invalid WebAuthn response".to_string()))?;
+
+    let mut registrations = parse_webauthn_registrations(&tf.data)?;
+    let registration = registrations
+        .iter_mut()
+        .find(|reg| webauthn::same_base64(&reg.credential.cred_id, assertion.credential_id()))
+        .ok_or_else(|| AppError::BadRequest("Unknown security key".to_string()))?;
+
+    let counter = webauthn::verify_assertion(
+        rp,
+        &challenge,
+        &registration.credential,
+        &assertion,
+        CredentialKind::SecurityKey,
+    )?;
+    registration.credential.counter = counter;
+
+    save_webauthn_registrations(db, &tf.user_uuid, &registrations).await
+}
+
+/// Email a fresh login code to the user's configured address.
+pub(crate) async fn send_email_login_token(
+    env: &Env,
+    db: &crate::db::Db,
+    twofactors: &[TwoFactor],
+) -> Result<(), AppError> {
+    let tf = twofactors
+        .iter()
+        .find(|tf| tf.enabled && tf.atype == TwoFactorType::Email as i32)
+        .ok_or_else(|| AppError::BadRequest("Email 2FA not configured".to_string()))?;
+    let mut token_data = EmailTokenData::from_json(&tf.data)?;
+
+    let token = generate_numeric_token(EMAIL_TOKEN_DIGITS)?;
+    token_data.set_token(token.clone(), Utc::now().timestamp());
+    save_email_twofactor(db, &tf.user_uuid, TwoFactorType::Email, &token_data).await?;
+
+    mail::send(env, &templates::twofactor_token(&token_data.email, &token)).await
+}
+
+/// Verify the emailed code sent as `twoFactorToken` during login.
+pub(crate) async fn validate_email_login(
+    db: &crate::db::Db,
+    twofactors: &[TwoFactor],
+    token: &str,
+) -> Result<(), AppError> {
+    let tf = twofactors
+        .iter()
+        .find(|tf| tf.enabled && tf.atype == TwoFactorType::Email as i32)
+        .ok_or_else(|| AppError::BadRequest("Email 2FA not configured".to_string()))?;
+    let mut token_data = EmailTokenData::from_json(&tf.data)?;
+
+    let result = check_email_token(&mut token_data, token);
+    save_email_twofactor(db, &tf.user_uuid, TwoFactorType::Email, &token_data).await?;
+    result
+}
+
 // Helper functions
 
+/// Load the user and check the master password sent with a protected action.
+pub(crate) async fn load_verified_user(
+    db: &crate::db::Db,
+    user_id: &str,
+    data: &PasswordOrOtpData,
+) -> Result<User, AppError> {
+    let user_value: Value = db
+        .prepare("SELECT * FROM users WHERE id = ?1")
+        .bind(&[user_id.into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .ok_or_else(|| AppError::Unauthorized("User not found".to_string()))?;
+    let user: User = serde_json::from_value(user_value).map_err(|_| AppError::Internal)?;
+
+    validate_password_or_otp(&user, data).await?;
+
+    Ok(user)
+}
+
+fn parse_webauthn_registrations(data: &str) -> Result<Vec<WebauthnRegistration>, AppError> {
+    serde_json::from_str(data).map_err(|_| AppError::Internal)
+}
+
+fn webauthn_keys_json(registrations: &[WebauthnRegistration]) -> Vec<Value> {
+    registrations.iter().map(|reg| reg.to_json()).collect()
+}
+
+async fn load_webauthn_registrations(
+    db: &crate::db::Db,
+    user_id: &str,
+) -> Result<Vec<WebauthnRegistration>, AppError> {
+    let existing: Option<TwoFactor> = db
+        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
+        .bind(&[user_id.into(), (TwoFactorType::Webauthn as i32).into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .map(|value| serde_json::from_value(value).map_err(|_| AppError::Internal))
+        .transpose()?;
+
+    match existing {
+        Some(tf) => parse_webauthn_registrations(&tf.data),
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn save_webauthn_registrations(
+    db: &crate::db::Db,
+    user_id: &str,
+    registrations: &[WebauthnRegistration],
+) -> Result<(), AppError> {
+    let data = serde_json::to_string(registrations).map_err(|_| AppError::Internal)?;
+    let twofactor = TwoFactor::new(user_id.to_string(), TwoFactorType::Webauthn, data);
+
+    d1_query!(
+        db,
+        "INSERT INTO twofactor (uuid, user_uuid, atype, enabled, data, last_used) VALUES (?1, ?2, ?3, 1, ?4, 0)
+         ON CONFLICT(user_uuid, atype) DO UPDATE SET data = excluded.data, enabled = 1",
+        &twofactor.uuid,
+        &twofactor.user_uuid,
+        twofactor.atype,
+        &twofactor.data
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(())
+}
+
+async fn find_twofactor(
+    db: &crate::db::Db,
+    user_id: &str,
+    kind: TwoFactorType,
+) -> Result<Option<TwoFactor>, AppError> {
+    db.prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
+        .bind(&[user_id.into(), (kind as i32).into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .map(|value: Value| serde_json::from_value(value).map_err(|_| AppError::Internal))
+        .transpose()
+}
+
+/// Check `token` against the pending email code, updating the attempt counter.
+/// The code is single use: it is cleared on success, on expiry and after too many misses.
+fn check_email_token(data: &mut EmailTokenData, token: &str) -> Result<(), AppError> {
+    let Some(expected) = data.last_token.clone() else {
+        return Err(AppError::BadRequest(
+            "No code has been sent, request a new one".to_string(),
+        ));
+    };
+
+    if data.is_expired(Utc::now().timestamp(), EMAIL_TOKEN_TTL_SECS) {
+        data.reset_token();
+        return Err(AppError::BadRequest(
+            "Code has expired, request a new one".to_string(),
+        ));
+    }
+
+    if !ct_eq(&expected, token.trim()) {
+        data.attempts += 1;
+        if data.attempts >= EMAIL_TOKEN_MAX_ATTEMPTS {
+            data.reset_token();
+        }
+        return Err(AppError::BadRequest("Code is invalid".to_string()));
+    }
+
+    data.reset_token();
+    Ok(())
+}
+
+/// Upsert the email provider row (or its pending setup challenge) of the user.
+async fn save_email_twofactor(
+    db: &crate::db::Db,
+    user_id: &str,
+    kind: TwoFactorType,
+    data: &EmailTokenData,
+) -> Result<(), AppError> {
+    let twofactor = TwoFactor::new(user_id.to_string(), kind, data.to_json()?);
+
+    d1_query!(
+        db,
+        "INSERT INTO twofactor (uuid, user_uuid, atype, enabled, data, last_used) VALUES (?1, ?2, ?3, 1, ?4, 0)
+         ON CONFLICT(user_uuid, atype) DO UPDATE SET data = excluded.data, enabled = 1",
+        &twofactor.uuid,
+        &twofactor.user_uuid,
+        twofactor.atype,
+        &twofactor.data
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(())
+}
+
+/// Persist a pending ceremony challenge, replacing any previous one of the same kind.
+/// `last_used` records the issue time so stale challenges can be rejected.
+async fn store_webauthn_challenge(
+    db: &crate::db::Db,
+    user_id: &str,
+    kind: TwoFactorType,
+    challenge: &str,
+) -> Result<(), AppError> {
+    let data = serde_json::json!({ "challenge": challenge }).to_string();
+    let mut twofactor = TwoFactor::new(user_id.to_string(), kind, data);
+    twofactor.last_used = Utc::now().timestamp();
+
+    d1_query!(
+        db,
+        "INSERT OR REPLACE INTO twofactor (uuid, user_uuid, atype, enabled, data, last_used) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &twofactor.uuid,
+        &twofactor.user_uuid,
+        twofactor.atype,
+        twofactor.enabled as i32,
+        &twofactor.data,
+        twofactor.last_used
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(())
+}
+
+/// Remove and return the pending challenge of `kind`, if one exists and has not expired.
+async fn take_webauthn_challenge(
+    db: &crate::db::Db,
+    user_id: &str,
+    kind: TwoFactorType,
+) -> Result<Option<String>, AppError> {
+    let pending: Option<TwoFactor> = db
+        .prepare("SELECT * FROM twofactor WHERE user_uuid = ?1 AND atype = ?2")
+        .bind(&[user_id.into(), (kind as i32).into()])?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?
+        .map(|value| serde_json::from_value(value).map_err(|_| AppError::Internal))
+        .transpose()?;
+
+    let Some(pending) = pending else {
+        return Ok(None);
+    };
+
+    d1_query!(db, "DELETE FROM twofactor WHERE uuid = ?1", &pending.uuid)
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+    if Utc::now().timestamp() - pending.last_used > WEBAUTHN_CHALLENGE_TTL_SECS {
+        return Ok(None);
+    }
+
+    let data: Value = serde_json::from_str(&pending.data).map_err(|_| AppError::Internal)?;
+    Ok(data
+        .get("challenge")
+        .and_then(|c| c.as_str())
+        .map(str::to_string))
+}
+
 async fn validate_password_or_otp(user: &User, data: &PasswordOrOtpData) -> Result<(), AppError> {
     if let Some(ref password_hash) = data.master_password_hash {
         let verification = user.verify_master_password(password_hash).await?;
@@ -457,3 +1247,152 @@ async fn clear_recovery_if_no_twofactor(db: &crate::db::Db, user_id: &str) -> Re
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{block_on, Env, TestClient, TestResponse, TestUser};
+
+    /// The code in the last two-step login mail sent to `to`.
+    fn mailed_code(env: &Env, to: &str) -> String {
+        let mail = env.sent_mail();
+        let message = mail
+            .iter()
+            .rev()
+            .find(|message| message.to == to)
+            .expect("a code was mailed");
+        message
+            .text
+            .split_whitespace()
+            .find(|word| word.len() == EMAIL_TOKEN_DIGITS as usize)
+            .filter(|word| word.bytes().all(|b| b.is_ascii_digit()))
+            .expect("the mail has a code")
+            .to_string()
+    }
+
+    /// Register an account and enable email two-step login with the code mailed to it.
+    fn user_with_email_2fa(env: &Env, client: &TestClient) -> TestUser {
+        let user = client.register();
+        let token = client.login(&user);
+        let sent = client.post(
+            "/api/two-factor/send-email",
+            Some(&token),
+            json!({ "email": user.email, "masterPasswordHash": user.master_password_hash }),
+        );
+        assert_eq!(sent.status, StatusCode::OK, "{}", sent.body);
+        let enabled = client.post(
+            "/api/two-factor/email",
+            Some(&token),
+            json!({
+                "email": user.email,
+                "token": mailed_code(env, &user.email),
+                "masterPasswordHash": user.master_password_hash,
+            }),
+        );
+        assert_eq!(enabled.status, StatusCode::OK, "{}", enabled.body);
+        user
+    }
+
+    /// Log in with a password and, when given, an emailed code. Without a code, the server
+    /// asks for one and mails it.
+    fn login(client: &TestClient, user: &TestUser, code: Option<&str>) -> TestResponse {
+        match code {
+            Some(code) => client.token(
+                user,
+                &[("twoFactorProvider", "1"), ("twoFactorToken", code)],
+            ),
+            None => client.token(user, &[]),
+        }
+    }
+
+    /// Move the issue time of the pending login code `secs` into the past.
+    fn backdate_login_code(env: &Env, email: &str, secs: i64) {
+        let db = db::get_db(env).unwrap();
+        block_on(async {
+            d1_query!(
+                &db,
+                "UPDATE twofactor SET data = json_set(data, '$.token_sent', json_extract(data, '$.token_sent') - ?1)
+                 WHERE atype = ?2 AND user_uuid = (SELECT id FROM users WHERE email = ?3)",
+                secs,
+                TwoFactorType::Email as i32,
+                email
+            )
+            .unwrap()
+            .run()
+            .await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn login_codes_expire_after_ten_minutes() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let user = user_with_email_2fa(&env, &client);
+
+        let asked = login(&client, &user, None);
+        assert_eq!(asked.status, StatusCode::BAD_REQUEST);
+        let code = mailed_code(&env, &user.email);
+        backdate_login_code(&env, &user.email, EMAIL_TOKEN_TTL_SECS + 1);
+        let expired = login(&client, &user, Some(&code));
+        assert_eq!(expired.status, StatusCode::BAD_REQUEST);
+        assert!(
+            expired.body.to_string().contains("expired"),
+            "{}",
+            expired.body
+        );
+
+        // An expired code stays unusable; a new one works within the TTL.
+        backdate_login_code(&env, &user.email, -EMAIL_TOKEN_TTL_SECS - 1);
+        assert_eq!(
+            login(&client, &user, Some(&code)).status,
+            StatusCode::BAD_REQUEST
+        );
+        login(&client, &user, None);
+        let code = mailed_code(&env, &user.email);
+        backdate_login_code(&env, &user.email, EMAIL_TOKEN_TTL_SECS - 10);
+        let fresh = login(&client, &user, Some(&code));
+        assert_eq!(fresh.status, StatusCode::OK, "{}", fresh.body);
+    }
+
+    #[test]
+    fn login_codes_are_dropped_after_too_many_wrong_guesses() {
+        let env = Env::with_mail();
+        let client = TestClient::with_env(env.clone());
+        let user = user_with_email_2fa(&env, &client);
+        let wrong = |code: &str| if code == "000000" { "111111" } else { "000000" };
+
+        login(&client, &user, None);
+        let code = mailed_code(&env, &user.email);
+        for _ in 0..EMAIL_TOKEN_MAX_ATTEMPTS {
+            assert_eq!(
+                login(&client, &user, Some(wrong(&code))).status,
+                StatusCode::BAD_REQUEST
+            );
+        }
+        let dropped = login(&client, &user, Some(&code));
+        assert_eq!(dropped.status, StatusCode::BAD_REQUEST);
+        assert!(
+            dropped.body.to_string().contains("No code has been sent"),
+            "{}",
+            dropped.body
+        );
+
+        // Fewer misses leave the code usable, and a new code resets the count.
+        login(&client, &user, None);
+        let code = mailed_code(&env, &user.email);
+        for _ in 1..EMAIL_TOKEN_MAX_ATTEMPTS {
+            login(&client, &user, Some(wrong(&code)));
+        }
+        let accepted = login(&client, &user, Some(&code));
+        assert_eq!(accepted.status, StatusCode::OK, "{}", accepted.body);
+        // Codes are single use.
+        assert_eq!(
+            login(&client, &user, Some(&code)).status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -1,16 +1,419 @@
-use axum::Json;
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
 
-/// GET /webauthn
-///
-/// Returns an empty list of WebAuthn credentials.
-/// This prevents 404 errors and key-rotation issues when passkey login is enabled.
-/// Vaultwarden does not yet support passkey login, so we return an empty list.
+use crate::{
+    auth::Claims,
+    d1_query, db,
+    env::Env,
+    error::AppError,
+    handlers::twofactor::load_verified_user,
+    models::{
+        user::PasswordOrOtpData,
+        webauthn_credential::{
+            WebAuthnCredential, WebAuthnCredentialCreateData, WebAuthnCredentialUpdateData,
+            MAX_WEBAUTHN_LOGIN_CREDENTIALS,
+        },
+    },
+    webauthn::{self, CredentialKind, PublicKeyCredential, RelyingParty, StoredCredential},
+    BaseUrl,
+};
+
+/// How long the client has between fetching options and answering with the token.
+const CEREMONY_TOKEN_MINUTES: i64 = 10;
+
+/// What a ceremony token may be redeemed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CeremonyPurpose {
+    Create,
+    Update,
+    Login,
+}
+
+impl CeremonyPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            CeremonyPurpose::Create => "create",
+            CeremonyPurpose::Update => "update",
+            CeremonyPurpose::Login => "login",
+        }
+    }
+}
+
+/// A pending challenge in `webauthn_challenges`, which the client names by its random id.
+#[derive(Debug, Deserialize)]
+struct PendingChallenge {
+    purpose: String,
+    challenge: String,
+    user_id: Option<String>,
+    expires_at: i64,
+}
+
+/// Store `challenge` server-side and return the opaque token the client answers with.
+/// Expired challenges are cleared out on the way.
+async fn issue_ceremony_token(
+    db: &crate::db::Db,
+    purpose: CeremonyPurpose,
+    challenge: &str,
+    user_id: Option<&str>,
+) -> Result<String, AppError> {
+    let token = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let expires_at = (now + Duration::minutes(CEREMONY_TOKEN_MINUTES)).timestamp();
+
+    db.batch(vec![
+        d1_query!(
+            db,
+            "DELETE FROM webauthn_challenges WHERE expires_at < ?1",
+            now.timestamp()
+        )
+        .map_err(|_| AppError::Database)?,
+        d1_query!(
+            db,
+            "INSERT INTO webauthn_challenges (id, purpose, challenge, user_id, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &token,
+            purpose.as_str(),
+            challenge,
+            user_id,
+            expires_at
+        )
+        .map_err(|_| AppError::Database)?,
+    ])
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(token)
+}
+
+/// Consume a ceremony token and return its challenge. The row is deleted whether or not the
+/// ceremony then succeeds, so every challenge can be answered at most once.
+async fn redeem_ceremony_token(
+    db: &crate::db::Db,
+    token: &str,
+    purpose: CeremonyPurpose,
+    user_id: Option<&str>,
+) -> Result<String, AppError> {
+    let invalid = || AppError::BadRequest("Invalid or expired WebAuthn token".to_string());
+
+    let pending: PendingChallenge = d1_query!(
+        db,
+        "DELETE FROM webauthn_challenges WHERE id = ?1 RETURNING purpose, challenge, user_id, expires_at",
+        token
+    )
+    .map_err(|_| AppError::Database)?
+    .first(None)
+    .await
+    .map_err(|_| AppError::Database)?
+    .ok_or_else(invalid)?;
+
+    if pending.purpose != purpose.as_str()
+        || pending.user_id.as_deref() != user_id
+        || pending.expires_at < Utc::now().timestamp()
+    {
+        return Err(invalid());
+    }
+
+    Ok(pending.challenge)
+}
+
+fn stored_credentials(credentials: &[WebAuthnCredential]) -> Vec<StoredCredential> {
+    credentials
+        .iter()
+        .map(WebAuthnCredential::stored_credential)
+        .collect()
+}
+
+fn with_status_ok(mut options: Value) -> Value {
+    options["status"] = "ok".into();
+    options["errorMessage"] = "".into();
+    options
+}
+
+/// GET /api/webauthn - List the passkeys registered for login
 #[worker::send]
-pub async fn get_webauthn_credentials() -> Json<Value> {
-    Json(json!({
+pub async fn get_webauthn_credentials(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let credentials = WebAuthnCredential::list_by_user(&db, &claims.sub).await?;
+    let data: Vec<Value> = credentials.iter().map(|c| c.to_json()).collect();
+
+    Ok(Json(json!({
         "object": "list",
-        "data": [],
+        "data": data,
         "continuationToken": null
-    }))
+    })))
+}
+
+/// POST /api/webauthn/attestation-options - Start registering a passkey
+#[worker::send]
+pub async fn post_attestation_options(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_verified_user(&db, &claims.sub, &data).await?;
+
+    let existing = WebAuthnCredential::list_by_user(&db, &user.id).await?;
+    if existing.len() as i64 >= MAX_WEBAUTHN_LOGIN_CREDENTIALS {
+        return Err(AppError::BadRequest(
+            "Maximum number of passkeys reached".to_string(),
+        ));
+    }
+
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let challenge = webauthn::generate_challenge()?;
+    let options = webauthn::creation_options(
+        &rp,
+        &challenge,
+        &user.id,
+        &user.email,
+        user.name.as_deref().unwrap_or(&user.email),
+        &stored_credentials(&existing),
+        CredentialKind::Passkey,
+    );
+    let token =
+        issue_ceremony_token(&db, CeremonyPurpose::Create, &challenge, Some(&user.id)).await?;
+
+    Ok(Json(json!({
+        "options": with_status_ok(options),
+        "token": token,
+        "object": "webauthnCredentialCreateOptions"
+    })))
+}
+
+/// POST /api/webauthn - Finish registering a passkey
+#[worker::send]
+pub async fn post_webauthn_credential(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(data): Json<WebAuthnCredentialCreateData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let challenge =
+        redeem_ceremony_token(&db, &data.token, CeremonyPurpose::Create, Some(&claims.sub)).await?;
+
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Passkey name is required".to_string()));
+    }
+
+    let count = WebAuthnCredential::list_by_user(&db, &claims.sub)
+        .await?
+        .len() as i64;
+    if count >= MAX_WEBAUTHN_LOGIN_CREDENTIALS {
+        return Err(AppError::BadRequest(
+            "Maximum number of passkeys reached".to_string(),
+        ));
+    }
+
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let stored = webauthn::verify_registration(
+        &rp,
+        &challenge,
+        &data.device_response,
+        CredentialKind::Passkey,
+    )?;
+    if WebAuthnCredential::find_by_credential_id(&db, &stored.cred_id)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest(
+            "This passkey is already registered".to_string(),
+        ));
+    }
+
+    let credential = WebAuthnCredential::new(
+        claims.sub.clone(),
+        name.to_string(),
+        stored,
+        data.supports_prf,
+        data.keys,
+    );
+    credential.insert(&db).await?;
+
+    log::info!("User {} registered passkey {}", claims.sub, credential.id);
+
+    Ok(Json(json!({})))
+}
+
+/// POST /api/webauthn/assertion-options - Start an assertion used to enable PRF unlock
+#[worker::send]
+pub async fn post_assertion_options(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    let user = load_verified_user(&db, &claims.sub, &data).await?;
+
+    let existing = WebAuthnCredential::list_by_user(&db, &user.id).await?;
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let challenge = webauthn::generate_challenge()?;
+    let options = webauthn::request_options(
+        &rp,
+        &challenge,
+        &stored_credentials(&existing),
+        CredentialKind::Passkey,
+    );
+    let token =
+        issue_ceremony_token(&db, CeremonyPurpose::Update, &challenge, Some(&user.id)).await?;
+
+    Ok(Json(json!({
+        "options": with_status_ok(options),
+        "token": token,
+        "object": "webAuthnCredentialAssertionOptions"
+    })))
+}
+
+/// PUT /api/webauthn - Store the PRF key set of an existing passkey
+#[worker::send]
+pub async fn put_webauthn_credential(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+    Json(data): Json<WebAuthnCredentialUpdateData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+
+    let challenge =
+        redeem_ceremony_token(&db, &data.token, CeremonyPurpose::Update, Some(&claims.sub)).await?;
+
+    let cred_id = webauthn::decode_base64(data.device_response.credential_id())
+        .map(|bytes| base64_url(&bytes))?;
+    let mut credential = WebAuthnCredential::find_by_credential_id(&db, &cred_id)
+        .await?
+        .filter(|c| c.user_id == claims.sub)
+        .ok_or_else(|| AppError::NotFound("Passkey not found".to_string()))?;
+    if credential.supports_prf == 0 {
+        return Err(AppError::BadRequest(
+            "This passkey does not support encryption".to_string(),
+        ));
+    }
+
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let counter = webauthn::verify_assertion(
+        &rp,
+        &challenge,
+        &credential.stored_credential(),
+        &data.device_response,
+        CredentialKind::Passkey,
+    )?;
+
+    let (Some(user_key), Some(public_key), Some(private_key)) = (
+        data.keys.encrypted_user_key,
+        data.keys.encrypted_public_key,
+        data.keys.encrypted_private_key,
+    ) else {
+        return Err(AppError::BadRequest(
+            "The encrypted key set is required".to_string(),
+        ));
+    };
+
+    credential.counter = i64::from(counter);
+    credential.encrypted_user_key = Some(user_key);
+    credential.encrypted_public_key = Some(public_key);
+    credential.encrypted_private_key = Some(private_key);
+    credential.updated_at = db::now_string();
+    credential.update(&db).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// POST /api/webauthn/{id}/delete - Remove a passkey
+#[worker::send]
+pub async fn delete_webauthn_credential(
+    claims: Claims,
+    State(env): State<Arc<Env>>,
+    Path(id): Path<String>,
+    Json(data): Json<PasswordOrOtpData>,
+) -> Result<Json<Value>, AppError> {
+    let db = db::get_db(&env)?;
+    load_verified_user(&db, &claims.sub, &data).await?;
+
+    let credential = WebAuthnCredential::find_by_id_and_user(&db, &id, &claims.sub)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Passkey not found".to_string()))?;
+    credential.delete(&db).await?;
+
+    log::info!("User {} removed passkey {}", claims.sub, credential.id);
+
+    Ok(Json(json!({})))
+}
+
+/// GET /identity/accounts/webauthn/assertion-options - Start a passkey login
+#[worker::send]
+pub async fn get_login_assertion_options(
+    State(env): State<Arc<Env>>,
+    Extension(BaseUrl(base_url)): Extension<BaseUrl>,
+) -> Result<Json<Value>, AppError> {
+    let rp = RelyingParty::from_base_url(&base_url)?;
+    let challenge = webauthn::generate_challenge()?;
+    // No allow list: the authenticator offers whichever discoverable credential it holds.
+    let options = webauthn::request_options(&rp, &challenge, &[], CredentialKind::Passkey);
+    let db = db::get_db(&env)?;
+    let token = issue_ceremony_token(&db, CeremonyPurpose::Login, &challenge, None).await?;
+
+    Ok(Json(json!({
+        "options": with_status_ok(options),
+        "token": token,
+        "object": "webAuthnLoginAssertionOptions"
+    })))
+}
+
+/// Verify the `webauthn` grant and return the passkey that signed in, with its counter updated.
+pub(crate) async fn authenticate_webauthn_login(
+    db: &crate::db::Db,
+    base_url: &str,
+    token: &str,
+    device_response: &str,
+) -> Result<WebAuthnCredential, AppError> {
+    let challenge = redeem_ceremony_token(db, token, CeremonyPurpose::Login, None).await?;
+    let assertion: PublicKeyCredential = serde_json::from_str(device_response)
+        .map_err(|_| AppError::BadRequest("Invalid WebAuthn response".to_string()))?;
+
+    let cred_id = base64_url(&webauthn::decode_base64(assertion.credential_id())?);
+    let mut credential = WebAuthnCredential::find_by_credential_id(db, &cred_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Invalid credentials".to_string()))?;
+
+    // Discoverable credentials report the user handle we registered (the user id).
+    if let Some(user_handle) = assertion.response.user_handle.as_deref() {
+        if webauthn::decode_base64(user_handle)? != credential.user_id.as_bytes() {
+            return Err(AppError::Unauthorized("Invalid credentials".to_string()));
+        }
+    }
+
+    let rp = RelyingParty::from_base_url(base_url)?;
+    let counter = webauthn::verify_assertion(
+        &rp,
+        &challenge,
+        &credential.stored_credential(),
+        &assertion,
+        CredentialKind::Passkey,
+    )?;
+
+    credential.counter = i64::from(counter);
+    credential.updated_at = db::now_string();
+    credential.update(db).await?;
+
+    Ok(credential)
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    URL_SAFE_NO_PAD.encode(bytes)
 }
@@ -0,0 +1,240 @@
+//! HaveIBeenPwned breach lookups for `GET /api/hibp/breach`.
+//!
+//! Lookups go to the HIBP v3 `breachedaccount` API with the `HIBP_API_KEY` secret. Results are
+//! cached in the optional `HIBP_CACHE` KV namespace, keyed by a SHA-256 hash of the username so
+//! the cache never holds email addresses in the clear.
+
+use futures_util::future::LocalBoxFuture;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use worker::{kv::KvStore, Fetch, Headers, Method, Request, RequestInit};
+
+use crate::env::Env;
+use crate::error::AppError;
+
+const HIBP_API_URL: &str = "https://haveibeenpwned.com/api/v3/breachedaccount";
+const HIBP_CACHE_BINDING: &str = "HIBP_CACHE";
+const HIBP_CACHE_PREFIX: &str = "hibp:";
+/// How long a lookup result is served from the cache.
+const HIBP_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Something that can look up the breaches of an account.
+///
+/// Futures are `!Send` on Workers, so the trait hands out boxed local futures
+/// instead of using `async fn` to stay object safe.
+pub trait BreachClient {
+    /// The breaches `account` appears in, as HIBP's JSON array; empty when there are none.
+    fn breached_account<'a>(
+        &'a self,
+        account: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Value, AppError>>;
+}
+
+/// Storage for lookup results, keyed by [`cache_key`].
+pub trait BreachCache {
+    fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Option<String>>;
+    fn put<'a>(&'a self, key: &'a str, value: &'a str) -> LocalBoxFuture<'a, ()>;
+}
+
+/// Queries the HIBP v3 API.
+pub struct HttpBreachClient {
+    pub api_key: String,
+}
+
+impl BreachClient for HttpBreachClient {
+    fn breached_account<'a>(
+        &'a self,
+        account: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Value, AppError>> {
+        Box::pin(async move {
+            let mut url = worker::Url::parse(HIBP_API_URL).map_err(|_| AppError::Internal)?;
+            url.path_segments_mut()
+                .map_err(|_| AppError::Internal)?
+                .push(account);
+            url.query_pairs_mut()
+                .append_pair("truncateResponse", "false");
+
+            let headers = Headers::new();
+            headers.set("hibp-api-key", &self.api_key)?;
+            headers.set("User-Agent", "warden-worker")?;
+            let mut init = RequestInit::new();
+            init.with_method(Method::Get).with_headers(headers);
+            let req = Request::new_with_init(url.as_str(), &init)?;
+
+            let mut response = Fetch::Request(req).send().await?;
+            match response.status_code() {
+                200 => response.json().await.map_err(AppError::Worker),
+                // HIBP answers 404 for accounts without breaches.
+                404 => Ok(Value::Array(Vec::new())),
+                429 => Err(AppError::TooManyRequests(
+                    "Breach report is rate limited, please try again later".to_string(),
+                )),
+                status => {
+                    let body = response.text().await.unwrap_or_default();
+                    log::error!("HIBP request failed ({status}): {body}");
+                    Err(AppError::Internal)
+                }
+            }
+        })
+    }
+}
+
+/// Caches lookups in a KV namespace, letting KV expire them.
+pub struct KvBreachCache {
+    pub store: KvStore,
+}
+
+impl BreachCache for KvBreachCache {
+    fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            match self.store.get(key).text().await {
+                Ok(value) => value,
+                Err(e) => {
+                    log::warn!("HIBP cache read failed: {e}");
+                    None
+                }
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: &'a str) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            let result = match self.store.put(key, value) {
+                Ok(put) => put.expiration_ttl(HIBP_CACHE_TTL_SECS).execute().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("HIBP cache write failed: {e}");
+            }
+        })
+    }
+}
+
+/// The configured client, or `None` when `HIBP_API_KEY` is not set.
+pub fn breach_client(env: &Env) -> Option<HttpBreachClient> {
+    env.secret("HIBP_API_KEY")
+        .ok()
+        .map(|key| key.to_string())
+        .filter(|key| !key.is_empty())
+        .map(|api_key| HttpBreachClient { api_key })
+}
+
+/// The configured cache, or `None` when the `HIBP_CACHE` binding is missing.
+pub fn breach_cache(env: &Env) -> Option<KvBreachCache> {
+    env.kv(HIBP_CACHE_BINDING)
+        .ok()
+        .map(|store| KvBreachCache { store })
+}
+
+/// Cache key for `account`: HIBP matches accounts case-insensitively, so the hash does too.
+pub fn cache_key(account: &str) -> String {
+    let digest = Sha256::digest(account.trim().to_lowercase().as_bytes());
+    format!("{HIBP_CACHE_PREFIX}{}", hex::encode(digest))
+}
+
+/// Look up `account`, serving and filling the cache when there is one.
+pub async fn lookup_breaches(
+    client: &dyn BreachClient,
+    cache: Option<&dyn BreachCache>,
+    account: &str,
+) -> Result<Value, AppError> {
+    let key = cache_key(account);
+    if let Some(cache) = cache {
+        if let Some(cached) = cache.get(&key).await {
+            if let Ok(value) = serde_json::from_str(&cached) {
+                return Ok(value);
+            }
+        }
+    }
+
+    let breaches = client.breached_account(account.trim()).await?;
+
+    if let Some(cache) = cache {
+        cache.put(&key, &breaches.to_string()).await;
+    }
+    Ok(breaches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::block_on;
+
+    /// Answers every lookup with a fixed breach list and counts the calls.
+    struct FakeClient {
+        breaches: Value,
+        calls: Cell<usize>,
+    }
+
+    impl BreachClient for FakeClient {
+        fn breached_account<'a>(
+            &'a self,
+            _account: &'a str,
+        ) -> LocalBoxFuture<'a, Result<Value, AppError>> {
+            self.calls.set(self.calls.get() + 1);
+            Box::pin(async move { Ok(self.breaches.clone()) })
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryCache {
+        entries: RefCell<HashMap<String, String>>,
+    }
+
+    impl BreachCache for MemoryCache {
+        fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Option<String>> {
+            let value = self.entries.borrow().get(key).cloned();
+            Box::pin(async move { value })
+        }
+
+        fn put<'a>(&'a self, key: &'a str, value: &'a str) -> LocalBoxFuture<'a, ()> {
+            self.entries
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+            Box::pin(async {})
+        }
+    }
+
+    #[test]
+    fn lookups_are_served_from_the_cache() {
+        let client = FakeClient {
+            breaches: json!([{ "Name": "Adobe" }]),
+            calls: Cell::new(0),
+        };
+        let cache = MemoryCache::default();
+
+        let first = block_on(lookup_breaches(&client, Some(&cache), "User@Example.com")).unwrap();
+        let second = block_on(lookup_breaches(&client, Some(&cache), "user@example.com")).unwrap();
+
+        assert_eq!(first, json!([{ "Name": "Adobe" }]));
+        assert_eq!(second, first);
+        assert_eq!(client.calls.get(), 1);
+    }
+
+    #[test]
+    fn cache_keys_do_not_contain_the_username() {
+        let key = cache_key("user@example.com");
+
+        assert!(key.starts_with(HIBP_CACHE_PREFIX));
+        assert!(!key.contains("example"));
+        assert_eq!(key, cache_key(" USER@example.com "));
+    }
+
+    #[test]
+    fn lookups_without_a_cache_always_query() {
+        let client = FakeClient {
+            breaches: json!([]),
+            calls: Cell::new(0),
+        };
+
+        block_on(lookup_breaches(&client, None, "user@example.com")).unwrap();
+        block_on(lookup_breaches(&client, None, "user@example.com")).unwrap();
+
+        assert_eq!(client.calls.get(), 2);
+    }
+}
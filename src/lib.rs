@@ -1,22 +1,35 @@
 use std::sync::Arc;
 
-use axum::{extract::DefaultBodyLimit, Extension};
+use axum::{extract::DefaultBodyLimit, response::IntoResponse, Extension};
 use tower_http::cors::{Any, CorsLayer};
 use tower_service::Service;
 use worker::*;
 
 mod auth;
 mod background;
+mod backup;
 mod client_context;
+mod config;
+mod converters;
 mod crypto;
 mod db;
 mod durable;
+mod env;
 mod error;
 mod handlers;
+mod hibp;
+mod mail;
+mod migrations;
 mod models;
 mod notifications;
+mod outbox;
 mod push;
 mod router;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod webauthn;
+mod webpush;
 
 /// Base URL extracted from the incoming request, used for config endpoint.
 #[derive(Clone)]
@@ -26,6 +39,11 @@ pub struct BaseUrl(pub String);
 pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<web_sys::Response> {
     console_error_panic_hook::set_once();
     let _ = console_log::init_with_level(log::Level::Debug);
+    let env = env::from_worker(env);
+
+    if let Err(e) = migrations::ensure_schema(&env).await {
+        return worker::response_to_wasm(e.into_response());
+    }
 
     let url = req.url()?;
     let method = req.method();
@@ -73,12 +91,38 @@ pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<web_sys::Resp
 /// Scheduled event handler for cron-triggered tasks.
 ///
 /// This handler is triggered by Cloudflare's cron triggers configured in wrangler.toml.
-/// It performs automatic cleanup of soft-deleted ciphers that have exceeded the
-/// retention period (default: 30 days, configurable via TRASH_AUTO_DELETE_DAYS env var).
+/// It applies pending schema migrations, then performs automatic cleanup of soft-deleted ciphers that have exceeded the
+/// retention period (default: 30 days, configurable via TRASH_AUTO_DELETE_DAYS env var),
+/// and writes a vault backup when the `BACKUP_BUCKET` R2 binding is configured.
+/// Every trigger also retries failed notification deliveries from the outbox; the frequent
+/// `outbox::RETRY_CRON` trigger does nothing else.
 #[event(scheduled)]
-pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
     console_error_panic_hook::set_once();
     let _ = console_log::init_with_level(log::Level::Debug);
+    let env = env::from_worker(env);
+
+    if let Err(e) = migrations::ensure_schema(&env).await {
+        log::error!("Schema migration failed, skipping scheduled tasks: {e:?}");
+        return;
+    }
+
+    match outbox::run_retries(&env).await {
+        Ok(stats) => {
+            if stats != outbox::RetryStats::default() {
+                log::info!(
+                    "Outbox retries: {} delivered, {} rescheduled, {} dead-lettered",
+                    stats.delivered,
+                    stats.rescheduled,
+                    stats.dead
+                );
+            }
+        }
+        Err(e) => log::error!("Outbox retries failed: {e:?}"),
+    }
+    if event.cron() == outbox::RETRY_CRON {
+        return;
+    }
 
     fn log_purge_result(name: &str, result: Result<u32, worker::Error>) {
         match result {
@@ -107,4 +151,19 @@ pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext)
         "expired auth requests",
         handlers::purge::purge_expired_auth_requests(&env).await,
     );
+    log_purge_result("old events", handlers::purge::purge_old_events(&env).await);
+
+    if backup::backups_enabled(&env) {
+        match backup::create_backup(&env).await {
+            Ok(backup) => log::info!("Backup {} completed: {} bytes", backup.name, backup.size),
+            Err(e) => log::error!("Backup failed: {e:?}"),
+        }
+    }
+
+    match handlers::emergency_access::emergency_request_timeout_job(&env).await {
+        Ok(count) => {
+            log::info!("Emergency access timeout job completed: {count} request(s) approved")
+        }
+        Err(e) => log::error!("Emergency access timeout job failed: {e:?}"),
+    }
 }
@@ -0,0 +1,196 @@
+pub mod templates;
+pub mod transport;
+
+use crate::env::Env;
+use crate::error::AppError;
+use transport::{HttpApiFlavor, HttpApiTransport, MailTransport, SendEmailBindingTransport};
+
+const DEFAULT_MAIL_FROM_NAME: &str = "Warden";
+const SEND_EMAIL_BINDING: &str = "SEND_EMAIL";
+
+/// A single outgoing email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+/// The `From` mailbox used for every outgoing email.
+#[derive(Debug, Clone)]
+pub struct MailSender {
+    pub address: String,
+    pub name: String,
+}
+
+impl MailSender {
+    /// `Name <address>` as accepted by most mail APIs.
+    pub fn mailbox(&self) -> String {
+        format!("{} <{}>", self.name, self.address)
+    }
+}
+
+/// Build the configured mail transport from environment variables.
+///
+/// Returns `None` when `MAIL_PROVIDER` is not set (mail disabled).
+/// Returns `Err` when a provider is selected but its configuration is incomplete.
+pub fn mail_transport(env: &Env) -> Result<Option<Box<dyn MailTransport>>, AppError> {
+    let Some(provider) = env
+        .var("MAIL_PROVIDER")
+        .ok()
+        .map(|v| v.to_string().trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let address = env
+        .var("MAIL_FROM")
+        .ok()
+        .map(|v| v.to_string())
+        .ok_or_else(|| {
+            log::error!("MAIL_PROVIDER is set but MAIL_FROM is missing");
+            AppError::Internal
+        })?;
+    let name = env
+        .var("MAIL_FROM_NAME")
+        .ok()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| DEFAULT_MAIL_FROM_NAME.to_string());
+    let sender = MailSender { address, name };
+
+    let flavor = match provider.as_str() {
+        "send_email" => {
+            let binding = env.send_email(SEND_EMAIL_BINDING).map_err(|_| {
+                log::error!(
+                    "MAIL_PROVIDER is send_email but the {SEND_EMAIL_BINDING} binding is missing"
+                );
+                AppError::Internal
+            })?;
+            return Ok(Some(Box::new(SendEmailBindingTransport {
+                binding,
+                sender,
+            })));
+        }
+        #[cfg(test)]
+        "test" => return Ok(Some(Box::new(env.mail_transport()))),
+        "resend" => HttpApiFlavor::Resend,
+        "mailchannels" => HttpApiFlavor::MailChannels,
+        other => {
+            log::error!("Unknown MAIL_PROVIDER: {other}");
+            return Err(AppError::Internal);
+        }
+    };
+
+    let url = env
+        .var("MAIL_API_URL")
+        .ok()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| flavor.default_url().to_string());
+    let api_key = env
+        .secret("MAIL_API_KEY")
+        .map(|v| v.to_string())
+        .map_err(|_| {
+            log::error!("MAIL_PROVIDER is {provider} but MAIL_API_KEY secret is missing");
+            AppError::Internal
+        })?;
+
+    Ok(Some(Box::new(HttpApiTransport {
+        flavor,
+        url,
+        api_key,
+        sender,
+    })))
+}
+
+/// Link to the web vault page `route` (e.g. `/accept-organization`) with `params` as its query.
+///
+/// The web vault routes on the URL fragment, so the query goes after `#`.
+pub fn web_vault_link(base_url: &str, route: &str, params: &[(&str, &str)]) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    format!("{base_url}/#{route}?{query}")
+}
+
+/// Whether a mail provider is configured.
+pub fn mail_enabled(env: &Env) -> bool {
+    env.var("MAIL_PROVIDER")
+        .ok()
+        .is_some_and(|v| !v.to_string().trim().is_empty())
+}
+
+/// Send a message after the response has been returned.
+///
+/// Does nothing when mail is disabled; delivery failures are only logged.
+pub fn send_in_background(env: Env, message: MailMessage) {
+    if !mail_enabled(&env) {
+        return;
+    }
+    crate::background::spawn_background(async move {
+        if let Err(e) = send(&env, &message).await {
+            log::warn!("Failed to send \"{}\" email: {e}", message.subject);
+        }
+    });
+}
+
+/// Send a message through the configured transport, failing when mail is disabled.
+pub async fn send(env: &Env, message: &MailMessage) -> Result<(), AppError> {
+    let transport = mail_transport(env)?
+        .ok_or_else(|| AppError::BadRequest("Mail is not configured on this server".to_string()))?;
+    transport.send(message).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transport::{MailTransport, RecordingTransport};
+    use super::*;
+    use crate::testing::block_on;
+
+    #[test]
+    fn recording_transport_keeps_sent_messages() {
+        let transport = RecordingTransport::default();
+        let dyn_transport: &dyn MailTransport = &transport;
+        let message = templates::twofactor_token("user@example.com", "123456");
+
+        block_on(dyn_transport.send(&message)).unwrap();
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "user@example.com");
+        assert!(sent[0].text.contains("123456"));
+    }
+
+    #[test]
+    fn web_vault_links_encode_the_query_after_the_fragment() {
+        let link = web_vault_link(
+            "https://vault.example.com",
+            "/verify-email",
+            &[("userId", "u 1"), ("token", "a+b/c=")],
+        );
+        assert_eq!(
+            link,
+            "https://vault.example.com/#/verify-email?userId=u+1&token=a%2Bb%2Fc%3D"
+        );
+    }
+
+    #[test]
+    fn templates_escape_user_values_in_html() {
+        let message = templates::password_hint("user@example.com", Some("<b>cat</b>"));
+
+        assert!(message.text.contains("<b>cat</b>"));
+        let html = message.html.unwrap();
+        assert!(html.contains("&lt;b&gt;cat&lt;/b&gt;"));
+        assert!(!html.contains("<b>cat</b>"));
+    }
+
+    #[test]
+    fn mailbox_includes_display_name() {
+        let sender = MailSender {
+            address: "vault@example.com".to_string(),
+            name: "Warden".to_string(),
+        };
+        assert_eq!(sender.mailbox(), "Warden <vault@example.com>");
+    }
+}
@@ -0,0 +1,185 @@
+//! Templated messages sent by the server.
+//!
+//! Every message has a plain-text body and a minimal HTML alternative. Values
+//! that come from users (names, hints, device names) are escaped in the HTML part.
+
+use crate::mail::MailMessage;
+
+/// The six-digit email two-factor code.
+pub fn twofactor_token(to: &str, token: &str) -> MailMessage {
+    message(
+        to,
+        "Your Two-step Login Verification Code",
+        &[
+            "Enter the following code to continue logging in:",
+            token,
+            "If you did not try to log in, you should change your master password.",
+        ],
+    )
+}
+
+/// Sent once an account has been created.
+pub fn welcome(to: &str, base_url: &str) -> MailMessage {
+    message(
+        to,
+        "Welcome",
+        &[
+            "Thank you for creating an account.",
+            &format!("You can log in to your vault at {base_url}"),
+        ],
+    )
+}
+
+/// Link that proves ownership of the address, used both when signing up and
+/// when verifying the address of an existing account.
+pub fn verify_email(to: &str, link: &str) -> MailMessage {
+    message(
+        to,
+        "Verify Your Email",
+        &[
+            "Verify this email address by opening the link below:",
+            link,
+            "If you did not request this, you can ignore this email.",
+        ],
+    )
+}
+
+/// Invitation from the operator, with a link to finish the signup.
+pub fn invite(to: &str, link: &str) -> MailMessage {
+    message(
+        to,
+        "You Have Been Invited",
+        &[
+            "You have been invited to create an account on this password manager. Open the link below to choose your master password:",
+            link,
+            "The link is valid for 24 hours. If you were not expecting this, you can ignore this email.",
+        ],
+    )
+}
+
+/// Invitation to become someone's emergency contact, with the link to accept it.
+pub fn emergency_access_invite(to: &str, grantor: &str, link: &str) -> MailMessage {
+    message(
+        to,
+        "Emergency Access Invitation",
+        &[
+            &format!("{grantor} has invited you to be an emergency contact. Open the link below to accept:"),
+            link,
+            "The link is valid for 5 days. If you were not expecting this, you can ignore this email.",
+        ],
+    )
+}
+
+/// Invitation to join an organization, with the link to accept it.
+pub fn organization_invite(to: &str, org_name: &str, link: &str) -> MailMessage {
+    message(
+        to,
+        &format!("Join {org_name}"),
+        &[
+            &format!("You have been invited to join the {org_name} organization. Open the link below to accept:"),
+            link,
+            "The link is valid for 5 days. If you were not expecting this, you can ignore this email.",
+        ],
+    )
+}
+
+/// The master password hint, or a note that none was set.
+pub fn password_hint(to: &str, hint: Option<&str>) -> MailMessage {
+    let body = match hint {
+        Some(hint) => format!("Your master password hint is: {hint}"),
+        None => "You did not set a master password hint.".to_string(),
+    };
+    message(
+        to,
+        "Your Master Password Hint",
+        &[
+            &body,
+            "If you did not request your master password hint, you can safely ignore this email.",
+        ],
+    )
+}
+
+/// Security alert for the first login from a device.
+pub fn new_device_logged_in(
+    to: &str,
+    device_name: &str,
+    device_type: &str,
+    ip: &str,
+    date: &str,
+) -> MailMessage {
+    message(
+        to,
+        &format!("New Device Logged In From {device_type}"),
+        &[
+            "Your account was just logged into from a new device.",
+            &format!("Device: {device_name} ({device_type})\nIP address: {ip}\nDate: {date}"),
+            "If this was not you, change your master password and deauthorize all sessions immediately.",
+        ],
+    )
+}
+
+/// Security alert sent when a two-step login provider is turned off, or when
+/// all of them were removed by logging in with the recovery code (`provider` is `None`).
+pub fn twofactor_disabled(to: &str, provider: Option<&str>) -> MailMessage {
+    let body = match provider {
+        Some(provider) => {
+            format!("Two-step login with {provider} has been disabled on your account.")
+        }
+        None => "Your recovery code was used to log in, so all two-step login methods have been disabled on your account.".to_string(),
+    };
+    message(
+        to,
+        "Two-step Login Disabled",
+        &[
+            &body,
+            "If you did not make this change, change your master password and enable two-step login again.",
+        ],
+    )
+}
+
+/// Confirmation that an account and its vault have been deleted.
+pub fn account_deleted(to: &str) -> MailMessage {
+    message(
+        to,
+        "Your Account Has Been Deleted",
+        &[
+            "Your account and all of its vault data have been permanently deleted.",
+            "If you did not delete your account, contact the administrator of this server.",
+        ],
+    )
+}
+
+/// Build a message whose paragraphs are joined by blank lines.
+fn message(to: &str, subject: &str, paragraphs: &[&str]) -> MailMessage {
+    let text = paragraphs.join("\n\n");
+    let html_paragraphs: String = paragraphs
+        .iter()
+        .map(|p| format!("<p>{}</p>", escape_html(p).replace('\n', "<br>")))
+        .collect();
+    let html = format!(
+        "<!DOCTYPE html><html><body style=\"font-family: sans-serif; line-height: 1.5;\"><h2>{}</h2>{html_paragraphs}</body></html>",
+        escape_html(subject)
+    );
+
+    MailMessage {
+        to: to.to_string(),
+        subject: subject.to_string(),
+        text,
+        html: Some(html),
+    }
+}
+
+fn escape_html(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
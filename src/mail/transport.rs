@@ -0,0 +1,166 @@
+use futures_util::future::LocalBoxFuture;
+use serde_json::{json, Value};
+use worker::{
+    email::{EmailAddress, SendEmail, SendEmailBuilder},
+    Fetch, Method, Request, RequestInit,
+};
+
+use crate::error::AppError;
+use crate::mail::{MailMessage, MailSender};
+
+/// Something that can deliver a [`MailMessage`].
+///
+/// Futures are `!Send` on Workers, so the trait hands out boxed local futures
+/// instead of using `async fn` to stay object safe.
+pub trait MailTransport {
+    fn send<'a>(&'a self, message: &'a MailMessage) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+/// JSON body layouts understood by [`HttpApiTransport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpApiFlavor {
+    /// `{from, to: [..], subject, text, html}` with a bearer token (Resend and compatible APIs).
+    Resend,
+    /// `{personalizations, from, subject, content}` with an `X-Api-Key` header.
+    MailChannels,
+}
+
+impl HttpApiFlavor {
+    pub fn default_url(self) -> &'static str {
+        match self {
+            Self::Resend => "https://api.resend.com/emails",
+            Self::MailChannels => "https://api.mailchannels.net/tx/v1/send",
+        }
+    }
+}
+
+/// Delivers mail through a transactional email HTTP API.
+pub struct HttpApiTransport {
+    pub flavor: HttpApiFlavor,
+    pub url: String,
+    pub api_key: String,
+    pub sender: MailSender,
+}
+
+impl HttpApiTransport {
+    fn body(&self, message: &MailMessage) -> Value {
+        match self.flavor {
+            HttpApiFlavor::Resend => {
+                let mut body = json!({
+                    "from": self.sender.mailbox(),
+                    "to": [message.to],
+                    "subject": message.subject,
+                    "text": message.text,
+                });
+                if let Some(html) = &message.html {
+                    body["html"] = json!(html);
+                }
+                body
+            }
+            HttpApiFlavor::MailChannels => {
+                let mut content = vec![json!({ "type": "text/plain", "value": message.text })];
+                if let Some(html) = &message.html {
+                    content.push(json!({ "type": "text/html", "value": html }));
+                }
+                json!({
+                    "personalizations": [{ "to": [{ "email": message.to }] }],
+                    "from": { "email": self.sender.address, "name": self.sender.name },
+                    "subject": message.subject,
+                    "content": content,
+                })
+            }
+        }
+    }
+}
+
+impl MailTransport for HttpApiTransport {
+    fn send<'a>(&'a self, message: &'a MailMessage) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let body = self.body(message).to_string();
+
+            let mut init = RequestInit::new();
+            init.with_method(Method::Post).with_body(Some(body.into()));
+            let mut req = Request::new_with_init(&self.url, &init).map_err(AppError::Worker)?;
+            let headers = req.headers_mut().map_err(AppError::Worker)?;
+            headers
+                .set("Content-Type", "application/json")
+                .map_err(AppError::Worker)?;
+            match self.flavor {
+                HttpApiFlavor::Resend => headers
+                    .set("Authorization", &format!("Bearer {}", self.api_key))
+                    .map_err(AppError::Worker)?,
+                HttpApiFlavor::MailChannels => headers
+                    .set("X-Api-Key", &self.api_key)
+                    .map_err(AppError::Worker)?,
+            }
+
+            let mut response = Fetch::Request(req).send().await.map_err(AppError::Worker)?;
+            if !(200..300).contains(&response.status_code()) {
+                let body = response.text().await.unwrap_or_default();
+                log::error!(
+                    "Mail API request failed ({}): {body}",
+                    response.status_code()
+                );
+                return Err(AppError::Internal);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Delivers mail through a Cloudflare Email Workers `send_email` binding.
+pub struct SendEmailBindingTransport {
+    pub binding: SendEmail,
+    pub sender: MailSender,
+}
+
+impl MailTransport for SendEmailBindingTransport {
+    fn send<'a>(&'a self, message: &'a MailMessage) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let from = EmailAddress::new(&self.sender.name, &self.sender.address);
+            let mut builder = SendEmailBuilder::builder_with_email_address_and_str(
+                &from,
+                &message.to,
+                &message.subject,
+            )
+            .text(&message.text);
+            if let Some(html) = &message.html {
+                builder = builder.html(html);
+            }
+
+            self.binding
+                .send_with_builder(&builder.build())
+                .await
+                .map_err(|e| {
+                    log::error!("send_email binding failed: {e:?}");
+                    AppError::Internal
+                })?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Keeps every message in memory instead of delivering it. Clones share the same messages,
+/// so the test `Env` can hand one out as `MAIL_PROVIDER=test` and read the mail afterwards.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingTransport {
+    sent: std::sync::Arc<std::sync::Mutex<Vec<MailMessage>>>,
+}
+
+#[cfg(test)]
+impl RecordingTransport {
+    pub fn sent(&self) -> Vec<MailMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl MailTransport for RecordingTransport {
+    fn send<'a>(&'a self, message: &'a MailMessage) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        self.sent.lock().unwrap().push(message.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
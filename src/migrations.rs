@@ -0,0 +1,534 @@
+//! Built-in schema migrations.
+//!
+//! The files in `migrations/` are embedded in the binary and applied on the first request an
+//! isolate serves, or from the cron trigger, whichever comes first. Applied versions are recorded
+//! in `schema_migrations`; a lease row in `schema_lock` keeps concurrent isolates from applying
+//! the same migration twice. An empty database is initialised from `sql/schema.sql`, which
+//! already contains every migration.
+//!
+//! Databases migrated with `wrangler d1 migrations apply` keep working: their `d1_migrations`
+//! history is imported, and migrations applied here are recorded there too.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use crate::db::Statement;
+use crate::env::Env;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use worker::Delay;
+
+use crate::d1_query;
+use crate::db::{self, Db};
+use crate::error::AppError;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+/// Every file in `migrations/`, in order. Add new migrations at the end.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_add_password_salt"),
+    migration!(2, "0002_add_argon2_fields"),
+    migration!(3, "0003_add_twofactor"),
+    migration!(4, "0004_add_avatar_color"),
+    migration!(5, "0005_add_attachments"),
+    migration!(6, "0006_add_pending_attachments"),
+    migration!(7, "0007_add_password_iterations"),
+    migration!(8, "0008_add_eq_domains"),
+    migration!(9, "0009_add_ciphers_folders_user_id_index"),
+    migration!(10, "0010_add_devices"),
+    migration!(11, "0011_add_sends"),
+    migration!(12, "0012_add_archived_at"),
+    migration!(13, "0013_add_auth_requests"),
+    migration!(14, "0014_add_emergency_access"),
+    migration!(15, "0015_add_organizations"),
+    migration!(16, "0016_add_webauthn_credentials"),
+    migration!(17, "0017_add_email_verification"),
+    migration!(18, "0018_add_api_key"),
+    migration!(19, "0019_add_admin"),
+    migration!(20, "0020_add_config"),
+    migration!(21, "0021_add_events"),
+    migration!(22, "0022_add_schema_migrations"),
+    migration!(23, "0023_add_web_push"),
+    migration!(24, "0024_add_outbox"),
+    migration!(25, "0025_add_webauthn_challenges"),
+];
+
+/// The schema version this build expects.
+pub const LATEST_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+const SCHEMA_SQL: &str = include_str!("../sql/schema.sql");
+/// Creates `schema_migrations` and `schema_lock`, which must exist before anything else runs.
+const BOOTSTRAP_SQL: &str = include_str!("../migrations/0022_add_schema_migrations.sql");
+
+/// How long a migration lease lasts if its holder dies without releasing it.
+const LOCK_TTL_MS: i64 = 60_000;
+/// How long a request waits for another isolate to finish migrating.
+const LOCK_WAIT: Duration = Duration::from_millis(500);
+const LOCK_WAIT_ATTEMPTS: u32 = 20;
+
+/// How long an isolate trusts its last look at the schema version. A database restored from an
+/// older backup or recreated behind a live deployment is migrated within this window.
+const SCHEMA_RECHECK_MS: i64 = 60_000;
+
+/// When this isolate last saw the database at [`LATEST_VERSION`], in Unix milliseconds.
+static SCHEMA_CHECKED_AT: AtomicI64 = AtomicI64::new(i64::MIN);
+
+#[derive(Deserialize)]
+struct AppliedRow {
+    version: i64,
+    name: String,
+    applied_at: String,
+}
+
+#[derive(Deserialize)]
+struct NameRow {
+    name: String,
+}
+
+/// Bring the database up to [`LATEST_VERSION`], or fail if it is newer than this build.
+///
+/// Cheap for [`SCHEMA_RECHECK_MS`] after it has succeeded in an isolate.
+pub async fn ensure_schema(env: &Env) -> Result<(), AppError> {
+    let now = Utc::now().timestamp_millis();
+    if now.saturating_sub(SCHEMA_CHECKED_AT.load(Ordering::Relaxed)) < SCHEMA_RECHECK_MS {
+        return Ok(());
+    }
+
+    let db = db::get_db(env)?;
+    for _ in 0..LOCK_WAIT_ATTEMPTS {
+        let version = current_version(&db).await;
+        if version > LATEST_VERSION {
+            log::error!(
+                "Database schema version {version} is newer than this build supports ({LATEST_VERSION})"
+            );
+            return Err(AppError::ServiceUnavailable(
+                "Database schema is newer than this server version".to_string(),
+            ));
+        }
+        if version == LATEST_VERSION {
+            SCHEMA_CHECKED_AT.store(now, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        run_script(&db, BOOTSTRAP_SQL).await?;
+        let owner = Uuid::new_v4().to_string();
+        if acquire_lock(&db, &owner).await? {
+            let result = migrate(&db).await;
+            release_lock(&db, &owner).await;
+            result?;
+            SCHEMA_CHECKED_AT.store(now, Ordering::Relaxed);
+            return Ok(());
+        }
+        Delay::from(LOCK_WAIT).await;
+    }
+
+    Err(AppError::ServiceUnavailable(
+        "Database migration in progress, please try again shortly".to_string(),
+    ))
+}
+
+/// Bring a new test database to [`LATEST_VERSION`], as the first request would.
+#[cfg(test)]
+pub(crate) async fn initialize(db: &Db) -> Result<(), AppError> {
+    run_script(db, BOOTSTRAP_SQL).await?;
+    migrate(db).await
+}
+
+/// The highest applied version; 0 before the runner has ever run.
+async fn current_version(db: &Db) -> i64 {
+    db.prepare("SELECT COALESCE(MAX(version), 0) AS version FROM schema_migrations")
+        .first::<i64>(Some("version"))
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0)
+}
+
+/// Applied migrations, oldest first, for the admin API.
+pub async fn schema_status(env: &Env) -> Result<Value, AppError> {
+    let db = db::get_db(env)?;
+    let applied: Vec<AppliedRow> = db
+        .prepare("SELECT version, name, applied_at FROM schema_migrations ORDER BY version")
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+    let version = applied.last().map_or(0, |row| row.version);
+
+    Ok(json!({
+        "version": version,
+        "latestVersion": LATEST_VERSION,
+        "migrations": applied
+            .iter()
+            .map(|row| json!({
+                "version": row.version,
+                "name": row.name,
+                "appliedAt": row.applied_at,
+            }))
+            .collect::<Vec<Value>>(),
+        "object": "schema",
+    }))
+}
+
+async fn acquire_lock(db: &Db, owner: &str) -> Result<bool, AppError> {
+    let now = Utc::now().timestamp_millis();
+    d1_query!(
+        db,
+        "INSERT INTO schema_lock (id, owner, expires_at) VALUES (1, ?1, ?2)
+         ON CONFLICT(id) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at
+         WHERE schema_lock.expires_at < ?3",
+        owner,
+        now + LOCK_TTL_MS,
+        now
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    let holder: Option<String> = db
+        .prepare("SELECT owner FROM schema_lock WHERE id = 1")
+        .first(Some("owner"))
+        .await
+        .map_err(|_| AppError::Database)?;
+    Ok(holder.as_deref() == Some(owner))
+}
+
+async fn release_lock(db: &Db, owner: &str) {
+    let result = match d1_query!(
+        db,
+        "DELETE FROM schema_lock WHERE id = 1 AND owner = ?1",
+        owner
+    ) {
+        Ok(statement) => statement.run().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::warn!("Releasing the migration lock failed: {e}");
+    }
+}
+
+async fn table_exists(db: &Db, name: &str) -> Result<bool, AppError> {
+    let found: Option<String> = d1_query!(
+        db,
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        name
+    )
+    .map_err(|_| AppError::Database)?
+    .first(Some("name"))
+    .await
+    .map_err(|_| AppError::Database)?;
+    Ok(found.is_some())
+}
+
+async fn applied_versions(db: &Db) -> Result<HashSet<i64>, AppError> {
+    let rows: Vec<AppliedRow> = db
+        .prepare("SELECT version, name, applied_at FROM schema_migrations")
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+    Ok(rows.into_iter().map(|row| row.version).collect())
+}
+
+/// Statements recording `migration` as applied, in our table and in wrangler's when present.
+fn record_statements(
+    db: &Db,
+    migration: &Migration,
+    wrangler_history: bool,
+) -> Result<Vec<Statement>, AppError> {
+    let mut statements = vec![d1_query!(
+        db,
+        "INSERT OR IGNORE INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+        migration.version,
+        migration.name,
+        db::now_string()
+    )
+    .map_err(|_| AppError::Database)?];
+    if wrangler_history {
+        statements.push(
+            d1_query!(
+                db,
+                "INSERT OR IGNORE INTO d1_migrations (name) VALUES (?1)",
+                format!("{}.sql", migration.name)
+            )
+            .map_err(|_| AppError::Database)?,
+        );
+    }
+    Ok(statements)
+}
+
+/// Apply every pending migration. Must be called with the lock held.
+async fn migrate(db: &Db) -> Result<(), AppError> {
+    let wrangler_history = table_exists(db, "d1_migrations").await?;
+
+    if !table_exists(db, "users").await? {
+        log::info!("Empty database, applying sql/schema.sql");
+        let mut statements = script_statements(db, SCHEMA_SQL);
+        for migration in MIGRATIONS {
+            statements.extend(record_statements(db, migration, wrangler_history)?);
+        }
+        db.batch(statements).await.map_err(|e| {
+            log::error!("Applying sql/schema.sql failed: {e}");
+            AppError::Database
+        })?;
+        return Ok(());
+    }
+
+    if wrangler_history {
+        import_wrangler_history(db).await?;
+    }
+
+    let applied = applied_versions(db).await?;
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        let mut statements = Vec::new();
+        for statement in split_statements(migration.sql) {
+            // Columns added by hand or by an older release are skipped one by one; the rest of
+            // the migration still runs.
+            if let Some((table, column)) = added_column(&statement) {
+                if column_exists(db, &table, &column).await? {
+                    log::warn!(
+                        "Migration {}: column {table}.{column} already exists",
+                        migration.name
+                    );
+                    continue;
+                }
+            }
+            statements.push(db.prepare(statement));
+        }
+        statements.extend(record_statements(db, migration, wrangler_history)?);
+        db.batch(statements).await.map_err(|e| {
+            log::error!("Migration {} failed: {e}", migration.name);
+            AppError::Database
+        })?;
+        log::info!("Applied migration {}", migration.name);
+    }
+    Ok(())
+}
+
+async fn column_exists(db: &Db, table: &str, column: &str) -> Result<bool, AppError> {
+    let found: Option<String> = d1_query!(
+        db,
+        "SELECT name FROM pragma_table_info(?1) WHERE name = ?2",
+        table,
+        column
+    )
+    .map_err(|_| AppError::Database)?
+    .first(Some("name"))
+    .await
+    .map_err(|_| AppError::Database)?;
+    Ok(found.is_some())
+}
+
+/// The table and column of an `ALTER TABLE ... ADD [COLUMN] ...` statement.
+fn added_column(statement: &str) -> Option<(String, String)> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    let [alter, table_kw, table, add, rest @ ..] = words.as_slice() else {
+        return None;
+    };
+    if !alter.eq_ignore_ascii_case("ALTER")
+        || !table_kw.eq_ignore_ascii_case("TABLE")
+        || !add.eq_ignore_ascii_case("ADD")
+    {
+        return None;
+    }
+    let column = match rest {
+        [column_kw, column, ..] if column_kw.eq_ignore_ascii_case("COLUMN") => column,
+        [column, ..] => column,
+        [] => return None,
+    };
+    let unquote = |name: &str| {
+        name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']'))
+            .to_string()
+    };
+    Some((unquote(table), unquote(column)))
+}
+
+/// Record the migrations wrangler has applied so they are not run again.
+async fn import_wrangler_history(db: &Db) -> Result<(), AppError> {
+    let rows: Vec<NameRow> = db
+        .prepare("SELECT name FROM d1_migrations")
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+    let names: HashSet<&str> = rows
+        .iter()
+        .map(|row| row.name.trim_end_matches(".sql"))
+        .collect();
+
+    let mut statements = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| names.contains(m.name)) {
+        statements.extend(record_statements(db, migration, false)?);
+    }
+    if !statements.is_empty() {
+        db.batch(statements).await.map_err(|_| AppError::Database)?;
+    }
+    Ok(())
+}
+
+fn script_statements(db: &Db, sql: &str) -> Vec<Statement> {
+    split_statements(sql)
+        .into_iter()
+        .map(|statement| db.prepare(statement))
+        .collect()
+}
+
+async fn run_script(db: &Db, sql: &str) -> Result<(), AppError> {
+    db.batch(script_statements(db, sql))
+        .await
+        .map_err(|_| AppError::Database)?;
+    Ok(())
+}
+
+/// Split a SQL script into statements, dropping `--` comments. Migrations contain no triggers,
+/// so a `;` outside a string literal always ends a statement.
+fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+            '-' if !in_string && chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            ';' if !in_string => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    let statement = current.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::block_on;
+
+    #[test]
+    fn every_migration_file_is_embedded_in_order() {
+        let mut files: Vec<String> =
+            std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.ends_with(".sql"))
+                .collect();
+        files.sort();
+
+        let embedded: Vec<String> = MIGRATIONS
+            .iter()
+            .map(|migration| format!("{}.sql", migration.name))
+            .collect();
+        assert_eq!(embedded, files);
+
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+            assert!(migration
+                .name
+                .starts_with(&format!("{:04}_", migration.version)));
+        }
+    }
+
+    #[test]
+    fn statements_are_split_outside_comments_and_strings() {
+        let sql = "-- a comment; with a semicolon\n\
+                   CREATE TABLE t (a TEXT DEFAULT 'x;y'); -- trailing\n\
+                   \n\
+                   DELETE FROM t WHERE a = 'it''s';\n\
+                   ALTER TABLE t ADD COLUMN b INTEGER";
+
+        assert_eq!(
+            split_statements(sql),
+            vec![
+                "CREATE TABLE t (a TEXT DEFAULT 'x;y')",
+                "DELETE FROM t WHERE a = 'it''s'",
+                "ALTER TABLE t ADD COLUMN b INTEGER",
+            ]
+        );
+    }
+
+    #[test]
+    fn added_columns_are_parsed_from_alter_statements() {
+        assert_eq!(
+            added_column("ALTER TABLE users ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1"),
+            Some(("users".to_string(), "enabled".to_string()))
+        );
+        assert_eq!(
+            added_column("alter table \"users\"\n  add `api_key` TEXT"),
+            Some(("users".to_string(), "api_key".to_string()))
+        );
+        assert_eq!(added_column("ALTER TABLE users RENAME TO people"), None);
+        assert_eq!(added_column("CREATE TABLE t (a TEXT)"), None);
+    }
+
+    #[test]
+    fn columns_added_by_hand_do_not_skip_the_rest_of_a_migration() {
+        let db = Db::Sqlite(crate::db::Database::open_in_memory());
+        block_on(initialize(&db)).unwrap();
+        // As if 0008 had been applied up to its first column by hand.
+        block_on(run_script(
+            &db,
+            "DROP TABLE global_equivalent_domains;
+             ALTER TABLE users DROP COLUMN excluded_globals;
+             DELETE FROM schema_migrations WHERE version = 8;",
+        ))
+        .unwrap();
+
+        block_on(migrate(&db)).unwrap();
+
+        assert!(block_on(column_exists(&db, "users", "excluded_globals")).unwrap());
+        assert!(block_on(table_exists(&db, "global_equivalent_domains")).unwrap());
+        assert!(block_on(applied_versions(&db)).unwrap().contains(&8));
+    }
+
+    #[test]
+    fn schema_includes_the_runner_tables() {
+        let statements = split_statements(SCHEMA_SQL);
+        for statement in split_statements(BOOTSTRAP_SQL) {
+            assert!(
+                statements.contains(&statement),
+                "missing from schema.sql: {statement}"
+            );
+        }
+    }
+}
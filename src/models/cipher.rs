@@ -51,7 +51,47 @@ pub struct CipherData {
     pub type_fields: CipherTypeFields,
 }
 
+/// Keys every passkey in `login.fido2Credentials` carries. Apart from `creationDate` the values
+/// are encrypted strings, so only their presence can be checked.
+const FIDO2_CREDENTIAL_KEYS: &[&str] = &[
+    "credentialId",
+    "keyType",
+    "keyAlgorithm",
+    "keyCurve",
+    "keyValue",
+    "rpId",
+    "counter",
+    "discoverable",
+    "creationDate",
+];
+
 impl CipherTypeFields {
+    /// The passkeys stored with a login, if any.
+    pub fn fido2_credentials(&self) -> Option<&Value> {
+        self.login
+            .as_ref()?
+            .get("fido2Credentials")
+            .filter(|credentials| !credentials.is_null())
+    }
+
+    /// Check that `login.fido2Credentials` is a list of complete passkeys.
+    pub fn validate_fido2_credentials(&self) -> Result<(), String> {
+        let Some(credentials) = self.fido2_credentials() else {
+            return Ok(());
+        };
+        let credentials = credentials
+            .as_array()
+            .ok_or("fido2Credentials must be a list")?;
+        for credential in credentials {
+            for key in FIDO2_CREDENTIAL_KEYS {
+                if !matches!(credential.get(key), Some(Value::String(value)) if !value.is_empty()) {
+                    return Err(format!("passkey is missing {key}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn normalize_for_storage(mut self) -> Self {
         self.password_history = {
             match self.password_history {
@@ -190,7 +230,7 @@ pub struct Cipher {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CipherDBModel {
     pub id: String,
-    pub user_id: String,
+    pub user_id: Option<String>,
     pub organization_id: Option<String>,
     pub r#type: i32,
     pub data: String,
@@ -206,7 +246,7 @@ impl From<CipherDBModel> for Cipher {
     fn from(val: CipherDBModel) -> Self {
         Cipher {
             id: val.id,
-            user_id: Some(val.user_id),
+            user_id: val.user_id,
             organization_id: val.organization_id,
             r#type: val.r#type,
             data: serde_json::from_str(&val.data).unwrap_or_default(),
@@ -372,6 +412,9 @@ pub struct CipherRequestData {
     pub notes: Option<String>,
     #[serde(default)]
     pub favorite: Option<bool>,
+    /// Only read by the import; archiving otherwise goes through the archive endpoints.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived_date: Option<String>,
     #[serde(flatten)]
     pub type_fields: CipherTypeFields,
     /// Used during key rotation to update attachment keys and encrypted filenames.
@@ -403,6 +446,24 @@ pub struct CreateCipherRequest {
     pub collection_ids: Vec<String>,
 }
 
+/// Request body for sharing several ciphers at once (PUT /api/ciphers/share)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareCiphersBulkRequest {
+    pub ciphers: Vec<CipherRequestData>,
+    #[serde(default)]
+    pub collection_ids: Vec<String>,
+}
+
+/// Request body for changing the collections of an organization cipher
+/// (PUT/POST /api/ciphers/{id}/collections)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CipherCollectionsData {
+    #[serde(default)]
+    pub collection_ids: Vec<String>,
+}
+
 /// Response for listing ciphers (GET /api/ciphers)
 /// Now we don't use this struct, we use RawJson instead. But we keep it here for reference.
 #[derive(Debug, Serialize)]
@@ -422,3 +483,54 @@ pub struct PartialCipherData {
     pub folder_id: Option<String>,
     pub favorite: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passkey() -> Value {
+        json!({
+            "credentialId": "2.cred", "keyType": "2.type", "keyAlgorithm": "2.alg",
+            "keyCurve": "2.curve", "keyValue": "2.key", "rpId": "2.rp", "userHandle": null,
+            "counter": "2.counter", "discoverable": "2.disc",
+            "creationDate": "2026-01-01T00:00:00.000Z",
+        })
+    }
+
+    #[test]
+    fn passkeys_survive_normalization() {
+        let type_fields = CipherTypeFields {
+            login: Some(json!({ "username": "2.user", "fido2Credentials": [passkey()] })),
+            password_history: Some(json!([{ "password": "2.old" }, "bogus"])),
+            ..Default::default()
+        };
+        assert!(type_fields.validate_fido2_credentials().is_ok());
+        let before = type_fields.fido2_credentials().cloned();
+
+        let data = CipherData::new("2.name".to_string(), None, type_fields);
+        assert_eq!(data.type_fields.fido2_credentials(), before.as_ref());
+        assert_eq!(
+            data.type_fields.password_history,
+            Some(json!([{ "password": "2.old" }]))
+        );
+    }
+
+    #[test]
+    fn incomplete_passkeys_are_rejected() {
+        let mut incomplete = passkey();
+        incomplete["keyValue"] = Value::Null;
+        for credentials in [json!([incomplete]), json!({}), json!(["2.cred"])] {
+            let type_fields = CipherTypeFields {
+                login: Some(json!({ "fido2Credentials": credentials })),
+                ..Default::default()
+            };
+            assert!(type_fields.validate_fido2_credentials().is_err());
+        }
+
+        let without = CipherTypeFields {
+            login: Some(json!({ "fido2Credentials": null })),
+            ..Default::default()
+        };
+        assert!(without.validate_fido2_credentials().is_ok());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::db::Statement;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::d1_query;
+use crate::{db, error::AppError, models::organization::CollectionAccessData};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub org_id: String,
+    pub name: String,
+    pub external_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A collection as seen by one user, with that user's access flags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectionWithAccess {
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub read_only: i32,
+    pub hide_passwords: i32,
+    pub manage: i32,
+}
+
+/// A row of `users_collections`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectionUser {
+    pub user_id: String,
+    pub collection_id: String,
+    pub read_only: i32,
+    pub hide_passwords: i32,
+    pub manage: i32,
+}
+
+impl Collection {
+    pub fn new(org_id: String, name: String, external_id: Option<String>) -> Self {
+        let now = db::now_string();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            org_id,
+            name,
+            external_id: external_id.filter(|id| !id.is_empty()),
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "externalId": self.external_id,
+            "id": self.id,
+            "organizationId": self.org_id,
+            "name": self.name,
+            "object": "collection",
+        })
+    }
+
+    pub fn to_json_details(&self, read_only: bool, hide_passwords: bool, manage: bool) -> Value {
+        let mut value = self.to_json();
+        value["readOnly"] = json!(read_only);
+        value["hidePasswords"] = json!(hide_passwords);
+        value["manage"] = json!(manage);
+        value["object"] = json!("collectionDetails");
+        value
+    }
+
+    pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO collections (id, org_id, name, external_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            &self.id,
+            &self.org_id,
+            &self.name,
+            self.external_id.as_deref(),
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn update(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE collections SET name = ?1, external_id = ?2, updated_at = ?3 WHERE id = ?4",
+            &self.name,
+            self.external_id.as_deref(),
+            &self.updated_at,
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(db, "DELETE FROM collections WHERE id = ?1", &self.id)
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id_and_org(
+        db: &crate::db::Db,
+        id: &str,
+        org_id: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT * FROM collections WHERE id = ?1 AND org_id = ?2",
+            id,
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    pub async fn list_by_org(db: &crate::db::Db, org_id: &str) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT * FROM collections WHERE org_id = ?1 ORDER BY created_at",
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// Collections visible to `user_id` across all organizations they are a confirmed member of.
+    /// Members with full access see every collection with full permissions.
+    pub async fn list_for_user(
+        db: &crate::db::Db,
+        user_id: &str,
+    ) -> Result<Vec<CollectionWithAccess>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT col.*,
+                    CASE WHEN uo.access_all = 1 OR uo.atype IN (0, 1) THEN 0 ELSE uc.read_only END AS read_only,
+                    CASE WHEN uo.access_all = 1 OR uo.atype IN (0, 1) THEN 0 ELSE uc.hide_passwords END AS hide_passwords,
+                    CASE WHEN uo.atype IN (0, 1) THEN 1 ELSE COALESCE(uc.manage, 0) END AS manage
+             FROM collections col
+             JOIN users_organizations uo ON uo.org_id = col.org_id AND uo.user_id = ?1 AND uo.status = 2
+             LEFT JOIN users_collections uc ON uc.collection_id = col.id AND uc.user_id = ?1
+             WHERE uo.access_all = 1 OR uo.atype IN (0, 1) OR uc.user_id IS NOT NULL
+             ORDER BY col.created_at",
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// All `users_collections` rows of an organization.
+    pub async fn list_users_by_org(
+        db: &crate::db::Db,
+        org_id: &str,
+    ) -> Result<Vec<CollectionUser>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT uc.* FROM users_collections uc
+             JOIN collections col ON col.id = uc.collection_id
+             WHERE col.org_id = ?1",
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// Build statements replacing the collection assignments of `user_id` inside `org_id`.
+    /// Collections that do not belong to the organization are ignored.
+    pub fn replace_user_access_statements(
+        db: &crate::db::Db,
+        org_id: &str,
+        user_id: &str,
+        access: &[CollectionAccessData],
+    ) -> Result<Vec<Statement>, AppError> {
+        let mut statements = Vec::with_capacity(access.len() + 1);
+        statements.push(
+            d1_query!(
+                db,
+                "DELETE FROM users_collections
+                 WHERE user_id = ?1 AND collection_id IN (SELECT id FROM collections WHERE org_id = ?2)",
+                user_id,
+                org_id
+            )
+            .map_err(|_| AppError::Database)?,
+        );
+        for entry in access {
+            statements.push(
+                d1_query!(
+                    db,
+                    "INSERT OR REPLACE INTO users_collections (user_id, collection_id, read_only, hide_passwords, manage)
+                     SELECT ?1, id, ?2, ?3, ?4 FROM collections WHERE id = ?5 AND org_id = ?6",
+                    user_id,
+                    entry.read_only,
+                    entry.hide_passwords,
+                    entry.manage,
+                    &entry.id,
+                    org_id
+                )
+                .map_err(|_| AppError::Database)?,
+            );
+        }
+        Ok(statements)
+    }
+
+    /// Build statements replacing the member assignments of a collection.
+    /// `access` holds membership ids; memberships outside the organization are ignored.
+    pub fn replace_collection_access_statements(
+        &self,
+        db: &crate::db::Db,
+        access: &[CollectionAccessData],
+    ) -> Result<Vec<Statement>, AppError> {
+        let mut statements = Vec::with_capacity(access.len() + 1);
+        statements.push(
+            d1_query!(
+                db,
+                "DELETE FROM users_collections WHERE collection_id = ?1",
+                &self.id
+            )
+            .map_err(|_| AppError::Database)?,
+        );
+        for entry in access {
+            statements.push(
+                d1_query!(
+                    db,
+                    "INSERT OR REPLACE INTO users_collections (user_id, collection_id, read_only, hide_passwords, manage)
+                     SELECT user_id, ?1, ?2, ?3, ?4 FROM users_organizations WHERE id = ?5 AND org_id = ?6",
+                    &self.id,
+                    entry.read_only,
+                    entry.hide_passwords,
+                    entry.manage,
+                    &entry.id,
+                    &self.org_id
+                )
+                .map_err(|_| AppError::Database)?,
+            );
+        }
+        Ok(statements)
+    }
+}
+
+impl CollectionWithAccess {
+    pub fn to_json_details(&self) -> Value {
+        self.collection.to_json_details(
+            self.read_only != 0,
+            self.hide_passwords != 0,
+            self.manage != 0,
+        )
+    }
+}
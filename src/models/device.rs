@@ -119,6 +119,28 @@ impl Device {
         )
     }
 
+    /// Browser extensions and the web vault, which receive updates through Web Push.
+    pub fn is_web_push_device(&self) -> bool {
+        matches!(
+            DeviceType::from_i32(self.r#type),
+            DeviceType::ChromeExtension
+                | DeviceType::FirefoxExtension
+                | DeviceType::OperaExtension
+                | DeviceType::EdgeExtension
+                | DeviceType::VivaldiExtension
+                | DeviceType::SafariExtension
+                | DeviceType::ChromeBrowser
+                | DeviceType::FirefoxBrowser
+                | DeviceType::OperaBrowser
+                | DeviceType::EdgeBrowser
+                | DeviceType::IEBrowser
+                | DeviceType::UnknownBrowser
+                | DeviceType::SafariBrowser
+                | DeviceType::VivaldiBrowser
+                | DeviceType::DuckDuckGoBrowser
+        )
+    }
+
     pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
         d1_query!(
             db,
@@ -0,0 +1,374 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::d1_query;
+use crate::{db, error::AppError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum EmergencyAccessType {
+    View = 0,
+    Takeover = 1,
+}
+
+impl EmergencyAccessType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::View),
+            1 => Some(Self::Takeover),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum EmergencyAccessStatus {
+    Invited = 0,
+    Accepted = 1,
+    Confirmed = 2,
+    RecoveryInitiated = 3,
+    RecoveryApproved = 4,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyAccess {
+    pub id: String,
+    pub grantor_id: String,
+    pub grantee_id: Option<String>,
+    pub email: Option<String>,
+    pub key_encrypted: Option<String>,
+    pub atype: i32,
+    pub status: i32,
+    pub wait_time_days: i32,
+    pub recovery_initiated_at: Option<String>,
+    pub last_notification_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// An emergency access row joined with the display fields of the other party
+/// (the grantee when listing as grantor, the grantor when listing as grantee).
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmergencyAccessDetails {
+    #[serde(flatten)]
+    pub access: EmergencyAccess,
+    pub contact_name: Option<String>,
+    pub contact_email: Option<String>,
+    pub contact_avatar_color: Option<String>,
+}
+
+impl EmergencyAccess {
+    pub fn new(
+        grantor_id: String,
+        grantee_id: Option<String>,
+        email: String,
+        atype: EmergencyAccessType,
+        wait_time_days: i32,
+    ) -> Self {
+        let now = db::now_string();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            grantor_id,
+            grantee_id,
+            email: Some(email),
+            key_encrypted: None,
+            atype: atype as i32,
+            status: EmergencyAccessStatus::Invited as i32,
+            wait_time_days,
+            recovery_initiated_at: None,
+            last_notification_at: None,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "status": self.status,
+            "type": self.atype,
+            "waitTimeDays": self.wait_time_days,
+            "object": "emergencyAccess",
+        })
+    }
+
+    pub fn has_status(&self, status: EmergencyAccessStatus) -> bool {
+        self.status == status as i32
+    }
+
+    pub fn has_type(&self, atype: EmergencyAccessType) -> bool {
+        self.atype == atype as i32
+    }
+
+    pub fn set_status(&mut self, status: EmergencyAccessStatus, now: &str) {
+        self.status = status as i32;
+        self.updated_at = now.to_string();
+    }
+
+    /// Whether the configured wait time has elapsed since recovery was initiated.
+    pub fn is_wait_time_elapsed(&self) -> bool {
+        let Some(initiated) = self.recovery_initiated_at.as_deref() else {
+            return false;
+        };
+        let Ok(initiated) = NaiveDateTime::parse_from_str(initiated, "%Y-%m-%dT%H:%M:%S%.3fZ")
+        else {
+            return false;
+        };
+        Utc::now() >= initiated.and_utc() + Duration::days(self.wait_time_days as i64)
+    }
+
+    pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO emergency_access (id, grantor_id, grantee_id, email, key_encrypted, atype, status, wait_time_days, recovery_initiated_at, last_notification_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            &self.id,
+            &self.grantor_id,
+            self.grantee_id.as_deref(),
+            self.email.as_deref(),
+            self.key_encrypted.as_deref(),
+            self.atype,
+            self.status,
+            self.wait_time_days,
+            self.recovery_initiated_at.as_deref(),
+            self.last_notification_at.as_deref(),
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    // The grantor never changes after creation, so it is excluded from updates.
+    pub async fn update(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE emergency_access
+             SET grantee_id = ?1,
+                 email = ?2,
+                 key_encrypted = ?3,
+                 atype = ?4,
+                 status = ?5,
+                 wait_time_days = ?6,
+                 recovery_initiated_at = ?7,
+                 last_notification_at = ?8,
+                 updated_at = ?9
+             WHERE id = ?10",
+            self.grantee_id.as_deref(),
+            self.email.as_deref(),
+            self.key_encrypted.as_deref(),
+            self.atype,
+            self.status,
+            self.wait_time_days,
+            self.recovery_initiated_at.as_deref(),
+            self.last_notification_at.as_deref(),
+            &self.updated_at,
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(db, "DELETE FROM emergency_access WHERE id = ?1", &self.id)
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(db: &crate::db::Db, id: &str) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(db, "SELECT * FROM emergency_access WHERE id = ?1", id)
+            .map_err(|_| AppError::Database)?
+            .first(None)
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    pub async fn find_by_grantor_and_email(
+        db: &crate::db::Db,
+        grantor_id: &str,
+        email: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT ea.* FROM emergency_access ea
+             LEFT JOIN users u ON u.id = ea.grantee_id
+             WHERE ea.grantor_id = ?1 AND (ea.email = ?2 OR u.email = ?2)",
+            grantor_id,
+            email
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    /// Grants created by `grantor_id`, joined with each grantee's profile.
+    pub async fn list_by_grantor(
+        db: &crate::db::Db,
+        grantor_id: &str,
+    ) -> Result<Vec<EmergencyAccessDetails>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT ea.*, u.name AS contact_name, COALESCE(u.email, ea.email) AS contact_email,
+                    u.avatar_color AS contact_avatar_color
+             FROM emergency_access ea
+             LEFT JOIN users u ON u.id = ea.grantee_id
+             WHERE ea.grantor_id = ?1
+             ORDER BY ea.created_at",
+            grantor_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// Grants given to `grantee_id`, joined with each grantor's profile.
+    /// Invitations that have not been accepted yet are not listed.
+    pub async fn list_by_grantee(
+        db: &crate::db::Db,
+        grantee_id: &str,
+    ) -> Result<Vec<EmergencyAccessDetails>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT ea.*, u.name AS contact_name, u.email AS contact_email,
+                    u.avatar_color AS contact_avatar_color
+             FROM emergency_access ea
+             JOIN users u ON u.id = ea.grantor_id
+             WHERE ea.grantee_id = ?1 AND ea.status != ?2
+             ORDER BY ea.created_at",
+            grantee_id,
+            EmergencyAccessStatus::Invited as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    pub async fn list_recovery_initiated(db: &crate::db::Db) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT * FROM emergency_access WHERE status = ?1 AND recovery_initiated_at IS NOT NULL",
+            EmergencyAccessStatus::RecoveryInitiated as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+}
+
+impl EmergencyAccessDetails {
+    pub fn to_json_grantee_details(&self) -> Value {
+        json!({
+            "id": self.access.id,
+            "status": self.access.status,
+            "type": self.access.atype,
+            "waitTimeDays": self.access.wait_time_days,
+            "granteeId": self.access.grantee_id,
+            "email": self.contact_email,
+            "name": self.contact_name,
+            "avatarColor": self.contact_avatar_color,
+            "object": "emergencyAccessGranteeDetails",
+        })
+    }
+
+    pub fn to_json_grantor_details(&self) -> Value {
+        json!({
+            "id": self.access.id,
+            "status": self.access.status,
+            "type": self.access.atype,
+            "waitTimeDays": self.access.wait_time_days,
+            "grantorId": self.access.grantor_id,
+            "email": self.contact_email,
+            "name": self.contact_name,
+            "avatarColor": self.contact_avatar_color,
+            "object": "emergencyAccessGrantorDetails",
+        })
+    }
+}
+
+// Request payloads
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessInviteData {
+    pub email: String,
+    pub r#type: i32,
+    pub wait_time_days: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessUpdateData {
+    pub r#type: i32,
+    pub wait_time_days: i32,
+    pub key_encrypted: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptData {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmData {
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessPasswordData {
+    pub new_master_password_hash: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyAccessKeyData {
+    pub id: String,
+    pub key_encrypted: String,
+}
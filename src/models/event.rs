@@ -0,0 +1,168 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::d1_query;
+use crate::{db, error::AppError};
+
+/// Event types, numbered like Bitwarden's `EventType` so the clients can render them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum EventType {
+    UserLoggedIn = 1000,
+    UserChangedPassword = 1001,
+    UserUpdated2fa = 1002,
+    UserDisabled2fa = 1003,
+    UserRecovered2fa = 1004,
+    UserFailedLogIn = 1005,
+    UserFailedLogIn2fa = 1006,
+
+    CipherDeleted = 1102,
+    CipherSoftDeleted = 1115,
+    CipherRestored = 1116,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub event_type: i32,
+    pub user_id: Option<String>,
+    pub organization_id: Option<String>,
+    pub cipher_id: Option<String>,
+    pub acting_user_id: Option<String>,
+    pub device_type: Option<i32>,
+    pub ip_address: Option<String>,
+    pub event_date: String,
+}
+
+impl Event {
+    pub fn new(event_type: EventType, device_type: i32, ip_address: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event_type: event_type as i32,
+            user_id: None,
+            organization_id: None,
+            cipher_id: None,
+            acting_user_id: None,
+            device_type: Some(device_type),
+            ip_address: Some(ip_address),
+            event_date: db::now_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": self.event_type,
+            "userId": self.user_id,
+            "organizationId": self.organization_id,
+            "cipherId": self.cipher_id,
+            "collectionId": null,
+            "groupId": null,
+            "organizationUserId": null,
+            "actingUserId": self.acting_user_id,
+            "installationId": null,
+            "deviceType": self.device_type,
+            "ipAddress": self.ip_address,
+            "date": self.event_date,
+            "object": "event",
+        })
+    }
+
+    pub fn insert_statement(&self, db: &db::Db) -> Result<db::Statement, AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO events (id, event_type, user_id, organization_id, cipher_id, acting_user_id, device_type, ip_address, event_date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            &self.id,
+            self.event_type,
+            &self.user_id,
+            &self.organization_id,
+            &self.cipher_id,
+            &self.acting_user_id,
+            self.device_type,
+            &self.ip_address,
+            &self.event_date
+        )
+        .map_err(|_| AppError::Database)
+    }
+
+    pub async fn save(&self, db: &db::Db) -> Result<(), AppError> {
+        self.insert_statement(db)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+
+    /// Events about `user_id` dated in `[start, end)`, plus those dated `end` whose id sorts
+    /// before `end_id`, newest first. Ties on the date are ordered by id, so `(end, end_id)`
+    /// can be the last event of the previous page.
+    pub async fn find_by_user(
+        db: &db::Db,
+        user_id: &str,
+        start: &str,
+        (end, end_id): (&str, &str),
+        limit: i64,
+    ) -> Result<Vec<Self>, AppError> {
+        d1_query!(
+            db,
+            "SELECT * FROM events
+             WHERE user_id = ?1 AND event_date >= ?2 AND (event_date < ?3 OR (event_date = ?3 AND id < ?4))
+             ORDER BY event_date DESC, id DESC LIMIT ?5",
+            user_id,
+            start,
+            end,
+            end_id,
+            limit
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)
+    }
+
+    /// Like [`Event::find_by_user`], for the events about `cipher_id`.
+    pub async fn find_by_cipher(
+        db: &db::Db,
+        cipher_id: &str,
+        start: &str,
+        (end, end_id): (&str, &str),
+        limit: i64,
+    ) -> Result<Vec<Self>, AppError> {
+        d1_query!(
+            db,
+            "SELECT * FROM events
+             WHERE cipher_id = ?1 AND event_date >= ?2 AND (event_date < ?3 OR (event_date = ?3 AND id < ?4))
+             ORDER BY event_date DESC, id DESC LIMIT ?5",
+            cipher_id,
+            start,
+            end,
+            end_id,
+            limit
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)
+    }
+
+    pub async fn delete_older_than(db: &db::Db, cutoff: &str) -> Result<u32, AppError> {
+        let result = d1_query!(db, "DELETE FROM events WHERE event_date < ?1", cutoff)
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        let changes = result
+            .meta()
+            .map_err(|_| AppError::Database)?
+            .and_then(|m| m.changes)
+            .unwrap_or(0) as u32;
+
+        Ok(changes)
+    }
+}
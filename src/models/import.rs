@@ -7,7 +7,8 @@ use crate::models::cipher::CipherRequestData;
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ImportFolder {
-    /// Optional folder ID - if provided and exists, the existing folder is used
+    /// Optional folder ID - if provided and the user owns it, the existing folder is used;
+    /// otherwise the folder is created with a new ID
     #[serde(default, deserialize_with = "super::deser_opt_nonempty_str")]
     pub id: Option<String>,
     pub name: String,
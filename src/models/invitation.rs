@@ -0,0 +1,44 @@
+use crate::d1_query;
+use crate::{db, error::AppError};
+
+/// An address the operator invited through the admin API.
+///
+/// An invitation lets the address sign up even when it is not matched by
+/// `ALLOWED_EMAILS`; it is consumed by the registration.
+pub struct Invitation;
+
+impl Invitation {
+    pub async fn exists(db: &db::Db, email: &str) -> Result<bool, AppError> {
+        let found: Option<String> =
+            d1_query!(db, "SELECT email FROM invitations WHERE email = ?1", email)
+                .map_err(|_| AppError::Database)?
+                .first(Some("email"))
+                .await
+                .map_err(|_| AppError::Database)?;
+        Ok(found.is_some())
+    }
+
+    pub async fn create(db: &db::Db, email: &str) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO invitations (email, created_at) VALUES (?1, ?2)
+             ON CONFLICT(email) DO UPDATE SET created_at = excluded.created_at",
+            email,
+            db::now_string()
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+
+    pub async fn delete(db: &db::Db, email: &str) -> Result<(), AppError> {
+        d1_query!(db, "DELETE FROM invitations WHERE email = ?1", email)
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+}
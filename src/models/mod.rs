@@ -1,13 +1,20 @@
 pub mod attachment;
 pub mod auth_request;
 pub mod cipher;
+pub mod collection;
 pub mod device;
+pub mod emergency_access;
+pub mod event;
 pub mod folder;
 pub mod import;
+pub mod invitation;
+pub mod organization;
 pub mod send;
 pub mod sync;
 pub mod twofactor;
 pub mod user;
+pub mod web_push_subscription;
+pub mod webauthn_credential;
 
 /// Deserialize `Option<String>` but treat `""` as `None`.
 /// Newer Bitwarden clients send `""` instead of `null` for absent folder IDs.
@@ -0,0 +1,745 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::d1_query;
+use crate::{db, error::AppError, models::user::KeyData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(i32)]
+pub enum MembershipType {
+    Owner = 0,
+    Admin = 1,
+    User = 2,
+    Manager = 3,
+}
+
+impl MembershipType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Owner),
+            1 => Some(Self::Admin),
+            2 => Some(Self::User),
+            3 => Some(Self::Manager),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum MembershipStatus {
+    Invited = 0,
+    Accepted = 1,
+    Confirmed = 2,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub billing_email: String,
+    pub private_key: Option<String>,
+    pub public_key: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Organization {
+    pub fn new(
+        name: String,
+        billing_email: String,
+        private_key: Option<String>,
+        public_key: Option<String>,
+    ) -> Self {
+        let now = db::now_string();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            billing_email,
+            private_key,
+            public_key,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "identifier": null,
+            "name": self.name,
+            "seats": null,
+            "maxCollections": null,
+            "maxStorageGb": i16::MAX,
+            "use2fa": true,
+            "useCustomPermissions": false,
+            "useDirectory": false,
+            "useEvents": false,
+            "useGroups": false,
+            "useTotp": true,
+            "usePolicies": false,
+            "useSso": false,
+            "selfHost": true,
+            "useApi": false,
+            "hasPublicAndPrivateKeys": self.private_key.is_some() && self.public_key.is_some(),
+            "useResetPassword": false,
+            "allowAdminAccessToAllCollectionItems": true,
+            "limitCollectionCreation": true,
+            "limitCollectionDeletion": true,
+            "businessName": self.name,
+            "businessAddress1": null,
+            "businessAddress2": null,
+            "businessAddress3": null,
+            "businessCountry": null,
+            "businessTaxNumber": null,
+            "billingEmail": self.billing_email,
+            "planType": 6, // Enterprise (annually), so clients don't limit seats or collections
+            "usersGetPremium": true,
+            "object": "organization",
+        })
+    }
+
+    pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO organizations (id, name, billing_email, private_key, public_key, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &self.id,
+            &self.name,
+            &self.billing_email,
+            self.private_key.as_deref(),
+            self.public_key.as_deref(),
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn update(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE organizations
+             SET name = ?1, billing_email = ?2, private_key = ?3, public_key = ?4, updated_at = ?5
+             WHERE id = ?6",
+            &self.name,
+            &self.billing_email,
+            self.private_key.as_deref(),
+            self.public_key.as_deref(),
+            &self.updated_at,
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Delete the organization together with its ciphers.
+    /// Memberships and collections are removed through `ON DELETE CASCADE`.
+    pub async fn delete(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        db.batch(vec![
+            d1_query!(
+                db,
+                "DELETE FROM ciphers WHERE organization_id = ?1",
+                &self.id
+            )
+            .map_err(|_| AppError::Database)?,
+            d1_query!(db, "DELETE FROM organizations WHERE id = ?1", &self.id)
+                .map_err(|_| AppError::Database)?,
+        ])
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(db: &crate::db::Db, id: &str) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(db, "SELECT * FROM organizations WHERE id = ?1", id)
+            .map_err(|_| AppError::Database)?
+            .first(None)
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub id: String,
+    pub user_id: String,
+    pub org_id: String,
+    pub akey: Option<String>,
+    pub status: i32,
+    pub atype: i32,
+    pub access_all: i32,
+    pub external_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A membership joined with the member's user profile, used by the admin member list.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MembershipDetails {
+    #[serde(flatten)]
+    pub membership: Membership,
+    pub user_name: Option<String>,
+    pub user_email: String,
+    pub user_avatar_color: Option<String>,
+    pub two_factor_enabled: i32,
+}
+
+/// A membership joined with its organization, used for the profile `organizations` array.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MembershipWithOrg {
+    #[serde(flatten)]
+    pub membership: Membership,
+    pub org_name: String,
+}
+
+/// Per-collection access of a member, as sent and returned by the clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionAccessData {
+    pub id: String,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub hide_passwords: bool,
+    #[serde(default)]
+    pub manage: bool,
+}
+
+fn permissions_json() -> Value {
+    json!({
+        "accessEventLogs": false,
+        "accessImportExport": false,
+        "accessReports": false,
+        "createNewCollections": false,
+        "editAnyCollection": false,
+        "deleteAnyCollection": false,
+        "manageGroups": false,
+        "managePolicies": false,
+        "manageSso": false,
+        "manageUsers": false,
+        "manageResetPassword": false,
+        "manageScim": false,
+    })
+}
+
+impl Membership {
+    pub fn new(user_id: String, org_id: String, atype: MembershipType, access_all: bool) -> Self {
+        let now = db::now_string();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            org_id,
+            akey: None,
+            status: MembershipStatus::Invited as i32,
+            atype: atype as i32,
+            access_all: i32::from(access_all),
+            external_id: None,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    pub fn has_status(&self, status: MembershipStatus) -> bool {
+        self.status == status as i32
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.has_status(MembershipStatus::Confirmed)
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.atype == MembershipType::Owner as i32
+    }
+
+    /// Owners and admins manage members and collections.
+    pub fn is_admin(&self) -> bool {
+        self.is_confirmed()
+            && (self.atype == MembershipType::Owner as i32
+                || self.atype == MembershipType::Admin as i32)
+    }
+
+    /// Whether the member can see every collection of the organization.
+    pub fn has_full_access(&self) -> bool {
+        self.is_admin() || (self.is_confirmed() && self.access_all != 0)
+    }
+
+    pub fn set_status(&mut self, status: MembershipStatus, now: &str) {
+        self.status = status as i32;
+        self.updated_at = now.to_string();
+    }
+
+    pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO users_organizations (id, user_id, org_id, akey, status, atype, access_all, external_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            &self.id,
+            &self.user_id,
+            &self.org_id,
+            self.akey.as_deref(),
+            self.status,
+            self.atype,
+            self.access_all,
+            self.external_id.as_deref(),
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn update(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE users_organizations
+             SET akey = ?1, status = ?2, atype = ?3, access_all = ?4, external_id = ?5, updated_at = ?6
+             WHERE id = ?7",
+            self.akey.as_deref(),
+            self.status,
+            self.atype,
+            self.access_all,
+            self.external_id.as_deref(),
+            &self.updated_at,
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Remove the membership and the member's collection assignments in this organization.
+    pub async fn delete(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        db.batch(vec![
+            d1_query!(
+                db,
+                "DELETE FROM users_collections
+                 WHERE user_id = ?1 AND collection_id IN (SELECT id FROM collections WHERE org_id = ?2)",
+                &self.user_id,
+                &self.org_id
+            )
+            .map_err(|_| AppError::Database)?,
+            d1_query!(db, "DELETE FROM users_organizations WHERE id = ?1", &self.id)
+                .map_err(|_| AppError::Database)?,
+        ])
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id_and_org(
+        db: &crate::db::Db,
+        id: &str,
+        org_id: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT * FROM users_organizations WHERE id = ?1 AND org_id = ?2",
+            id,
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    pub async fn find_by_user_and_org(
+        db: &crate::db::Db,
+        user_id: &str,
+        org_id: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT * FROM users_organizations WHERE user_id = ?1 AND org_id = ?2",
+            user_id,
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    pub async fn list_by_org(db: &crate::db::Db, org_id: &str) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT * FROM users_organizations WHERE org_id = ?1",
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    pub async fn list_details_by_org(
+        db: &crate::db::Db,
+        org_id: &str,
+    ) -> Result<Vec<MembershipDetails>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT uo.*, u.name AS user_name, u.email AS user_email, u.avatar_color AS user_avatar_color,
+                    EXISTS(SELECT 1 FROM twofactor tf WHERE tf.user_uuid = u.id AND tf.enabled = 1 AND tf.atype < 1000) AS two_factor_enabled
+             FROM users_organizations uo
+             JOIN users u ON u.id = uo.user_id
+             WHERE uo.org_id = ?1
+             ORDER BY u.email",
+            org_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    pub async fn list_with_org_by_user(
+        db: &crate::db::Db,
+        user_id: &str,
+    ) -> Result<Vec<MembershipWithOrg>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT uo.*, o.name AS org_name
+             FROM users_organizations uo
+             JOIN organizations o ON o.id = uo.org_id
+             WHERE uo.user_id = ?1",
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// Number of confirmed owners of an organization.
+    pub async fn count_confirmed_owners(db: &crate::db::Db, org_id: &str) -> Result<i64, AppError> {
+        let count: Option<i64> = d1_query!(
+            db,
+            "SELECT COUNT(*) AS cnt FROM users_organizations WHERE org_id = ?1 AND atype = ?2 AND status = ?3",
+            org_id,
+            MembershipType::Owner as i32,
+            MembershipStatus::Confirmed as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .first(Some("cnt"))
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Organizations in which `user_id` is the only confirmed owner.
+    pub async fn list_orgs_solely_owned_by(
+        db: &crate::db::Db,
+        user_id: &str,
+    ) -> Result<Vec<String>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT uo.org_id FROM users_organizations uo
+             WHERE uo.user_id = ?1 AND uo.atype = ?2 AND uo.status = ?3
+               AND NOT EXISTS (
+                   SELECT 1 FROM users_organizations other
+                   WHERE other.org_id = uo.org_id AND other.user_id != ?1
+                     AND other.atype = ?2 AND other.status = ?3
+               )",
+            user_id,
+            MembershipType::Owner as i32,
+            MembershipStatus::Confirmed as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.get("org_id")?.as_str().map(str::to_owned))
+            .collect())
+    }
+
+    /// User ids of every confirmed member of an organization.
+    pub async fn list_confirmed_user_ids(
+        db: &crate::db::Db,
+        org_id: &str,
+    ) -> Result<Vec<String>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT user_id FROM users_organizations WHERE org_id = ?1 AND status = ?2",
+            org_id,
+            MembershipStatus::Confirmed as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.get("user_id")?.as_str().map(str::to_owned))
+            .collect())
+    }
+
+    /// Bump the revision date of every confirmed member so their clients resync.
+    pub async fn touch_members_updated_at(
+        db: &crate::db::Db,
+        org_id: &str,
+        now: &str,
+    ) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE users SET updated_at = ?1
+             WHERE id IN (SELECT user_id FROM users_organizations WHERE org_id = ?2 AND status = ?3)",
+            now,
+            org_id,
+            MembershipStatus::Confirmed as i32
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+impl MembershipWithOrg {
+    /// Entry of the profile `organizations` array.
+    pub fn to_json_profile(&self) -> Value {
+        let membership = &self.membership;
+        let mut value = json!({
+            "id": membership.org_id,
+            "identifier": null,
+            "name": self.org_name,
+            "seats": null,
+            "maxCollections": null,
+            "maxStorageGb": i16::MAX,
+            "usersGetPremium": true,
+            "use2fa": true,
+            "useDirectory": false,
+            "useEvents": false,
+            "useGroups": false,
+            "useTotp": true,
+            "useScim": false,
+            "usePolicies": false,
+            "useApi": false,
+            "selfHost": true,
+            "hasPublicAndPrivateKeys": true,
+            "resetPasswordEnrolled": false,
+            "useResetPassword": false,
+            "ssoBound": false,
+            "useSso": false,
+            "useKeyConnector": false,
+            "useSecretsManager": false,
+            "usePasswordManager": true,
+            "useCustomPermissions": false,
+            "useActivateAutofillPolicy": false,
+            "useAdminSponsoredFamilies": false,
+            "useRiskInsights": false,
+            "useAccessIntelligence": false,
+        });
+        // Split in two so the `json!` expansion stays within the default recursion limit.
+        let membership_fields = json!({
+            "organizationUserId": membership.id,
+            "providerId": null,
+            "providerName": null,
+            "providerType": null,
+            "familySponsorshipFriendlyName": null,
+            "familySponsorshipAvailable": false,
+            "planProductType": 3,
+            "productTierType": 3,
+            "keyConnectorEnabled": false,
+            "keyConnectorUrl": null,
+            "familySponsorshipLastSyncDate": null,
+            "familySponsorshipValidUntil": null,
+            "familySponsorshipToDelete": null,
+            "accessSecretsManager": false,
+            "limitCollectionCreation": true,
+            "limitCollectionDeletion": true,
+            "limitItemDeletion": false,
+            "allowAdminAccessToAllCollectionItems": true,
+            "userIsManagedByOrganization": false,
+            "userIsClaimedByOrganization": false,
+            "permissions": permissions_json(),
+            "userId": membership.user_id,
+            "key": membership.akey,
+            "status": membership.status,
+            "type": membership.atype,
+            "enabled": true,
+            "object": "profileOrganization",
+        });
+        if let (Some(value), Value::Object(fields)) = (value.as_object_mut(), membership_fields) {
+            value.extend(fields);
+        }
+        value
+    }
+}
+
+impl MembershipDetails {
+    /// Entry of the admin member list; `collections` is only included when requested.
+    pub fn to_json_user_details(&self, collections: Option<&[CollectionAccessData]>) -> Value {
+        let membership = &self.membership;
+        let mut value = json!({
+            "id": membership.id,
+            "userId": membership.user_id,
+            "name": self.user_name,
+            "email": self.user_email,
+            "externalId": membership.external_id,
+            "avatarColor": self.user_avatar_color,
+            "groups": [],
+            "status": membership.status,
+            "type": membership.atype,
+            "accessAll": membership.access_all != 0,
+            "twoFactorEnabled": self.two_factor_enabled != 0,
+            "resetPasswordEnrolled": false,
+            "hasMasterPassword": true,
+            "permissions": permissions_json(),
+            "ssoBound": false,
+            "managedByOrganization": false,
+            "claimedByOrganization": false,
+            "usesKeyConnector": false,
+            "accessSecretsManager": false,
+            "object": "organizationUserUserDetails",
+        });
+        if let Some(collections) = collections {
+            value["collections"] = json!(collections);
+        }
+        value
+    }
+
+    pub fn to_json_details(&self, collections: &[CollectionAccessData]) -> Value {
+        let mut value = self.to_json_user_details(Some(collections));
+        value["object"] = json!("organizationUserDetails");
+        value
+    }
+}
+
+// Request payloads
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationCreateData {
+    pub name: String,
+    pub billing_email: String,
+    pub collection_name: String,
+    pub key: String,
+    pub keys: Option<KeyData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationUpdateData {
+    pub name: String,
+    pub billing_email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteData {
+    pub emails: Vec<String>,
+    pub r#type: i32,
+    #[serde(default)]
+    pub collections: Option<Vec<CollectionAccessData>>,
+    #[serde(default)]
+    pub access_all: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptMemberData {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipUpdateData {
+    pub r#type: i32,
+    #[serde(default)]
+    pub collections: Option<Vec<CollectionAccessData>>,
+    #[serde(default)]
+    pub access_all: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmMemberData {
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberKeyData {
+    pub id: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkConfirmData {
+    pub keys: Vec<MemberKeyData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberIdsData {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionData {
+    pub name: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub users: Vec<CollectionAccessData>,
+}
@@ -35,7 +35,13 @@ pub struct Profile {
 }
 
 impl Profile {
-    pub fn from_user(user: User, two_factor_enabled: bool) -> Result<Self, AppError> {
+    /// `mail_enabled`: without outgoing mail an address cannot be verified, so it is
+    /// reported as verified to keep clients from prompting for it.
+    pub fn from_user(
+        user: User,
+        two_factor_enabled: bool,
+        mail_enabled: bool,
+    ) -> Result<Self, AppError> {
         let creation_date = chrono::DateTime::parse_from_rfc3339(&user.created_at)
             .map_err(|_| AppError::Internal)?
             .to_rfc3339_opts(SecondsFormat::Micros, true);
@@ -50,7 +56,7 @@ impl Profile {
             premium_from_organization: false,
             culture: "en-US".to_string(),
             force_password_reset: false,
-            email_verified: true,
+            email_verified: user.email_verified || !mail_enabled,
             two_factor_enabled,
             premium: true,
             uses_key_connector: false,
@@ -1,4 +1,6 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::webauthn::{RegisterPublicKeyCredential, StoredCredential};
 
 /// Two-factor authentication types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,6 +15,11 @@ pub enum TwoFactorType {
     OrganizationDuo = 6,
     Webauthn = 7,
     RecoveryCode = 8,
+
+    // Pending ceremony state, never reported as a provider (atype >= 1000).
+    WebauthnLoginChallenge = 1002,
+    WebauthnRegisterChallenge = 1003,
+    EmailVerificationChallenge = 1004,
 }
 
 impl TwoFactorType {
@@ -27,9 +34,24 @@ impl TwoFactorType {
             6 => Some(TwoFactorType::OrganizationDuo),
             7 => Some(TwoFactorType::Webauthn),
             8 => Some(TwoFactorType::RecoveryCode),
+            1002 => Some(TwoFactorType::WebauthnLoginChallenge),
+            1003 => Some(TwoFactorType::WebauthnRegisterChallenge),
+            1004 => Some(TwoFactorType::EmailVerificationChallenge),
             _ => None,
         }
     }
+
+    /// Name used in notification emails.
+    pub fn display_name(self) -> &'static str {
+        match self {
+            TwoFactorType::Authenticator => "an authenticator app",
+            TwoFactorType::Email => "email",
+            TwoFactorType::Duo | TwoFactorType::OrganizationDuo => "Duo",
+            TwoFactorType::YubiKey => "YubiKey OTP",
+            TwoFactorType::U2f | TwoFactorType::Webauthn => "security keys",
+            _ => "an unknown provider",
+        }
+    }
 }
 
 /// TwoFactor database model
@@ -122,3 +144,154 @@ pub struct DisableAuthenticatorData {
     #[serde(rename = "type")]
     pub r#type: i32,
 }
+
+/// One registered security key, stored as an element of the JSON array in the
+/// `Webauthn` twofactor row's `data`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebauthnRegistration {
+    pub id: i32,
+    pub name: String,
+    pub migrated: bool,
+    pub credential: StoredCredential,
+}
+
+impl WebauthnRegistration {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "id": self.id,
+            "migrated": self.migrated,
+        })
+    }
+}
+
+/// Clients send key slot ids either as numbers or as strings.
+fn deserialize_key_id<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .and_then(|n| i32::try_from(n).ok())
+            .ok_or_else(|| serde::de::Error::custom("invalid key id")),
+        serde_json::Value::String(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| serde::de::Error::custom("invalid key id")),
+        _ => Err(serde::de::Error::custom("invalid key id")),
+    }
+}
+
+/// POST /api/two-factor/webauthn - Register a security key
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnableWebauthnData {
+    #[serde(deserialize_with = "deserialize_key_id")]
+    pub id: i32,
+    pub name: String,
+    pub device_response: RegisterPublicKeyCredential,
+    pub master_password_hash: Option<String>,
+    pub otp: Option<String>,
+}
+
+/// DELETE /api/two-factor/webauthn - Remove a security key
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteWebauthnData {
+    #[serde(deserialize_with = "deserialize_key_id")]
+    pub id: i32,
+    pub master_password_hash: Option<String>,
+    pub otp: Option<String>,
+}
+
+/// State of the email provider, stored as JSON in the `Email` twofactor row's
+/// `data` (and in the pending `EmailVerificationChallenge` row during setup).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailTokenData {
+    pub email: String,
+    pub last_token: Option<String>,
+    /// Unix timestamp at which `last_token` was issued.
+    pub token_sent: i64,
+    /// Failed guesses against `last_token`.
+    pub attempts: u32,
+}
+
+impl EmailTokenData {
+    pub fn new(email: String, token: String, now: i64) -> Self {
+        Self {
+            email,
+            last_token: Some(token),
+            token_sent: now,
+            attempts: 0,
+        }
+    }
+
+    pub fn set_token(&mut self, token: String, now: i64) {
+        self.last_token = Some(token);
+        self.token_sent = now;
+        self.attempts = 0;
+    }
+
+    pub fn reset_token(&mut self) {
+        self.last_token = None;
+        self.attempts = 0;
+    }
+
+    pub fn is_expired(&self, now: i64, ttl_secs: i64) -> bool {
+        now - self.token_sent > ttl_secs
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, crate::error::AppError> {
+        serde_json::from_str(data).map_err(|_| crate::error::AppError::Internal)
+    }
+
+    pub fn to_json(&self) -> Result<String, crate::error::AppError> {
+        serde_json::to_string(self).map_err(|_| crate::error::AppError::Internal)
+    }
+}
+
+/// Hide most of the local part of an address, e.g. `jo*****@example.com`.
+pub fn obscure_email(email: &str) -> String {
+    let Some((name, domain)) = email.split_once('@') else {
+        return email.to_string();
+    };
+    let visible = match name.chars().count() {
+        0..=2 => 0,
+        3..=4 => 1,
+        _ => 2,
+    };
+    let shown: String = name.chars().take(visible).collect();
+    let hidden = "*".repeat(name.chars().count() - visible);
+    format!("{shown}{hidden}@{domain}")
+}
+
+/// POST /api/two-factor/send-email - Send a setup code to an address
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendEmailData {
+    pub email: String,
+    pub master_password_hash: Option<String>,
+    pub otp: Option<String>,
+}
+
+/// POST /api/two-factor/email - Activate the email provider
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnableEmailData {
+    pub email: String,
+    pub token: String,
+    pub master_password_hash: Option<String>,
+    pub otp: Option<String>,
+}
+
+/// POST /api/two-factor/send-email-login - Send a login code during the 2FA step
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendEmailLoginData {
+    pub email: String,
+    pub master_password_hash: Option<String>,
+    pub device_identifier: Option<String>,
+    pub auth_request_id: Option<String>,
+    pub auth_request_access_code: Option<String>,
+}
@@ -9,6 +9,10 @@ fn default_json_array_string() -> String {
     "[]".to_string()
 }
 
+fn default_enabled() -> bool {
+    true
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
@@ -36,6 +40,12 @@ pub struct User {
     #[serde(default = "default_json_array_string")]
     pub excluded_globals: String,
     pub totp_recover: Option<String>, // Recovery code for 2FA
+    #[serde(default)]
+    pub last_verifying_at: Option<String>, // When the last verification link was sent
+    #[serde(default)]
+    pub api_key: Option<String>, // Personal API key for the client_credentials grant
+    #[serde(with = "bool_from_int", default = "default_enabled")]
+    pub enabled: bool, // False when disabled by the operator
     pub created_at: String,
     pub updated_at: String,
 }
@@ -61,6 +71,17 @@ impl PasswordVerification {
 }
 
 impl User {
+    pub async fn find_by_id(db: &crate::db::Db, id: &str) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(db, "SELECT * FROM users WHERE id = ?1", id)
+            .map_err(|_| AppError::Database)?
+            .first(None)
+            .await
+            .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
     pub async fn find_by_email(db: &crate::db::Db, email: &str) -> Result<Option<Self>, AppError> {
         let row: Option<Value> = d1_query!(db, "SELECT * FROM users WHERE email = ?1", email)
             .map_err(|_| AppError::Database)?
@@ -155,6 +176,23 @@ pub struct RegisterRequest {
     pub kdf_iterations: i32,
     pub kdf_memory: Option<i32>, // Argon2 memory parameter (15-1024 MB)
     pub kdf_parallelism: Option<i32>, // Argon2 parallelism parameter (1-16)
+    /// Token from the signup link (`register/finish` only).
+    pub email_verification_token: Option<String>,
+}
+
+// For POST /identity/accounts/register/send-verification-email request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendVerificationEmailRequest {
+    pub email: String,
+}
+
+// For POST /api/accounts/verify-email-token request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyEmailTokenRequest {
+    pub user_id: String,
+    pub token: String,
 }
 
 // For POST /accounts/password-hint request
@@ -250,6 +288,10 @@ pub struct RotateKeyRequest {
 #[serde(rename_all = "camelCase")]
 pub struct RotateAccountUnlockData {
     pub master_password_unlock_data: MasterPasswordUnlockData,
+    #[serde(default)]
+    pub emergency_access_unlock_data: Vec<crate::models::emergency_access::EmergencyAccessKeyData>,
+    #[serde(default)]
+    pub passkey_unlock_data: Vec<crate::models::webauthn_credential::WebAuthnRotateKeyData>,
 }
 
 #[derive(Debug, Deserialize)]
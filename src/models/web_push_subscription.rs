@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::d1_query;
+use crate::{db, error::AppError};
+
+/// A browser's Web Push subscription (`PushSubscription.toJSON()`), registered by a browser
+/// extension or the web vault for its device. Keys are base64url as the browser gives them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebPushSubscription {
+    pub device_identifier: String,
+    pub user_id: String,
+    pub endpoint: String,
+    /// The browser's P-256 public key, uncompressed.
+    pub p256dh: String,
+    /// The 16-byte authentication secret.
+    pub auth: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl WebPushSubscription {
+    pub fn new(
+        device_identifier: String,
+        user_id: String,
+        endpoint: String,
+        p256dh: String,
+        auth: String,
+    ) -> Self {
+        let now = db::now_string();
+        Self {
+            device_identifier,
+            user_id,
+            endpoint,
+            p256dh,
+            auth,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    /// Store the subscription, replacing the device's previous one.
+    pub async fn save(&self, db: &db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO web_push_subscriptions
+                (device_identifier, user_id, endpoint, p256dh, auth, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(device_identifier, user_id) DO UPDATE SET
+                endpoint = excluded.endpoint, p256dh = excluded.p256dh, auth = excluded.auth,
+                updated_at = excluded.updated_at",
+            &self.device_identifier,
+            &self.user_id,
+            &self.endpoint,
+            &self.p256dh,
+            &self.auth,
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+
+    pub async fn list_by_user(db: &db::Db, user_id: &str) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT * FROM web_push_subscriptions WHERE user_id = ?1",
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    pub async fn delete(
+        db: &db::Db,
+        device_identifier: &str,
+        user_id: &str,
+    ) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "DELETE FROM web_push_subscriptions WHERE device_identifier = ?1 AND user_id = ?2",
+            device_identifier,
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+
+    /// Drop a subscription the push service reported as gone, unless the device has
+    /// registered a new endpoint in the meantime.
+    pub async fn delete_expired(&self, db: &db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "DELETE FROM web_push_subscriptions
+             WHERE device_identifier = ?1 AND user_id = ?2 AND endpoint = ?3",
+            &self.device_identifier,
+            &self.user_id,
+            &self.endpoint
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+        Ok(())
+    }
+}
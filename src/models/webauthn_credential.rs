@@ -0,0 +1,308 @@
+use crate::db::Statement;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::d1_query;
+use crate::webauthn::{PublicKeyCredential, RegisterPublicKeyCredential, StoredCredential};
+use crate::{db, error::AppError};
+
+/// Maximum number of passkeys a user can register for login.
+pub const MAX_WEBAUTHN_LOGIN_CREDENTIALS: i64 = 5;
+
+/// Whether a passkey can decrypt the vault through the PRF extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum WebAuthnPrfStatus {
+    Enabled = 0,
+    Supported = 1,
+    Unsupported = 2,
+}
+
+/// A passkey registered for login.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebAuthnCredential {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub credential_id: String,
+    pub public_key: String,
+    pub counter: i64,
+    pub supports_prf: i32,
+    pub encrypted_user_key: Option<String>,
+    pub encrypted_public_key: Option<String>,
+    pub encrypted_private_key: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl WebAuthnCredential {
+    pub fn new(
+        user_id: String,
+        name: String,
+        credential: StoredCredential,
+        supports_prf: bool,
+        keys: WebAuthnKeySet,
+    ) -> Self {
+        let now = db::now_string();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            name,
+            credential_id: credential.cred_id,
+            public_key: credential.public_key,
+            counter: i64::from(credential.counter),
+            supports_prf: i32::from(supports_prf),
+            encrypted_user_key: keys.encrypted_user_key,
+            encrypted_public_key: keys.encrypted_public_key,
+            encrypted_private_key: keys.encrypted_private_key,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    pub fn prf_status(&self) -> WebAuthnPrfStatus {
+        if self.supports_prf == 0 {
+            WebAuthnPrfStatus::Unsupported
+        } else if self.encrypted_user_key.is_some() && self.encrypted_private_key.is_some() {
+            WebAuthnPrfStatus::Enabled
+        } else {
+            WebAuthnPrfStatus::Supported
+        }
+    }
+
+    pub fn stored_credential(&self) -> StoredCredential {
+        StoredCredential {
+            cred_id: self.credential_id.clone(),
+            public_key: self.public_key.clone(),
+            counter: u32::try_from(self.counter).unwrap_or(u32::MAX),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "prfStatus": self.prf_status() as i32,
+            "encryptedUserKey": self.encrypted_user_key,
+            "encryptedPublicKey": self.encrypted_public_key,
+            "object": "webauthnCredential",
+        })
+    }
+
+    /// `UserDecryptionOptions.WebAuthnPrfOption` for a login with this passkey,
+    /// present only once the PRF key set has been stored.
+    pub fn to_prf_decryption_option(&self) -> Option<Value> {
+        if self.prf_status() != WebAuthnPrfStatus::Enabled {
+            return None;
+        }
+        Some(json!({
+            "EncryptedPrivateKey": self.encrypted_private_key,
+            "EncryptedUserKey": self.encrypted_user_key,
+        }))
+    }
+
+    pub async fn insert(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "INSERT INTO webauthn_credentials (id, user_id, name, credential_id, public_key, counter, supports_prf, encrypted_user_key, encrypted_public_key, encrypted_private_key, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            &self.id,
+            &self.user_id,
+            &self.name,
+            &self.credential_id,
+            &self.public_key,
+            self.counter,
+            self.supports_prf,
+            self.encrypted_user_key.as_deref(),
+            self.encrypted_public_key.as_deref(),
+            self.encrypted_private_key.as_deref(),
+            &self.created_at,
+            &self.updated_at
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Persist the signature counter and PRF key set.
+    pub async fn update(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "UPDATE webauthn_credentials
+             SET counter = ?1, encrypted_user_key = ?2, encrypted_public_key = ?3, encrypted_private_key = ?4, updated_at = ?5
+             WHERE id = ?6",
+            self.counter,
+            self.encrypted_user_key.as_deref(),
+            self.encrypted_public_key.as_deref(),
+            self.encrypted_private_key.as_deref(),
+            &self.updated_at,
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &crate::db::Db) -> Result<(), AppError> {
+        d1_query!(
+            db,
+            "DELETE FROM webauthn_credentials WHERE id = ?1",
+            &self.id
+        )
+        .map_err(|_| AppError::Database)?
+        .run()
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id_and_user(
+        db: &crate::db::Db,
+        id: &str,
+        user_id: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT * FROM webauthn_credentials WHERE id = ?1 AND user_id = ?2",
+            id,
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    /// Look up a passkey by its base64url credential id.
+    pub async fn find_by_credential_id(
+        db: &crate::db::Db,
+        credential_id: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let row: Option<Value> = d1_query!(
+            db,
+            "SELECT * FROM webauthn_credentials WHERE credential_id = ?1",
+            credential_id
+        )
+        .map_err(|_| AppError::Database)?
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+
+        row.map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .transpose()
+    }
+
+    pub async fn list_by_user(db: &crate::db::Db, user_id: &str) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<Value> = d1_query!(
+            db,
+            "SELECT * FROM webauthn_credentials WHERE user_id = ?1 ORDER BY created_at",
+            user_id
+        )
+        .map_err(|_| AppError::Database)?
+        .all()
+        .await
+        .map_err(|_| AppError::Database)?
+        .results()
+        .map_err(|_| AppError::Database)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).map_err(|_| AppError::Internal))
+            .collect()
+    }
+
+    /// Build statements re-wrapping the PRF key sets after a user key rotation.
+    /// Credentials not listed keep their old (now useless) keys, so they are cleared.
+    pub fn rotate_keys_statements(
+        db: &crate::db::Db,
+        user_id: &str,
+        keys: &[WebAuthnRotateKeyData],
+        now: &str,
+    ) -> Result<Vec<Statement>, AppError> {
+        let ids: Vec<&str> = keys.iter().map(|k| k.id.as_str()).collect();
+        let ids_json = serde_json::to_string(&ids).map_err(|_| AppError::Internal)?;
+
+        let mut statements = Vec::with_capacity(keys.len() + 1);
+        statements.push(
+            d1_query!(
+                db,
+                "UPDATE webauthn_credentials
+                 SET encrypted_user_key = NULL, encrypted_public_key = NULL, encrypted_private_key = NULL, updated_at = ?1
+                 WHERE user_id = ?2 AND id NOT IN (SELECT value FROM json_each(?3))",
+                now,
+                user_id,
+                &ids_json
+            )
+            .map_err(|_| AppError::Database)?,
+        );
+        for key in keys {
+            statements.push(
+                d1_query!(
+                    db,
+                    "UPDATE webauthn_credentials
+                     SET encrypted_user_key = ?1, encrypted_public_key = ?2, updated_at = ?3
+                     WHERE id = ?4 AND user_id = ?5 AND supports_prf = 1",
+                    &key.encrypted_user_key,
+                    &key.encrypted_public_key,
+                    now,
+                    &key.id,
+                    user_id
+                )
+                .map_err(|_| AppError::Database)?,
+            );
+        }
+        Ok(statements)
+    }
+}
+
+/// The PRF key set sent by clients.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnKeySet {
+    pub encrypted_user_key: Option<String>,
+    pub encrypted_public_key: Option<String>,
+    pub encrypted_private_key: Option<String>,
+}
+
+/// POST /api/webauthn - Register a passkey for login
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnCredentialCreateData {
+    pub device_response: RegisterPublicKeyCredential,
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub supports_prf: bool,
+    #[serde(flatten)]
+    pub keys: WebAuthnKeySet,
+}
+
+/// PUT /api/webauthn - Store the PRF key set of an existing passkey
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnCredentialUpdateData {
+    pub device_response: PublicKeyCredential,
+    pub token: String,
+    #[serde(flatten)]
+    pub keys: WebAuthnKeySet,
+}
+
+/// Re-wrapped PRF keys sent during user key rotation.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnRotateKeyData {
+    pub id: String,
+    pub encrypted_user_key: String,
+    pub encrypted_public_key: String,
+}
@@ -4,8 +4,11 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use log::warn;
 use rmpv::Value;
 use serde::{Deserialize, Serialize};
-use worker::{wasm_bindgen::JsValue, Env, Method, Request, RequestInit};
+use worker::{wasm_bindgen::JsValue, Method, Request, RequestInit};
 
+use crate::env::Env;
+use crate::error::AppError;
+use crate::outbox::{self, OutboxJob};
 use crate::push;
 
 const INTERNAL_FANOUT_URL: &str = "https://notify.internal/fanout";
@@ -13,6 +16,8 @@ pub const RECORD_SEPARATOR: u8 = 0x1e;
 pub const INITIAL_RESPONSE: [u8; 3] = [b'{', b'}', RECORD_SEPARATOR];
 pub const USER_KIND_TAG: &str = "k:user";
 pub const ANONYMOUS_KIND_TAG: &str = "k:anon";
+/// Set by `entry.js` on hub requests to the shard name it routed them by.
+pub const SHARD_HEADER: &str = "X-Notify-Shard";
 
 // ── UpdateType ──────────────────────────────────────────────────────
 
@@ -38,6 +43,20 @@ pub enum UpdateType {
     None = 100,
 }
 
+impl UpdateType {
+    /// Whether a device that missed the update should get it when it reconnects. Log-outs and
+    /// login requests are stale by then; the rest only ask the client to refetch something.
+    pub fn is_replayable(self) -> bool {
+        !matches!(
+            self,
+            UpdateType::LogOut
+                | UpdateType::AuthRequest
+                | UpdateType::AuthRequestResponse
+                | UpdateType::None
+        )
+    }
+}
+
 // ── Connection model ────────────────────────────────────────────────
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -56,10 +75,18 @@ pub struct ConnectionAttachment {
     pub device_id: Option<String>,
     pub protocol_initialized: bool,
     pub connected_at: String,
+    /// The shard the socket was accepted on. Sockets from before sharding have none.
+    #[serde(default)]
+    pub shard: Option<String>,
+    /// Latest replay sequence when the client last sent a message. The next message from the
+    /// client confirms that everything up to it arrived.
+    #[serde(default)]
+    pub pending_seq: Option<i64>,
 }
 
 impl ConnectionAttachment {
     pub fn user(user_id: String, device_id: Option<String>, connected_at: String) -> Self {
+        let shard = PublishSelector::user(user_id.as_str()).shard_name();
         Self {
             kind: ConnectionKind::User,
             user_id: Some(user_id),
@@ -67,10 +94,13 @@ impl ConnectionAttachment {
             device_id,
             protocol_initialized: false,
             connected_at,
+            shard: Some(shard),
+            pending_seq: None,
         }
     }
 
     pub fn anonymous(token: String, connected_at: String) -> Self {
+        let shard = PublishSelector::anonymous(token.as_str()).shard_name();
         Self {
             kind: ConnectionKind::Anonymous,
             user_id: None,
@@ -78,6 +108,8 @@ impl ConnectionAttachment {
             device_id: None,
             protocol_initialized: false,
             connected_at,
+            shard: Some(shard),
+            pending_seq: None,
         }
     }
 
@@ -123,6 +155,15 @@ impl PublishSelector {
             PublishSelector::ByAnonymousToken { token } => anonymous_tag(token),
         }
     }
+
+    /// Name of the `NOTIFY_DO` instance holding every socket this selector matches.
+    /// `entry.js` derives the same name when it routes hub connections.
+    pub fn shard_name(&self) -> String {
+        match self {
+            PublishSelector::ByUser { user_id } => format!("user:{user_id}"),
+            PublishSelector::ByAnonymousToken { token } => format!("anon:{token}"),
+        }
+    }
 }
 
 // ── Tag helpers ─────────────────────────────────────────────────────
@@ -161,78 +202,124 @@ pub fn create_ping() -> Vec<u8> {
     serialize(&Value::Array(vec![6.into()]))
 }
 
+// ── Replay ──────────────────────────────────────────────────────────
+
+/// Number of recent updates each user's NotifyDo keeps for devices that reconnect.
+pub const REPLAY_BUFFER_SIZE: i64 = 100;
+
+/// What a reconnecting device is sent after the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// The device has seen everything.
+    UpToDate,
+    /// Every buffered update after this sequence.
+    After(i64),
+    /// Updates the device missed were already dropped from the buffer, so it has to sync.
+    SyncVault,
+}
+
+/// Decide the replay for a device whose last confirmed sequence is `last_seen`, given the
+/// oldest and latest sequences still buffered.
+pub fn plan_replay(last_seen: i64, oldest: Option<i64>, latest: Option<i64>) -> Replay {
+    match (oldest, latest) {
+        (Some(oldest), Some(latest)) if last_seen < latest => {
+            if last_seen + 1 < oldest {
+                Replay::SyncVault
+            } else {
+                Replay::After(last_seen)
+            }
+        }
+        _ => Replay::UpToDate,
+    }
+}
+
+pub fn create_sync_vault(user_id: &str) -> Vec<u8> {
+    create_update(
+        vec![
+            ("UserId".into(), user_id.into()),
+            ("Date".into(), serialize_date(Utc::now().naive_utc())),
+        ],
+        UpdateType::SyncVault as i32,
+        None,
+    )
+}
+
 // ── DO fan-out protocol ─────────────────────────────────────────────
 
 #[derive(Serialize)]
 struct DoFanoutRequest<'a> {
     selector: &'a PublishSelector,
-    message: String,
+    message: &'a str,
+    replay: bool,
 }
 
-async fn send_ws_to_do(env: &Env, selector: &PublishSelector, ws_bytes: &[u8]) {
-    use base64::{engine::general_purpose::STANDARD, Engine};
-
+/// Hand `message` (base64 MessagePack) to the selector's NotifyDo. With `replay`, the instance
+/// also keeps the message for devices that are offline right now. Without a `NOTIFY_DO` binding
+/// there is nothing to deliver to, which is not an error.
+pub async fn send_ws_to_do(
+    env: &Env,
+    selector: &PublishSelector,
+    message: &str,
+    replay: bool,
+) -> Result<(), AppError> {
     let selector_tag = selector.tag();
     let namespace = match env.durable_object("NOTIFY_DO") {
         Ok(namespace) => namespace,
         Err(error) => {
             warn!("Skipping ws notification for {selector_tag}: NOTIFY_DO lookup failed: {error}");
-            return;
-        }
-    };
-    let stub = match namespace.get_by_name("global") {
-        Ok(stub) => stub,
-        Err(error) => {
-            warn!("Skipping ws notification for {selector_tag}: DO stub lookup failed: {error}");
-            return;
+            return Ok(());
         }
     };
+    let stub = namespace
+        .get_by_name(&selector.shard_name())
+        .map_err(AppError::Worker)?;
 
-    let body = match serde_json::to_string(&DoFanoutRequest {
+    let body = serde_json::to_string(&DoFanoutRequest {
         selector,
-        message: STANDARD.encode(ws_bytes),
-    }) {
-        Ok(body) => body,
-        Err(error) => {
-            warn!("Skipping ws notification for {selector_tag}: payload encode failed: {error}");
-            return;
-        }
-    };
+        message,
+        replay,
+    })
+    .map_err(|_| AppError::Internal)?;
 
     let mut init = RequestInit::new();
     init.with_method(Method::Post)
         .with_body(Some(JsValue::from_str(&body)));
-
-    let mut request = match Request::new_with_init(INTERNAL_FANOUT_URL, &init) {
-        Ok(request) => request,
-        Err(error) => {
-            warn!("Skipping ws notification for {selector_tag}: request creation failed: {error}");
-            return;
-        }
-    };
-    let headers = match request.headers_mut() {
-        Ok(headers) => headers,
-        Err(error) => {
-            warn!("Skipping ws notification for {selector_tag}: headers init failed: {error}");
-            return;
-        }
-    };
-    if let Err(error) = headers.set("Content-Type", "application/json") {
-        warn!("Skipping ws notification for {selector_tag}: header set failed: {error}");
-        return;
-    }
-
-    let mut response = match stub.fetch_with_request(request).await {
-        Ok(response) => response,
-        Err(error) => {
-            warn!("Skipping ws notification for {selector_tag}: DO fanout failed: {error}");
-            return;
-        }
-    };
+    let mut request =
+        Request::new_with_init(INTERNAL_FANOUT_URL, &init).map_err(AppError::Worker)?;
+    request
+        .headers_mut()
+        .map_err(AppError::Worker)?
+        .set("Content-Type", "application/json")
+        .map_err(AppError::Worker)?;
+
+    let mut response = stub
+        .fetch_with_request(request)
+        .await
+        .map_err(AppError::Worker)?;
     if !(200..300).contains(&response.status_code()) {
         let status = response.status_code();
         let body = response.text().await.unwrap_or_else(|_| String::new());
-        warn!("Skipping ws notification for {selector_tag}: NotifyDo fanout failed with status {status}: {body}");
+        return Err(AppError::ServiceUnavailable(format!(
+            "NotifyDo fanout for {selector_tag} failed with status {status}: {body}"
+        )));
+    }
+    Ok(())
+}
+
+/// Fan `ws_bytes` out now. If NotifyDo cannot be reached, the message goes to the outbox and
+/// the cron job retries it.
+async fn publish_ws(env: &Env, selector: &PublishSelector, ws_bytes: &[u8], replay: bool) {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let message = STANDARD.encode(ws_bytes);
+    if let Err(error) = send_ws_to_do(env, selector, &message, replay).await {
+        warn!("ws notification for {} failed: {error}", selector.tag());
+        let job = OutboxJob::Fanout {
+            selector: selector.clone(),
+            message,
+            replay,
+        };
+        outbox::enqueue_failed(env, job, &error).await;
     }
 }
 
@@ -256,7 +343,7 @@ pub fn publish_user_update(
         );
         let selector = PublishSelector::user(&user_id);
         futures_util::join!(
-            send_ws_to_do(&env, &selector, &ws_bytes),
+            publish_ws(&env, &selector, &ws_bytes, update_type.is_replayable()),
             push::push_user_update(
                 &env,
                 &user_id,
@@ -295,7 +382,7 @@ pub fn publish_folder_update(
         );
         let selector = PublishSelector::user(&user_id);
         futures_util::join!(
-            send_ws_to_do(&env, &selector, &ws_bytes),
+            publish_ws(&env, &selector, &ws_bytes, update_type.is_replayable()),
             push::push_folder_update(
                 &env,
                 &user_id,
@@ -320,8 +407,8 @@ pub fn publish_cipher_update(
         let ws_bytes = create_update(
             vec![
                 ("Id".into(), cipher_id.as_str().into()),
-                // Org feature is not supported,
-                // so we simply set all related parameters to null.
+                // Clients refetch the cipher by id, which returns the organization
+                // and collection fields, so they are left null here.
                 ("UserId".into(), user_id.as_str().into()),
                 ("OrganizationId".into(), Value::Nil),
                 ("CollectionIds".into(), Value::Nil),
@@ -335,7 +422,7 @@ pub fn publish_cipher_update(
         );
         let selector = PublishSelector::user(&user_id);
         futures_util::join!(
-            send_ws_to_do(&env, &selector, &ws_bytes),
+            publish_ws(&env, &selector, &ws_bytes, update_type.is_replayable()),
             push::push_cipher_update(
                 &env,
                 &user_id,
@@ -371,7 +458,7 @@ pub fn publish_send_update(
         );
         let selector = PublishSelector::user(&user_id);
         futures_util::join!(
-            send_ws_to_do(&env, &selector, &ws_bytes),
+            publish_ws(&env, &selector, &ws_bytes, update_type.is_replayable()),
             push::push_send_update(
                 &env,
                 &user_id,
@@ -402,7 +489,7 @@ pub fn publish_auth_update(
         );
         let selector = PublishSelector::user(&user_id);
         futures_util::join!(
-            send_ws_to_do(&env, &selector, &ws_bytes),
+            publish_ws(&env, &selector, &ws_bytes, update_type.is_replayable()),
             push::push_auth_update(
                 &env,
                 &user_id,
@@ -425,7 +512,7 @@ pub fn publish_anonymous_update(env: Env, token: String, user_id: String, auth_r
             &user_id,
         );
         let selector = PublishSelector::anonymous(&token);
-        send_ws_to_do(&env, &selector, &ws_bytes).await;
+        publish_ws(&env, &selector, &ws_bytes, false).await;
     });
 }
 
@@ -530,3 +617,51 @@ fn parse_timestamp(date: &str) -> NaiveDateTime {
             Utc::now().naive_utc()
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_accepted_on_the_shard_their_selector_publishes_to() {
+        let user = ConnectionAttachment::user("u1".into(), None, String::new());
+        let selector = PublishSelector::user("u1");
+        assert!(user.matches_selector(&selector));
+        assert_eq!(user.shard, Some(selector.shard_name()));
+
+        let anonymous = ConnectionAttachment::anonymous("t1".into(), String::new());
+        let selector = PublishSelector::anonymous("t1");
+        assert!(anonymous.matches_selector(&selector));
+        assert_eq!(anonymous.shard, Some(selector.shard_name()));
+        assert_ne!(
+            PublishSelector::user("t1").shard_name(),
+            selector.shard_name()
+        );
+    }
+
+    #[test]
+    fn attachments_from_before_sharding_have_no_shard() {
+        let legacy = r#"{"kind":"user","userId":"u1","token":null,"deviceId":"d1",
+            "protocolInitialized":true,"connectedAt":"2026-01-01T00:00:00.000Z"}"#;
+        let attachment: ConnectionAttachment = serde_json::from_str(legacy).unwrap();
+        assert_eq!(attachment.shard, None);
+        assert_eq!(attachment.pending_seq, None);
+    }
+
+    #[test]
+    fn replay_resumes_after_the_last_seen_update_or_falls_back_to_sync_vault() {
+        assert_eq!(plan_replay(0, None, None), Replay::UpToDate);
+        assert_eq!(plan_replay(7, Some(1), Some(7)), Replay::UpToDate);
+        assert_eq!(plan_replay(3, Some(1), Some(7)), Replay::After(3));
+        assert_eq!(plan_replay(3, Some(4), Some(7)), Replay::After(3));
+        assert_eq!(plan_replay(3, Some(5), Some(7)), Replay::SyncVault);
+    }
+
+    #[test]
+    fn only_refetch_updates_are_replayed() {
+        assert!(UpdateType::SyncCipherUpdate.is_replayable());
+        assert!(UpdateType::SyncSendDelete.is_replayable());
+        assert!(!UpdateType::LogOut.is_replayable());
+        assert!(!UpdateType::AuthRequest.is_replayable());
+    }
+}
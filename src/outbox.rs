@@ -0,0 +1,623 @@
+//! Durable outbox for WebSocket fan-out and push deliveries.
+//!
+//! Deliveries are attempted straight away from `waitUntil`. One that fails is written to the
+//! `outbox` D1 table, and the `scheduled` handler retries it with exponential backoff, starting
+//! at five minutes. After `OUTBOX_MAX_ATTEMPTS` attempts (default 8) the job is kept as a dead
+//! letter, visible through `GET /admin/outbox`, and deleted a week later. A job the receiver
+//! rejects outright (see [`is_permanent`]) becomes a dead letter at once.
+//!
+//! A cron run claims the jobs it retries by pushing their next attempt out by [`CLAIM_LEASE`],
+//! so overlapping runs never deliver the same job twice. If a run dies mid-way, its jobs are
+//! due again once the lease runs out.
+
+use chrono::{DateTime, Duration, Utc};
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::d1_query;
+use crate::db::{self, Db};
+use crate::env::Env;
+use crate::error::AppError;
+use crate::notifications::{self, PublishSelector};
+use crate::push::{self, PushNotification};
+
+/// Cron expression that only retries the outbox; any other trigger also runs the daily jobs.
+pub const RETRY_CRON: &str = "*/5 * * * *";
+
+const BASE_DELAY_SECS: i64 = 5 * 60;
+const MAX_DELAY_SECS: i64 = 12 * 60 * 60;
+/// Jobs retried per cron run, to stay well inside its time budget.
+const RETRY_BATCH: u32 = 50;
+const DEAD_LETTER_RETENTION_DAYS: i64 = 7;
+/// How long a claimed job is hidden from other runs; longer than a cron run can last.
+const CLAIM_LEASE: Duration = Duration::minutes(15);
+
+/// A delivery the outbox can retry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum OutboxJob {
+    /// A message for a NotifyDo instance, base64 MessagePack as `send_ws_to_do` takes it.
+    Fanout {
+        selector: PublishSelector,
+        message: String,
+        replay: bool,
+    },
+    /// A notification for one push backend, by name.
+    Push {
+        backend: String,
+        notification: PushNotification,
+    },
+}
+
+impl OutboxJob {
+    fn kind(&self) -> &'static str {
+        match self {
+            OutboxJob::Fanout { .. } => "fanout",
+            OutboxJob::Push { .. } => "push",
+        }
+    }
+
+    /// Where the job goes, for the admin overview.
+    fn target(&self) -> String {
+        match self {
+            OutboxJob::Fanout { selector, .. } => selector.tag(),
+            OutboxJob::Push { backend, .. } => backend.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub job: OutboxJob,
+    /// Attempts made so far, the first inline one included.
+    pub attempts: u32,
+}
+
+/// What happens to a job after a failed attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NextAttempt {
+    At(DateTime<Utc>),
+    DeadLetter,
+}
+
+/// Delay after the `attempts`-th failure: five minutes, doubling up to twelve hours.
+pub fn retry_delay(attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(16);
+    Duration::seconds((BASE_DELAY_SECS << doublings).min(MAX_DELAY_SECS))
+}
+
+/// Whether `error` means the receiver rejected the job, so retrying cannot help. Backends
+/// report such answers (a 4xx other than 408 or 429) as [`AppError::BadRequest`].
+pub fn is_permanent(error: &AppError) -> bool {
+    matches!(error, AppError::BadRequest(_))
+}
+
+pub fn next_attempt(attempts: u32, max_attempts: u32, now: DateTime<Utc>) -> NextAttempt {
+    if attempts >= max_attempts {
+        NextAttempt::DeadLetter
+    } else {
+        NextAttempt::At(now + retry_delay(attempts))
+    }
+}
+
+/// Where queued jobs live: D1 in the Worker, memory in tests.
+pub trait OutboxStore {
+    /// Insert or update `entry` after a failed attempt.
+    fn record_failure<'a>(
+        &'a self,
+        entry: &'a OutboxEntry,
+        next: &'a NextAttempt,
+        error: &'a str,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>>;
+
+    /// Claim up to `limit` pending jobs whose next attempt is due at `now`, oldest first. A
+    /// claimed job is not handed out again until `now + CLAIM_LEASE`.
+    fn claim_due<'a>(
+        &'a self,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> LocalBoxFuture<'a, Result<Vec<OutboxEntry>, AppError>>;
+
+    fn remove<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+/// The `outbox` table.
+pub struct D1Outbox {
+    db: Db,
+}
+
+/// Row shape of `outbox`, as D1 returns it.
+#[derive(Deserialize)]
+struct OutboxRow {
+    id: String,
+    payload: String,
+    attempts: u32,
+}
+
+impl OutboxStore for D1Outbox {
+    fn record_failure<'a>(
+        &'a self,
+        entry: &'a OutboxEntry,
+        next: &'a NextAttempt,
+        error: &'a str,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let payload = serde_json::to_string(&entry.job).map_err(|_| AppError::Internal)?;
+            let now = db::now_string();
+            let (status, next_attempt_at) = match next {
+                NextAttempt::At(at) => ("pending", timestamp(*at)),
+                NextAttempt::DeadLetter => ("dead", now.clone()),
+            };
+            d1_query!(
+                &self.db,
+                "INSERT INTO outbox
+                    (id, kind, target, payload, status, attempts, next_attempt_at, last_error,
+                     created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)
+                 ON CONFLICT(id) DO UPDATE SET
+                    status = excluded.status, attempts = excluded.attempts,
+                    next_attempt_at = excluded.next_attempt_at,
+                    last_error = excluded.last_error, updated_at = excluded.updated_at",
+                &entry.id,
+                entry.job.kind(),
+                entry.job.target(),
+                payload,
+                status,
+                entry.attempts,
+                next_attempt_at,
+                error,
+                now
+            )
+            .map_err(|_| AppError::Database)?
+            .run()
+            .await
+            .map_err(|_| AppError::Database)?;
+            Ok(())
+        })
+    }
+
+    fn claim_due<'a>(
+        &'a self,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> LocalBoxFuture<'a, Result<Vec<OutboxEntry>, AppError>> {
+        Box::pin(async move {
+            // One statement, so two runs racing for the same rows cannot both win them.
+            let rows: Vec<OutboxRow> = d1_query!(
+                &self.db,
+                "UPDATE outbox SET next_attempt_at = ?2
+                 WHERE id IN (
+                    SELECT id FROM outbox
+                    WHERE status = 'pending' AND next_attempt_at <= ?1
+                    ORDER BY next_attempt_at LIMIT ?3
+                 ) AND status = 'pending' AND next_attempt_at <= ?1
+                 RETURNING id, payload, attempts",
+                timestamp(now),
+                timestamp(now + CLAIM_LEASE),
+                limit
+            )
+            .map_err(|_| AppError::Database)?
+            .all()
+            .await
+            .map_err(|_| AppError::Database)?
+            .results()
+            .map_err(|_| AppError::Database)?;
+
+            let mut entries = Vec::with_capacity(rows.len());
+            for row in rows {
+                match serde_json::from_str(&row.payload) {
+                    Ok(job) => entries.push(OutboxEntry {
+                        id: row.id,
+                        job,
+                        attempts: row.attempts,
+                    }),
+                    Err(e) => {
+                        log::error!("Dropping unreadable outbox job {}: {e}", row.id);
+                        self.remove(&row.id).await?;
+                    }
+                }
+            }
+            Ok(entries)
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            d1_query!(&self.db, "DELETE FROM outbox WHERE id = ?1", id)
+                .map_err(|_| AppError::Database)?
+                .run()
+                .await
+                .map_err(|_| AppError::Database)?;
+            Ok(())
+        })
+    }
+}
+
+impl D1Outbox {
+    pub fn new(env: &Env) -> Result<Self, AppError> {
+        Ok(Self {
+            db: db::get_db(env)?,
+        })
+    }
+}
+
+/// Keeps jobs in memory instead of D1.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryOutbox {
+    pub entries: std::cell::RefCell<Vec<(OutboxEntry, NextAttempt, String)>>,
+}
+
+#[cfg(test)]
+impl OutboxStore for MemoryOutbox {
+    fn record_failure<'a>(
+        &'a self,
+        entry: &'a OutboxEntry,
+        next: &'a NextAttempt,
+        error: &'a str,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|(e, _, _)| e.id != entry.id);
+        entries.push((entry.clone(), next.clone(), error.to_string()));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn claim_due<'a>(
+        &'a self,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> LocalBoxFuture<'a, Result<Vec<OutboxEntry>, AppError>> {
+        let due = self
+            .entries
+            .borrow_mut()
+            .iter_mut()
+            .filter(|(_, next, _)| matches!(next, NextAttempt::At(at) if *at <= now))
+            .take(limit as usize)
+            .map(|(entry, next, _)| {
+                *next = NextAttempt::At(now + CLAIM_LEASE);
+                entry.clone()
+            })
+            .collect();
+        Box::pin(async { Ok(due) })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        self.entries.borrow_mut().retain(|(e, _, _)| e.id != id);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+async fn max_attempts(env: &Env) -> u32 {
+    match Config::load(env).await {
+        Ok(config) => config.outbox_max_attempts,
+        Err(e) => {
+            log::warn!("Failed to load the configuration, using the default outbox attempts: {e}");
+            Config::default().outbox_max_attempts
+        }
+    }
+}
+
+/// Record a job whose first attempt failed. Failing to record it is only logged.
+pub async fn enqueue_failed(env: &Env, job: OutboxJob, error: &AppError) {
+    let entry = OutboxEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        job,
+        attempts: 1,
+    };
+    let next = if is_permanent(error) {
+        NextAttempt::DeadLetter
+    } else {
+        next_attempt(entry.attempts, max_attempts(env).await, Utc::now())
+    };
+    let result = match D1Outbox::new(env) {
+        Ok(store) => {
+            store
+                .record_failure(&entry, &next, &error.to_string())
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to queue {} job for retry: {e}", entry.job.kind());
+    }
+}
+
+/// Counts from one pass over the due jobs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    pub delivered: u32,
+    pub rescheduled: u32,
+    pub dead: u32,
+}
+
+/// Claim the due jobs and attempt each once through `deliver`, then drop, reschedule or bury it.
+pub async fn retry_due<'d, S, F>(
+    store: &S,
+    now: DateTime<Utc>,
+    max_attempts: u32,
+    deliver: F,
+) -> Result<RetryStats, AppError>
+where
+    S: OutboxStore + ?Sized,
+    F: Fn(OutboxJob) -> LocalBoxFuture<'d, Result<(), AppError>>,
+{
+    let mut stats = RetryStats::default();
+    for mut entry in store.claim_due(now, RETRY_BATCH).await? {
+        match deliver(entry.job.clone()).await {
+            Ok(()) => {
+                store.remove(&entry.id).await?;
+                stats.delivered += 1;
+            }
+            Err(e) => {
+                entry.attempts += 1;
+                let next = if is_permanent(&e) {
+                    NextAttempt::DeadLetter
+                } else {
+                    next_attempt(entry.attempts, max_attempts, now)
+                };
+                if next == NextAttempt::DeadLetter {
+                    log::warn!(
+                        "Outbox job {} ({} to {}) failed after {} attempts, giving up: {e}",
+                        entry.id,
+                        entry.job.kind(),
+                        entry.job.target(),
+                        entry.attempts
+                    );
+                    stats.dead += 1;
+                } else {
+                    stats.rescheduled += 1;
+                }
+                store.record_failure(&entry, &next, &e.to_string()).await?;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+async fn deliver(env: &Env, job: OutboxJob) -> Result<(), AppError> {
+    match job {
+        OutboxJob::Fanout {
+            selector,
+            message,
+            replay,
+        } => notifications::send_ws_to_do(env, &selector, &message, replay).await,
+        OutboxJob::Push {
+            backend,
+            notification,
+        } => push::send_with_backend(env, &backend, &notification).await,
+    }
+}
+
+/// Called from the `scheduled` handler: retry due jobs and delete old dead letters.
+pub async fn run_retries(env: &Env) -> Result<RetryStats, AppError> {
+    let store = D1Outbox::new(env)?;
+    let now = Utc::now();
+    let max_attempts = Config::load(env).await?.outbox_max_attempts;
+    let stats = retry_due(&store, now, max_attempts, |job| Box::pin(deliver(env, job))).await?;
+
+    let cutoff = timestamp(now - Duration::days(DEAD_LETTER_RETENTION_DAYS));
+    d1_query!(
+        &store.db,
+        "DELETE FROM outbox WHERE status = 'dead' AND updated_at < ?1",
+        cutoff
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    Ok(stats)
+}
+
+/// Dead letter as listed by `GET /admin/outbox`.
+#[derive(Deserialize)]
+struct DeadLetterRow {
+    id: String,
+    kind: String,
+    target: String,
+    attempts: u32,
+    last_error: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+/// Queue depth and the most recent dead letters, for the admin API.
+pub async fn status(env: &Env) -> Result<Value, AppError> {
+    let db = db::get_db(env)?;
+    let counts: Vec<Value> = d1_query!(
+        &db,
+        "SELECT status, COUNT(*) AS count, MIN(created_at) AS oldest FROM outbox GROUP BY status"
+    )
+    .all()
+    .await
+    .map_err(|_| AppError::Database)?
+    .results()
+    .map_err(|_| AppError::Database)?;
+    let count_of = |status: &str| {
+        counts
+            .iter()
+            .find(|row| row["status"] == status)
+            .cloned()
+            .unwrap_or_else(|| json!({ "count": 0, "oldest": null }))
+    };
+    let pending = count_of("pending");
+    let dead = count_of("dead");
+
+    let dead_letters: Vec<DeadLetterRow> = d1_query!(
+        &db,
+        "SELECT id, kind, target, attempts, last_error, created_at, updated_at FROM outbox
+         WHERE status = 'dead' ORDER BY updated_at DESC LIMIT 50"
+    )
+    .all()
+    .await
+    .map_err(|_| AppError::Database)?
+    .results()
+    .map_err(|_| AppError::Database)?;
+
+    Ok(json!({
+        "pending": pending["count"],
+        "oldestPending": pending["oldest"],
+        "dead": dead["count"],
+        "deadLetters": dead_letters
+            .into_iter()
+            .map(|row| json!({
+                "id": row.id,
+                "kind": row.kind,
+                "target": row.target,
+                "attempts": row.attempts,
+                "lastError": row.last_error,
+                "createdAt": row.created_at,
+                "updatedAt": row.updated_at,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::block_on;
+
+    fn push_job() -> OutboxJob {
+        OutboxJob::Push {
+            backend: "webhook".to_string(),
+            notification: PushNotification {
+                user_id: "user-1".to_string(),
+                update_type: 1,
+                payload: json!({ "id": "cipher-1" }),
+                context_id: None,
+            },
+        }
+    }
+
+    fn queue(store: &MemoryOutbox, now: DateTime<Utc>) {
+        let entry = OutboxEntry {
+            id: "job-1".to_string(),
+            job: push_job(),
+            attempts: 1,
+        };
+        let next = next_attempt(1, 3, now);
+        block_on(store.record_failure(&entry, &next, "relay down")).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay(1), Duration::minutes(5));
+        assert_eq!(retry_delay(2), Duration::minutes(10));
+        assert_eq!(retry_delay(4), Duration::minutes(40));
+        assert_eq!(retry_delay(9), Duration::hours(12));
+        assert_eq!(retry_delay(u32::MAX), Duration::hours(12));
+    }
+
+    #[test]
+    fn jobs_round_trip_through_json() {
+        let fanout = OutboxJob::Fanout {
+            selector: PublishSelector::user("user-1"),
+            message: "kwE=".to_string(),
+            replay: true,
+        };
+        for job in [fanout, push_job()] {
+            let stored = serde_json::to_string(&job).unwrap();
+            assert_eq!(serde_json::from_str::<OutboxJob>(&stored).unwrap(), job);
+        }
+    }
+
+    #[test]
+    fn retries_wait_for_the_backoff_and_stop_once_delivered() {
+        let store = MemoryOutbox::default();
+        let start = Utc::now();
+        queue(&store, start);
+        let calls = Cell::new(0);
+        let ok = |_: OutboxJob| -> LocalBoxFuture<'static, Result<(), AppError>> {
+            calls.set(calls.get() + 1);
+            Box::pin(async { Ok(()) })
+        };
+
+        let early = block_on(retry_due(&store, start, 3, ok)).unwrap();
+        assert_eq!(early, RetryStats::default());
+        assert_eq!(calls.get(), 0);
+
+        let later = start + Duration::minutes(5);
+        let stats = block_on(retry_due(&store, later, 3, ok)).unwrap();
+        assert_eq!(stats.delivered, 1);
+        assert_eq!(calls.get(), 1);
+        assert!(store.entries.borrow().is_empty());
+    }
+
+    #[test]
+    fn failing_jobs_become_dead_letters_after_max_attempts() {
+        let store = MemoryOutbox::default();
+        let mut now = Utc::now();
+        queue(&store, now);
+        let fail = |_: OutboxJob| -> LocalBoxFuture<'static, Result<(), AppError>> {
+            Box::pin(async { Err(AppError::ServiceUnavailable("still down".to_string())) })
+        };
+
+        now += Duration::minutes(5);
+        let second = block_on(retry_due(&store, now, 3, fail)).unwrap();
+        assert_eq!(second.rescheduled, 1);
+        assert_eq!(
+            store.entries.borrow()[0].1,
+            NextAttempt::At(now + Duration::minutes(10))
+        );
+
+        now += Duration::minutes(10);
+        let third = block_on(retry_due(&store, now, 3, fail)).unwrap();
+        assert_eq!(third.dead, 1);
+        let entries = store.entries.borrow();
+        assert_eq!(entries[0].0.attempts, 3);
+        assert_eq!(entries[0].1, NextAttempt::DeadLetter);
+        assert!(entries[0].2.contains("still down"));
+        drop(entries);
+
+        let after = block_on(retry_due(&store, now + Duration::days(1), 3, fail)).unwrap();
+        assert_eq!(after, RetryStats::default());
+    }
+
+    #[test]
+    fn rejected_jobs_become_dead_letters_at_once() {
+        let store = MemoryOutbox::default();
+        let start = Utc::now();
+        queue(&store, start);
+        let reject = |_: OutboxJob| -> LocalBoxFuture<'static, Result<(), AppError>> {
+            Box::pin(async { Err(AppError::BadRequest("relay said 400".to_string())) })
+        };
+
+        let stats = block_on(retry_due(&store, start + Duration::minutes(5), 8, reject)).unwrap();
+        assert_eq!(stats.dead, 1);
+        assert_eq!(store.entries.borrow()[0].1, NextAttempt::DeadLetter);
+    }
+
+    #[test]
+    fn claimed_jobs_are_leased_to_one_run() {
+        let store = D1Outbox::new(&crate::testing::Env::new()).unwrap();
+        let start = Utc::now();
+        let entry = OutboxEntry {
+            id: "job-1".to_string(),
+            job: push_job(),
+            attempts: 1,
+        };
+        let next = NextAttempt::At(start);
+        block_on(store.record_failure(&entry, &next, "relay down")).unwrap();
+
+        let first = block_on(store.claim_due(start, RETRY_BATCH)).unwrap();
+        assert_eq!(first, vec![entry.clone()]);
+        let overlapping = block_on(store.claim_due(start + Duration::minutes(5), RETRY_BATCH));
+        assert_eq!(overlapping.unwrap(), vec![]);
+
+        // The run that claimed it died; the job comes back once the lease runs out.
+        let expired = block_on(store.claim_due(start + CLAIM_LEASE, RETRY_BATCH)).unwrap();
+        assert_eq!(expired, vec![entry]);
+    }
+}
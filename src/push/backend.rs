@@ -0,0 +1,245 @@
+use chrono::Utc;
+use futures_util::future::LocalBoxFuture;
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use worker::{Fetch, Method, Request, RequestInit};
+
+use super::{
+    http_error, resolve_device_info, send_to_push_relay, user_has_push_device, PushConfig,
+    PushNotification,
+};
+use crate::{env::Env, error::AppError, webpush};
+
+/// A way of delivering vault updates to a user's devices outside the WebSocket.
+///
+/// Futures are boxed and local because fetches on Workers are `!Send`.
+pub trait PushBackend {
+    /// The name used for this backend in `PUSH_BACKENDS` and in logs.
+    fn name(&self) -> &'static str;
+
+    fn send<'a>(
+        &'a self,
+        env: &'a Env,
+        notification: &'a PushNotification,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+/// The Bitwarden push relay, which reaches the official mobile apps.
+pub struct RelayBackend {
+    pub config: PushConfig,
+}
+
+impl PushBackend for RelayBackend {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    fn send<'a>(
+        &'a self,
+        env: &'a Env,
+        notification: &'a PushNotification,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            if !user_has_push_device(env, &notification.user_id).await? {
+                return Ok(());
+            }
+            let device = resolve_device_info(
+                env,
+                &notification.user_id,
+                notification.context_id.as_deref(),
+            )
+            .await;
+            let relay_payload = json!({
+                "userId": notification.user_id,
+                "organizationId": null,
+                "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
+                "identifier": device.as_ref().map(|d| d.identifier.as_str()),
+                "type": notification.update_type,
+                "payload": notification.payload,
+                "clientType": null,
+                "installationId": null,
+            });
+            send_to_push_relay(&self.config, &relay_payload).await
+        })
+    }
+}
+
+/// POSTs each notification as JSON to a fixed URL, signed with a shared secret.
+///
+/// Receivers verify `X-Warden-Signature` (`sha256=` and the hex HMAC-SHA-256 of
+/// `"{timestamp}.{body}"`) and reject stale `X-Warden-Timestamp` values.
+pub struct WebhookBackend {
+    pub url: String,
+    pub secret: String,
+}
+
+impl WebhookBackend {
+    fn signature(&self, timestamp: i64, body: &str) -> Result<String, AppError> {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(self.secret.as_bytes())
+            .map_err(|_| AppError::Internal)?;
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        Ok(format!(
+            "sha256={}",
+            hex::encode(mac.finalize().into_bytes())
+        ))
+    }
+}
+
+impl PushBackend for WebhookBackend {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send<'a>(
+        &'a self,
+        _env: &'a Env,
+        notification: &'a PushNotification,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let body = notification.to_json().to_string();
+            let timestamp = Utc::now().timestamp();
+            let signature = self.signature(timestamp, &body)?;
+
+            let mut init = RequestInit::new();
+            init.with_method(Method::Post).with_body(Some(body.into()));
+            let mut req = Request::new_with_init(&self.url, &init).map_err(AppError::Worker)?;
+            let headers = req.headers_mut().map_err(AppError::Worker)?;
+            headers
+                .set("Content-Type", "application/json")
+                .map_err(AppError::Worker)?;
+            headers
+                .set("X-Warden-Timestamp", &timestamp.to_string())
+                .map_err(AppError::Worker)?;
+            headers
+                .set("X-Warden-Signature", &signature)
+                .map_err(AppError::Worker)?;
+
+            post(req, "Push webhook").await
+        })
+    }
+}
+
+/// Publishes to a per-user ntfy topic, which UnifiedPush distributors deliver to the device.
+pub struct NtfyBackend {
+    pub url: String,
+    /// Topic name; `{user_id}` is replaced with the user's id.
+    pub topic: String,
+    pub token: Option<String>,
+}
+
+impl NtfyBackend {
+    fn topic_url(&self, user_id: &str) -> String {
+        format!(
+            "{}/{}",
+            self.url.trim_end_matches('/'),
+            self.topic.replace("{user_id}", user_id)
+        )
+    }
+}
+
+impl PushBackend for NtfyBackend {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send<'a>(
+        &'a self,
+        _env: &'a Env,
+        notification: &'a PushNotification,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let body = notification.to_json().to_string();
+
+            let mut init = RequestInit::new();
+            init.with_method(Method::Post).with_body(Some(body.into()));
+            let url = self.topic_url(&notification.user_id);
+            let mut req = Request::new_with_init(&url, &init).map_err(AppError::Worker)?;
+            let headers = req.headers_mut().map_err(AppError::Worker)?;
+            headers
+                .set("Content-Type", "application/json")
+                .map_err(AppError::Worker)?;
+            if let Some(token) = &self.token {
+                headers
+                    .set("Authorization", &format!("Bearer {token}"))
+                    .map_err(AppError::Worker)?;
+            }
+
+            post(req, "ntfy publish").await
+        })
+    }
+}
+
+/// Web Push to subscribed browser extensions and web vaults (see `webpush`).
+pub struct WebPushBackend;
+
+impl PushBackend for WebPushBackend {
+    fn name(&self) -> &'static str {
+        "webpush"
+    }
+
+    fn send<'a>(
+        &'a self,
+        env: &'a Env,
+        notification: &'a PushNotification,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            webpush::push_update(
+                env,
+                &notification.user_id,
+                notification.update_type,
+                &notification.payload,
+                notification.context_id.as_deref(),
+            )
+            .await
+        })
+    }
+}
+
+async fn post(req: Request, what: &str) -> Result<(), AppError> {
+    let mut response = Fetch::Request(req).send().await.map_err(AppError::Worker)?;
+    let status = response.status_code();
+    if !(200..300).contains(&status) {
+        let body = response.text().await.unwrap_or_default();
+        log::error!("{what} failed ({status}): {body}");
+        return Err(http_error(what, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature_covers_timestamp_and_body() {
+        let backend = WebhookBackend {
+            url: "https://hooks.example.com/warden".to_string(),
+            secret: "It's a Secret to Everybody".to_string(),
+        };
+        // Computed independently: HMAC-SHA-256 of "1700000000.Hello, World!".
+        assert_eq!(
+            backend.signature(1_700_000_000, "Hello, World!").unwrap(),
+            "sha256=76c83fd0acdf22faed320674fe8e04d528cfe8a17905e720a9611e40677c03b7"
+        );
+        assert_eq!(
+            backend.signature(1_700_000_001, "Hello, World!").unwrap(),
+            "sha256=0d410c0a0a189f978016381999994d494ceb4f022cf79c5290dc8ccdbe430e56"
+        );
+    }
+
+    #[test]
+    fn ntfy_topic_is_per_user() {
+        let backend = NtfyBackend {
+            url: "https://ntfy.example.com/".to_string(),
+            topic: "warden-{user_id}".to_string(),
+            token: None,
+        };
+        assert_eq!(
+            backend.topic_url("abc"),
+            "https://ntfy.example.com/warden-abc"
+        );
+    }
+}
@@ -1,18 +1,25 @@
 #![allow(dead_code)]
 
+mod backend;
+
+use futures_util::future::join_all;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use web_sys::UrlSearchParams;
 use worker::{
-    wasm_bindgen::JsValue, Cache, Env, Fetch, Headers, Method, Request, RequestInit, Response,
+    wasm_bindgen::JsValue, Cache, Fetch, Headers, Method, Request, RequestInit, Response,
 };
 
-use crate::db;
+use crate::outbox::{self, OutboxJob};
+use crate::{db, env::Env, webpush};
 use crate::{error::AppError, models::device::Device};
 
+pub use backend::{NtfyBackend, PushBackend, RelayBackend, WebPushBackend, WebhookBackend};
+
 const PUSH_TOKEN_CACHE_BASE: &str = "https://push-token.internal/relay-token";
 const DEFAULT_PUSH_RELAY_URI: &str = "https://push.bitwarden.com";
 const DEFAULT_PUSH_IDENTITY_URI: &str = "https://identity.bitwarden.com";
+const DEFAULT_NTFY_TOPIC: &str = "warden-{user_id}";
 
 // ── PushConfig ──────────────────────────────────────────────────────
 
@@ -26,14 +33,10 @@ pub struct PushConfig {
 
 /// Try to build a `PushConfig` from environment variables.
 ///
-/// Returns `None` when `PUSH_ENABLED` is not `"true"`.
-/// Returns `Err` when push is enabled but required secrets are missing.
+/// Returns `None` when the relay is not among the configured push backends.
+/// Returns `Err` when the relay is enabled but required secrets are missing.
 pub fn push_config(env: &Env) -> Result<Option<PushConfig>, AppError> {
-    let enabled = env
-        .var("PUSH_ENABLED")
-        .ok()
-        .is_some_and(|v| v.to_string() == "true");
-    if !enabled {
+    if !configured_backend_kinds(env).contains(&BackendKind::Relay) {
         return Ok(None);
     }
 
@@ -53,7 +56,7 @@ pub fn push_config(env: &Env) -> Result<Option<PushConfig>, AppError> {
         .secret("PUSH_INSTALLATION_ID")
         .map(|v| v.to_string())
         .map_err(|_| {
-            log::error!("PUSH_ENABLED is true but PUSH_INSTALLATION_ID secret is missing");
+            log::error!("Push relay is enabled but PUSH_INSTALLATION_ID secret is missing");
             AppError::Internal
         })?;
 
@@ -61,7 +64,7 @@ pub fn push_config(env: &Env) -> Result<Option<PushConfig>, AppError> {
         .secret("PUSH_INSTALLATION_KEY")
         .map(|v| v.to_string())
         .map_err(|_| {
-            log::error!("PUSH_ENABLED is true but PUSH_INSTALLATION_KEY secret is missing");
+            log::error!("Push relay is enabled but PUSH_INSTALLATION_KEY secret is missing");
             AppError::Internal
         })?;
 
@@ -237,7 +240,7 @@ pub async fn unregister_push_device(
 pub async fn send_to_push_relay(cfg: &PushConfig, payload: &Value) -> Result<(), AppError> {
     let token = get_relay_token(cfg).await?;
     let url = format!("{}/push/send", cfg.relay_uri);
-    post_to_relay(&url, &token, payload, false).await
+    post_to_relay(&url, &token, payload, true).await
 }
 
 // In this project, org feature is not supported, so we set organizationId and collectionIds to null
@@ -276,19 +279,27 @@ async fn post_to_relay(
         .map_err(AppError::Worker)?;
 
     let mut response = Fetch::Request(req).send().await.map_err(AppError::Worker)?;
-    if !(200..300).contains(&response.status_code()) {
+    let status = response.status_code();
+    if !(200..300).contains(&status) {
         let body = response.text().await.unwrap_or_default();
-        log::error!(
-            "Push relay POST to {url} failed ({}): {body}",
-            response.status_code()
-        );
+        log::error!("Push relay POST to {url} failed ({status}): {body}");
         if fail_on_http_error {
-            return Err(AppError::Internal);
+            return Err(http_error("Push relay", status));
         }
     }
     Ok(())
 }
 
+/// The error for a non-2xx answer from a push receiver. A 4xx other than 408 or 429 means the
+/// notification itself was refused, which the outbox does not retry (see `outbox::is_permanent`).
+pub(crate) fn http_error(what: &str, status: u16) -> AppError {
+    if (400..500).contains(&status) && status != 408 && status != 429 {
+        AppError::BadRequest(format!("{what} rejected the notification ({status})"))
+    } else {
+        AppError::ServiceUnavailable(format!("{what} answered {status}"))
+    }
+}
+
 // ── D1 queries (push device checks) ────────────────────────────
 
 pub async fn user_has_push_device(env: &Env, user_id: &str) -> Result<bool, AppError> {
@@ -392,6 +403,197 @@ async fn resolve_device_info(
         .flatten()
 }
 
+// ── Backends ────────────────────────────────────────────────────────
+
+/// The backends `PUSH_BACKENDS` can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BackendKind {
+    Relay,
+    Webhook,
+    Ntfy,
+}
+
+/// Parse the comma-separated `PUSH_BACKENDS`. Without it, `PUSH_ENABLED=true` selects the
+/// relay alone, as before backends were pluggable. Unknown names are logged and skipped.
+fn backend_kinds(raw: Option<&str>, push_enabled: bool) -> Vec<BackendKind> {
+    let Some(raw) = raw else {
+        return if push_enabled {
+            vec![BackendKind::Relay]
+        } else {
+            Vec::new()
+        };
+    };
+    let mut kinds = Vec::new();
+    for name in raw.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let kind = match name.to_ascii_lowercase().as_str() {
+            "relay" => BackendKind::Relay,
+            "webhook" => BackendKind::Webhook,
+            "ntfy" | "unifiedpush" => BackendKind::Ntfy,
+            _ => {
+                log::warn!("Ignoring unknown push backend {name:?} in PUSH_BACKENDS");
+                continue;
+            }
+        };
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+fn configured_backend_kinds(env: &Env) -> Vec<BackendKind> {
+    let raw = env.var("PUSH_BACKENDS").ok().map(|v| v.to_string());
+    let push_enabled = env
+        .var("PUSH_ENABLED")
+        .ok()
+        .is_some_and(|v| v.to_string() == "true");
+    backend_kinds(raw.as_deref(), push_enabled)
+}
+
+fn webhook_backend(env: &Env) -> Option<WebhookBackend> {
+    let url = env.var("PUSH_WEBHOOK_URL").ok().map(|v| v.to_string());
+    let secret = env
+        .secret("PUSH_WEBHOOK_SECRET")
+        .ok()
+        .map(|v| v.to_string());
+    match (url, secret) {
+        (Some(url), Some(secret)) if !url.is_empty() && !secret.is_empty() => {
+            Some(WebhookBackend { url, secret })
+        }
+        _ => {
+            log::error!("Webhook push needs PUSH_WEBHOOK_URL and the PUSH_WEBHOOK_SECRET secret");
+            None
+        }
+    }
+}
+
+fn ntfy_backend(env: &Env) -> Option<NtfyBackend> {
+    let Some(url) = env
+        .var("PUSH_NTFY_URL")
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.is_empty())
+    else {
+        log::error!("ntfy push needs PUSH_NTFY_URL");
+        return None;
+    };
+    let topic = env
+        .var("PUSH_NTFY_TOPIC")
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| DEFAULT_NTFY_TOPIC.to_string());
+    let token = env
+        .secret("PUSH_NTFY_TOKEN")
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.is_empty());
+    Some(NtfyBackend { url, topic, token })
+}
+
+/// Every backend that is enabled and fully configured. Misconfigured ones are logged and left out,
+/// so one bad setting does not stop the others.
+pub fn push_backends(env: &Env) -> Vec<Box<dyn PushBackend>> {
+    let mut backends: Vec<Box<dyn PushBackend>> = Vec::new();
+    for kind in configured_backend_kinds(env) {
+        match kind {
+            BackendKind::Relay => {
+                if let Some(config) = try_get_push_config(env) {
+                    backends.push(Box::new(RelayBackend { config }));
+                }
+            }
+            BackendKind::Webhook => {
+                if let Some(backend) = webhook_backend(env) {
+                    backends.push(Box::new(backend));
+                }
+            }
+            BackendKind::Ntfy => {
+                if let Some(backend) = ntfy_backend(env) {
+                    backends.push(Box::new(backend));
+                }
+            }
+        }
+    }
+    if webpush::web_push_enabled(env) {
+        backends.push(Box::new(WebPushBackend));
+    }
+    backends
+}
+
+/// One update for a user's devices, as every backend receives it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushNotification {
+    pub user_id: String,
+    pub update_type: i32,
+    pub payload: Value,
+    /// The device that made the change, if any.
+    pub context_id: Option<String>,
+}
+
+impl PushNotification {
+    fn new(user_id: &str, update_type: i32, payload: Value, context_id: Option<&str>) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            update_type,
+            payload,
+            context_id: context_id.map(str::to_string),
+        }
+    }
+
+    /// The body the webhook and ntfy backends send.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "userId": self.user_id,
+            "type": self.update_type,
+            "contextId": self.context_id,
+            "payload": self.payload,
+        })
+    }
+}
+
+/// Send `notification` through every configured backend at once. Failures are logged per backend.
+async fn dispatch(env: &Env, notification: PushNotification) {
+    let backends = push_backends(env);
+    if backends.is_empty() {
+        return;
+    }
+    let results = join_all(
+        backends
+            .iter()
+            .map(|backend| backend.send(env, &notification)),
+    )
+    .await;
+    for (backend, result) in backends.iter().zip(results) {
+        if let Err(e) = result {
+            log::warn!(
+                "Push backend {} failed for update type {}: {e}",
+                backend.name(),
+                notification.update_type
+            );
+            let job = OutboxJob::Push {
+                backend: backend.name().to_string(),
+                notification: notification.clone(),
+            };
+            outbox::enqueue_failed(env, job, &e).await;
+        }
+    }
+}
+
+/// Send `notification` through the backend called `name` again, for the outbox. A backend
+/// that has been switched off since is skipped.
+pub async fn send_with_backend(
+    env: &Env,
+    name: &str,
+    notification: &PushNotification,
+) -> Result<(), AppError> {
+    let Some(backend) = push_backends(env).into_iter().find(|b| b.name() == name) else {
+        log::info!("Dropping queued push for backend {name}, which is no longer configured");
+        return Ok(());
+    };
+    backend.send(env, notification).await
+}
+
 // ── High-level push functions (called from notification entry points) ──
 
 pub async fn push_user_update(
@@ -401,29 +603,15 @@ pub async fn push_user_update(
     date: &str,
     context_id: Option<&str>,
 ) {
-    let Some(cfg) = try_get_push_config(env) else {
-        return;
-    };
-    if !user_has_push_device(env, user_id).await.unwrap_or(false) {
-        return;
-    }
-    let device = resolve_device_info(env, user_id, context_id).await;
     let payload = json!({
         "userId": user_id,
-        "organizationId": null,
-        "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
-        "identifier": device.as_ref().map(|d| d.identifier.as_str()),
-        "type": update_type,
-        "payload": {
-            "userId": user_id,
-            "date": date,
-        },
-        "clientType": null,
-        "installationId": null,
+        "date": date,
     });
-    if let Err(e) = send_to_push_relay(&cfg, &payload).await {
-        log::warn!("Push relay failed for user_update: {e}");
-    }
+    dispatch(
+        env,
+        PushNotification::new(user_id, update_type, payload, context_id),
+    )
+    .await;
 }
 
 pub async fn push_folder_update(
@@ -434,30 +622,16 @@ pub async fn push_folder_update(
     revision_date: &str,
     context_id: Option<&str>,
 ) {
-    let Some(cfg) = try_get_push_config(env) else {
-        return;
-    };
-    if !user_has_push_device(env, user_id).await.unwrap_or(false) {
-        return;
-    }
-    let device = resolve_device_info(env, user_id, context_id).await;
     let payload = json!({
+        "id": folder_id,
         "userId": user_id,
-        "organizationId": null,
-        "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
-        "identifier": device.as_ref().map(|d| d.identifier.as_str()),
-        "type": update_type,
-        "payload": {
-            "id": folder_id,
-            "userId": user_id,
-            "revisionDate": revision_date,
-        },
-        "clientType": null,
-        "installationId": null,
+        "revisionDate": revision_date,
     });
-    if let Err(e) = send_to_push_relay(&cfg, &payload).await {
-        log::warn!("Push relay failed for folder_update: {e}");
-    }
+    dispatch(
+        env,
+        PushNotification::new(user_id, update_type, payload, context_id),
+    )
+    .await;
 }
 
 pub async fn push_cipher_update(
@@ -468,32 +642,18 @@ pub async fn push_cipher_update(
     revision_date: &str,
     context_id: Option<&str>,
 ) {
-    let Some(cfg) = try_get_push_config(env) else {
-        return;
-    };
-    if !user_has_push_device(env, user_id).await.unwrap_or(false) {
-        return;
-    }
-    let device = resolve_device_info(env, user_id, context_id).await;
     let payload = json!({
+        "id": cipher_id,
         "userId": user_id,
         "organizationId": null,
-        "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
-        "identifier": device.as_ref().map(|d| d.identifier.as_str()),
-        "type": update_type,
-        "payload": {
-            "id": cipher_id,
-            "userId": user_id,
-            "organizationId": null,
-            "collectionIds": null,
-            "revisionDate": revision_date,
-        },
-        "clientType": null,
-        "installationId": null,
+        "collectionIds": null,
+        "revisionDate": revision_date,
     });
-    if let Err(e) = send_to_push_relay(&cfg, &payload).await {
-        log::warn!("Push relay failed for cipher_update: {e}");
-    }
+    dispatch(
+        env,
+        PushNotification::new(user_id, update_type, payload, context_id),
+    )
+    .await;
 }
 
 pub async fn push_send_update(
@@ -504,30 +664,16 @@ pub async fn push_send_update(
     revision_date: &str,
     context_id: Option<&str>,
 ) {
-    let Some(cfg) = try_get_push_config(env) else {
-        return;
-    };
-    if !user_has_push_device(env, user_id).await.unwrap_or(false) {
-        return;
-    }
-    let device = resolve_device_info(env, user_id, context_id).await;
     let payload = json!({
+        "id": send_id,
         "userId": user_id,
-        "organizationId": null,
-        "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
-        "identifier": device.as_ref().map(|d| d.identifier.as_str()),
-        "type": update_type,
-        "payload": {
-            "id": send_id,
-            "userId": user_id,
-            "revisionDate": revision_date,
-        },
-        "clientType": null,
-        "installationId": null,
+        "revisionDate": revision_date,
     });
-    if let Err(e) = send_to_push_relay(&cfg, &payload).await {
-        log::warn!("Push relay failed for send_update: {e}");
-    }
+    dispatch(
+        env,
+        PushNotification::new(user_id, update_type, payload, context_id),
+    )
+    .await;
 }
 
 pub async fn push_auth_update(
@@ -537,27 +683,68 @@ pub async fn push_auth_update(
     auth_request_id: &str,
     context_id: Option<&str>,
 ) {
-    let Some(cfg) = try_get_push_config(env) else {
-        return;
-    };
-    if !user_has_push_device(env, user_id).await.unwrap_or(false) {
-        return;
-    }
-    let device = resolve_device_info(env, user_id, context_id).await;
     let payload = json!({
         "userId": user_id,
-        "organizationId": null,
-        "deviceId": device.as_ref().and_then(|d| d.push_uuid.as_deref()),
-        "identifier": device.as_ref().map(|d| d.identifier.as_str()),
-        "type": update_type,
-        "payload": {
-            "userId": user_id,
-            "id": auth_request_id,
-        },
-        "clientType": null,
-        "installationId": null,
+        "id": auth_request_id,
     });
-    if let Err(e) = send_to_push_relay(&cfg, &payload).await {
-        log::warn!("Push relay failed for auth update (type {update_type}): {e}");
+    dispatch(
+        env,
+        PushNotification::new(user_id, update_type, payload, context_id),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_refusals_are_permanent() {
+        for status in [400, 401, 404, 410] {
+            assert!(crate::outbox::is_permanent(&http_error(
+                "Push relay",
+                status
+            )));
+        }
+        for status in [408, 429, 500, 503] {
+            assert!(!crate::outbox::is_permanent(&http_error(
+                "Push relay",
+                status
+            )));
+        }
+    }
+
+    #[test]
+    fn push_enabled_alone_selects_the_relay() {
+        assert_eq!(backend_kinds(None, true), vec![BackendKind::Relay]);
+        assert!(backend_kinds(None, false).is_empty());
+    }
+
+    #[test]
+    fn push_backends_lists_each_backend_once() {
+        assert_eq!(
+            backend_kinds(Some(" ntfy, webhook ,bogus,,NTFY,relay"), false),
+            vec![BackendKind::Ntfy, BackendKind::Webhook, BackendKind::Relay]
+        );
+        assert_eq!(
+            backend_kinds(Some("unifiedpush"), true),
+            vec![BackendKind::Ntfy]
+        );
+        assert!(backend_kinds(Some(""), true).is_empty());
+    }
+
+    #[test]
+    fn notification_json_carries_the_update() {
+        let notification =
+            PushNotification::new("user-1", 1, json!({ "id": "cipher-1" }), Some("device-1"));
+        assert_eq!(
+            notification.to_json(),
+            json!({
+                "userId": "user-1",
+                "type": 1,
+                "contextId": "device-1",
+                "payload": { "id": "cipher-1" },
+            })
+        );
     }
 }
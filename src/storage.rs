@@ -0,0 +1,212 @@
+//! Storage for attachment and Send files.
+//!
+//! Files live in the `ATTACHMENTS_BUCKET` R2 bucket when it is bound, otherwise in the
+//! `ATTACHMENTS_KV` namespace; without either, attachments and file Sends are disabled.
+//! Handlers go through [`FileStorage`], so tests can swap in [`MemoryStorage`]. Streaming
+//! uploads and downloads (`handlers::streaming`) bypass the router and talk to the bindings
+//! directly.
+
+use futures_util::future::LocalBoxFuture;
+use worker::{kv::KvStore, Bucket, HttpMetadata};
+
+use crate::env::Env;
+use crate::error::AppError;
+
+pub(crate) const ATTACHMENTS_BUCKET: &str = "ATTACHMENTS_BUCKET";
+pub(crate) const ATTACHMENTS_KV: &str = "ATTACHMENTS_KV";
+
+pub(crate) const KV_MAX_VALUE_BYTES: i64 = 25 * 1024 * 1024; // 25 MiB (KV hard limit)
+
+/// Storage backend for attachments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StorageBackend {
+    /// Cloudflare KV - no credit card required, 25MB limit per value
+    KV,
+    /// Cloudflare R2 - requires credit card, no practical size limit
+    R2,
+}
+
+/// Where attachment and Send files are kept, by key (`{cipher_id}/{attachment_id}` or
+/// `sends/{send_id}/{file_id}`).
+pub(crate) trait FileStorage {
+    fn backend(&self) -> StorageBackend;
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: Option<&'a str>,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>>;
+
+    /// Delete `keys`; keys that are already gone are not an error.
+    fn delete<'a>(&'a self, keys: &'a [String]) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+/// The configured file storage. Priority: R2 if bound, otherwise KV.
+pub(crate) fn file_storage(env: &Env) -> Option<Box<dyn FileStorage>> {
+    #[cfg(test)]
+    if let Some(files) = env.file_storage() {
+        return Some(Box::new(files));
+    }
+    if let Ok(bucket) = env.bucket(ATTACHMENTS_BUCKET) {
+        Some(Box::new(R2Storage { bucket }))
+    } else if let Ok(kv) = env.kv(ATTACHMENTS_KV) {
+        Some(Box::new(KvStorage { kv }))
+    } else {
+        None
+    }
+}
+
+/// Detect which storage backend is available.
+pub(crate) fn get_storage_backend(env: &Env) -> Option<StorageBackend> {
+    file_storage(env).map(|storage| storage.backend())
+}
+
+/// Check if using KV backend (for behavior differences)
+pub(crate) fn is_kv_backend(env: &Env) -> bool {
+    get_storage_backend(env) == Some(StorageBackend::KV)
+}
+
+struct R2Storage {
+    bucket: Bucket,
+}
+
+fn is_not_found_error(err: &worker::Error) -> bool {
+    let msg = err.to_string();
+    msg.contains("NoSuchKey") || msg.contains("404") || msg.contains("NotFound")
+}
+
+impl FileStorage for R2Storage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::R2
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: Option<&'a str>,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let mut builder = self.bucket.put(key, data);
+            if let Some(ct) = content_type {
+                builder = builder.http_metadata(HttpMetadata {
+                    content_type: Some(ct.to_string()),
+                    ..Default::default()
+                });
+            }
+            builder.execute().await.map_err(AppError::Worker)?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, keys: &'a [String]) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            for key in keys {
+                if let Err(err) = self.bucket.delete(key).await {
+                    if !is_not_found_error(&err) {
+                        log::error!("R2 delete error for key '{}': {:?}", key, err);
+                        return Err(AppError::Worker(err));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+struct KvStorage {
+    kv: KvStore,
+}
+
+impl FileStorage for KvStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::KV
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        _content_type: Option<&'a str>,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            // KV put_bytes stores raw binary data
+            if let Err(e) = self
+                .kv
+                .put_bytes(key, &data)
+                .map_err(|_| AppError::Internal)?
+                .execute()
+                .await
+            {
+                log::error!("KV put error for key '{}': {:?}", key, e);
+                return Err(AppError::Internal);
+            }
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, keys: &'a [String]) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            for key in keys {
+                // KV delete is idempotent - no error if key doesn't exist
+                if let Err(e) = self.kv.delete(key).await {
+                    log::error!("KV delete error for key '{}': {:?}", key, e);
+                    return Err(AppError::Internal);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A stored file: its content type and bytes.
+#[cfg(test)]
+pub type MemoryFile = (Option<String>, Vec<u8>);
+
+/// Keeps files in memory instead of R2 or KV, behaving like R2 (no size limit per file).
+/// Clones share the same files, so the test `Env` can hand one out and read it afterwards.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<String, MemoryFile>>>,
+}
+
+#[cfg(test)]
+impl MemoryStorage {
+    pub fn get(&self, key: &str) -> Option<MemoryFile> {
+        self.files.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+impl FileStorage for MemoryStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::R2
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: Option<&'a str>,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (content_type.map(str::to_string), data));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, keys: &'a [String]) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        let mut files = self.files.lock().unwrap();
+        for key in keys {
+            files.remove(key);
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,408 @@
+//! Native stand-ins for the Worker runtime, so unit tests can drive `router::api_router`.
+//!
+//! [`Env`] takes the place of `worker::Env` (see `crate::env`). D1 is an in-memory SQLite
+//! database set up like a fresh deployment, secrets and variables come from a map, and the
+//! other bindings (KV, R2, Durable Objects, rate limiters and email) are unbound, so the
+//! features that need them behave as if they were not configured. Setting `MAIL_PROVIDER`
+//! to `test` records outgoing mail instead, for [`Env::sent_mail`], and
+//! [`Env::with_file_storage`] keeps attachment and Send files in memory.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Extension, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use tower_service::Service;
+use uuid::Uuid;
+use worker::{email::SendEmail, kv::KvStore, Bucket, ObjectNamespace, RateLimiter};
+
+use crate::{
+    crypto,
+    db::{Database, Db},
+    mail::{transport::RecordingTransport, MailMessage},
+    migrations, router,
+    storage::MemoryStorage,
+    BaseUrl,
+};
+
+pub const BASE_URL: &str = "https://vault.example.com";
+
+const DEFAULT_VARS: &[(&str, &str)] = &[
+    ("JWT_SECRET", "test-jwt-secret-0123456789abcdef"),
+    ("JWT_REFRESH_SECRET", "test-jwt-refresh-secret-0123456789"),
+    ("ALLOWED_EMAILS", "*@example.com"),
+];
+
+/// Drive `future` to completion. Nothing here waits on I/O, so polling in a loop is enough.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+thread_local! {
+    static BACKGROUND: RefCell<Vec<Pin<Box<dyn Future<Output = ()>>>>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Queue a task that `background::spawn_background` would hand to `waitUntil`.
+pub fn spawn_background(future: impl Future<Output = ()> + 'static) {
+    BACKGROUND.with(|tasks| tasks.borrow_mut().push(Box::pin(future)));
+}
+
+/// Run the queued background tasks, including any they queue themselves.
+pub fn run_background() {
+    while let Some(task) = BACKGROUND.with(|tasks| tasks.borrow_mut().pop()) {
+        block_on(task);
+    }
+}
+
+/// The value of a secret or variable, which is read with `to_string()` like `worker::Secret`.
+pub struct Binding(String);
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Env {
+    database: Database,
+    vars: Arc<HashMap<String, String>>,
+    mail: RecordingTransport,
+    files: Option<MemoryStorage>,
+}
+
+impl Env {
+    /// A fresh database at the latest schema and the default test secrets.
+    pub fn new() -> Self {
+        Self::with_vars(&[])
+    }
+
+    /// Like [`Env::new`], with `vars` set on top of the defaults.
+    pub fn with_vars(vars: &[(&str, &str)]) -> Self {
+        let database = Database::open_in_memory();
+        block_on(migrations::initialize(&Db::Sqlite(database.clone())))
+            .expect("the schema applies to an empty database");
+        let vars = DEFAULT_VARS
+            .iter()
+            .chain(vars)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Self {
+            database,
+            vars: Arc::new(vars),
+            mail: RecordingTransport::default(),
+            files: None,
+        }
+    }
+
+    /// Enable attachments and file Sends, stored in memory.
+    pub fn with_file_storage(mut self) -> Self {
+        self.files = Some(MemoryStorage::default());
+        self
+    }
+
+    /// The files stored so far, when [`Env::with_file_storage`] enabled them.
+    pub fn file_storage(&self) -> Option<MemoryStorage> {
+        self.files.clone()
+    }
+
+    /// Like [`Env::new`], with mail recorded for [`Env::sent_mail`].
+    pub fn with_mail() -> Self {
+        Self::with_vars(&[
+            ("MAIL_PROVIDER", "test"),
+            ("MAIL_FROM", "vault@example.com"),
+        ])
+    }
+
+    /// The transport behind `MAIL_PROVIDER=test`.
+    pub fn mail_transport(&self) -> RecordingTransport {
+        self.mail.clone()
+    }
+
+    /// Every message sent so far, oldest first.
+    pub fn sent_mail(&self) -> Vec<MailMessage> {
+        self.mail.sent()
+    }
+
+    pub fn database(&self) -> Database {
+        self.database.clone()
+    }
+
+    pub fn secret(&self, binding: &str) -> worker::Result<Binding> {
+        self.var(binding)
+    }
+
+    pub fn var(&self, binding: &str) -> worker::Result<Binding> {
+        self.vars
+            .get(binding)
+            .map(|value| Binding(value.clone()))
+            .ok_or_else(|| unbound(binding))
+    }
+
+    pub fn kv(&self, binding: &str) -> worker::Result<KvStore> {
+        Err(unbound(binding))
+    }
+
+    pub fn bucket(&self, binding: &str) -> worker::Result<Bucket> {
+        Err(unbound(binding))
+    }
+
+    pub fn durable_object(&self, binding: &str) -> worker::Result<ObjectNamespace> {
+        Err(unbound(binding))
+    }
+
+    pub fn rate_limiter(&self, binding: &str) -> worker::Result<RateLimiter> {
+        Err(unbound(binding))
+    }
+
+    pub fn send_email(&self, binding: &str) -> worker::Result<SendEmail> {
+        Err(unbound(binding))
+    }
+}
+
+fn unbound(binding: &str) -> worker::Error {
+    worker::Error::RustError(format!("Binding {binding} is not set in tests"))
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+/// An account registered through the API; keys are opaque to the server, so placeholders do.
+pub struct TestUser {
+    pub email: String,
+    pub master_password_hash: String,
+}
+
+/// Sends requests through `router::api_router`, the way the `fetch` handler does.
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub fn new() -> Self {
+        Self::with_env(Env::new())
+    }
+
+    pub fn with_env(env: Env) -> Self {
+        Self {
+            router: router::api_router(env).layer(Extension(BaseUrl(BASE_URL.to_string()))),
+        }
+    }
+
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<(&str, String)>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let request = match body {
+            Some((content_type, body)) => request
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body)),
+            None => request.body(Body::empty()),
+        }
+        .expect("a valid request");
+        self.send(request)
+    }
+
+    fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = block_on(self.router.clone().call(request)).expect("routing is infallible");
+        run_background();
+        let status = response.status();
+        let bytes = block_on(axum::body::to_bytes(response.into_body(), usize::MAX))
+            .expect("the response body is in memory");
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+        TestResponse { status, body }
+    }
+
+    pub fn get(&self, path: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::GET, path, token, None)
+    }
+
+    pub fn delete(&self, path: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::DELETE, path, token, None)
+    }
+
+    pub fn post(&self, path: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.send_json(Method::POST, path, token, body)
+    }
+
+    pub fn put(&self, path: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.send_json(Method::PUT, path, token, body)
+    }
+
+    fn send_json(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> TestResponse {
+        self.request(
+            method,
+            path,
+            token,
+            Some(("application/json", body.to_string())),
+        )
+    }
+
+    pub fn post_form(&self, path: &str, form: &[(&str, &str)]) -> TestResponse {
+        let body = form
+            .iter()
+            .map(|(key, value)| format!("{key}={}", urlencode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        self.request(
+            Method::POST,
+            path,
+            None,
+            Some(("application/x-www-form-urlencoded", body)),
+        )
+    }
+
+    /// A `multipart/form-data` POST. Each part is `(name, file name, contents)`; parts with a
+    /// file name are sent as `application/octet-stream` files.
+    pub fn post_multipart(
+        &self,
+        path: &str,
+        token: Option<&str>,
+        parts: &[(&str, Option<&str>, &[u8])],
+    ) -> TestResponse {
+        const BOUNDARY: &str = "warden-test-boundary";
+        let mut body = Vec::new();
+        for (name, file_name, contents) in parts {
+            body.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+            match file_name {
+                Some(file_name) => body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{name}\"; filename=\"{file_name}\"\r\n\
+                         Content-Type: application/octet-stream\r\n\r\n"
+                    )
+                    .as_bytes(),
+                ),
+                None => body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+                ),
+            }
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let mut request = Request::builder().method(Method::POST).uri(path).header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        );
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        self.send(request.body(Body::from(body)).expect("a valid request"))
+    }
+
+    pub fn register(&self) -> TestUser {
+        let user = TestUser {
+            email: format!("{}@example.com", Uuid::new_v4()),
+            master_password_hash: STANDARD.encode(random_bytes(32)),
+        };
+        let response = self.post(
+            "/identity/accounts/register",
+            None,
+            json!({
+                "name": "Test User",
+                "email": user.email,
+                "masterPasswordHash": user.master_password_hash,
+                "masterPasswordHint": null,
+                "userSymmetricKey": enc_string(),
+                "userAsymmetricKeys": {
+                    "publicKey": STANDARD.encode(random_bytes(32)),
+                    "encryptedPrivateKey": enc_string(),
+                },
+                "kdf": 0,
+                "kdfIterations": 600000,
+            }),
+        );
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        user
+    }
+
+    /// The password grant as the web vault sends it, plus `extra` fields.
+    pub fn token(&self, user: &TestUser, extra: &[(&str, &str)]) -> TestResponse {
+        let mut form = vec![
+            ("grant_type", "password"),
+            ("username", user.email.as_str()),
+            ("password", user.master_password_hash.as_str()),
+            ("scope", "api offline_access"),
+            ("client_id", "web"),
+            ("deviceType", "9"),
+            ("deviceIdentifier", "2c1e5e1a-7d3b-4f63-9c43-1f5a7d0e9b21"),
+            ("deviceName", "chrome"),
+        ];
+        form.extend_from_slice(extra);
+        self.post_form("/identity/connect/token", &form)
+    }
+
+    /// Log in and return the access token.
+    pub fn login(&self, user: &TestUser) -> String {
+        let response = self.token(user, &[]);
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body["access_token"]
+            .as_str()
+            .expect("an access token")
+            .to_string()
+    }
+}
+
+/// A type 2 (AES-CBC-256 + HMAC) encrypted string with random contents.
+pub fn enc_string() -> String {
+    format!(
+        "2.{}|{}|{}",
+        STANDARD.encode(random_bytes(16)),
+        STANDARD.encode(random_bytes(32)),
+        STANDARD.encode(random_bytes(32))
+    )
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    crypto::random_bytes(len).expect("the OS provides randomness")
+}
+
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,486 @@
+//! Web Push for browser extensions and the web vault.
+//!
+//! Updates are encrypted for each subscribed browser with `aes128gcm` (RFC 8291) and posted to
+//! its push service with a VAPID authorization (RFC 8292). The server's VAPID key pair is
+//! generated on first use and kept in the `vapid_keys` table. Key agreement, HKDF and signing run
+//! in pure Rust; only the final AES-GCM step goes through Web Crypto.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, KeyInit, Mac};
+use js_sys::Uint8Array;
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey, SecretKey,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use worker::{Fetch, Method, Request, RequestInit};
+
+use crate::d1_query;
+use crate::models::web_push_subscription::WebPushSubscription;
+use crate::{crypto, db, env::Env, error::AppError, outbox, push};
+
+/// Bytes per encrypted record; every message fits in one.
+const RECORD_SIZE: u32 = 4096;
+/// How long a push service keeps a message for a browser that is offline.
+const MESSAGE_TTL_SECS: u32 = 24 * 60 * 60;
+/// Lifetime of a VAPID token; RFC 8292 allows at most 24 hours.
+const VAPID_TOKEN_SECS: i64 = 12 * 60 * 60;
+
+/// Whether `WEB_PUSH_ENABLED` is `"true"`.
+pub fn web_push_enabled(env: &Env) -> bool {
+    env.var("WEB_PUSH_ENABLED")
+        .ok()
+        .is_some_and(|v| v.to_string() == "true")
+}
+
+/// The `sub` claim of VAPID tokens: `WEB_PUSH_SUBJECT`, else `BASE_URL`. Some push services
+/// refuse tokens without one.
+fn vapid_subject(env: &Env) -> Option<String> {
+    ["WEB_PUSH_SUBJECT", "BASE_URL"]
+        .iter()
+        .filter_map(|name| env.var(name).ok())
+        .map(|v| v.to_string().trim().to_string())
+        .find(|v| !v.is_empty())
+}
+
+// ── VAPID key pair ──────────────────────────────────────────────────
+
+pub struct VapidKeys {
+    secret: SecretKey,
+    /// Uncompressed public key, base64url, as `/api/config` reports it.
+    pub public_key: String,
+}
+
+#[derive(Deserialize)]
+struct VapidKeysRow {
+    private_key: String,
+    public_key: String,
+}
+
+/// The server's VAPID key pair, generated on first use. Concurrent first uses agree on
+/// whichever key pair was stored first.
+pub async fn vapid_keys(db: &db::Db) -> Result<VapidKeys, AppError> {
+    if let Some(keys) = load_vapid_keys(db).await? {
+        return Ok(keys);
+    }
+
+    let secret = generate_secret()?;
+    d1_query!(
+        db,
+        "INSERT INTO vapid_keys (id, private_key, public_key, created_at) VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO NOTHING",
+        URL_SAFE_NO_PAD.encode(secret.to_bytes()),
+        URL_SAFE_NO_PAD.encode(public_key_bytes(&secret)),
+        db::now_string()
+    )
+    .map_err(|_| AppError::Database)?
+    .run()
+    .await
+    .map_err(|_| AppError::Database)?;
+
+    load_vapid_keys(db).await?.ok_or(AppError::Database)
+}
+
+async fn load_vapid_keys(db: &db::Db) -> Result<Option<VapidKeys>, AppError> {
+    let row: Option<VapidKeysRow> = db
+        .prepare("SELECT private_key, public_key FROM vapid_keys WHERE id = 1")
+        .first(None)
+        .await
+        .map_err(|_| AppError::Database)?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let secret = URL_SAFE_NO_PAD
+        .decode(&row.private_key)
+        .ok()
+        .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+        .ok_or_else(|| AppError::Crypto("Stored VAPID private key is invalid".to_string()))?;
+    Ok(Some(VapidKeys {
+        secret,
+        public_key: row.public_key,
+    }))
+}
+
+/// The VAPID public key to advertise in `/api/config`, or `None` when Web Push is off.
+pub async fn vapid_public_key(env: &Env) -> Option<String> {
+    if !web_push_enabled(env) {
+        return None;
+    }
+    let keys = match db::get_db_unconstrained(env) {
+        Ok(db) => vapid_keys(&db).await,
+        Err(e) => Err(e),
+    };
+    match keys {
+        Ok(keys) => Some(keys.public_key),
+        Err(e) => {
+            log::error!("Failed to load the VAPID key pair: {e}");
+            None
+        }
+    }
+}
+
+fn generate_secret() -> Result<SecretKey, AppError> {
+    // Nearly every 32-byte string is a valid scalar; retry on the rare one that is not.
+    loop {
+        if let Ok(secret) = SecretKey::from_slice(&crypto::random_bytes(32)?) {
+            return Ok(secret);
+        }
+    }
+}
+
+fn public_key_bytes(secret: &SecretKey) -> Vec<u8> {
+    secret
+        .public_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec()
+}
+
+// ── Subscriptions ───────────────────────────────────────────────────
+
+/// Check a subscription before it is stored. Endpoints must be `https`; plain `http` is only
+/// accepted on loopback hosts, for a local push service.
+pub fn validate_subscription(endpoint: &str, p256dh: &str, auth: &str) -> Result<(), String> {
+    let url = worker::Url::parse(endpoint).map_err(|_| "Invalid endpoint".to_string())?;
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    if url.scheme() != "https" && !(url.scheme() == "http" && loopback) {
+        return Err("The endpoint must use https".to_string());
+    }
+    let valid_key = URL_SAFE_NO_PAD
+        .decode(p256dh.trim_end_matches('='))
+        .is_ok_and(|key| key.len() == 65 && PublicKey::from_sec1_bytes(&key).is_ok());
+    if !valid_key {
+        return Err("p256dh is not an uncompressed P-256 public key".to_string());
+    }
+    let valid_auth = URL_SAFE_NO_PAD
+        .decode(auth.trim_end_matches('='))
+        .is_ok_and(|secret| secret.len() == 16);
+    if !valid_auth {
+        return Err("auth must be 16 bytes".to_string());
+    }
+    Ok(())
+}
+
+// ── Sending ─────────────────────────────────────────────────────────
+
+/// Send an update to every Web Push subscription of `user_id` except the device it came from,
+/// in the shape the clients read from `PushEvent.data.json().data`. Subscriptions the push
+/// service reports as gone are deleted.
+///
+/// Every subscription is tried; if any fails otherwise, the error is returned so the outbox
+/// retries the update. The retry reaches the other subscriptions again, which only makes those
+/// clients sync once more.
+pub async fn push_update(
+    env: &Env,
+    user_id: &str,
+    update_type: i32,
+    payload: &Value,
+    context_id: Option<&str>,
+) -> Result<(), AppError> {
+    if !web_push_enabled(env) {
+        return Ok(());
+    }
+    let db = db::get_db_unconstrained(env)?;
+    let subscriptions: Vec<_> = WebPushSubscription::list_by_user(&db, user_id)
+        .await?
+        .into_iter()
+        .filter(|s| Some(s.device_identifier.as_str()) != context_id)
+        .collect();
+    if subscriptions.is_empty() {
+        return Ok(());
+    }
+    let keys = vapid_keys(&db).await?;
+
+    let message = json!({
+        "data": {
+            "type": update_type,
+            "contextId": context_id,
+            "payload": payload,
+        }
+    })
+    .to_string();
+    let subject = vapid_subject(env);
+    let mut failure: Option<AppError> = None;
+    for subscription in &subscriptions {
+        match send(&keys, subject.as_deref(), subscription, message.as_bytes()).await {
+            Ok(Delivery::Sent) => {}
+            Ok(Delivery::Gone) => {
+                log::info!(
+                    "Web push subscription of device {} expired",
+                    subscription.device_identifier
+                );
+                if let Err(e) = subscription.delete_expired(&db).await {
+                    log::warn!("Failed to delete expired web push subscription: {e}");
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    "Web push to device {} failed: {e}",
+                    subscription.device_identifier
+                );
+                // Report a failure worth retrying over one that is not.
+                if failure.as_ref().is_none_or(outbox::is_permanent) {
+                    failure = Some(e);
+                }
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+enum Delivery {
+    Sent,
+    /// The push service no longer knows the subscription.
+    Gone,
+}
+
+async fn send(
+    keys: &VapidKeys,
+    subject: Option<&str>,
+    subscription: &WebPushSubscription,
+    message: &[u8],
+) -> Result<Delivery, AppError> {
+    let body = encrypt(subscription, message).await?;
+    let authorization = vapid_authorization(
+        keys,
+        &subscription.endpoint,
+        subject,
+        chrono::Utc::now().timestamp(),
+    )?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(Uint8Array::from(body.as_slice()).into()));
+    let mut req = Request::new_with_init(&subscription.endpoint, &init)?;
+    let headers = req.headers_mut()?;
+    headers.set("Authorization", &authorization)?;
+    headers.set("Content-Encoding", "aes128gcm")?;
+    headers.set("Content-Type", "application/octet-stream")?;
+    headers.set("TTL", &MESSAGE_TTL_SECS.to_string())?;
+    headers.set("Urgency", "normal")?;
+
+    let mut response = Fetch::Request(req).send().await?;
+    match response.status_code() {
+        200..=299 => Ok(Delivery::Sent),
+        404 | 410 => Ok(Delivery::Gone),
+        status => {
+            let text = response.text().await.unwrap_or_default();
+            log::warn!("Push service answered {status}: {text}");
+            Err(push::http_error("Push service", status))
+        }
+    }
+}
+
+/// Encrypt `message` for the subscription as a single `aes128gcm` record.
+async fn encrypt(subscription: &WebPushSubscription, message: &[u8]) -> Result<Vec<u8>, AppError> {
+    let invalid = || AppError::Crypto("Invalid web push subscription keys".to_string());
+    let ua_public = URL_SAFE_NO_PAD
+        .decode(subscription.p256dh.trim_end_matches('='))
+        .map_err(|_| invalid())?;
+    let auth = URL_SAFE_NO_PAD
+        .decode(subscription.auth.trim_end_matches('='))
+        .map_err(|_| invalid())?;
+    let ua_key = PublicKey::from_sec1_bytes(&ua_public).map_err(|_| invalid())?;
+
+    let as_secret = generate_secret()?;
+    let as_public = public_key_bytes(&as_secret);
+    let salt = crypto::random_bytes(16)?;
+    let keys = content_keys(
+        &ecdh(&as_secret, &ua_key),
+        &auth,
+        &ua_public,
+        &as_public,
+        &salt,
+    );
+
+    // A single record ends with the 0x02 delimiter and needs no padding.
+    let mut plaintext = message.to_vec();
+    plaintext.push(2);
+    let ciphertext = crypto::aes_gcm_encrypt(&keys.cek, &keys.nonce, &plaintext).await?;
+
+    let mut body = record_header(&salt, &as_public);
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+// ── RFC 8291 / RFC 8292 ─────────────────────────────────────────────
+
+struct ContentKeys {
+    cek: [u8; 16],
+    nonce: [u8; 12],
+}
+
+/// The x coordinate of `secret` times `public`, the ECDH shared secret.
+fn ecdh(secret: &SecretKey, public: &PublicKey) -> [u8; 32] {
+    let shared = (public.to_projective() * *secret.to_nonzero_scalar()).to_affine();
+    let point = shared.to_encoded_point(false);
+    let mut x = [0u8; 32];
+    x.copy_from_slice(point.x().expect("shared point is not the identity"));
+    x
+}
+
+/// Content encryption key and nonce for one message (RFC 8291 section 3.4, RFC 8188 section 2.2).
+fn content_keys(
+    ecdh_secret: &[u8],
+    auth: &[u8],
+    ua_public: &[u8],
+    as_public: &[u8],
+    salt: &[u8],
+) -> ContentKeys {
+    let key_info = [b"WebPush: info\0".as_slice(), ua_public, as_public].concat();
+    let ikm = hkdf(auth, ecdh_secret, &key_info);
+    let cek = hkdf(salt, &ikm, b"Content-Encoding: aes128gcm\0");
+    let nonce = hkdf(salt, &ikm, b"Content-Encoding: nonce\0");
+    ContentKeys {
+        cek: cek[..16].try_into().expect("slice of 16"),
+        nonce: nonce[..12].try_into().expect("slice of 12"),
+    }
+}
+
+/// HKDF-SHA-256 (RFC 5869) limited to one output block, which is all Web Push needs.
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+    let prk = hmac_sha256(salt, &[ikm]);
+    hmac_sha256(&prk, &[info, &[1]])
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(key).expect("HMAC takes any key");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// The `aes128gcm` header: salt, record size and the sender's public key as key id.
+fn record_header(salt: &[u8], as_public: &[u8]) -> Vec<u8> {
+    let mut header = salt.to_vec();
+    header.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    header.push(as_public.len() as u8);
+    header.extend_from_slice(as_public);
+    header
+}
+
+/// The `Authorization: vapid t=…, k=…` header for a push to `endpoint` at time `now`.
+fn vapid_authorization(
+    keys: &VapidKeys,
+    endpoint: &str,
+    subject: Option<&str>,
+    now: i64,
+) -> Result<String, AppError> {
+    let url = worker::Url::parse(endpoint)
+        .map_err(|_| AppError::Crypto("Invalid web push endpoint".to_string()))?;
+    let mut claims = json!({
+        "aud": url.origin().ascii_serialization(),
+        "exp": now + VAPID_TOKEN_SECS,
+    });
+    if let Some(subject) = subject {
+        claims["sub"] = Value::String(subject.to_string());
+    }
+
+    let header = URL_SAFE_NO_PAD.encode(br#"{"typ":"JWT","alg":"ES256"}"#);
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signing_input = format!("{header}.{claims}");
+    let signature: Signature = SigningKey::from(&keys.secret).sign(signing_input.as_bytes());
+    let token = format!(
+        "{signing_input}.{}",
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    );
+    Ok(format!("vapid t={token}, k={}", keys.public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Verifier, VerifyingKey};
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn hkdf_matches_rfc5869_test_case_1() {
+        let ikm = [0x0b; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        assert_eq!(
+            hex::encode(hkdf(&salt, &ikm, &info)),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+        );
+    }
+
+    #[test]
+    fn browser_and_server_derive_the_same_content_keys() {
+        let (ua, server) = (secret(1), secret(2));
+        let (ua_public, as_public) = (public_key_bytes(&ua), public_key_bytes(&server));
+        let (auth, salt) = ([7u8; 16], [9u8; 16]);
+
+        let sent = content_keys(
+            &ecdh(&server, &ua.public_key()),
+            &auth,
+            &ua_public,
+            &as_public,
+            &salt,
+        );
+        let received = content_keys(
+            &ecdh(&ua, &server.public_key()),
+            &auth,
+            &ua_public,
+            &as_public,
+            &salt,
+        );
+        assert_eq!(sent.cek, received.cek);
+        assert_eq!(sent.nonce, received.nonce);
+
+        let header = record_header(&salt, &as_public);
+        assert_eq!(header.len(), 16 + 4 + 1 + 65);
+        assert_eq!(&header[16..21], &[0, 0, 0x10, 0, 65]);
+    }
+
+    #[test]
+    fn vapid_tokens_are_signed_for_the_push_service_origin() {
+        let secret = secret(3);
+        let keys = VapidKeys {
+            public_key: URL_SAFE_NO_PAD.encode(public_key_bytes(&secret)),
+            secret,
+        };
+        let header = vapid_authorization(
+            &keys,
+            "https://push.example.net:8443/send/abc?x=1",
+            Some("mailto:admin@example.com"),
+            1_000,
+        )
+        .unwrap();
+        let token = header
+            .strip_prefix("vapid t=")
+            .and_then(|rest| rest.split(", k=").next())
+            .unwrap();
+        assert!(header.ends_with(&format!(", k={}", keys.public_key)));
+
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        VerifyingKey::from(keys.secret.public_key())
+            .verify(signing_input.as_bytes(), &signature)
+            .unwrap();
+
+        let claims = signing_input.split('.').nth(1).unwrap();
+        let claims: Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], "https://push.example.net:8443");
+        assert_eq!(claims["exp"], 1_000 + VAPID_TOKEN_SECS);
+        assert_eq!(claims["sub"], "mailto:admin@example.com");
+    }
+
+    #[test]
+    fn subscriptions_need_https_and_valid_keys() {
+        let p256dh = URL_SAFE_NO_PAD.encode(public_key_bytes(&secret(4)));
+        let auth = URL_SAFE_NO_PAD.encode([1u8; 16]);
+        assert!(validate_subscription("https://push.example.net/x", &p256dh, &auth).is_ok());
+        assert!(validate_subscription("http://127.0.0.1:9000/x", &p256dh, &auth).is_ok());
+        assert!(validate_subscription("http://push.example.net/x", &p256dh, &auth).is_err());
+        assert!(validate_subscription("https://push.example.net/x", "AAAA", &auth).is_err());
+        assert!(validate_subscription("https://push.example.net/x", &p256dh, "AAAA").is_err());
+    }
+}
@@ -0,0 +1,314 @@
+// End-to-end tests for the client API. Run with `node --test tests/e2e/*.test.mjs`.
+
+import assert from "node:assert/strict";
+import { randomUUID } from "node:crypto";
+import { after, before, describe, test } from "node:test";
+
+import { encString, startWorker, totp, totpKey } from "./harness.mjs";
+
+let worker;
+let client;
+
+before(async () => {
+  worker = await startWorker();
+  client = worker.client;
+});
+
+after(() => worker?.stop());
+
+function loginCipher(overrides = {}) {
+  return {
+    type: 1,
+    name: encString(),
+    notes: null,
+    favorite: false,
+    login: { username: encString(), password: encString(), uris: [{ uri: encString(), match: null }] },
+    ...overrides,
+  };
+}
+
+describe("accounts", () => {
+  test("register, prelogin and log in", async () => {
+    const user = await client.register();
+
+    const prelogin = await client.post("/identity/accounts/prelogin", { json: { email: user.email } });
+    assert.equal(prelogin.status, 200);
+    assert.equal(prelogin.body.kdf, 0);
+    assert.equal(prelogin.body.kdfIterations, 600000);
+
+    const token = await client.token(user);
+    assert.equal(token.status, 200);
+    assert.equal(token.body.token_type, "Bearer");
+    assert.equal(token.body.scope, "api offline_access");
+    assert.ok(token.body.access_token);
+    assert.ok(token.body.refresh_token);
+    assert.ok(token.body.Key);
+  });
+
+  test("a wrong password is rejected", async () => {
+    const user = await client.register();
+    const response = await client.token({ ...user, masterPasswordHash: "d3JvbmcK" });
+    assert.equal(response.status, 401);
+  });
+
+  test("refresh tokens mint new access tokens", async () => {
+    const user = await client.register();
+    const { body } = await client.token(user);
+
+    const refreshed = await client.post("/identity/connect/token", {
+      form: { grant_type: "refresh_token", client_id: "web", refresh_token: body.refresh_token },
+    });
+    assert.equal(refreshed.status, 200);
+
+    const sync = await client.get("/api/sync", { token: refreshed.body.access_token });
+    assert.equal(sync.status, 200);
+  });
+
+  test("sync returns an empty vault for a new account", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+
+    const sync = await client.get("/api/sync", { token });
+    assert.equal(sync.status, 200);
+    assert.equal(sync.body.object, "sync");
+    assert.equal(sync.body.profile.email, user.email);
+    assert.deepEqual(sync.body.ciphers, []);
+    assert.deepEqual(sync.body.folders, []);
+    assert.deepEqual(sync.body.sends, []);
+  });
+
+  test("requests without a token are unauthorized", async () => {
+    const sync = await client.get("/api/sync");
+    assert.equal(sync.status, 401);
+  });
+});
+
+describe("ciphers", () => {
+  test("create, read, update and delete", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+
+    const created = await client.post("/api/ciphers", { token, json: loginCipher() });
+    assert.equal(created.status, 200);
+    assert.equal(created.body.object, "cipher");
+    const id = created.body.id;
+
+    const fetched = await client.get(`/api/ciphers/${id}`, { token });
+    assert.equal(fetched.status, 200);
+    assert.equal(fetched.body.name, created.body.name);
+
+    const name = encString();
+    const updated = await client.put(`/api/ciphers/${id}`, {
+      token,
+      json: loginCipher({ name, lastKnownRevisionDate: fetched.body.revisionDate }),
+    });
+    assert.equal(updated.status, 200);
+    assert.equal(updated.body.name, name);
+
+    const sync = await client.get("/api/sync", { token });
+    assert.deepEqual(
+      sync.body.ciphers.map((cipher) => [cipher.id, cipher.name]),
+      [[id, name]],
+    );
+
+    const deleted = await client.delete(`/api/ciphers/${id}`, { token });
+    assert.equal(deleted.status, 200);
+    const gone = await client.get(`/api/ciphers/${id}`, { token });
+    assert.equal(gone.status, 404);
+  });
+
+  test("imports keep passkeys and reject incomplete ones", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const passkey = {
+      credentialId: encString(), keyType: encString(), keyAlgorithm: encString(),
+      keyCurve: encString(), keyValue: encString(), rpId: encString(), userHandle: null,
+      userName: encString(), counter: encString(), rpName: encString(),
+      userDisplayName: encString(), discoverable: encString(),
+      creationDate: new Date().toISOString(),
+    };
+    const withPasskeys = (fido2Credentials) => ({
+      ciphers: [loginCipher({ login: { username: encString(), fido2Credentials } })],
+      folders: [],
+      folderRelationships: [],
+    });
+
+    const incomplete = { ...passkey, keyValue: null };
+    const rejected = await client.post("/api/ciphers/import", { token, json: withPasskeys([incomplete]) });
+    assert.equal(rejected.status, 400);
+
+    const imported = await client.post("/api/ciphers/import", { token, json: withPasskeys([passkey]) });
+    assert.equal(imported.status, 200);
+    const sync = await client.get("/api/sync", { token });
+    assert.equal(sync.body.ciphers.length, 1);
+    assert.deepEqual(sync.body.ciphers[0].login.fido2Credentials, [passkey]);
+  });
+
+  test("personal imports cannot place items in an organization", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const planted = await client.post("/api/ciphers/import", {
+      token,
+      json: {
+        ciphers: [loginCipher({ organizationId: randomUUID() })],
+        folders: [],
+        folderRelationships: [],
+      },
+    });
+    assert.equal(planted.status, 400);
+    const sync = await client.get("/api/sync", { token });
+    assert.deepEqual(sync.body.ciphers, []);
+  });
+
+  test("ciphers are not visible to other users", async () => {
+    const owner = await client.register();
+    const other = await client.register();
+    const ownerToken = await client.login(owner);
+    const otherToken = await client.login(other);
+
+    const created = await client.post("/api/ciphers", { token: ownerToken, json: loginCipher() });
+    const fetched = await client.get(`/api/ciphers/${created.body.id}`, { token: otherToken });
+    assert.equal(fetched.status, 404);
+
+    const sync = await client.get("/api/sync", { token: otherToken });
+    assert.deepEqual(sync.body.ciphers, []);
+  });
+});
+
+describe("plaintext import", () => {
+  test("a LastPass export converts to a Bitwarden export", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const csv =
+      "url,username,password,totp,extra,name,grouping,fav\n" +
+      "https://example.com,alice,secret,,,Example,Work,1\n";
+
+    const converted = await client.post("/api/ciphers/import/lastpass", {
+      token,
+      body: csv,
+      headers: { "Content-Type": "text/csv" },
+    });
+    assert.equal(converted.status, 200);
+    assert.equal(converted.body.object, "importConversion");
+    assert.deepEqual(converted.body.warnings, []);
+    const { folders, items } = converted.body.data;
+    assert.deepEqual(folders.map((folder) => folder.name), ["Work"]);
+    assert.equal(items[0].login.password, "secret");
+    assert.equal(items[0].folderId, folders[0].id);
+
+    const unknown = await client.post("/api/ciphers/import/csv", { token, body: csv });
+    assert.equal(unknown.status, 400);
+  });
+});
+
+describe("sends", () => {
+  function textSend(overrides = {}) {
+    return {
+      type: 0,
+      key: encString(),
+      name: encString(),
+      notes: null,
+      text: { text: encString(), hidden: false },
+      deletionDate: new Date(Date.now() + 7 * 24 * 60 * 60 * 1000).toISOString(),
+      disabled: false,
+      hideEmail: false,
+      ...overrides,
+    };
+  }
+
+  test("text sends can be accessed anonymously", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+
+    const created = await client.post("/api/sends", { token, json: textSend() });
+    assert.equal(created.status, 200);
+    assert.equal(created.body.object, "send");
+
+    const accessed = await client.post(`/api/sends/access/${created.body.accessId}`, { json: {} });
+    assert.equal(accessed.status, 200);
+    assert.equal(accessed.body.object, "send-access");
+    assert.equal(accessed.body.id, created.body.id);
+    assert.deepEqual(accessed.body.text, created.body.text);
+
+    const fetched = await client.get(`/api/sends/${created.body.id}`, { token });
+    assert.equal(fetched.body.accessCount, 1);
+  });
+
+  test("password protected sends need the password", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const password = "c2VuZC1wYXNzd29yZA==";
+
+    const created = await client.post("/api/sends", { token, json: textSend({ password }) });
+    assert.equal(created.status, 200);
+    const path = `/api/sends/access/${created.body.accessId}`;
+
+    assert.equal((await client.post(path, { json: {} })).status, 401);
+    assert.equal((await client.post(path, { json: { password: "d3Jvbmc=" } })).status, 400);
+    assert.equal((await client.post(path, { json: { password } })).status, 200);
+  });
+
+  test("deleted sends are gone", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+
+    const created = await client.post("/api/sends", { token, json: textSend() });
+    assert.equal((await client.delete(`/api/sends/${created.body.id}`, { token })).status, 200);
+
+    const accessed = await client.post(`/api/sends/access/${created.body.accessId}`, { json: {} });
+    assert.equal(accessed.status, 404);
+  });
+});
+
+describe("two-factor", () => {
+  test("an authenticator is required at login once enabled", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const key = totpKey();
+
+    const enabled = await client.post("/api/two-factor/authenticator", {
+      token,
+      // The previous step is inside the drift window and leaves the current one unspent.
+      json: { key, token: totp(key, -1), masterPasswordHash: user.masterPasswordHash },
+    });
+    assert.equal(enabled.status, 200);
+    assert.equal(enabled.body.enabled, true);
+
+    const challenged = await client.token(user);
+    assert.equal(challenged.status, 400);
+    assert.equal(challenged.body.error, "invalid_grant");
+    assert.deepEqual(challenged.body.TwoFactorProviders, ["0"]);
+
+    const wrong = await client.token(user, { twoFactorToken: "000000", twoFactorProvider: "0" });
+    assert.notEqual(wrong.status, 200);
+
+    const loggedIn = await client.token(user, { twoFactorToken: totp(key), twoFactorProvider: "0" });
+    assert.equal(loggedIn.status, 200);
+    assert.ok(loggedIn.body.access_token);
+  });
+});
+
+describe("passkeys", () => {
+  test("login challenges can be answered only once", async () => {
+    const options = await client.get("/identity/accounts/webauthn/assertion-options");
+    assert.equal(options.status, 200);
+    const grant = {
+      grant_type: "webauthn",
+      token: options.body.token,
+      deviceResponse: "{}",
+      scope: "api offline_access",
+      client_id: "web",
+      deviceType: "9",
+      deviceIdentifier: randomUUID(),
+      deviceName: "chrome",
+    };
+
+    const first = await client.post("/identity/connect/token", { form: grant });
+    assert.equal(first.status, 400);
+    assert.match(JSON.stringify(first.body), /Invalid WebAuthn response/);
+
+    const replayed = await client.post("/identity/connect/token", { form: grant });
+    assert.equal(replayed.status, 400);
+    assert.match(JSON.stringify(replayed.body), /Invalid or expired WebAuthn token/);
+  });
+});
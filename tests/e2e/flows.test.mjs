@@ -0,0 +1,146 @@
+// Multi-step API flows: replays the requests in `flows/` and checks every response against the
+// expected shape written in the flow. The flows are written by hand, so they guard the response
+// casing and types against regressions; they are not captures of real client traffic and say
+// nothing about compatibility with any particular client. See README "Tests" for the format.
+
+import assert from "node:assert/strict";
+import { randomBytes, randomUUID } from "node:crypto";
+import { readdirSync, readFileSync } from "node:fs";
+import { join } from "node:path";
+import { after, before, describe, test } from "node:test";
+
+import { ROOT, encString, startWorker, totp, totpKey } from "./harness.mjs";
+
+const FLOWS = join(ROOT, "tests/e2e/flows");
+
+let worker;
+
+before(async () => {
+  worker = await startWorker();
+});
+
+after(() => worker?.stop());
+
+/// Values available to `{{name}}` placeholders before any step runs.
+function initialVars() {
+  return {
+    email: `${randomUUID()}@example.com`,
+    masterPasswordHash: randomBytes(32).toString("base64"),
+    deviceId: randomUUID(),
+    otherDeviceId: randomUUID(),
+    totpKey: totpKey(),
+    accessCode: randomBytes(12).toString("base64url"),
+    publicKey: randomBytes(32).toString("base64"),
+    deletionDate: new Date(Date.now() + 7 * 24 * 60 * 60 * 1000).toISOString(),
+  };
+}
+
+function substitute(value, vars) {
+  if (typeof value === "string") {
+    return value.replace(/\{\{([^}]+)\}\}/g, (_, name) => {
+      if (name === "encString") return encString();
+      const step = /^totp([+-]\d+)?$/.exec(name);
+      if (step) return totp(vars.totpKey, Number(step[1] ?? 0));
+      if (!(name in vars)) throw new Error(`unknown placeholder {{${name}}}`);
+      return vars[name];
+    });
+  }
+  if (Array.isArray(value)) return value.map((item) => substitute(item, vars));
+  if (value && typeof value === "object") {
+    return Object.fromEntries(Object.entries(value).map(([k, v]) => [k, substitute(v, vars)]));
+  }
+  return value;
+}
+
+function lookup(value, path) {
+  return path.split(".").reduce((node, key) => node?.[key], value);
+}
+
+function kind(value) {
+  if (value === null) return "null";
+  if (Array.isArray(value)) return "array";
+  return typeof value;
+}
+
+/// Check `actual` against the `expected` body and return every mismatch.
+///
+/// Expected keys must be present with the same casing, `null` must stay `null` and everything
+/// else must keep its JSON type. Arrays are checked element-wise against the first expected
+/// element; an expected `[]` only requires an array. `object` values must match exactly.
+function shapeErrors(actual, expected, path = "$") {
+  const expectedKind = kind(expected);
+  const actualKind = kind(actual);
+  if (expectedKind !== actualKind) {
+    return [`${path}: expected ${expectedKind}, got ${actualKind}`];
+  }
+
+  if (expectedKind === "array") {
+    if (expected.length === 0) return [];
+    if (actual.length === 0) return [`${path}: expected at least one element`];
+    return actual.flatMap((item, index) => shapeErrors(item, expected[0], `${path}[${index}]`));
+  }
+
+  if (expectedKind !== "object") return [];
+
+  const errors = [];
+  for (const [key, value] of Object.entries(expected)) {
+    if (!(key in actual)) {
+      const other = Object.keys(actual).find((k) => k.toLowerCase() === key.toLowerCase());
+      errors.push(other ? `${path}.${key}: sent as "${other}"` : `${path}.${key}: missing`);
+      continue;
+    }
+    if (key.toLowerCase() === "object" && actual[key] !== value) {
+      errors.push(`${path}.${key}: expected "${value}", got ${JSON.stringify(actual[key])}`);
+      continue;
+    }
+    errors.push(...shapeErrors(actual[key], value, `${path}.${key}`));
+  }
+  for (const key of Object.keys(actual)) {
+    const twin = Object.keys(expected).find((k) => k !== key && k.toLowerCase() === key.toLowerCase());
+    if (!(key in expected) && twin && twin in actual) {
+      errors.push(`${path}.${key}: duplicate of an expected key with different casing`);
+    }
+  }
+  return errors;
+}
+
+async function replay(flow) {
+  const vars = initialVars();
+  for (const [index, step] of flow.steps.entries()) {
+    const label = `step ${index + 1} (${step.request.method} ${step.request.path})`;
+    const request = substitute(step.request, vars);
+    const response = await worker.client.request(request.method, request.path, {
+      headers: request.headers,
+      json: request.json,
+      form: request.form,
+    });
+
+    assert.equal(
+      response.status,
+      step.response.status,
+      `${label}: status ${response.status}, body ${JSON.stringify(response.body)}`,
+    );
+    if (step.response.body !== undefined) {
+      const errors = shapeErrors(response.body, step.response.body);
+      assert.deepEqual(errors, [], `${label}: response shape differs`);
+    }
+    for (const path of step.response.exact ?? []) {
+      assert.deepEqual(lookup(response.body, path), lookup(step.response.body, path), `${label}: ${path}`);
+    }
+    for (const [name, path] of Object.entries(step.capture ?? {})) {
+      vars[name] = lookup(response.body, path);
+      assert.ok(vars[name] !== undefined, `${label}: nothing to capture at ${path}`);
+    }
+  }
+}
+
+const flows = readdirSync(FLOWS)
+  .filter((name) => name.endsWith(".json"))
+  .sort()
+  .map((name) => ({ name, ...JSON.parse(readFileSync(join(FLOWS, name), "utf8")) }));
+
+describe("API flows", () => {
+  for (const flow of flows) {
+    test(`${flow.description} (${flow.name})`, () => replay(flow));
+  }
+});
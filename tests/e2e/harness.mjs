@@ -0,0 +1,256 @@
+// Runs the worker under `wrangler dev --local` for the end-to-end tests.
+//
+// Every run gets a fresh, SQLite-backed local D1 database, so the tests exercise the real router,
+// bindings, migrations and SQL. See README "Tests".
+
+import { spawn } from "node:child_process";
+import { createHmac, randomBytes, randomUUID } from "node:crypto";
+import { mkdirSync, mkdtempSync, readFileSync, rmSync, writeFileSync } from "node:fs";
+import { createServer } from "node:net";
+import { tmpdir } from "node:os";
+import { dirname, join, resolve } from "node:path";
+import { fileURLToPath } from "node:url";
+
+export const ROOT = resolve(dirname(fileURLToPath(import.meta.url)), "../..");
+const WRANGLER = process.env.WRANGLER ?? "wrangler@4.82.1";
+const STARTUP_TIMEOUT_MS = Number(process.env.E2E_STARTUP_TIMEOUT_MS ?? 10 * 60 * 1000);
+
+export const TEST_VARS = {
+  JWT_SECRET: "e2e-jwt-secret-0123456789abcdef",
+  JWT_REFRESH_SECRET: "e2e-jwt-refresh-secret-0123456789",
+  ALLOWED_EMAILS: "*@example.com",
+  ALLOW_PLAINTEXT_CONVERSION: "true",
+};
+
+/// A copy of wrangler.toml usable offline, written to `dir`: a fixed local D1 id, no rate
+/// limiter (so the tests can log in as often as they need) and an empty web vault.
+function writeTestConfig(dir) {
+  // wrangler resolves paths against the config file, so point them back at the checkout.
+  const inRoot = (path) => JSON.stringify(join(ROOT, path));
+  // The API does not need the web vault, but wrangler refuses a missing assets directory.
+  const assets = join(dir, "web-vault");
+  mkdirSync(assets);
+  const config = readFileSync(join(ROOT, "wrangler.toml"), "utf8")
+    .replaceAll("${D1_DATABASE_ID}", "00000000-0000-0000-0000-000000000000")
+    .replaceAll(/^\[\[ratelimits\]\][\s\S]*?(?=^\[)/gm, "")
+    .replace(/^main = .*$/m, `main = ${inRoot("src/entry.js")}`)
+    .replace(/^\[build\]$/m, `[build]\ncwd = ${inRoot(".")}\nwatch_dir = ${inRoot("src")}`)
+    .replaceAll(/^migrations_dir = .*$/gm, `migrations_dir = ${inRoot("migrations")}`)
+    .replaceAll(/^directory = .*$/gm, `directory = ${JSON.stringify(assets)}`);
+  const path = join(dir, "wrangler.toml");
+  writeFileSync(path, config);
+  return path;
+}
+
+/// A scratch directory holding the test config and a fresh local D1 database. The database
+/// starts empty; the worker applies the schema on its first request, so every run also
+/// exercises the migration runner.
+export function createDatabase() {
+  const dir = mkdtempSync(join(tmpdir(), "warden-e2e-"));
+  const config = writeTestConfig(dir);
+  return { dir, config, persistTo: join(dir, "state") };
+}
+
+function freePort() {
+  return new Promise((resolvePort, reject) => {
+    const server = createServer();
+    server.unref();
+    server.on("error", reject);
+    server.listen(0, "127.0.0.1", () => {
+      const { port } = server.address();
+      server.close(() => resolvePort(port));
+    });
+  });
+}
+
+/// Build and start the worker with `TEST_VARS` plus `extraVars`. Resolves once `/api/alive`
+/// answers.
+export async function startWorker(extraVars = {}) {
+  const { dir, config, persistTo } = createDatabase();
+  const port = await freePort();
+  const vars = Object.entries({ ...TEST_VARS, ...extraVars }).flatMap(([key, value]) => ["--var", `${key}:${value}`]);
+  const child = spawn(
+    "npx",
+    [
+      "--yes", WRANGLER, "dev", "--local",
+      "--config", config,
+      "--persist-to", persistTo,
+      "--ip", "127.0.0.1",
+      "--port", String(port),
+      "--show-interactive-dev-session=false",
+      ...vars,
+    ],
+    {
+      cwd: ROOT,
+      detached: true,
+      stdio: ["ignore", "pipe", "pipe"],
+      env: { ...process.env, CI: "true", WRANGLER_SEND_METRICS: "false" },
+    },
+  );
+  let output = "";
+  child.stdout.on("data", (chunk) => (output += chunk));
+  child.stderr.on("data", (chunk) => (output += chunk));
+
+  const baseUrl = `http://127.0.0.1:${port}`;
+  const stop = () => {
+    try {
+      process.kill(-child.pid, "SIGTERM");
+    } catch {
+      // Already gone.
+    }
+    rmSync(dir, { recursive: true, force: true });
+  };
+
+  const deadline = Date.now() + STARTUP_TIMEOUT_MS;
+  while (Date.now() < deadline) {
+    if (child.exitCode !== null) {
+      stop();
+      throw new Error(`wrangler dev exited with ${child.exitCode}:\n${output}`);
+    }
+    try {
+      const response = await fetch(`${baseUrl}/api/alive`);
+      if (response.ok) {
+        return { baseUrl, stop, client: new Client(baseUrl) };
+      }
+    } catch {
+      // Not listening yet.
+    }
+    await new Promise((r) => setTimeout(r, 1000));
+  }
+  stop();
+  throw new Error(`wrangler dev did not become ready in time:\n${output}`);
+}
+
+/// Minimal API client speaking the same wire format as the official clients.
+export class Client {
+  constructor(baseUrl) {
+    this.baseUrl = baseUrl;
+  }
+
+  async request(method, path, { token, json, form, body, headers = {} } = {}) {
+    const init = { method, headers: { ...headers }, body };
+    if (token) init.headers.Authorization = `Bearer ${token}`;
+    if (json !== undefined) {
+      init.headers["Content-Type"] = "application/json";
+      init.body = JSON.stringify(json);
+    }
+    if (form !== undefined) {
+      init.headers["Content-Type"] = "application/x-www-form-urlencoded";
+      init.body = new URLSearchParams(form).toString();
+    }
+    const response = await fetch(`${this.baseUrl}${path}`, init);
+    const text = await response.text();
+    let parsed = text;
+    try {
+      parsed = text ? JSON.parse(text) : null;
+    } catch {
+      // Not JSON; keep the text.
+    }
+    return { status: response.status, headers: response.headers, body: parsed };
+  }
+
+  get(path, options) {
+    return this.request("GET", path, options);
+  }
+
+  post(path, options) {
+    return this.request("POST", path, options);
+  }
+
+  put(path, options) {
+    return this.request("PUT", path, options);
+  }
+
+  delete(path, options) {
+    return this.request("DELETE", path, options);
+  }
+
+  /// Register a new account and return its credentials. Keys are opaque to the server, so
+  /// random encrypted-string look-alikes are enough.
+  async register(email = `${randomUUID()}@example.com`) {
+    const masterPasswordHash = randomBytes(32).toString("base64");
+    const response = await this.post("/identity/accounts/register", {
+      json: {
+        name: "E2E User",
+        email,
+        masterPasswordHash,
+        masterPasswordHint: null,
+        userSymmetricKey: encString(),
+        userAsymmetricKeys: { publicKey: randomBytes(32).toString("base64"), encryptedPrivateKey: encString() },
+        kdf: 0,
+        kdfIterations: 600000,
+      },
+    });
+    if (response.status !== 200) {
+      throw new Error(`register failed: ${response.status} ${JSON.stringify(response.body)}`);
+    }
+    return { email, masterPasswordHash };
+  }
+
+  /// Password grant as sent by the web vault. Returns the raw response.
+  token(user, extra = {}) {
+    return this.post("/identity/connect/token", {
+      form: {
+        grant_type: "password",
+        username: user.email,
+        password: user.masterPasswordHash,
+        scope: "api offline_access",
+        client_id: "web",
+        deviceType: "9",
+        deviceIdentifier: user.deviceIdentifier ?? (user.deviceIdentifier = randomUUID()),
+        deviceName: "chrome",
+        ...extra,
+      },
+    });
+  }
+
+  /// Log in and return the access token.
+  async login(user, extra = {}) {
+    const response = await this.token(user, extra);
+    if (response.status !== 200) {
+      throw new Error(`login failed: ${response.status} ${JSON.stringify(response.body)}`);
+    }
+    return response.body.access_token;
+  }
+}
+
+/// A type 2 (AES-CBC-256 + HMAC) encrypted string with random contents.
+export function encString() {
+  const part = (n) => randomBytes(n).toString("base64");
+  return `2.${part(16)}|${part(32)}|${part(32)}`;
+}
+
+const BASE32 = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A random 20-byte authenticator key, base32 encoded as clients send it.
+export function totpKey() {
+  const bits = [...randomBytes(20)].map((b) => b.toString(2).padStart(8, "0")).join("");
+  let key = "";
+  for (let i = 0; i < bits.length; i += 5) {
+    key += BASE32[parseInt(bits.slice(i, i + 5), 2)];
+  }
+  return key;
+}
+
+function base32Decode(input) {
+  let bits = "";
+  for (const char of input.replace(/=+$/, "").toUpperCase()) {
+    bits += BASE32.indexOf(char).toString(2).padStart(5, "0");
+  }
+  const bytes = [];
+  for (let i = 0; i + 8 <= bits.length; i += 8) {
+    bytes.push(parseInt(bits.slice(i, i + 8), 2));
+  }
+  return Buffer.from(bytes);
+}
+
+/// RFC 6238 code for `secret` (base32), `offset` 30-second steps from now.
+export function totp(secret, offset = 0) {
+  const step = Math.floor(Date.now() / 1000 / 30) + offset;
+  const counter = Buffer.alloc(8);
+  counter.writeBigUInt64BE(BigInt(step));
+  const hmac = createHmac("sha1", base32Decode(secret)).update(counter).digest();
+  const start = hmac[hmac.length - 1] & 0x0f;
+  const code = (hmac.readUInt32BE(start) & 0x7fffffff) % 1_000_000;
+  return String(code).padStart(6, "0");
+}
@@ -0,0 +1,92 @@
+// End-to-end tests for the webhook and ntfy push backends. One local HTTP server receives both.
+
+import assert from "node:assert/strict";
+import { createHmac } from "node:crypto";
+import { createServer } from "node:http";
+import { after, before, describe, test } from "node:test";
+
+import { encString, startWorker } from "./harness.mjs";
+
+const WEBHOOK_SECRET = "webhook-test-secret";
+
+let worker;
+let client;
+let receiver;
+
+before(async () => {
+  receiver = await startReceiver();
+  worker = await startWorker({
+    PUSH_BACKENDS: "webhook,ntfy",
+    PUSH_WEBHOOK_URL: `${receiver.origin}/hook`,
+    PUSH_WEBHOOK_SECRET: WEBHOOK_SECRET,
+    PUSH_NTFY_URL: `${receiver.origin}/ntfy`,
+    PUSH_NTFY_TOKEN: "ntfy-token",
+  });
+  client = worker.client;
+});
+
+after(() => {
+  worker?.stop();
+  receiver?.close();
+});
+
+/// Records every POST by path.
+function startReceiver() {
+  return new Promise((resolve) => {
+    const service = { messages: [] };
+    const server = createServer((req, res) => {
+      const chunks = [];
+      req.on("data", (chunk) => chunks.push(chunk));
+      req.on("end", () => {
+        service.messages.push({ path: req.url, headers: req.headers, body: Buffer.concat(chunks).toString("utf8") });
+        res.writeHead(200).end();
+      });
+    });
+    server.listen(0, "127.0.0.1", () => {
+      service.origin = `http://127.0.0.1:${server.address().port}`;
+      service.close = () => server.close();
+      /// Wait for the first message on `path` whose body matches `predicate`.
+      service.waitFor = async (path, predicate) => {
+        const deadline = Date.now() + 15_000;
+        for (;;) {
+          const found = service.messages.find((m) => m.path === path && predicate(JSON.parse(m.body)));
+          if (found) return found;
+          assert.ok(Date.now() < deadline, `nothing arrived on ${path}`);
+          await new Promise((wake) => setTimeout(wake, 200));
+        }
+      };
+      resolve(service);
+    });
+  });
+}
+
+describe("push backends", () => {
+  test("cipher updates reach the signed webhook and the user's ntfy topic", async () => {
+    const user = await client.register();
+    const token = await client.login(user);
+    const created = await client.post("/api/ciphers", {
+      token,
+      json: {
+        type: 1,
+        name: encString(),
+        notes: null,
+        favorite: false,
+        login: { username: encString(), password: encString(), uris: null },
+      },
+    });
+    assert.equal(created.status, 200);
+    const isCreated = (body) => body.payload?.id === created.body.id;
+
+    const hook = await receiver.waitFor("/hook", isCreated);
+    const expected = createHmac("sha256", WEBHOOK_SECRET)
+      .update(`${hook.headers["x-warden-timestamp"]}.${hook.body}`)
+      .digest("hex");
+    assert.equal(hook.headers["x-warden-signature"], `sha256=${expected}`);
+    const body = JSON.parse(hook.body);
+    assert.equal(body.type, 1);
+    assert.equal(body.contextId, user.deviceIdentifier);
+
+    const ntfy = await receiver.waitFor(`/ntfy/warden-${body.userId}`, isCreated);
+    assert.equal(ntfy.headers.authorization, "Bearer ntfy-token");
+  });
+});
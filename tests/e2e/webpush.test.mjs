@@ -0,0 +1,228 @@
+// End-to-end tests for Web Push. A local HTTP server stands in for the browser's push service:
+// it checks the VAPID token and decrypts each message with the subscription's keys.
+
+import assert from "node:assert/strict";
+import {
+  createDecipheriv,
+  createECDH,
+  createPublicKey,
+  hkdfSync,
+  randomBytes,
+  randomUUID,
+  verify,
+} from "node:crypto";
+import { createServer } from "node:http";
+import { after, before, describe, test } from "node:test";
+
+import { encString, startWorker } from "./harness.mjs";
+
+let worker;
+let client;
+let pushService;
+
+const ADMIN_TOKEN = "e2e-admin-token-0123456789abcdef";
+
+before(async () => {
+  worker = await startWorker({ WEB_PUSH_ENABLED: "true", ADMIN_TOKEN });
+  client = worker.client;
+  pushService = await startPushService();
+});
+
+after(() => {
+  worker?.stop();
+  pushService?.close();
+});
+
+/// Records every POST; answers with `pushService.status` (201 unless a test changes it).
+function startPushService() {
+  return new Promise((resolve) => {
+    const service = { messages: [], status: 201, waiters: [] };
+    const server = createServer((req, res) => {
+      const chunks = [];
+      req.on("data", (chunk) => chunks.push(chunk));
+      req.on("end", () => {
+        service.messages.push({ path: req.url, headers: req.headers, body: Buffer.concat(chunks) });
+        service.waiters.splice(0).forEach((wake) => wake());
+        res.writeHead(service.status).end();
+      });
+    });
+    server.listen(0, "127.0.0.1", () => {
+      service.origin = `http://127.0.0.1:${server.address().port}`;
+      service.close = () => server.close();
+      /// Wait until `count` messages have arrived.
+      service.received = async (count) => {
+        const deadline = Date.now() + 15_000;
+        while (service.messages.length < count) {
+          assert.ok(Date.now() < deadline, "no push message arrived");
+          await new Promise((wake) => {
+            service.waiters.push(wake);
+            setTimeout(wake, 500);
+          });
+        }
+        return service.messages[count - 1];
+      };
+      resolve(service);
+    });
+  });
+}
+
+/// A browser-side subscription: the key pair and auth secret a `PushSubscription` holds.
+function subscription(path) {
+  const ecdh = createECDH("prime256v1");
+  ecdh.generateKeys();
+  const auth = randomBytes(16);
+  return {
+    ecdh,
+    auth,
+    json: {
+      endpoint: `${pushService.origin}${path}`,
+      p256dh: ecdh.getPublicKey().toString("base64url"),
+      auth: auth.toString("base64url"),
+    },
+  };
+}
+
+/// Decrypt an `aes128gcm` message body (RFC 8291) for `sub`.
+function decrypt(sub, body) {
+  const salt = body.subarray(0, 16);
+  const keyIdLength = body[20];
+  const serverPublic = body.subarray(21, 21 + keyIdLength);
+  const ciphertext = body.subarray(21 + keyIdLength);
+
+  const uaPublic = sub.ecdh.getPublicKey();
+  const keyInfo = Buffer.concat([Buffer.from("WebPush: info\0"), uaPublic, serverPublic]);
+  const ikm = Buffer.from(hkdfSync("sha256", sub.ecdh.computeSecret(serverPublic), sub.auth, keyInfo, 32));
+  const cek = Buffer.from(hkdfSync("sha256", ikm, salt, Buffer.from("Content-Encoding: aes128gcm\0"), 16));
+  const nonce = Buffer.from(hkdfSync("sha256", ikm, salt, Buffer.from("Content-Encoding: nonce\0"), 12));
+
+  const decipher = createDecipheriv("aes-128-gcm", cek, nonce);
+  decipher.setAuthTag(ciphertext.subarray(-16));
+  const plaintext = Buffer.concat([decipher.update(ciphertext.subarray(0, -16)), decipher.final()]);
+  assert.equal(plaintext[plaintext.length - 1], 2, "last record delimiter");
+  return JSON.parse(plaintext.subarray(0, -1).toString("utf8"));
+}
+
+/// Check the `vapid t=…, k=…` header against the key from `/api/config`; returns the claims.
+function verifyVapid(header, vapidPublicKey) {
+  const match = /^vapid t=([^,]+), k=(.+)$/.exec(header);
+  assert.ok(match, `unexpected Authorization header: ${header}`);
+  const [, token, key] = match;
+  assert.equal(key, vapidPublicKey);
+
+  const raw = Buffer.from(key, "base64url");
+  const publicKey = createPublicKey({
+    key: {
+      kty: "EC",
+      crv: "P-256",
+      x: raw.subarray(1, 33).toString("base64url"),
+      y: raw.subarray(33).toString("base64url"),
+    },
+    format: "jwk",
+  });
+  const [header64, claims64, signature64] = token.split(".");
+  const valid = verify(
+    "sha256",
+    Buffer.from(`${header64}.${claims64}`),
+    { key: publicKey, dsaEncoding: "ieee-p1363" },
+    Buffer.from(signature64, "base64url"),
+  );
+  assert.ok(valid, "VAPID signature does not verify");
+  return JSON.parse(Buffer.from(claims64, "base64url").toString("utf8"));
+}
+
+describe("web push", () => {
+  test("config offers Web Push with a stable VAPID key", async () => {
+    const first = await client.get("/api/config");
+    assert.equal(first.body.push.pushTechnology, 1);
+    assert.equal(Buffer.from(first.body.push.vapidPublicKey, "base64url").length, 65);
+    const second = await client.get("/api/config");
+    assert.equal(second.body.push.vapidPublicKey, first.body.push.vapidPublicKey);
+  });
+
+  test("extensions receive encrypted updates from other devices", async () => {
+    const user = await client.register();
+    const extension = { ...user, deviceIdentifier: randomUUID() };
+    const extensionToken = await client.login(extension, { deviceType: "2", deviceName: "chrome" });
+    const webToken = await client.login(user);
+
+    const sub = subscription(`/push/${randomUUID()}`);
+    const path = `/api/devices/identifier/${extension.deviceIdentifier}/web-push-auth`;
+    const registered = await client.put(path, { token: extensionToken, json: sub.json });
+    assert.equal(registered.status, 200);
+
+    const before = pushService.messages.length;
+    const created = await client.post("/api/ciphers", {
+      token: webToken,
+      json: {
+        type: 1,
+        name: encString(),
+        notes: null,
+        favorite: false,
+        login: { username: encString(), password: encString(), uris: null },
+      },
+    });
+    assert.equal(created.status, 200);
+
+    const message = await pushService.received(before + 1);
+    assert.equal(message.path, new URL(sub.json.endpoint).pathname);
+    assert.equal(message.headers["content-encoding"], "aes128gcm");
+    assert.ok(Number(message.headers.ttl) > 0);
+
+    const { vapidPublicKey } = (await client.get("/api/config")).body.push;
+    const claims = verifyVapid(message.headers.authorization, vapidPublicKey);
+    assert.equal(claims.aud, pushService.origin);
+    assert.ok(claims.exp > Date.now() / 1000);
+
+    const { data } = decrypt(sub, message.body);
+    assert.equal(data.type, 1);
+    assert.equal(data.payload.id, created.body.id);
+    assert.equal(data.contextId, user.deviceIdentifier);
+  });
+
+  test("failed deliveries are queued for retry", async () => {
+    const user = await client.register();
+    const extension = { ...user, deviceIdentifier: randomUUID() };
+    const extensionToken = await client.login(extension, { deviceType: "2", deviceName: "chrome" });
+    const webToken = await client.login(user);
+    const sub = subscription(`/push/${randomUUID()}`);
+    const path = `/api/devices/identifier/${extension.deviceIdentifier}/web-push-auth`;
+    assert.equal((await client.put(path, { token: extensionToken, json: sub.json })).status, 200);
+
+    const pending = async () =>
+      (await client.get("/admin/outbox", { token: ADMIN_TOKEN })).body.pending;
+    const queued = await pending();
+    const before = pushService.messages.length;
+    pushService.status = 500;
+    try {
+      await client.post("/api/folders", { token: webToken, json: { name: encString() } });
+      await pushService.received(before + 1);
+    } finally {
+      pushService.status = 201;
+    }
+
+    const deadline = Date.now() + 15_000;
+    while ((await pending()) === queued) {
+      assert.ok(Date.now() < deadline, "the failed delivery was not queued");
+      await new Promise((wake) => setTimeout(wake, 500));
+    }
+  });
+
+  test("only browser devices can subscribe, with valid keys", async () => {
+    const user = await client.register();
+    const phone = { ...user, deviceIdentifier: randomUUID() };
+    const phoneToken = await client.login(phone, { deviceType: "0", deviceName: "android" });
+    const sub = subscription("/push/phone");
+    const refused = await client.post(`/api/devices/identifier/${phone.deviceIdentifier}/web-push-auth`, {
+      token: phoneToken,
+      json: sub.json,
+    });
+    assert.equal(refused.status, 400);
+
+    const webToken = await client.login(user);
+    const invalid = await client.post(`/api/devices/identifier/${user.deviceIdentifier}/web-push-auth`, {
+      token: webToken,
+      json: { ...sub.json, endpoint: "http://push.example.net/x" },
+    });
+    assert.equal(invalid.status, 400);
+  });
+});